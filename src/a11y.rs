@@ -0,0 +1,38 @@
+// `--a11y`: output that reads well through a screen reader or on a braille display.
+//
+// - rail's own messages become one plain sentence, "rail: Log file rotation detected",
+//   instead of a "--- ... ---" banner between blank lines.
+// - With --format, each record starts with its level spelled out, "[ERROR] ", so the
+//   severity is announced first (logcat's one-letter levels included).
+// - --color-traces tags lines with "[trace 4bf92f35] " instead of colouring them.
+// - --columns is left off; records stay key=value, which reads linearly.
+// - --highlight colours nothing.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::fields::Record;
+use crate::otlp::LEVEL_FIELDS;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// "\n--- Log file rotation detected ---\n" -> "rail: Log file rotation detected"
+pub fn status(message: &str) -> String {
+    let text = message.trim();
+    let text = text.strip_prefix("---").unwrap_or(text);
+    let text = text.strip_suffix("---").unwrap_or(text);
+    format!("rail: {}", text.trim())
+}
+
+// The record's level as a tag to start its line with
+pub fn level_tag(record: &Record) -> Option<String> {
+    let level = LEVEL_FIELDS.iter().find_map(|name| record.get(name))?;
+    Some(format!("[{}] ", level.to_ascii_uppercase()))
+}
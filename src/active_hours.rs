@@ -0,0 +1,136 @@
+// `--active-hours '08:00-18:00 Mon-Fri'`: only read and ship lines during the given
+// windows of local time. Outside them the file isn't read at all; the position is kept
+// (and recorded in --state-file), so whatever was written meanwhile goes out once the
+// next window opens.
+//
+// A spec is one or more groups separated by ';', each a comma list of HH:MM-HH:MM
+// ranges and optionally a comma list of days or day ranges (every day if left out):
+//
+//   08:00-12:00,13:00-17:00 Mon-Fri; 10:00-14:00 Sat
+//
+// A range that ends before it starts runs past midnight and belongs to the day it
+// starts on: "22:00-06:00 Fri" covers Friday night into Saturday morning.
+
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use crate::output;
+use crate::tz;
+
+const DAYS: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+
+#[derive(Debug)]
+struct Window {
+    // Minutes since midnight; `end` may be 1440 (24:00)
+    start: u32,
+    end: u32,
+    days: [bool; 7],
+}
+
+#[derive(Debug)]
+pub struct Schedule {
+    spec: String,
+    windows: Vec<Window>,
+}
+
+static SCHEDULE: OnceLock<Schedule> = OnceLock::new();
+
+pub fn parse_schedule(spec: &str) -> Result<Schedule, String> {
+    let mut windows = Vec::new();
+    for group in spec.split(';').map(str::trim) {
+        let (ranges, days) = match group.split_once(char::is_whitespace) {
+            Some((ranges, days)) => (ranges, parse_days(days.trim())?),
+            None => (group, [true; 7]),
+        };
+        if ranges.is_empty() {
+            return Err(format!("missing time range in '{}'", group));
+        }
+        for range in ranges.split(',') {
+            let (start, end) = range
+                .split_once('-')
+                .ok_or(format!("expected HH:MM-HH:MM, got '{}'", range))?;
+            let (start, end) = (parse_time(start)?, parse_time(end)?);
+            if start == end || start == 1440 {
+                return Err(format!("empty time range '{}'", range));
+            }
+            windows.push(Window { start, end, days });
+        }
+    }
+    Ok(Schedule { spec: spec.to_string(), windows })
+}
+
+pub fn set_schedule(schedule: Schedule) {
+    let _ = SCHEDULE.set(schedule);
+}
+
+fn parse_time(text: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid time '{}' (expected HH:MM)", text);
+    let (h, m) = text.split_once(':').ok_or_else(invalid)?;
+    let (h, m): (u32, u32) = (h.parse().map_err(|_| invalid())?, m.parse().map_err(|_| invalid())?);
+    if m >= 60 || h > 24 || (h == 24 && m != 0) {
+        return Err(invalid());
+    }
+    Ok(h * 60 + m)
+}
+
+fn parse_days(text: &str) -> Result<[bool; 7], String> {
+    let day = |name: &str| {
+        DAYS.iter()
+            .position(|d| name.get(..3).is_some_and(|prefix| d.eq_ignore_ascii_case(prefix)))
+            .ok_or(format!("unknown day '{}'", name))
+    };
+    let mut days = [false; 7];
+    for item in text.split(',').map(str::trim) {
+        match item.split_once('-') {
+            // Ranges may wrap around the week, e.g. Fri-Mon
+            Some((from, to)) => {
+                let (from, to) = (day(from)?, day(to)?);
+                let mut d = from;
+                loop {
+                    days[d] = true;
+                    if d == to {
+                        break;
+                    }
+                    d = (d + 1) % 7;
+                }
+            }
+            None => days[day(item)?] = true,
+        }
+    }
+    Ok(days)
+}
+
+impl Schedule {
+    pub fn covers(&self, time: tz::LocalTime) -> bool {
+        let minute = time.hour * 60 + time.minute;
+        let day = time.weekday as usize;
+        let yesterday = (day + 6) % 7;
+        self.windows.iter().any(|w| {
+            if w.start < w.end {
+                w.days[day] && minute >= w.start && minute < w.end
+            } else {
+                (w.days[day] && minute >= w.start) || (w.days[yesterday] && minute < w.end)
+            }
+        })
+    }
+}
+
+// Block until the schedule (if any) allows reading again
+pub fn wait() {
+    let Some(schedule) = SCHEDULE.get() else {
+        return;
+    };
+    let mut now = tz::now();
+    if schedule.covers(now) {
+        return;
+    }
+    output::flush();
+    output::status(format_args!("\n--- Outside active hours ({}); paused ---\n", schedule.spec));
+    while !schedule.covers(now) {
+        // Windows open on the minute
+        thread::sleep(Duration::from_secs(60 - now.second as u64));
+        now = tz::now();
+    }
+    output::status(format_args!("\n--- Active hours ({}); resuming ---\n", schedule.spec));
+}
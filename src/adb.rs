@@ -0,0 +1,164 @@
+// `rail adb [device] [--buffer <name>] [--json] [--fail-on <regex>]`: stream an Android
+// device's log through rail. Runs `adb logcat -v threadtime` and parses each line into
+// fields (see the logcat format in fields.rs), so --json and --fail-on work on device
+// logs the same way they do on files.
+//
+// When adb exits (device unplugged, adb server restarted) logcat is restarted with -T
+// from the last printed timestamp, per the --reconnect-* policy.
+
+use std::io::{self, Read};
+use std::process::{self, Child, Command, Stdio};
+
+use crate::fail_on;
+use crate::fields::{self, Format};
+use crate::regex::Regex;
+use crate::transport::{self, Reconnect, Transport};
+
+const BUFFERS: &[&str] = &["main", "system", "crash", "events", "radio", "kernel", "all", "default"];
+
+pub fn run(args: &[String]) -> io::Result<()> {
+    let mut device: Option<&str> = None;
+    let mut buffer: Option<&str> = None;
+    let mut json = false;
+    let mut reconnect = Reconnect::default();
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--json" => {
+                json = true;
+                i += 1;
+            }
+            "--buffer" => {
+                if i + 1 < args.len() && BUFFERS.contains(&args[i + 1].as_str()) {
+                    buffer = Some(&args[i + 1]);
+                    i += 2;
+                } else {
+                    eprintln!("Error: --buffer requires one of: {}", BUFFERS.join(", "));
+                    process::exit(1);
+                }
+            }
+            "--reconnect-max" => {
+                match args.get(i + 1).map(|n| n.parse::<u32>()) {
+                    Some(Ok(n)) => reconnect.max_attempts = n,
+                    _ => {
+                        eprintln!("Error: --reconnect-max requires a number argument");
+                        process::exit(1);
+                    }
+                }
+                i += 2;
+            }
+            "--reconnect-backoff" => {
+                match args.get(i + 1).map(|d| transport::parse_backoff(d)) {
+                    Some(Ok(d)) => reconnect.backoff = d,
+                    Some(Err(e)) => {
+                        eprintln!("Error: Invalid --reconnect-backoff: {}", e);
+                        process::exit(1);
+                    }
+                    None => {
+                        eprintln!("Error: --reconnect-backoff requires an argument");
+                        process::exit(1);
+                    }
+                }
+                i += 2;
+            }
+            "--fail-on" => {
+                if i + 1 < args.len() {
+                    match Regex::new(&args[i + 1]) {
+                        Ok(re) => fail_on::set_pattern(re),
+                        Err(e) => {
+                            eprintln!("Error: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --fail-on requires a regex argument");
+                    process::exit(1);
+                }
+            }
+            arg if !arg.starts_with('-') && device.is_none() => {
+                device = Some(arg);
+                i += 1;
+            }
+            _ => {
+                eprintln!("Unknown option: {}", args[i]);
+                process::exit(1);
+            }
+        }
+    }
+
+    fields::set_format(Format::Logcat);
+    fields::set_json(json);
+
+    let mut adb = Adb { device: device.map(String::from), buffer: buffer.map(String::from), child: None };
+    if let Err(e) = transport::stream(&mut adb, reconnect) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+    if fail_on::matched() {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+struct Adb {
+    device: Option<String>,
+    buffer: Option<String>,
+    child: Option<Child>,
+}
+
+impl Transport for Adb {
+    fn describe(&self) -> String {
+        match &self.device {
+            Some(serial) => format!("adb device '{}'", serial),
+            None => "adb".to_string(),
+        }
+    }
+
+    fn connect(&mut self, resume: Option<&str>) -> io::Result<Box<dyn Read>> {
+        let mut command = Command::new("adb");
+        if let Some(serial) = &self.device {
+            command.args(["-s", serial]);
+        }
+        command.args(["logcat", "-v", "threadtime"]);
+        if let Some(name) = &self.buffer {
+            command.args(["-b", name]);
+        }
+        // After a dropped connection, ask only for what came after the last line we printed
+        if let Some(since) = resume {
+            command.args(["-T", since]);
+        }
+        let mut child = command.stdout(Stdio::piped()).spawn().map_err(|e| {
+            io::Error::new(e.kind(), format!("could not run adb: {}", e))
+        })?;
+        let stdout = child.stdout.take().unwrap();
+        self.child = Some(child);
+        Ok(Box::new(stdout))
+    }
+
+    fn disconnect(&mut self) -> io::Result<()> {
+        if let Some(mut child) = self.child.take() {
+            let status = child.wait()?;
+            if !status.success() {
+                return Err(io::Error::other(format!("logcat exited with {}", status)));
+            }
+        }
+        Ok(())
+    }
+
+    // The "MM-DD HH:MM:SS.mmm" stamp, which logcat -T accepts
+    fn resume_token(&self, line: &str) -> Option<String> {
+        let stamp = line.get(..18)?;
+        let b = stamp.as_bytes();
+        (b[2] == b'-' && b[5] == b' ' && b[8] == b':' && b[14] == b'.').then(|| stamp.to_string())
+    }
+
+    // -T includes lines at exactly the given time, and logcat's banners repeat
+    fn already_seen(&self, line: &str, resume: &str) -> bool {
+        match self.resume_token(line) {
+            Some(stamp) => stamp.as_str() <= resume,
+            None => line.starts_with("--------- beginning of"),
+        }
+    }
+}
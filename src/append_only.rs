@@ -0,0 +1,123 @@
+// `--verify-append-only <warn|exit>`: a tamper tripwire for audit logs. Everything rail
+// has read of the file (from the start: the part before the tail is hashed when
+// following begins) is remembered as a hash per BLOCK bytes, and each time the follow
+// loop goes idle the block being appended to and one older block, in turn, are read
+// back and compared. A change, or the file being truncated, is reported on stderr
+// (then rail carries on from the new content, or with `exit` stops with status 1).
+//
+// After a rotation the new file is checked from its start. With --reopen-each-poll a
+// rename rotation looks like a truncation, so it is reported as one.
+
+use std::hash::{DefaultHasher, Hasher};
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::follow::Fs;
+
+const BLOCK: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Warn,
+    Exit,
+}
+
+pub fn parse_mode(s: &str) -> Result<Mode, String> {
+    match s {
+        "warn" => Ok(Mode::Warn),
+        "exit" => Ok(Mode::Exit),
+        _ => Err(format!("expected warn or exit, got '{}'", s)),
+    }
+}
+
+pub struct Verifier {
+    // Hashes of the full blocks read so far
+    blocks: Vec<u64>,
+    // What was read after the last full block
+    partial: Vec<u8>,
+    // The next older block to check
+    next: usize,
+}
+
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+impl Verifier {
+    // Remember the file up to `pos`, where following starts
+    pub fn new<F: Fs>(fs: &F, filename: &str, pos: u64) -> io::Result<Verifier> {
+        let mut verifier = Verifier { blocks: Vec::new(), partial: Vec::new(), next: 0 };
+        let mut file = fs.open(filename)?;
+        let mut buf = vec![0; BLOCK];
+        let mut left = pos;
+        while left > 0 {
+            let n = file.read(&mut buf[..BLOCK.min(left as usize)])?;
+            if n == 0 {
+                break;
+            }
+            verifier.read(verifier.len(), &buf[..n]);
+            left -= n as u64;
+        }
+        Ok(verifier)
+    }
+
+    // How much of the file is covered
+    pub fn len(&self) -> u64 {
+        (self.blocks.len() * BLOCK + self.partial.len()) as u64
+    }
+
+    // `bytes` were read at `pos`. Reading that doesn't continue where the last read
+    // ended has started over on a new file
+    pub fn read(&mut self, pos: u64, mut bytes: &[u8]) {
+        if pos != self.len() {
+            self.blocks.clear();
+            self.partial.clear();
+            self.next = 0;
+            if pos != 0 {
+                return;
+            }
+        }
+        while !bytes.is_empty() {
+            let n = bytes.len().min(BLOCK - self.partial.len());
+            self.partial.extend_from_slice(&bytes[..n]);
+            bytes = &bytes[n..];
+            if self.partial.len() == BLOCK {
+                self.blocks.push(hash(&self.partial));
+                self.partial.clear();
+            }
+        }
+    }
+
+    // Reread the block being appended to and the next older one; the byte range of a
+    // block that changed. Only meaningful while `pos` (the follow position) is where
+    // our coverage ends; after a restart nothing is checked until reading resumes
+    pub fn check<F: Fs>(&mut self, fs: &F, filename: &str, pos: u64) -> io::Result<Option<(u64, u64)>> {
+        if pos != self.len() || pos == 0 {
+            return Ok(None);
+        }
+        let mut file = fs.open(filename)?;
+        let start = (self.blocks.len() * BLOCK) as u64;
+        if !self.partial.is_empty() && read_at(&mut file, start, self.partial.len())? != self.partial {
+            return Ok(Some((start, start + self.partial.len() as u64)));
+        }
+        if self.blocks.is_empty() {
+            return Ok(None);
+        }
+        let i = self.next % self.blocks.len();
+        self.next = i + 1;
+        let start = (i * BLOCK) as u64;
+        if hash(&read_at(&mut file, start, BLOCK)?) != self.blocks[i] {
+            return Ok(Some((start, start + BLOCK as u64)));
+        }
+        Ok(None)
+    }
+}
+
+// Up to `len` bytes at `pos`; fewer if the file ends first
+fn read_at<R: Read + Seek>(file: &mut R, pos: u64, len: usize) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(pos))?;
+    let mut bytes = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,107 @@
+// `--backfill-max <lines>` and `--backfill-max-bytes <size>`: a bound on the history
+// printed before rail goes live. Resuming from --state-file or a bookmark, -n +N, or a
+// large -n on a busy log can mean minutes of old lines before the first new one shows;
+// with a bound, only the last that many lines or bytes of that history are printed
+// (whichever is less), after a note of how much was left out:
+//
+//   --- 3.2 GiB of history skipped (--backfill-max) ---
+//
+// Sizes take K, M or G (powers of 1024), with or without a B: 50MB, 512k. A byte bound
+// that falls mid-line starts at the next whole line. Pipes can't seek and are printed
+// as they come; gzip input is bounded as it is inflated.
+
+use std::fs::File;
+use std::io::{self, BufReader, Seek, SeekFrom};
+use std::sync::OnceLock;
+
+use crate::{encoding, humanize, output};
+
+static MAX_LINES: OnceLock<usize> = OnceLock::new();
+static MAX_BYTES: OnceLock<u64> = OnceLock::new();
+
+pub fn set_max_lines(n: usize) {
+    let _ = MAX_LINES.set(n);
+}
+
+pub fn set_max_bytes(n: u64) {
+    let _ = MAX_BYTES.set(n);
+}
+
+pub fn enabled() -> bool {
+    MAX_LINES.get().is_some() || MAX_BYTES.get().is_some()
+}
+
+pub fn max_lines() -> Option<usize> {
+    MAX_LINES.get().copied()
+}
+
+pub fn max_bytes() -> Option<u64> {
+    MAX_BYTES.get().copied()
+}
+
+// The note for `bytes` of history left out, if any were
+pub fn note_skipped(bytes: u64) {
+    if bytes > 0 {
+        output::status(format_args!("--- {} of history skipped (--backfill-max) ---", humanize::bytes(bytes as f64)));
+    }
+}
+
+// "50MB", "512k", "1G", "4096"
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let invalid = || format!("expected a size like 4096, 512K or 50MB, got '{}'", s);
+    let upper = s.trim().to_ascii_uppercase();
+    let number = upper.strip_suffix("IB").or_else(|| upper.strip_suffix('B')).unwrap_or(&upper);
+    let (digits, scale) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        _ => (number, 1),
+    };
+    let n: u64 = digits.trim().parse().map_err(|_| invalid())?;
+    n.checked_mul(scale).filter(|n| *n > 0).ok_or_else(invalid)
+}
+
+// Where history that would start at `offset` starts within the bounds, with the note
+// printed if that skips some of it and `file` left there
+pub fn limit(file: &mut File, offset: u64) -> io::Result<u64> {
+    if !enabled() {
+        return Ok(offset);
+    }
+    let Ok(len) = file.seek(SeekFrom::End(0)) else {
+        return Ok(offset);
+    };
+    let start = bound(file, offset, len)?;
+    note_skipped(start - offset);
+    file.seek(SeekFrom::Start(start))?;
+    Ok(start)
+}
+
+// Where history from `offset` to `end` starts within the bounds
+pub fn bound(file: &mut File, offset: u64, end: u64) -> io::Result<u64> {
+    let mut start = offset;
+    if let Some(&max) = MAX_LINES.get()
+        && end > 0
+    {
+        start = start.max(crate::start_of_last_before(file, end, max)?);
+    }
+    if let Some(&max) = MAX_BYTES.get()
+        && end.saturating_sub(start) > max
+    {
+        start = next_line(file, end - max)?;
+    }
+    Ok(start)
+}
+
+// The first line start at or after `pos`
+fn next_line(file: &mut File, pos: u64) -> io::Result<u64> {
+    if pos == 0 {
+        return Ok(0);
+    }
+    // From the code unit before, as the line may start right at `pos`
+    let unit = encoding::unit();
+    let pos = pos.next_multiple_of(unit);
+    file.seek(SeekFrom::Start(pos - unit))?;
+    let mut skipped = Vec::new();
+    let n = encoding::read_line(&mut BufReader::new(file), &mut skipped)?;
+    Ok(pos - unit + n as u64)
+}
@@ -0,0 +1,346 @@
+// bzip2 decompression, for compressed input. A stream is "BZh" and a level, then blocks
+// of up to 900k: each is Huffman-coded move-to-front indexes with runs of the first one
+// counted in RUNA/RUNB digits, over a Burrows-Wheeler transform of text whose runs of 4
+// or more equal bytes were shortened to 4 and a count. Blocks don't refer to each other,
+// so nothing needs keeping once a block is out.
+//
+// Bits are read most significant first. Randomised blocks (from bzip2 0.9.0) aren't
+// supported.
+
+use crate::compressed::Input;
+
+const BLOCK_MAGIC: u64 = 0x3141_5926_5359;
+const END_MAGIC: u64 = 0x1772_4538_5090;
+const MAX_GROUPS: usize = 6;
+const GROUP_SIZE: usize = 50;
+const MAX_CODE_LEN: u32 = 20;
+const RUNA: u16 = 0;
+const RUNB: u16 = 1;
+
+struct Bits<'a, I> {
+    input: &'a mut I,
+    out: &'a mut Vec<u8>,
+    acc: u64,
+    count: u32,
+}
+
+impl<I: Input> Bits<'_, I> {
+    fn take(&mut self, n: u32) -> Option<u32> {
+        while self.count < n {
+            self.acc = (self.acc << 8) | self.input.byte(self.out)? as u64;
+            self.count += 8;
+        }
+        self.count -= n;
+        Some(((self.acc >> self.count) & ((1 << n) - 1)) as u32)
+    }
+
+    fn take48(&mut self) -> Option<u64> {
+        Some(((self.take(24)? as u64) << 24) | self.take(24)? as u64)
+    }
+}
+
+// bzip2 streams one after another, as bzcat reads them
+pub fn decode<I: Input>(input: &mut I, out: &mut Vec<u8>) {
+    let mut bits = Bits { input, out, acc: 0, count: 0 };
+    while stream(&mut bits).is_some() {
+        bits.input.boundary();
+    }
+}
+
+fn stream<I: Input>(bits: &mut Bits<I>) -> Option<()> {
+    // A stream starts on a byte boundary
+    bits.count -= bits.count % 8;
+    if bits.take(24)? != 0x425a68 {
+        return None;
+    }
+    let level = bits.take(8)?;
+    if !(b'1' as u32..=b'9' as u32).contains(&level) {
+        return None;
+    }
+    let max_block = (level - b'0' as u32) as usize * 100_000;
+    loop {
+        match bits.take48()? {
+            BLOCK_MAGIC => {
+                bits.take(32)?;
+                let block = block(bits, max_block)?;
+                unrle(&block, bits.out);
+            }
+            END_MAGIC => {
+                bits.take(32)?;
+                return Some(());
+            }
+            _ => return None,
+        }
+    }
+}
+
+// A block's bytes, before the runs of 4 are expanded
+fn block<I: Input>(bits: &mut Bits<I>, max_block: usize) -> Option<Vec<u8>> {
+    if bits.take(1)? != 0 {
+        return None;
+    }
+    let orig_ptr = bits.take(24)? as usize;
+
+    // Which bytes occur: 16 ranges of 16, then the ranges that have any
+    let ranges = bits.take(16)?;
+    let mut used = Vec::new();
+    for range in 0..16 {
+        if ranges & (0x8000 >> range) != 0 {
+            let present = bits.take(16)?;
+            used.extend((0..16).filter(|i| present & (0x8000 >> i) != 0).map(|i| (range * 16 + i) as u8));
+        }
+    }
+    if used.is_empty() {
+        return None;
+    }
+    let alphabet = used.len() + 2;
+    let end_of_block = (alphabet - 1) as u16;
+
+    let groups = bits.take(3)? as usize;
+    if !(2..=MAX_GROUPS).contains(&groups) {
+        return None;
+    }
+    let selectors = bits.take(15)? as usize;
+    if selectors == 0 {
+        return None;
+    }
+    // Which table each run of GROUP_SIZE symbols uses, move-to-front coded in unary
+    let mut order: Vec<u8> = (0..groups as u8).collect();
+    let mut selector = Vec::with_capacity(selectors);
+    for _ in 0..selectors {
+        let mut i = 0;
+        while bits.take(1)? == 1 {
+            i += 1;
+            if i >= groups {
+                return None;
+            }
+        }
+        let table = order.remove(i);
+        order.insert(0, table);
+        selector.push(table);
+    }
+
+    // Code lengths, each a change from the last
+    let mut tables = Vec::with_capacity(groups);
+    for _ in 0..groups {
+        let mut len = bits.take(5)?;
+        let mut lengths = Vec::with_capacity(alphabet);
+        for _ in 0..alphabet {
+            loop {
+                if !(1..=MAX_CODE_LEN).contains(&len) {
+                    return None;
+                }
+                if bits.take(1)? == 0 {
+                    break;
+                }
+                if bits.take(1)? == 0 {
+                    len += 1;
+                } else {
+                    len -= 1;
+                }
+            }
+            lengths.push(len as u8);
+        }
+        tables.push(Huffman::new(&lengths));
+    }
+
+    // The move-to-front indexes, and runs of the first
+    let mut mtf: Vec<u8> = (0..used.len() as u8).collect();
+    let mut block = Vec::with_capacity(max_block);
+    let mut run = 0usize;
+    let mut run_digit = 1usize;
+    let mut decoded = 0;
+    loop {
+        let table = &tables[*selector.get(decoded / GROUP_SIZE)? as usize];
+        let sym = table.decode(bits)?;
+        decoded += 1;
+        if sym == RUNA || sym == RUNB {
+            run += run_digit << (sym == RUNB) as usize;
+            run_digit <<= 1;
+            if run > max_block {
+                return None;
+            }
+            continue;
+        }
+        if run > 0 {
+            if block.len() + run > max_block {
+                return None;
+            }
+            let b = used[mtf[0] as usize];
+            block.extend(std::iter::repeat_n(b, run));
+            (run, run_digit) = (0, 1);
+        }
+        if sym == end_of_block {
+            break;
+        }
+        let index = (sym - 1) as usize;
+        if index >= mtf.len() {
+            return None;
+        }
+        let value = mtf.remove(index);
+        mtf.insert(0, value);
+        if block.len() == max_block {
+            return None;
+        }
+        block.push(used[value as usize]);
+    }
+    if orig_ptr >= block.len() {
+        return None;
+    }
+
+    // Undo the Burrows-Wheeler transform: follow each byte to the one after it
+    let mut starts = [0usize; 256];
+    for &b in &block {
+        starts[b as usize] += 1;
+    }
+    let mut sum = 0;
+    for start in starts.iter_mut() {
+        (*start, sum) = (sum, sum + *start);
+    }
+    let mut next = vec![0u32; block.len()];
+    for (i, &b) in block.iter().enumerate() {
+        next[starts[b as usize]] = i as u32;
+        starts[b as usize] += 1;
+    }
+    let mut text = Vec::with_capacity(block.len());
+    let mut pos = next[orig_ptr] as usize;
+    for _ in 0..block.len() {
+        text.push(block[pos]);
+        pos = next[pos] as usize;
+    }
+    Some(text)
+}
+
+// Expand each run of 4 equal bytes by the count after it
+fn unrle(text: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    let mut same = 0;
+    let mut last = None;
+    while i < text.len() {
+        let b = text[i];
+        i += 1;
+        if same == 4 {
+            out.extend(std::iter::repeat_n(last.unwrap_or(0), b as usize));
+            same = 0;
+            last = None;
+            continue;
+        }
+        if Some(b) == last {
+            same += 1;
+        } else {
+            (last, same) = (Some(b), 1);
+        }
+        out.push(b);
+    }
+}
+
+// A canonical Huffman code, as in DEFLATE but up to MAX_CODE_LEN bits
+struct Huffman {
+    count: [u16; MAX_CODE_LEN as usize + 1],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut count = [0u16; MAX_CODE_LEN as usize + 1];
+        for &len in lengths {
+            count[len as usize] += 1;
+        }
+        let mut offset = [0u16; MAX_CODE_LEN as usize + 2];
+        for len in 1..=MAX_CODE_LEN as usize {
+            offset[len + 1] = offset[len] + count[len];
+        }
+        let mut symbol = vec![0; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            symbol[offset[len as usize] as usize] = sym as u16;
+            offset[len as usize] += 1;
+        }
+        Huffman { count, symbol }
+    }
+
+    fn decode<I: Input>(&self, bits: &mut Bits<I>) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..=MAX_CODE_LEN as usize {
+            code |= bits.take(1)? as i32;
+            let count = self.count[len] as i32;
+            if code - first < count {
+                return self.symbol.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressed::fixtures::{Units, binary, text};
+
+    // `bzip2 -9` of fixtures::text() and fixtures::binary()
+    const TEXT: [u8; 177] = [
+        0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x52, 0x96, 0x69, 0xe1, 0x00, 0x00,
+        0xb2, 0x5d, 0x80, 0x00, 0x10, 0x40, 0x02, 0x7f, 0xf0, 0x01, 0x21, 0x82, 0x08, 0xbe, 0x00, 0x30,
+        0x00, 0xc5, 0x60, 0x92, 0x6a, 0x93, 0xda, 0x4f, 0x50, 0xca, 0x0d, 0x00, 0x12, 0x22, 0x81, 0xa0,
+        0x00, 0x00, 0x54, 0xa9, 0x1a, 0x68, 0xd1, 0x84, 0xd3, 0xd1, 0x3d, 0x34, 0xe4, 0x11, 0xf4, 0x90,
+        0x44, 0x08, 0x27, 0x5a, 0xdf, 0x89, 0xcc, 0x71, 0x6c, 0x5b, 0x35, 0x6a, 0xb5, 0x5a, 0xaf, 0x78,
+        0xd5, 0xb6, 0xdb, 0x6e, 0xed, 0xb6, 0xdb, 0xe4, 0x11, 0xa8, 0x22, 0xc0, 0x8d, 0x36, 0xcc, 0x6f,
+        0x7b, 0x4e, 0xfd, 0x90, 0x8e, 0xc0, 0x88, 0x24, 0x11, 0x0a, 0x01, 0x0c, 0x29, 0x70, 0x21, 0xa4,
+        0x81, 0xb8, 0xa2, 0x10, 0xd3, 0x62, 0x99, 0x9d, 0x41, 0x0e, 0x10, 0x89, 0x88, 0x96, 0x3d, 0x38,
+        0xce, 0x64, 0x11, 0x24, 0xca, 0x10, 0xc8, 0x6c, 0x87, 0xd3, 0x18, 0xc6, 0x36, 0xda, 0x8a, 0xa6,
+        0x43, 0x63, 0x04, 0x50, 0x8e, 0xc4, 0x50, 0x8a, 0x11, 0x52, 0x2c, 0x45, 0x48, 0xf0, 0x45, 0x48,
+        0xb8, 0x8b, 0x88, 0xb1, 0x16, 0x23, 0xa1, 0x77, 0x24, 0x53, 0x85, 0x09, 0x05, 0x29, 0x66, 0x9e,
+        0x10,
+    ];
+    const BINARY: [u8; 134] = [
+        0x42, 0x5a, 0x68, 0x39, 0x31, 0x41, 0x59, 0x26, 0x53, 0x59, 0x54, 0xc1, 0xfe, 0xad, 0x00, 0x00,
+        0x05, 0x7f, 0xff, 0x84, 0x62, 0x11, 0x08, 0x84, 0x62, 0x31, 0x08, 0x84, 0x42, 0x31, 0x08, 0x84,
+        0x42, 0x31, 0x18, 0x84, 0x42, 0x21, 0x18, 0x84, 0x42, 0x21, 0x18, 0x84, 0x42, 0x21, 0x10, 0x8c,
+        0x42, 0x21, 0x10, 0x20, 0x00, 0x41, 0x80, 0x00, 0x13, 0x00, 0x00, 0x4d, 0x18, 0x00, 0x00, 0x00,
+        0x39, 0x80, 0x02, 0x60, 0x00, 0x26, 0x00, 0x00, 0x00, 0x00, 0x57, 0x65, 0xb7, 0x5f, 0x86, 0x39,
+        0x67, 0xa4, 0xda, 0xed, 0xbf, 0x1c, 0xf5, 0xdf, 0x89, 0xa8, 0xaa, 0xcb, 0xb0, 0xcb, 0x4d, 0xb8,
+        0xeb, 0xcf, 0xc1, 0x0f, 0xd8, 0xbf, 0x7f, 0x8e, 0x49, 0x66, 0x9e, 0x8a, 0x6a, 0xae, 0xcb, 0x6e,
+        0xbf, 0x0c, 0x72, 0xcf, 0x4d, 0x76, 0xdf, 0x8e, 0x7a, 0xef, 0xcf, 0x45, 0xdc, 0x91, 0x4e, 0x14,
+        0x24, 0x15, 0x30, 0x7f, 0xab, 0x40,
+    ];
+
+    fn decompress(data: &[u8]) -> (Vec<u8>, usize) {
+        let mut input = Units::new(data);
+        let mut out = Vec::new();
+        decode(&mut input, &mut out);
+        (out, input.units)
+    }
+
+    #[test]
+    fn decodes_what_the_reference_tool_wrote() {
+        assert_eq!(decompress(&TEXT), (text(), 1));
+        assert_eq!(decompress(&BINARY), (binary(), 1));
+    }
+
+    #[test]
+    fn decodes_concatenated_units() {
+        let (out, units) = decompress(&[&TEXT[..], &BINARY[..], &TEXT[..]].concat());
+        assert_eq!(out, [text(), binary(), text()].concat());
+        assert_eq!(units, 3);
+    }
+
+    #[test]
+    fn stops_where_input_is_cut_short() {
+        for len in [TEXT.len() - 1, TEXT.len() / 2, 3] {
+            let (out, units) = decompress(&TEXT[..len]);
+            assert_eq!(units, 0);
+            assert!(text().starts_with(&out));
+        }
+    }
+
+    #[test]
+    fn survives_corrupt_input() {
+        for i in 0..TEXT.len() {
+            let mut data = TEXT;
+            data[i] ^= 0x55;
+            decompress(&data);
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// `--columns`: print parsed records as an aligned table instead of key=value pairs.
+//
+// Columns are the field names in the order they are first seen. A header row goes out
+// first and again whenever a new field shows up. A column widens as soon as a value
+// needs more room, but only narrows once a whole window of records has fit in less,
+// and then only halfway, so one long value doesn't make every later row jump around.
+// With --column-max, longer values are cut to that width and end in '…'.
+
+use crate::fields::Record;
+
+// Records between chances to narrow
+const SHRINK_WINDOW: usize = 100;
+
+struct Column {
+    name: String,
+    width: usize,
+    // Widest value since the last chance to narrow
+    recent: usize,
+}
+
+pub struct Table {
+    columns: Vec<Column>,
+    max_width: Option<usize>,
+    rows: usize,
+}
+
+impl Table {
+    pub fn new(max_width: Option<usize>) -> Table {
+        Table { columns: Vec::new(), max_width, rows: 0 }
+    }
+
+    // The row for `record`, preceded by a header row if the columns changed
+    pub fn render(&mut self, record: &Record) -> String {
+        let mut changed = false;
+        for (name, _) in &record.fields {
+            if !self.columns.iter().any(|c| &c.name == name) {
+                let width = clip(self.max_width, name.chars().count());
+                self.columns.push(Column { name: name.clone(), width, recent: 0 });
+                changed = true;
+            }
+        }
+
+        let cells: Vec<String> = self
+            .columns
+            .iter()
+            .map(|c| record.get(&c.name).map_or("-".to_string(), |v| v.replace(['\n', '\r', '\t'], " ")))
+            .collect();
+        let max_width = self.max_width;
+        for (column, cell) in self.columns.iter_mut().zip(&cells) {
+            let len = clip(max_width, cell.chars().count());
+            column.recent = column.recent.max(len);
+            column.width = column.width.max(len);
+        }
+
+        // Every so often let columns that have been wider than needed narrow a bit
+        self.rows += 1;
+        if self.rows.is_multiple_of(SHRINK_WINDOW) {
+            for column in &mut self.columns {
+                // Never narrower than the header
+                let fit = column.recent.max(clip(max_width, column.name.chars().count()));
+                if fit < column.width {
+                    column.width = fit + (column.width - fit) / 2;
+                }
+                column.recent = 0;
+            }
+        }
+
+        let mut out = String::new();
+        if changed {
+            let names: Vec<String> = self.columns.iter().map(|c| c.name.clone()).collect();
+            out.push_str(&self.row(&names));
+            out.push('\n');
+        }
+        out.push_str(&self.row(&cells));
+        out
+    }
+
+    fn row(&self, cells: &[String]) -> String {
+        let mut out = String::new();
+        let last = self.columns.len().saturating_sub(1);
+        for (i, (column, cell)) in self.columns.iter().zip(cells).enumerate() {
+            if i > 0 {
+                out.push_str("  ");
+            }
+            let len = cell.chars().count();
+            if len > clip(self.max_width, len) {
+                let max = self.max_width.unwrap_or(len);
+                out.extend(cell.chars().take(max.saturating_sub(1)));
+                out.push('…');
+            } else {
+                out.push_str(cell);
+            }
+            // Nothing to line up after the last column, so don't pad it
+            if i < last {
+                let pad = column.width.saturating_sub(clip(self.max_width, len));
+                out.extend(std::iter::repeat_n(' ', pad));
+            }
+        }
+        out
+    }
+}
+
+fn clip(max_width: Option<usize>, len: usize) -> usize {
+    max_width.map_or(len, |max| len.min(max))
+}
@@ -0,0 +1,76 @@
+// `--compact`: denser lines for narrow panes. The text as printed is shortened (what
+// --grep, --fail-on and the rest match against is left whole):
+//
+//   time    2024-05-01T10:00:00.123+02:00 and May  1 10:00:00 become 10:00:00
+//   level   ERROR becomes E, WARNING W, ... and level=error level=e
+//   names   dotted class names keep their initials: com.example.api.OrderService
+//           becomes c.e.a.OrderService
+//
+// rail has no config file or profiles, so the rules are picked on the command line:
+// `--compact=time,level`; bare --compact uses all of them. Each rule is a
+// --sub rule, and runs after any given with --sub.
+
+use std::borrow::Cow;
+use std::sync::Mutex;
+
+use crate::sub::{self, Rule};
+
+const RULES: &[(&str, &[&str])] = &[
+    ("time", &[
+        r"s/\b\d{4}-\d{2}-\d{2}[T ](\d{2}:\d{2}:\d{2})([.,]\d+)?(Z|[+-]\d{2}:?\d{2})?/\1/g",
+        r"s/\b(Jan|Feb|Mar|Apr|May|Jun|Jul|Aug|Sep|Oct|Nov|Dec) +\d{1,2} (\d{2}:\d{2}:\d{2})\b/\2/g",
+    ]),
+    ("level", &[
+        r"s/\b(F)ATAL\b|\b(C)RITICAL\b|\b(E)RROR\b|\b(W)ARN(ING)?\b|\b(N)OTICE\b|\b(I)NFO\b|\b(D)EBUG\b|\b(T)RACE\b/\1\2\3\4\6\7\8\9/g",
+        r"s/(?i)\b(level|severity)=(\w)\w*/\1=\2/g",
+    ]),
+    // One package name per pass; apply() repeats it until the whole name is done
+    ("names", &[r"s/\b([a-z])[a-z0-9_]+\.((?:[a-z][a-z0-9_]*\.)*[A-Z])/\1.\2/g"]),
+];
+
+// A class name has only so many packages; stop repeating after this many passes
+const MAX_PASSES: usize = 16;
+
+static ACTIVE: Mutex<Vec<Rule>> = Mutex::new(Vec::new());
+
+// "" for all rules, else a comma separated list of their names
+pub fn enable(names: &str) -> Result<(), String> {
+    let names: Vec<&str> = if names.is_empty() { RULES.iter().map(|(n, _)| *n).collect() } else { names.split(',').collect() };
+    let mut active = ACTIVE.lock().unwrap();
+    for name in names {
+        let (_, specs) = RULES.iter().find(|(n, _)| *n == name).ok_or_else(|| {
+            let known: Vec<&str> = RULES.iter().map(|(n, _)| *n).collect();
+            format!("unknown rule '{}' (expected {})", name, known.join(", "))
+        })?;
+        for spec in specs.iter() {
+            active.push(sub::parse_rule(spec).expect("built-in compact rule must parse"));
+        }
+    }
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    !ACTIVE.lock().unwrap().is_empty()
+}
+
+pub fn transform(line: String) -> String {
+    let rules = ACTIVE.lock().unwrap();
+    if rules.is_empty() {
+        return line;
+    }
+    let (body, newline) = match line.strip_suffix('\n') {
+        Some(body) => (body, "\n"),
+        None => (line.as_str(), ""),
+    };
+    let mut text = body.to_string();
+    for rule in rules.iter() {
+        for _ in 0..MAX_PASSES {
+            match rule.apply(&text) {
+                Cow::Owned(changed) if changed != text => text = changed,
+                _ => break,
+            }
+        }
+    }
+    text.push_str(newline);
+    text
+}
@@ -0,0 +1,134 @@
+// Stand-ins for older tools, so scripts and habits can move to rail unchanged. Each is
+// a subcommand (`rail tailf app.log`) and also what rail does when run under that name
+// (`ln -s rail tailf`); the tool's arguments are turned into rail's:
+//
+//   tailf [-n N | -N] <file>                  rail <file> -f -n N
+//   logtail -f <file> [-o <offset file>]      rail <file> -n +1 --state-file <offset file>
+//   multitail [-i] <file>... [-f] [-n N]      rail <file>... -f -n N
+//
+// logtail prints what was added since its last run (everything on the first run), and
+// the offset file defaults to <file>.offset as in logtail. rail keeps its own record in
+// it, so an offset file written by logtail itself is started over. multitail's windows
+// aren't emulated: all files go to one stream under "==> name <==" headers, and its
+// layout options (-s, -sw, -sn) are accepted and ignored.
+
+pub const NAMES: [&str; 3] = ["tailf", "logtail", "multitail"];
+
+// rail's arguments (without the program name) for `args` given to tool `name`
+pub fn translate(name: &str, args: &[String]) -> Result<Vec<String>, String> {
+    match name {
+        "tailf" => tailf(args),
+        "logtail" => logtail(args),
+        _ => multitail(args),
+    }
+}
+
+fn count(args: &[String], i: usize, option: &str) -> Result<String, String> {
+    match args.get(i + 1) {
+        Some(n) if n.parse::<usize>().is_ok() => Ok(n.clone()),
+        Some(n) => Err(format!("invalid number for {}: {}", option, n)),
+        None => Err(format!("{} requires a number", option)),
+    }
+}
+
+fn tailf(args: &[String]) -> Result<Vec<String>, String> {
+    let mut lines = "10".to_string();
+    let mut file = None;
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        match arg {
+            "-n" | "--lines" => {
+                lines = count(args, i, arg)?;
+                i += 2;
+            }
+            _ if arg.len() > 1 && arg.starts_with('-') && arg[1..].bytes().all(|b| b.is_ascii_digit()) => {
+                lines = arg[1..].to_string();
+                i += 1;
+            }
+            _ if arg.starts_with('-') => return Err(format!("unknown tailf option: {}", arg)),
+            _ if file.is_some() => return Err("tailf follows one file".to_string()),
+            _ => {
+                file = Some(arg.to_string());
+                i += 1;
+            }
+        }
+    }
+    let file = file.ok_or("tailf requires a file")?;
+    Ok(vec![file, "-f".to_string(), "-n".to_string(), lines])
+}
+
+fn logtail(args: &[String]) -> Result<Vec<String>, String> {
+    let mut file = None;
+    let mut offset = None;
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        match arg {
+            "-f" | "-o" => {
+                let value = args.get(i + 1).ok_or(format!("logtail {} requires a file", arg))?;
+                if arg == "-f" {
+                    file = Some(value.clone());
+                } else {
+                    offset = Some(value.clone());
+                }
+                i += 2;
+            }
+            "-t" => return Err("logtail -t (don't update the offset file) is not supported".to_string()),
+            // Old logtail took the files as plain arguments
+            _ if !arg.starts_with('-') && file.is_none() => {
+                file = Some(arg.to_string());
+                i += 1;
+            }
+            _ if !arg.starts_with('-') && offset.is_none() => {
+                offset = Some(arg.to_string());
+                i += 1;
+            }
+            _ => return Err(format!("unknown logtail option: {}", arg)),
+        }
+    }
+    let file = file.ok_or("logtail requires -f <file>")?;
+    let offset = offset.unwrap_or_else(|| format!("{}.offset", file));
+    Ok(vec![file, "-n".to_string(), "+1".to_string(), "--state-file".to_string(), offset])
+}
+
+fn multitail(args: &[String]) -> Result<Vec<String>, String> {
+    let mut files = Vec::new();
+    let mut lines = None;
+    let mut follow_name = false;
+    let mut i = 0;
+    while i < args.len() {
+        let arg = args[i].as_str();
+        match arg {
+            "-i" => {
+                files.push(args.get(i + 1).ok_or("multitail -i requires a file")?.clone());
+                i += 2;
+            }
+            "-n" => {
+                lines = Some(count(args, i, arg)?);
+                i += 2;
+            }
+            "-f" => {
+                follow_name = true;
+                i += 1;
+            }
+            "-s" | "-sw" | "-sn" => {
+                args.get(i + 1).ok_or(format!("multitail {} requires an argument", arg))?;
+                i += 2;
+            }
+            _ if arg.starts_with('-') => return Err(format!("unsupported multitail option: {}", arg)),
+            _ => {
+                files.push(arg.to_string());
+                i += 1;
+            }
+        }
+    }
+    if files.is_empty() {
+        return Err("multitail requires a file".to_string());
+    }
+    files.push(if follow_name { "--follow=name" } else { "-f" }.to_string());
+    if let Some(lines) = lines {
+        files.extend(["-n".to_string(), lines]);
+    }
+    Ok(files)
+}
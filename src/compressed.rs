@@ -0,0 +1,459 @@
+// Compressed input: a file that starts like gzip, zstd, bzip2 or xz data (rotated logs,
+// mostly) is read decompressed, as if through zcat, without holding more of it in memory
+// than its back-references need. `--compression` names the format instead, for standard
+// input too, or with `none` shows the file as it is. -n N keeps the last N lines while
+// the whole file is decompressed; -n +N skips to line N. With -f, what is appended later
+// (gzip members from `gzip -c >> app.log.gz`, zstd frames, bzip2 and xz streams, as a
+// shipper that compresses each batch writes them) is decoded and shown as it arrives,
+// as is a member the writer has flushed part of.
+//
+// The position in a compressed file isn't a position in the log, so -c, --index and
+// --state-file can't be used with one. A file that ends inside a member is shown up to
+// there, with a warning. Checksums aren't verified.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::thread;
+
+use crate::open::{STDIN, open_log};
+use crate::{Start, backfill, bzip2, follow, inflate, line_numbers, output, pid, report, xz, zstd};
+
+// How much decompressed data is split into lines at a time
+const CHUNK: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+// --compression: None for auto, Some(None) for none
+static FORCED: OnceLock<Option<Compression>> = OnceLock::new();
+
+impl Compression {
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+            Compression::Bzip2 => "bzip2",
+            Compression::Xz => "xz",
+        }
+    }
+
+    fn magic(self) -> &'static [u8] {
+        match self {
+            Compression::Gzip => &[0x1f, 0x8b],
+            Compression::Zstd => &[0x28, 0xb5, 0x2f, 0xfd],
+            Compression::Bzip2 => b"BZh",
+            Compression::Xz => &[0xfd, b'7', b'z', b'X', b'Z', 0],
+        }
+    }
+
+    // What a unit of this format that can be appended is called
+    fn unit(self) -> &'static str {
+        match self {
+            Compression::Gzip => "member",
+            Compression::Zstd => "frame",
+            Compression::Bzip2 | Compression::Xz => "stream",
+        }
+    }
+}
+
+const ALL: [Compression; 4] = [Compression::Gzip, Compression::Zstd, Compression::Bzip2, Compression::Xz];
+
+// --compression auto|none|gzip|zstd|bzip2|xz
+pub fn set_forced(name: &str) -> Result<(), String> {
+    let forced = match name {
+        "auto" => return Ok(()),
+        "none" => None,
+        _ => Some(ALL.into_iter().find(|c| c.name() == name).ok_or_else(|| format!("expected auto, none, gzip, zstd, bzip2 or xz, got '{}'", name))?),
+    };
+    let _ = FORCED.set(forced);
+    Ok(())
+}
+
+// How `filename` is compressed: as --compression says, or as its first bytes do for a
+// regular file (reading a pipe's would take them from it)
+pub fn detect(filename: &str) -> Option<Compression> {
+    if let Some(&forced) = FORCED.get() {
+        return forced;
+    }
+    if filename == STDIN || !Path::new(filename).is_file() {
+        return None;
+    }
+    let mut start = Vec::new();
+    open_log(filename).and_then(|file| file.take(6).read_to_end(&mut start)).ok()?;
+    // pzstd starts with a skippable frame, 0x184d2a50 to 0x184d2a5f
+    if start.len() >= 4 && start[0] & 0xf0 == 0x50 && start[1..4] == [0x2a, 0x4d, 0x18] {
+        return Some(Compression::Zstd);
+    }
+    ALL.into_iter().find(|c| start.starts_with(c.magic()))
+}
+
+pub trait Input {
+    // The next compressed byte, or None if there are no more; `out` is what has been
+    // decompressed so far, for a source that hands it on while it waits
+    fn byte(&mut self, out: &mut Vec<u8>) -> Option<u8>;
+
+    // Back-references from here on reach at most `size` bytes back into `out`
+    fn window(&mut self, _size: usize) {}
+
+    // A whole member, frame or stream has been read
+    fn boundary(&mut self) {}
+}
+
+impl Input for std::slice::Iter<'_, u8> {
+    fn byte(&mut self, _out: &mut Vec<u8>) -> Option<u8> {
+        self.next().copied()
+    }
+}
+
+impl<T: Input + ?Sized> Input for &mut T {
+    fn byte(&mut self, out: &mut Vec<u8>) -> Option<u8> {
+        (**self).byte(out)
+    }
+
+    fn window(&mut self, size: usize) {
+        (**self).window(size);
+    }
+
+    fn boundary(&mut self) {
+        (**self).boundary();
+    }
+}
+
+// Print the start of `filename` decompressed (nothing of it with no `start`, for a
+// caller that has shown it already), then with `follow` what is appended; `announce`
+// says "Following" once the history is shown
+pub fn tail(filename: &str, compression: Compression, start: Option<Start>, follow: bool, announce: bool) -> io::Result<()> {
+    let mut source = Source::new(filename, start, follow, announce)?;
+    let mut out = Vec::new();
+    source.decode(compression, &mut out);
+    source.end();
+    source.result(compression)
+}
+
+// The last `n` lines of `filename` decompressed, without showing them, and how many
+// lines come before them
+pub fn last_lines(filename: &str, compression: Compression, n: usize) -> io::Result<(VecDeque<Vec<u8>>, u64)> {
+    let mut source = Source::new(filename, Some(Start::Last(n)), false, false)?;
+    let mut out = Vec::new();
+    source.decode(compression, &mut out);
+    let partial = std::mem::take(&mut source.lines.partial);
+    if !partial.is_empty() {
+        source.lines.line(partial)?;
+    }
+    source.result(compression)?;
+    Ok((source.lines.backlog.take().unwrap_or_default(), source.lines.before))
+}
+
+// The compressed file as the decoder reads it; splits what has been decompressed into
+// lines now and then, and when caught up, waits for more with -f
+struct Source {
+    file: BufReader<File>,
+    follow: bool,
+    announce: bool,
+    filename: String,
+    lines: Lines,
+    // How much of the decompressed data has been split into lines
+    taken: usize,
+    // How much of it back-references need
+    window: usize,
+    // Compressed bytes read, and where the last whole member, frame or stream ended
+    read: u64,
+    units: u64,
+    unit_end: u64,
+    // Reading stopped at the end of the file or when asked to stop
+    ended: bool,
+    error: Option<io::Error>,
+}
+
+impl Input for Source {
+    fn byte(&mut self, out: &mut Vec<u8>) -> Option<u8> {
+        if out.len() - self.taken >= CHUNK {
+            self.take(out);
+        }
+        loop {
+            if self.error.is_some() {
+                self.ended = true;
+                return None;
+            }
+            match self.file.fill_buf() {
+                Ok([]) => {}
+                Ok(buf) => {
+                    let b = buf[0];
+                    self.file.consume(1);
+                    self.read += 1;
+                    return Some(b);
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    self.error = Some(e);
+                    self.ended = true;
+                    return None;
+                }
+            }
+            self.take(out);
+            if !self.follow || report::interrupted() || pid::exited() {
+                self.ended = true;
+                return None;
+            }
+            let shown = self.lines.caught_up();
+            output::flush();
+            match shown {
+                Ok(true) if self.announce => {
+                    output::following(&self.filename);
+                }
+                Ok(_) => {}
+                Err(e) => self.error = Some(e),
+            }
+            thread::sleep(follow::poll_interval());
+        }
+    }
+
+    fn window(&mut self, size: usize) {
+        self.window = self.window.max(size);
+    }
+
+    fn boundary(&mut self) {
+        self.units += 1;
+        self.unit_end = self.read;
+    }
+}
+
+impl Source {
+    fn new(filename: &str, start: Option<Start>, follow: bool, announce: bool) -> io::Result<Source> {
+        Ok(Source {
+            file: BufReader::new(open_log(filename)?),
+            follow,
+            announce,
+            filename: filename.to_string(),
+            lines: Lines::new(start),
+            taken: 0,
+            window: 0,
+            read: 0,
+            units: 0,
+            unit_end: 0,
+            ended: false,
+            error: None,
+        })
+    }
+
+    fn decode(&mut self, compression: Compression, out: &mut Vec<u8>) {
+        match compression {
+            Compression::Gzip => inflate::gunzip(self, out),
+            Compression::Zstd => zstd::decode(self, out),
+            Compression::Bzip2 => bzip2::decode(self, out),
+            Compression::Xz => xz::decode(self, out),
+        }
+        self.take(out);
+    }
+
+    // The error reading stopped at, if any; data that couldn't be decompressed is
+    // warned about
+    fn result(&mut self, compression: Compression) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        let stopped = report::interrupted() || pid::exited();
+        if self.read != self.unit_end && !stopped {
+            let (name, unit) = (compression.name(), compression.unit());
+            if !self.ended {
+                eprintln!(
+                    "Warning: '{}' is corrupt or has data that isn't {} after byte {} ({} {}(s)); the rest is ignored",
+                    self.filename, name, self.unit_end, self.units, unit
+                );
+            } else {
+                eprintln!("Warning: '{}' ends in the middle of its last {} {}", self.filename, name, unit);
+            }
+        }
+        Ok(())
+    }
+
+    // Split what has been decompressed since last time into lines, keeping the window
+    // back-references need
+    fn take(&mut self, out: &mut Vec<u8>) {
+        if let Err(e) = self.lines.add(&out[self.taken..]) {
+            self.error.get_or_insert(e);
+        }
+        self.taken = out.len();
+        if out.len() >= 2 * self.window.max(CHUNK) {
+            let cut = out.len() - self.window;
+            out.drain(..cut);
+            self.taken -= cut;
+        }
+    }
+
+    fn end(&mut self) {
+        if let Err(e) = self.lines.finish() {
+            self.error.get_or_insert(e);
+        }
+        output::flush();
+    }
+}
+
+// What becomes of each decompressed line: skipped, kept among the last N until the history
+// is shown, or shown
+struct Lines {
+    partial: Vec<u8>,
+    // -n +N: lines still to skip
+    skip: u64,
+    // The history so far, while it has bounds; None once it's shown, or if it has none
+    backlog: Option<VecDeque<Vec<u8>>>,
+    backlog_bytes: u64,
+    // How many lines of history the backlog keeps, and whether dropping one for that
+    // (rather than for -n) is a skip to note
+    keep: usize,
+    keep_bytes: u64,
+    capped: bool,
+    // Lines of history not shown, and how much of it was left out for --backfill-max
+    before: u64,
+    skipped: u64,
+    // Whether the history has been shown
+    live: bool,
+}
+
+impl Lines {
+    fn new(start: Option<Start>) -> Lines {
+        let max_lines = backfill::max_lines().unwrap_or(usize::MAX);
+        let max_bytes = backfill::max_bytes().unwrap_or(u64::MAX);
+        let mut lines = Lines {
+            partial: Vec::new(),
+            skip: 0,
+            backlog: Some(VecDeque::new()),
+            backlog_bytes: 0,
+            keep: 0,
+            keep_bytes: u64::MAX,
+            capped: false,
+            before: 0,
+            skipped: 0,
+            live: false,
+        };
+        match start {
+            Some(Start::Last(n)) => lines.keep = n,
+            Some(Start::FromLine(n)) if backfill::enabled() => {
+                lines.skip = n.saturating_sub(1) as u64;
+                (lines.keep, lines.keep_bytes, lines.capped) = (max_lines, max_bytes, true);
+            }
+            Some(Start::FromLine(n)) => {
+                lines.skip = n.saturating_sub(1) as u64;
+                lines.backlog = None;
+                line_numbers::start_at(lines.skip + 1);
+            }
+            // main doesn't let -c through
+            Some(Start::LastBytes(_)) | None => {}
+        }
+        lines
+    }
+
+    fn add(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while let Some(end) = bytes.iter().position(|&b| b == output::delimiter()) {
+            let mut line = std::mem::take(&mut self.partial);
+            line.extend_from_slice(&bytes[..=end]);
+            self.line(line)?;
+            bytes = &bytes[end + 1..];
+        }
+        self.partial.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn line(&mut self, line: Vec<u8>) -> io::Result<()> {
+        if self.skip > 0 {
+            self.skip -= 1;
+            self.before += 1;
+            return Ok(());
+        }
+        let Some(backlog) = &mut self.backlog else {
+            return output::emit_bytes(line, true);
+        };
+        self.backlog_bytes += line.len() as u64;
+        backlog.push_back(line);
+        while backlog.len() > self.keep || (self.backlog_bytes > self.keep_bytes && !backlog.is_empty()) {
+            let dropped = backlog.pop_front().unwrap_or_default();
+            self.backlog_bytes -= dropped.len() as u64;
+            self.before += 1;
+            if self.capped {
+                self.skipped += dropped.len() as u64;
+            }
+        }
+        Ok(())
+    }
+
+    // Show the history if it hasn't been yet; true the first time
+    fn caught_up(&mut self) -> io::Result<bool> {
+        if self.live {
+            return Ok(false);
+        }
+        self.live = true;
+        let Some(mut backlog) = self.backlog.take() else {
+            return Ok(true);
+        };
+        let max_lines = backfill::max_lines().unwrap_or(usize::MAX);
+        let max_bytes = backfill::max_bytes().unwrap_or(u64::MAX);
+        while backlog.len() > max_lines || (self.backlog_bytes > max_bytes && !backlog.is_empty()) {
+            let dropped = backlog.pop_front().unwrap_or_default();
+            self.backlog_bytes -= dropped.len() as u64;
+            self.before += 1;
+            self.skipped += dropped.len() as u64;
+        }
+        backfill::note_skipped(self.skipped);
+        line_numbers::start_at(self.before + 1);
+        for line in backlog {
+            output::emit_bytes(line, true)?;
+        }
+        Ok(true)
+    }
+
+    // The input is done: a last line without a newline is a line too
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.partial.is_empty() {
+            let line = std::mem::take(&mut self.partial);
+            self.line(line)?;
+        }
+        self.caught_up()?;
+        Ok(())
+    }
+}
+
+// What the decoders' tests decompress
+#[cfg(test)]
+pub mod fixtures {
+    use super::Input;
+
+    // Compressed bytes in memory, counting the members, frames or streams read whole
+    pub struct Units<'a> {
+        bytes: std::slice::Iter<'a, u8>,
+        pub units: usize,
+    }
+
+    impl Units<'_> {
+        pub fn new(data: &[u8]) -> Units<'_> {
+            Units { bytes: data.iter(), units: 0 }
+        }
+    }
+
+    impl Input for Units<'_> {
+        fn byte(&mut self, _out: &mut Vec<u8>) -> Option<u8> {
+            self.bytes.next().copied()
+        }
+
+        fn boundary(&mut self) {
+            self.units += 1;
+        }
+    }
+
+    // 20 log lines, compressible
+    pub fn text() -> Vec<u8> {
+        (0..20).map(|i| format!("2024-05-01 10:00:{:02} INFO request {} ok\n", i, i * 37 % 101)).collect::<String>().into_bytes()
+    }
+
+    // 64 bytes with no repeats, so they're stored rather than compressed
+    pub fn binary() -> Vec<u8> {
+        (0..64).map(|i| ((i * 73 + 41) % 251) as u8).collect()
+    }
+}
@@ -0,0 +1,327 @@
+// `--control <socket>`: take commands from `rail ctl <socket> <command>` while following,
+// so a long-running rail can be looked at and adjusted without a restart. The socket is
+// a Unix domain socket path, or 127.0.0.1:<port> (e.g. on Windows) for plain TCP on the
+// loopback interface; any local user can connect to that one, while the Unix socket is
+// only accessible to rail's user.
+//
+//   status                  how long rail has run, the poll interval and filters, and for
+//                           each followed file its offset, lines read and rate
+//   offsets                 each followed file and the offset reached
+//   pause <file|all>        stop reading a file (lines written meanwhile wait in it)
+//   resume <file|all>
+//   sleep-interval <secs>   change -s
+//   grep <regex>            add a --grep or --exclude filter
+//   exclude <regex>
+//   bookmark <name>         bookmark the offset reached in the state file (for
+//                           --goto-bookmark)
+//
+// One command per connection: rail answers "ok" or "error: <why>" on the first line,
+// with what was asked for after it, and closes the connection.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::follow;
+use crate::grep;
+use crate::output;
+use crate::regex::Regex;
+use crate::report;
+use crate::state;
+
+// Rates are lines over the last RATE_WINDOW or so
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+const PAUSE_CHECK: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug)]
+pub enum Address {
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+    Tcp(SocketAddr),
+}
+
+struct Source {
+    name: String,
+    offset: u64,
+    lines: u64,
+    window_start: Instant,
+    window_lines: u64,
+    rate: f64,
+    paused: bool,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static ANY_PAUSED: AtomicBool = AtomicBool::new(false);
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static SOCKET: OnceLock<Address> = OnceLock::new();
+static SOURCES: Mutex<Vec<Source>> = Mutex::new(Vec::new());
+// Filters added over the socket, for status
+static FILTERS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn parse_address(s: &str) -> Result<Address, String> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        if !addr.ip().is_loopback() {
+            return Err(format!("'{}' is not a loopback address; the control socket takes commands from anyone who can reach it", s));
+        }
+        return Ok(Address::Tcp(addr));
+    }
+    #[cfg(unix)]
+    {
+        Ok(Address::Unix(std::path::PathBuf::from(s)))
+    }
+    #[cfg(not(unix))]
+    {
+        Err(format!("expected 127.0.0.1:<port>, got '{}' (Unix domain sockets aren't available here)", s))
+    }
+}
+
+// Start taking commands on `address`
+pub fn listen(address: Address) -> io::Result<()> {
+    STARTED.get_or_init(Instant::now);
+    match &address {
+        #[cfg(unix)]
+        Address::Unix(path) => {
+            let listener = bind_unix(path)?;
+            thread::spawn(move || {
+                for mut stream in listener.incoming().flatten() {
+                    serve(&mut stream);
+                }
+            });
+        }
+        Address::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            thread::spawn(move || {
+                for mut stream in listener.incoming().flatten() {
+                    serve(&mut stream);
+                }
+            });
+        }
+    }
+    let _ = SOCKET.set(address);
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+// A Unix socket at `path` only rail's user can connect to
+#[cfg(unix)]
+pub fn bind_unix(path: &std::path::Path) -> io::Result<std::os::unix::net::UnixListener> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    // A socket left behind by a rail that is gone can be replaced; a live one can't
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "another rail is listening there"));
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+// Remove the socket; call before exiting
+pub fn finish() {
+    #[cfg(unix)]
+    if let Some(Address::Unix(path)) = SOCKET.get() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+// `filename` is being followed from `offset`
+pub fn follow(filename: &str, offset: u64) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut sources = SOURCES.lock().unwrap();
+    sources.retain(|s| s.name != filename);
+    sources.push(Source {
+        name: filename.to_string(),
+        offset,
+        lines: 0,
+        window_start: Instant::now(),
+        window_lines: 0,
+        rate: 0.0,
+        paused: false,
+    });
+}
+
+// A line was read from `filename`, which is now read up to `offset`
+pub fn read(filename: &str, offset: u64) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut sources = SOURCES.lock().unwrap();
+    if let Some(source) = sources.iter_mut().find(|s| s.name == filename) {
+        source.offset = offset;
+        source.lines += 1;
+        source.window_lines += 1;
+        let elapsed = source.window_start.elapsed();
+        if elapsed >= RATE_WINDOW {
+            source.rate = source.window_lines as f64 / elapsed.as_secs_f64();
+            source.window_start = Instant::now();
+            source.window_lines = 0;
+        }
+    }
+}
+
+// Hold the caller while `filename` is paused
+pub fn wait_if_paused(filename: &str) {
+    if !ANY_PAUSED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut flushed = false;
+    while is_paused(filename) && !report::interrupted() {
+        if !flushed {
+            output::flush();
+            flushed = true;
+        }
+        thread::sleep(PAUSE_CHECK);
+    }
+}
+
+fn is_paused(filename: &str) -> bool {
+    SOURCES.lock().unwrap().iter().any(|s| s.name == filename && s.paused)
+}
+
+fn serve<S: Read + Write>(stream: &mut S) {
+    let mut request = String::new();
+    if BufReader::new(&mut *stream).read_line(&mut request).is_err() {
+        return;
+    }
+    let reply = match execute(request.trim()) {
+        Ok(text) => format!("ok\n{}", text),
+        Err(e) => format!("error: {}\n", e),
+    };
+    let _ = stream.write_all(reply.as_bytes());
+}
+
+fn execute(request: &str) -> Result<String, String> {
+    let (command, arg) = request.split_once(' ').map_or((request, ""), |(c, a)| (c, a.trim()));
+    let needs_arg = |what: &str| if arg.is_empty() { Err(format!("{} requires {}", command, what)) } else { Ok(()) };
+    match command {
+        "status" => Ok(status()),
+        "offsets" => {
+            let sources = SOURCES.lock().unwrap();
+            Ok(sources.iter().map(|s| format!("{}\t{}\n", s.name, s.offset)).collect())
+        }
+        "pause" | "resume" => {
+            needs_arg("a file name or all")?;
+            let mut sources = SOURCES.lock().unwrap();
+            let mut found = false;
+            for source in sources.iter_mut().filter(|s| arg == "all" || s.name == arg) {
+                source.paused = command == "pause";
+                found = true;
+            }
+            if !found {
+                return Err(format!("'{}' is not being followed", arg));
+            }
+            ANY_PAUSED.store(sources.iter().any(|s| s.paused), Ordering::Relaxed);
+            Ok(String::new())
+        }
+        "sleep-interval" => {
+            needs_arg("a number of seconds")?;
+            follow::set_poll_interval(follow::parse_interval(arg)?);
+            Ok(String::new())
+        }
+        "grep" | "exclude" => {
+            needs_arg("a regex")?;
+            let regex = Regex::new(arg).map_err(|e| e.to_string())?;
+            if command == "grep" {
+                grep::add(regex);
+            } else {
+                grep::exclude(regex);
+            }
+            FILTERS.lock().unwrap().push(format!("{} {}", command, arg));
+            Ok(String::new())
+        }
+        "bookmark" => {
+            needs_arg("a name")?;
+            let sources = SOURCES.lock().unwrap();
+            let Some(source) = sources.first() else {
+                return Err("no file is being followed yet".to_string());
+            };
+            state::bookmark_later(arg, &source.name, source.offset)?;
+            Ok(format!("{}\t{}\n", source.name, source.offset))
+        }
+        "" => Err("no command".to_string()),
+        _ => Err(format!("unknown command '{}'", command)),
+    }
+}
+
+fn status() -> String {
+    let uptime = STARTED.get().map_or(Duration::ZERO, Instant::elapsed).as_secs();
+    let mut out = format!(
+        "pid\t{}\nuptime\t{}s\nsleep-interval\t{}s\n",
+        process::id(),
+        uptime,
+        follow::poll_interval().as_secs_f64()
+    );
+    for filter in FILTERS.lock().unwrap().iter() {
+        out.push_str(&format!("filter\t{}\n", filter));
+    }
+    for source in SOURCES.lock().unwrap().iter() {
+        let elapsed = source.window_start.elapsed();
+        // Once a window is over with no line to close it, its count is the better guess
+        let rate = if elapsed >= RATE_WINDOW { source.window_lines as f64 / elapsed.as_secs_f64() } else { source.rate };
+        out.push_str(&format!(
+            "file\t{}\toffset={}\tlines={}\trate={:.1}/s{}\n",
+            source.name,
+            source.offset,
+            source.lines,
+            rate,
+            if source.paused { "\tpaused" } else { "" }
+        ));
+    }
+    out
+}
+
+// `rail ctl <socket> <command> [argument]`
+pub fn command(args: &[String]) -> io::Result<()> {
+    if args.len() < 2 {
+        eprintln!("Error: usage: rail ctl <socket> <status|offsets|pause|resume|sleep-interval|grep|exclude|bookmark> [argument]");
+        process::exit(1);
+    }
+    let address = match parse_address(&args[0]) {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let request = format!("{}\n", args[1..].join(" "));
+    let reply = match address {
+        #[cfg(unix)]
+        Address::Unix(path) => exchange(std::os::unix::net::UnixStream::connect(&path), &request),
+        Address::Tcp(addr) => exchange(TcpStream::connect(addr), &request),
+    };
+    let reply = match reply {
+        Ok(reply) => reply,
+        Err(e) => {
+            eprintln!("Error: Could not reach rail at '{}': {}", args[0], e);
+            process::exit(1);
+        }
+    };
+    match reply.split_once('\n') {
+        Some(("ok", rest)) => {
+            print!("{}", rest);
+            Ok(())
+        }
+        _ => {
+            eprintln!("Error: {}", reply.trim_end().strip_prefix("error: ").unwrap_or(reply.trim_end()));
+            process::exit(1);
+        }
+    }
+}
+
+fn exchange<S: Read + Write>(stream: io::Result<S>, request: &str) -> io::Result<String> {
+    let mut stream = stream?;
+    stream.write_all(request.as_bytes())?;
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
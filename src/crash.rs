@@ -0,0 +1,217 @@
+// `--crash-dir <dir>`: if rail panics or is killed by a core-dumping signal (SIGSEGV,
+// SIGABRT, SIGQUIT, ...), write a report to <dir>/rail-crash-<start time>-<pid>.txt
+// with the last --crash-lines lines rail printed (default 100) and what it was doing:
+// the input, the position it had read to, how long ago the last line went out and how
+// many stalled reads the watchdog recovered from.
+//
+// The ring is a fixed block of atomics allocated up front, so the signal handler can
+// read it without taking locks or allocating. A line being recorded just as the signal
+// lands may come out garbled. Lines longer than SLOT_SIZE bytes are cut.
+//
+// After writing the report the signal is set back to its default action and raised
+// again, so the process still dies the way it would have and core dumps still happen.
+// A stack overflow leaves no stack to run the handler on, so it gets no report.
+
+use std::env;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::panic;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::watchdog;
+
+pub const DEFAULT_LINES: usize = 100;
+const SLOT_SIZE: usize = 512;
+
+struct Ring {
+    bytes: Box<[AtomicU8]>,
+    lens: Box<[AtomicUsize]>,
+    // Lines recorded so far; the next one goes in slot written % lens.len()
+    written: AtomicUsize,
+}
+
+struct Crash {
+    ring: Ring,
+    path: String,
+    input: String,
+    command: String,
+    started: Instant,
+}
+
+static CRASH: OnceLock<Crash> = OnceLock::new();
+static REPORTED: AtomicBool = AtomicBool::new(false);
+static OFFSET: AtomicU64 = AtomicU64::new(0);
+// Milliseconds after start when the last line was recorded
+static LAST_LINE_MS: AtomicU64 = AtomicU64::new(0);
+
+pub fn enable(dir: &str, lines: usize, input: &str) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let started_unix = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let path = format!("{}/rail-crash-{}-{}.txt", dir.trim_end_matches('/'), started_unix, std::process::id());
+    let ring = Ring {
+        bytes: (0..lines * SLOT_SIZE).map(|_| AtomicU8::new(0)).collect(),
+        lens: (0..lines).map(|_| AtomicUsize::new(0)).collect(),
+        written: AtomicUsize::new(0),
+    };
+    let command = env::args().collect::<Vec<_>>().join(" ");
+    let _ = CRASH.set(Crash { ring, path, input: input.to_string(), command, started: Instant::now() });
+
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        previous(info);
+        if let Some(crash) = CRASH.get()
+            && !REPORTED.swap(true, Ordering::SeqCst)
+            && let Ok(mut file) = File::create(&crash.path)
+        {
+            let _ = write_report(crash, &mut file, format_args!("{}", info));
+            eprintln!("rail: crash report written to {}", crash.path);
+        }
+    }));
+    #[cfg(unix)]
+    signals::install();
+    Ok(())
+}
+
+// Called for every line that goes out
+pub fn record(line: &str) {
+    let Some(crash) = CRASH.get() else {
+        return;
+    };
+    let ring = &crash.ring;
+    if ring.lens.is_empty() {
+        return;
+    }
+    let n = ring.written.load(Ordering::Relaxed);
+    let slot = n % ring.lens.len();
+    let line = line.trim_end_matches('\n').as_bytes();
+    let len = line.len().min(SLOT_SIZE);
+    for (dst, &b) in ring.bytes[slot * SLOT_SIZE..].iter().zip(&line[..len]) {
+        dst.store(b, Ordering::Relaxed);
+    }
+    ring.lens[slot].store(len, Ordering::Relaxed);
+    ring.written.store(n + 1, Ordering::Release);
+    LAST_LINE_MS.store(crash.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+}
+
+// Where the reader has got to in the input
+pub fn set_offset(offset: u64) {
+    OFFSET.store(offset, Ordering::Relaxed);
+}
+
+// Only formats numbers and strings, so nothing here allocates
+fn write_report(crash: &Crash, out: &mut impl Write, reason: fmt::Arguments) -> io::Result<()> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let uptime = crash.started.elapsed();
+    let ring = &crash.ring;
+    let written = ring.written.load(Ordering::Acquire);
+    writeln!(out, "rail crash report")?;
+    writeln!(out, "reason: {}", reason)?;
+    writeln!(out, "time: {} (unix)", now)?;
+    writeln!(out, "pid: {}", std::process::id())?;
+    writeln!(out, "command: {}", crash.command)?;
+    writeln!(out, "input: {}", crash.input)?;
+    writeln!(out, "offset: {}", OFFSET.load(Ordering::Relaxed))?;
+    writeln!(out, "uptime: {:.1}s", uptime.as_secs_f64())?;
+    writeln!(out, "lines printed: {}", written)?;
+    writeln!(out, "stalls recovered: {}", watchdog::incidents())?;
+    if written > 0 {
+        let since = uptime.as_millis() as u64 - LAST_LINE_MS.load(Ordering::Relaxed).min(uptime.as_millis() as u64);
+        writeln!(out, "last line: {:.1}s ago", since as f64 / 1000.0)?;
+    }
+
+    let kept = written.min(ring.lens.len());
+    writeln!(out, "\n--- last {} lines ---", kept)?;
+    let mut line = [0u8; SLOT_SIZE];
+    for n in written - kept..written {
+        let slot = n % ring.lens.len();
+        let len = ring.lens[slot].load(Ordering::Relaxed).min(SLOT_SIZE);
+        for (dst, src) in line[..len].iter_mut().zip(&ring.bytes[slot * SLOT_SIZE..]) {
+            *dst = src.load(Ordering::Relaxed);
+        }
+        out.write_all(&line[..len])?;
+        out.write_all(b"\n")?;
+    }
+    out.flush()
+}
+
+#[cfg(unix)]
+mod signals {
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io::Write;
+    use std::mem::ManuallyDrop;
+    use std::os::fd::FromRawFd;
+    use std::os::raw::{c_char, c_int};
+    use std::sync::OnceLock;
+    use std::sync::atomic::Ordering;
+
+    use super::{CRASH, REPORTED, write_report};
+
+    const SIGQUIT: c_int = 3;
+    const SIGILL: c_int = 4;
+    const SIGABRT: c_int = 6;
+    const SIGFPE: c_int = 8;
+    const SIGSEGV: c_int = 11;
+    #[cfg(target_os = "linux")]
+    const SIGBUS: c_int = 7;
+    #[cfg(not(target_os = "linux"))]
+    const SIGBUS: c_int = 10;
+
+    const SIGNALS: [(c_int, &str); 6] =
+        [(SIGQUIT, "SIGQUIT"), (SIGILL, "SIGILL"), (SIGABRT, "SIGABRT"), (SIGFPE, "SIGFPE"), (SIGSEGV, "SIGSEGV"), (SIGBUS, "SIGBUS")];
+
+    const O_WRONLY: c_int = 1;
+    #[cfg(target_os = "linux")]
+    const O_CREAT_TRUNC: c_int = 0o100 | 0o1000;
+    #[cfg(not(target_os = "linux"))]
+    const O_CREAT_TRUNC: c_int = 0x200 | 0x400;
+
+    const SIG_DFL: usize = 0;
+
+    unsafe extern "C" {
+        fn signal(sig: c_int, handler: usize) -> usize;
+        fn raise(sig: c_int) -> c_int;
+        fn open(path: *const c_char, flags: c_int, ...) -> c_int;
+    }
+
+    // The report path, ready for open(2)
+    static PATH: OnceLock<CString> = OnceLock::new();
+
+    pub fn install() {
+        let Some(crash) = CRASH.get() else {
+            return;
+        };
+        let Ok(path) = CString::new(crash.path.as_str()) else {
+            return;
+        };
+        let _ = PATH.set(path);
+        for (sig, _) in SIGNALS {
+            unsafe { signal(sig, on_signal as extern "C" fn(c_int) as usize) };
+        }
+    }
+
+    extern "C" fn on_signal(sig: c_int) {
+        if let (Some(crash), Some(path)) = (CRASH.get(), PATH.get())
+            && !REPORTED.swap(true, Ordering::SeqCst)
+        {
+            let name = SIGNALS.iter().find(|(s, _)| *s == sig).map_or("?", |(_, name)| name);
+            let fd = unsafe { open(path.as_ptr(), O_WRONLY | O_CREAT_TRUNC, 0o644 as c_int) };
+            if fd >= 0 {
+                let mut file = unsafe { File::from_raw_fd(fd) };
+                let _ = write_report(crash, &mut file, format_args!("killed by signal {} ({})", sig, name));
+                // Not eprintln!: the signal may have landed while stderr was locked
+                let mut stderr = ManuallyDrop::new(unsafe { File::from_raw_fd(2) });
+                let _ = stderr.write_all(b"rail: crash report written to ");
+                let _ = stderr.write_all(crash.path.as_bytes());
+                let _ = stderr.write_all(b"\n");
+            }
+        }
+        unsafe {
+            signal(sig, SIG_DFL);
+            raise(sig);
+        }
+    }
+}
@@ -0,0 +1,369 @@
+// `rail diff <a> <b> [-f]`: compare two logs, e.g. a canary's against the baseline's,
+// side by side (the default) or, with --unified, as - and + lines. Lines are compared
+// without the date and time they start with (2024-05-01T10:00:00.123Z, 2024-05-01
+// 10:00:00,123 and the like) and without what any `--ignore <regex>` matches (request
+// IDs, durations), so the same event logged at another time or with another ID counts
+// as the same line.
+//
+// --by line (the default) pairs the logs' lines in order, as diff does, but working
+// forwards: when lines differ, the next one of either log is looked for in the other's
+// unpaired lines, and the lines before it are what that log has extra. A line neither
+// log has a partner for yet is shown as changed once more than WINDOW lines wait, or
+// when the input ends.
+//
+// --by time pairs a line with one of the same text in the other log that was logged
+// within --window seconds of it (default 1), and shows both logs' lines in time order;
+// lines without a timestamp take that of the line before them. A line is shown as only
+// in its log once the other has got --window past it without a partner.
+//
+// With -f both logs are followed, and lines are shown as they pair up. A line still
+// unpaired after IDLE without anything new from either log is shown as it is.
+//
+// The exit status is 0 if the logs are the same and 1 if not, as with diff.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::follow;
+use crate::open::open_log;
+use crate::otlp;
+use crate::output;
+use crate::regex::Regex;
+
+// Unpaired lines a log may have before the oldest is given up on
+const WINDOW: usize = 200;
+const IDLE: Duration = Duration::from_secs(2);
+const DEFAULT_WIDTH: usize = 160;
+
+#[derive(Clone, Copy, PartialEq)]
+enum By {
+    Line,
+    Time,
+}
+
+struct Line {
+    text: String,
+    key: String,
+    // Nanoseconds since the epoch
+    time: u128,
+}
+
+struct Log {
+    name: String,
+    reader: BufReader<File>,
+    pos: u64,
+    partial: Vec<u8>,
+    // The time of the latest line read
+    time: u128,
+    unpaired: VecDeque<Line>,
+}
+
+enum Row<'a> {
+    Same(&'a Line, &'a Line),
+    Changed(&'a Line, &'a Line),
+    Left(&'a Line),
+    Right(&'a Line),
+}
+
+struct Options {
+    by: By,
+    window: u128,
+    ignore: Vec<Regex>,
+    unified: bool,
+    width: usize,
+    color: bool,
+}
+
+// `rail diff <a> <b> [options]`
+pub fn command(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        eprintln!("Usage: rail diff <a> <b> [-f] [--by line|time] [--window <secs>] [--ignore <regex>]... [--unified] [--width <columns>]");
+        process::exit(1);
+    };
+    let mut files = Vec::new();
+    let mut follow_mode = false;
+    let width = std::env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).unwrap_or(DEFAULT_WIDTH);
+    let mut options = Options { by: By::Line, window: 1_000_000_000, ignore: Vec::new(), unified: false, width, color: output::color_wanted() };
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).map(String::as_str);
+        match (args[i].as_str(), value) {
+            ("-f" | "--follow", _) => {
+                follow_mode = true;
+                i += 1;
+                continue;
+            }
+            ("--unified" | "-u", _) => {
+                options.unified = true;
+                i += 1;
+                continue;
+            }
+            ("--by", Some("line")) => options.by = By::Line,
+            ("--by", Some("time")) => options.by = By::Time,
+            ("--window", Some(secs)) => match follow::parse_interval(secs) {
+                Ok(window) => options.window = window.as_nanos(),
+                Err(e) => {
+                    eprintln!("Error: Invalid --window: {}", e);
+                    process::exit(1);
+                }
+            },
+            ("--ignore", Some(pattern)) => match Regex::new(pattern) {
+                Ok(regex) => options.ignore.push(regex),
+                Err(e) => {
+                    eprintln!("Error: Invalid --ignore regex: {}", e);
+                    process::exit(1);
+                }
+            },
+            ("--width", Some(n)) => match n.parse::<usize>() {
+                Ok(n) if n >= 20 => options.width = n,
+                _ => {
+                    eprintln!("Error: --width requires a number of columns (at least 20)");
+                    process::exit(1);
+                }
+            },
+            (arg, _) if !arg.starts_with('-') => {
+                files.push(arg.to_string());
+                i += 1;
+                continue;
+            }
+            _ => usage(),
+        }
+        i += 2;
+    }
+    let [a, b] = files.as_slice() else {
+        usage();
+        return Ok(());
+    };
+    let mut logs = [Log::open(a)?, Log::open(b)?];
+    let out = &mut io::stdout().lock();
+    let different = run(&mut logs, &options, follow_mode, out)?;
+    if different {
+        process::exit(1);
+    }
+    Ok(())
+}
+
+impl Log {
+    fn open(name: &str) -> io::Result<Log> {
+        let file = open_log(name).map_err(|e| io::Error::new(e.kind(), format!("Could not open '{}': {}", name, e)))?;
+        Ok(Log { name: name.to_string(), reader: BufReader::new(file), pos: 0, partial: Vec::new(), time: 0, unpaired: VecDeque::new() })
+    }
+
+    // Read the lines written since last time; true if there were any
+    fn read(&mut self, options: &Options) -> io::Result<bool> {
+        // A truncated log starts over
+        let len = self.reader.get_ref().metadata()?.len();
+        if len < self.pos {
+            self.reader.seek(SeekFrom::Start(0))?;
+            self.pos = 0;
+            self.partial.clear();
+        }
+        let mut any = false;
+        loop {
+            let n = self.reader.read_until(b'\n', &mut self.partial)?;
+            if n == 0 || !self.partial.ends_with(b"\n") {
+                self.pos += n as u64;
+                return Ok(any);
+            }
+            self.pos += n as u64;
+            let bytes = std::mem::take(&mut self.partial);
+            let text = String::from_utf8_lossy(&bytes).trim_end_matches(['\n', '\r']).to_string();
+            let (time, rest) = match leading_time(&text) {
+                Some((time, len)) => (time, &text[len..]),
+                None => (self.time, text.as_str()),
+            };
+            let mut key = rest.trim().to_string();
+            for regex in &options.ignore {
+                key = remove(regex, &key);
+            }
+            self.time = time;
+            self.unpaired.push_back(Line { key, text, time });
+            any = true;
+        }
+    }
+}
+
+// The timestamp a line starts with (after an opening bracket, if any), and how long it is
+pub fn leading_time(text: &str) -> Option<(u128, usize)> {
+    let start = text.len() - text.trim_start_matches(['[', ' ']).len();
+    let rest = &text[start..];
+    let date_and_time = rest.get(..19)?;
+    // The time runs to the next space (or bracket), or the one after it if the zone is
+    // apart from it
+    let end = |from: usize| rest[from..].find([' ', ']']).map_or(rest.len(), |i| from + i);
+    let mut len = end(19);
+    if let Some(zone) = rest.get(len..).and_then(|r| r.strip_prefix(' ')) {
+        let word = zone.split([' ', ']']).next().unwrap_or("");
+        if matches!(word, "UTC" | "GMT" | "Z") || (word.len() == 5 && word.starts_with(['+', '-'])) {
+            len += 1 + word.len();
+        }
+    }
+    let time = match otlp::parse_timestamp(&rest[..len]) {
+        Some(time) => time,
+        None => {
+            len = 19;
+            otlp::parse_timestamp(date_and_time)?
+        }
+    };
+    let after = &rest[len..];
+    Some((time, start + len + (after.len() - after.trim_start_matches([']', ' ']).len())))
+}
+
+// `text` without what `regex` matches
+fn remove(regex: &Regex, text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let (mut copied, mut pos) = (0, 0);
+    while pos < text.len() {
+        let Some(groups) = regex.captures_at(text, pos) else {
+            break;
+        };
+        let (start, end) = groups[0].unwrap();
+        if end > start {
+            out.push_str(&text[copied..start]);
+            copied = end;
+            pos = end;
+        } else {
+            // Step past an empty match, to the next character
+            pos = end + text[end..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+// Compare until the logs end (or forever, with -f); true if they differed
+fn run(logs: &mut [Log; 2], options: &Options, follow_mode: bool, out: &mut impl Write) -> io::Result<bool> {
+    let mut different = false;
+    if options.unified {
+        writeln!(out, "--- {}\n+++ {}", logs[0].name, logs[1].name)?;
+    } else {
+        let half = (options.width - 3) / 2;
+        writeln!(out, "{} | {}", fit(&logs[0].name, half), logs[1].name)?;
+    }
+    let mut last_input = Instant::now();
+    loop {
+        let read = logs[0].read(options)? | logs[1].read(options)?;
+        if read {
+            last_input = Instant::now();
+        }
+        let ended = !follow_mode || last_input.elapsed() >= IDLE;
+        different |= pair(logs, options, ended, out)?;
+        out.flush()?;
+        if !follow_mode {
+            return Ok(different);
+        }
+        if !read {
+            thread::sleep(follow::poll_interval());
+        }
+    }
+}
+
+// Show what can be told about the unpaired lines; with `ended`, all of them
+fn pair(logs: &mut [Log; 2], options: &Options, ended: bool, out: &mut impl Write) -> io::Result<bool> {
+    let mut different = false;
+    loop {
+        let [left, right] = logs;
+        let (a, b) = (&mut left.unpaired, &mut right.unpaired);
+        let full = a.len() > WINDOW || b.len() > WINDOW;
+        match options.by {
+            By::Line => match (a.front(), b.front()) {
+                (Some(x), Some(y)) if x.key == y.key => {
+                    let (x, y) = (a.pop_front().unwrap(), b.pop_front().unwrap());
+                    show(out, options, Row::Same(&x, &y))?;
+                    continue;
+                }
+                (Some(x), Some(y)) => {
+                    let in_b = b.iter().position(|l| l.key == x.key);
+                    let in_a = a.iter().position(|l| l.key == y.key);
+                    match (in_b, in_a) {
+                        (Some(i), j) if j.is_none_or(|j| i <= j) => {
+                            for line in b.drain(..i) {
+                                show(out, options, Row::Right(&line))?;
+                            }
+                        }
+                        (_, Some(j)) => {
+                            for line in a.drain(..j) {
+                                show(out, options, Row::Left(&line))?;
+                            }
+                        }
+                        _ if ended || full => {
+                            let (x, y) = (a.pop_front().unwrap(), b.pop_front().unwrap());
+                            show(out, options, Row::Changed(&x, &y))?;
+                        }
+                        _ => return Ok(different),
+                    }
+                }
+                (Some(_), None) if ended || full => show(out, options, Row::Left(&a.pop_front().unwrap()))?,
+                (None, Some(_)) if ended || full => show(out, options, Row::Right(&b.pop_front().unwrap()))?,
+                _ => return Ok(different),
+            },
+            By::Time => {
+                // The earlier of the two first lines, and where its partner is
+                let left_first = match (a.front(), b.front()) {
+                    (Some(x), Some(y)) => x.time <= y.time,
+                    (Some(_), None) => true,
+                    (None, Some(_)) => false,
+                    (None, None) => return Ok(different),
+                };
+                let (mine, theirs, other_time) = if left_first { (a, b, right.time) } else { (b, a, left.time) };
+                let line = mine.front().unwrap();
+                let partner = theirs.iter().position(|l| l.key == line.key && l.time.abs_diff(line.time) <= options.window);
+                if let Some(i) = partner {
+                    let (line, partner) = (mine.pop_front().unwrap(), theirs.remove(i).unwrap());
+                    let row = if left_first { Row::Same(&line, &partner) } else { Row::Same(&partner, &line) };
+                    show(out, options, row)?;
+                    continue;
+                }
+                if !(ended || full || other_time > line.time + options.window) {
+                    return Ok(different);
+                }
+                let line = mine.pop_front().unwrap();
+                show(out, options, if left_first { Row::Left(&line) } else { Row::Right(&line) })?;
+            }
+        }
+        different = true;
+    }
+}
+
+fn show(out: &mut impl Write, options: &Options, row: Row) -> io::Result<()> {
+    let paint = |sgr: &str, text: String| if options.color { format!("\x1b[{}m{}\x1b[0m", sgr, text) } else { text };
+    if options.unified {
+        return match row {
+            Row::Same(x, _) => writeln!(out, "  {}", x.text),
+            Row::Changed(x, y) => writeln!(out, "{}\n{}", paint("31", format!("- {}", x.text)), paint("32", format!("+ {}", y.text))),
+            Row::Left(x) => writeln!(out, "{}", paint("31", format!("- {}", x.text))),
+            Row::Right(y) => writeln!(out, "{}", paint("32", format!("+ {}", y.text))),
+        };
+    }
+    // As diff -y does: | for a changed line, < and > for lines only on one side
+    let half = (options.width - 3) / 2;
+    let (left, mark, right, sgr) = match row {
+        Row::Same(x, y) => (x.text.as_str(), ' ', y.text.as_str(), None),
+        Row::Changed(x, y) => (x.text.as_str(), '|', y.text.as_str(), Some("33")),
+        Row::Left(x) => (x.text.as_str(), '<', "", Some("31")),
+        Row::Right(y) => ("", '>', y.text.as_str(), Some("32")),
+    };
+    let row = format!("{} {} {}", fit(left, half), mark, cut(right, half));
+    match sgr {
+        Some(sgr) => writeln!(out, "{}", paint(sgr, row.trim_end().to_string())),
+        None => writeln!(out, "{}", row.trim_end()),
+    }
+}
+
+// `text` cut or padded to `width` characters
+fn fit(text: &str, width: usize) -> String {
+    format!("{:<width$}", cut(text, width), width = width)
+}
+
+fn cut(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_string();
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
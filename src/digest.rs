@@ -0,0 +1,226 @@
+// `--digest <regex> --email <address>`: collect the output lines that match and mail a
+// summary of them every --digest-interval (default 1h), for patterns worth knowing
+// about that shouldn't page anyone. Nothing is sent for a period without matches; what
+// has been collected when rail finishes goes out then.
+//
+// rail has no config file, so the mail relay is given with `--smtp host[:port]`
+// (default localhost:25). It is spoken to in plain SMTP without authentication, as a
+// local MTA or an internal relay accepts it; mail goes out from rail@<host name>.
+//
+// The summary counts identical lines, most frequent first, up to MAX_DISTINCT of them;
+// the rest are counted together:
+//
+//   312 lines matched 'timeout' in app.log on web-3 between 10:00 and 11:00.
+//
+//      120  WARN upstream timeout talking to payments
+//       80  WARN upstream timeout talking to search
+//      112  (other lines)
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::otlp;
+use crate::regex::Regex;
+use crate::tz::{self, LocalTime};
+
+const QUEUE_SIZE: usize = 10_000;
+const MAX_DISTINCT: usize = 50;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Digest {
+    pub pattern: Regex,
+    pub interval: Duration,
+    pub to: Vec<String>,
+    pub smtp: String,
+}
+
+enum Message {
+    Line(String),
+    // Send what is collected, then acknowledge
+    Finish(SyncSender<()>),
+}
+
+struct Collected {
+    since: LocalTime,
+    total: u64,
+    lines: Vec<(String, u64)>,
+    other: u64,
+}
+
+static QUEUE: OnceLock<SyncSender<Message>> = OnceLock::new();
+static PATTERN: OnceLock<Regex> = OnceLock::new();
+
+// "90s", "30m", "1h", "1d"
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    let scale = match s.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86400,
+        _ => 0,
+    };
+    match s[..s.len().saturating_sub(1)].parse::<u64>() {
+        Ok(n) if n > 0 && scale > 0 => Ok(Duration::from_secs(n * scale)),
+        _ => Err(format!("expected a duration like 30m, 1h or 1d, got '{}'", s)),
+    }
+}
+
+// host[:port], port 25 if not given
+pub fn parse_smtp(s: &str) -> String {
+    let has_port = s.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    if has_port { s.to_string() } else { format!("{}:25", s) }
+}
+
+pub fn enable(digest: Digest, filename: &str) {
+    let about = format!("'{}' in {} on {}", digest.pattern.as_str(), filename, otlp::host_name());
+    let _ = PATTERN.set(digest.pattern);
+    QUEUE.get_or_init(|| {
+        let (queue, messages) = mpsc::sync_channel(QUEUE_SIZE);
+        let (interval, to, smtp) = (digest.interval, digest.to, digest.smtp);
+        thread::spawn(move || worker(messages, interval, &to, &smtp, &about));
+        queue
+    });
+}
+
+pub fn observe(line: &str) {
+    if let (Some(pattern), Some(queue)) = (PATTERN.get(), QUEUE.get()) {
+        let line = line.trim_end_matches('\n');
+        if pattern.is_match(line) {
+            let _ = queue.try_send(Message::Line(line.to_string()));
+        }
+    }
+}
+
+// Send what has been collected so far; call before exiting
+pub fn finish() {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    let (ack, done) = mpsc::sync_channel(1);
+    if queue.send(Message::Finish(ack)).is_ok() {
+        let _ = done.recv_timeout(TIMEOUT * 2);
+    }
+}
+
+fn worker(messages: Receiver<Message>, interval: Duration, to: &[String], smtp: &str, about: &str) {
+    let mut collected = Collected { since: tz::now(), total: 0, lines: Vec::new(), other: 0 };
+    let mut deadline = Instant::now() + interval;
+    loop {
+        let finish = match messages.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(Message::Line(line)) => {
+                collected.add(line);
+                continue;
+            }
+            Ok(Message::Finish(ack)) => Some(ack),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        if collected.total > 0 {
+            let (subject, body) = collected.summary(about);
+            if let Err(e) = send(smtp, to, &subject, &body) {
+                eprintln!("Warning: Could not mail the digest through {}: {}", smtp, e);
+            }
+        }
+        collected = Collected { since: tz::now(), total: 0, lines: Vec::new(), other: 0 };
+        deadline = Instant::now() + interval;
+        if let Some(ack) = finish {
+            let _ = ack.send(());
+        }
+    }
+}
+
+impl Collected {
+    fn add(&mut self, line: String) {
+        self.total += 1;
+        if let Some((_, count)) = self.lines.iter_mut().find(|(l, _)| *l == line) {
+            *count += 1;
+        } else if self.lines.len() < MAX_DISTINCT {
+            self.lines.push((line, 1));
+        } else {
+            self.other += 1;
+        }
+    }
+
+    fn summary(&mut self, about: &str) -> (String, String) {
+        let now = tz::now();
+        let subject = format!("rail digest: {} lines matched {}", self.total, about);
+        let mut body = format!(
+            "{} lines matched {} between {:02}:{:02} and {:02}:{:02}.\n\n",
+            self.total, about, self.since.hour, self.since.minute, now.hour, now.minute
+        );
+        self.lines.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        for (line, count) in &self.lines {
+            body.push_str(&format!("{:>8}  {}\n", count, line));
+        }
+        if self.other > 0 {
+            body.push_str(&format!("{:>8}  (other lines)\n", self.other));
+        }
+        (subject, body)
+    }
+}
+
+fn send(smtp: &str, to: &[String], subject: &str, body: &str) -> io::Result<()> {
+    let addr = smtp
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for the mail relay"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut replies = BufReader::new(stream.try_clone()?);
+    let host = Some(otlp::host_name()).filter(|h| !h.is_empty()).unwrap_or("localhost".to_string());
+    let from = format!("rail@{}", host);
+
+    reply(&mut replies, "220")?;
+    command(&mut stream, &mut replies, &format!("EHLO {}", host), "250")?;
+    command(&mut stream, &mut replies, &format!("MAIL FROM:<{}>", from), "250")?;
+    for address in to {
+        command(&mut stream, &mut replies, &format!("RCPT TO:<{}>", address), "25")?;
+    }
+    command(&mut stream, &mut replies, "DATA", "354")?;
+    let mut message = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+        from,
+        to.join(", "),
+        subject
+    );
+    // A line starting with a dot gets another one, so it can't end the message
+    for line in body.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push_str(".\r\n");
+    stream.write_all(message.as_bytes())?;
+    reply(&mut replies, "250")?;
+    command(&mut stream, &mut replies, "QUIT", "221")
+}
+
+fn command(stream: &mut TcpStream, replies: &mut impl BufRead, line: &str, expect: &str) -> io::Result<()> {
+    stream.write_all(format!("{}\r\n", line).as_bytes())?;
+    reply(replies, expect)
+}
+
+// Read a reply (its last line is "NNN text", the ones before "NNN-text") and check that
+// its code starts with `expect`
+fn reply(replies: &mut impl BufRead, expect: &str) -> io::Result<()> {
+    loop {
+        let mut line = String::new();
+        if replies.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the relay closed the connection"));
+        }
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        if line.starts_with(expect) {
+            return Ok(());
+        }
+        return Err(io::Error::other(format!("the relay answered '{}'", line.trim_end())));
+    }
+}
@@ -0,0 +1,247 @@
+// `--resolve-ips`: add <field>_host with the reverse DNS name of every field whose value
+// is an IP address.
+//
+// Lookups never hold up output. A record is annotated from the cache when the answer is
+// already known; otherwise the address is queued for a background thread and the record
+// goes out as is, so the name shows up on later lines from the same address. Answers
+// (including "no name") are cached, up to CACHE_SIZE addresses, oldest dropped first.
+//
+// Queries are PTR lookups sent over UDP straight to the system's nameservers (those in
+// /etc/resolv.conf, or on Windows the ones GetNetworkParams lists), since std has no
+// reverse resolver.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::fields::Record;
+
+const CACHE_SIZE: usize = 4096;
+// Addresses waiting for a lookup; beyond this new ones are skipped until the queue drains
+const QUEUE_SIZE: usize = 256;
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+const TYPE_PTR: u16 = 12;
+const CLASS_IN: u16 = 1;
+
+struct Cache {
+    names: HashMap<IpAddr, Option<String>>,
+    order: VecDeque<IpAddr>,
+    pending: HashSet<IpAddr>,
+}
+
+struct Resolver {
+    cache: Mutex<Cache>,
+    queue: SyncSender<IpAddr>,
+}
+
+static RESOLVER: OnceLock<Resolver> = OnceLock::new();
+
+pub fn enable() {
+    RESOLVER.get_or_init(|| {
+        let (queue, requests) = mpsc::sync_channel(QUEUE_SIZE);
+        let servers = nameservers();
+        thread::spawn(move || worker(requests, servers));
+        Resolver {
+            cache: Mutex::new(Cache { names: HashMap::new(), order: VecDeque::new(), pending: HashSet::new() }),
+            queue,
+        }
+    });
+}
+
+pub fn annotate(record: &mut Record) {
+    let Some(resolver) = RESOLVER.get() else {
+        return;
+    };
+    let mut added = Vec::new();
+    let mut cache = resolver.cache.lock().unwrap();
+    for (name, value) in &record.fields {
+        let Ok(ip) = value.parse::<IpAddr>() else {
+            continue;
+        };
+        match cache.names.get(&ip) {
+            Some(Some(host)) => added.push((format!("{}_host", name), host.clone())),
+            Some(None) => {}
+            None => {
+                if !cache.pending.contains(&ip) && resolver.queue.try_send(ip).is_ok() {
+                    cache.pending.insert(ip);
+                }
+            }
+        }
+    }
+    drop(cache);
+    record.fields.extend(added);
+}
+
+fn worker(requests: Receiver<IpAddr>, servers: Vec<SocketAddr>) {
+    for ip in requests {
+        let host = servers.iter().find_map(|server| reverse_lookup(ip, *server).ok()).flatten();
+        let Some(resolver) = RESOLVER.get() else {
+            return;
+        };
+        let mut cache = resolver.cache.lock().unwrap();
+        cache.pending.remove(&ip);
+        if cache.order.len() >= CACHE_SIZE
+            && let Some(oldest) = cache.order.pop_front()
+        {
+            cache.names.remove(&oldest);
+        }
+        cache.order.push_back(ip);
+        cache.names.insert(ip, host);
+    }
+}
+
+fn nameservers() -> Vec<SocketAddr> {
+    let servers: Vec<SocketAddr> = system_nameservers().into_iter().map(|ip| SocketAddr::new(ip, 53)).collect();
+    if servers.is_empty() { vec![SocketAddr::from(([127, 0, 0, 1], 53))] } else { servers }
+}
+
+#[cfg(not(windows))]
+fn system_nameservers() -> Vec<IpAddr> {
+    std::fs::read_to_string("/etc/resolv.conf")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("nameserver"))
+        .filter_map(|addr| addr.trim().parse::<IpAddr>().ok())
+        .collect()
+}
+
+// FIXED_INFO's DnsServerList, a linked list of dotted IPv4 addresses
+#[cfg(windows)]
+fn system_nameservers() -> Vec<IpAddr> {
+    use std::ffi::CStr;
+
+    use winapi::shared::winerror::{ERROR_BUFFER_OVERFLOW, NO_ERROR};
+    use winapi::um::iphlpapi::GetNetworkParams;
+    use winapi::um::iptypes::{FIXED_INFO, IP_ADDR_STRING};
+
+    let mut len = 0;
+    if unsafe { GetNetworkParams(std::ptr::null_mut(), &mut len) } != ERROR_BUFFER_OVERFLOW {
+        eprintln!("Warning: Could not read the DNS servers; --resolve-ips asks 127.0.0.1");
+        return Vec::new();
+    }
+    // u64s, so the buffer is aligned for FIXED_INFO's pointers
+    let mut buffer = vec![0u64; (len as usize).div_ceil(8)];
+    let info = buffer.as_mut_ptr() as *mut FIXED_INFO;
+    if unsafe { GetNetworkParams(info, &mut len) } != NO_ERROR {
+        eprintln!("Warning: Could not read the DNS servers; --resolve-ips asks 127.0.0.1");
+        return Vec::new();
+    }
+    let mut servers = Vec::new();
+    let mut server: *const IP_ADDR_STRING = unsafe { &(*info).DnsServerList };
+    while !server.is_null() {
+        let address = unsafe { CStr::from_ptr((*server).IpAddress.String.as_ptr()) };
+        servers.extend(address.to_str().ok().and_then(|a| a.parse::<IpAddr>().ok()));
+        server = unsafe { (*server).Next };
+    }
+    servers
+}
+
+// "4.3.2.1.in-addr.arpa" / nibble-reversed ".ip6.arpa"
+fn reverse_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let o = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(v6) => {
+            let mut name = String::new();
+            for byte in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0xf, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+// Ok(None) means the server answered that there's no name
+fn reverse_lookup(ip: IpAddr, server: SocketAddr) -> io::Result<Option<String>> {
+    let id = (SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos() & 0xffff) as u16;
+    let mut query = Vec::with_capacity(64);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+    for label in reverse_name(ip).split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&TYPE_PTR.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+    let bind: SocketAddr = if server.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { (std::net::Ipv6Addr::UNSPECIFIED, 0).into() };
+    let socket = UdpSocket::bind(bind)?;
+    socket.set_read_timeout(Some(TIMEOUT))?;
+    socket.send_to(&query, server)?;
+
+    let mut buf = [0u8; 1500];
+    loop {
+        let (n, from) = socket.recv_from(&mut buf)?;
+        let reply = &buf[..n];
+        if from != server || n < 12 || reply[0..2] != id.to_be_bytes() {
+            continue;
+        }
+        return parse_reply(reply).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed DNS reply"));
+    }
+}
+
+fn parse_reply(reply: &[u8]) -> Option<Option<String>> {
+    let u16_at = |at: usize| Some(u16::from_be_bytes([*reply.get(at)?, *reply.get(at + 1)?]));
+    let rcode = reply[3] & 0x0f;
+    // NXDOMAIN: a definite "no name"
+    if rcode == 3 {
+        return Some(None);
+    }
+    if rcode != 0 {
+        return None;
+    }
+    let questions = u16_at(4)?;
+    let answers = u16_at(6)?;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = read_name(reply, pos)?.1 + 4;
+    }
+    for _ in 0..answers {
+        pos = read_name(reply, pos)?.1;
+        let kind = u16_at(pos)?;
+        let len = u16_at(pos + 8)? as usize;
+        let data = pos + 10;
+        if kind == TYPE_PTR {
+            return Some(Some(read_name(reply, data)?.0));
+        }
+        pos = data + len;
+    }
+    Some(None)
+}
+
+// A possibly compressed name at `pos`, and the offset just past it
+fn read_name(msg: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut name = String::new();
+    let mut end = None;
+    // Compression pointers must go backwards, but bound the jumps anyway
+    for _ in 0..64 {
+        let len = *msg.get(pos)? as usize;
+        if len == 0 {
+            return Some((name, end.unwrap_or(pos + 1)));
+        }
+        if len & 0xc0 == 0xc0 {
+            let target = ((len & 0x3f) << 8) | *msg.get(pos + 1)? as usize;
+            end.get_or_insert(pos + 2);
+            pos = target;
+            continue;
+        }
+        let label = msg.get(pos + 1..pos + 1 + len)?;
+        if !name.is_empty() {
+            name.push('.');
+        }
+        name.push_str(&String::from_utf8_lossy(label));
+        pos += 1 + len;
+    }
+    None
+}
@@ -0,0 +1,98 @@
+// `--expand-encoded`: add <field>_expanded with a readable preview of a base64 blob in a
+// field's value, gunzipping it first when the blob is gzip data (as services do to fit
+// request/response bodies into one log field).
+//
+// A blob is a run of at least MIN_ENCODED base64 characters (standard or URL-safe
+// alphabet). It only counts if what it decodes to is text, so IDs, hashes and paths
+// that merely look like base64 are left alone. Blobs longer than MAX_ENCODED aren't
+// tried, inflating stops after MAX_DECODED bytes, and the preview is cut at
+// PREVIEW_CHARS characters.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::fields::Record;
+use crate::inflate::Inflater;
+
+const MIN_ENCODED: usize = 24;
+const MAX_ENCODED: usize = 256 * 1024;
+const MAX_DECODED: usize = 64 * 1024;
+const PREVIEW_CHARS: usize = 200;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn expand(record: &mut Record) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut added = Vec::new();
+    for (name, value) in &record.fields {
+        if let Some(preview) = blobs(value).find_map(expand_blob) {
+            added.push((format!("{}_expanded", name), preview));
+        }
+    }
+    record.fields.extend(added);
+}
+
+fn is_base64(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '+' | '/' | '-' | '_')
+}
+
+// Runs of base64 characters long enough to be worth decoding; padding ends a run, and
+// isn't needed to decode it
+fn blobs(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(|c: char| !is_base64(c))
+        .filter(|run| (MIN_ENCODED..=MAX_ENCODED).contains(&run.len()))
+}
+
+fn expand_blob(blob: &str) -> Option<String> {
+    let bytes = base64_decode(blob)?;
+    let (bytes, cut) = if bytes.starts_with(&[0x1f, 0x8b]) { gunzip(&bytes)? } else { (bytes, false) };
+    let text = std::str::from_utf8(&bytes).ok()?;
+    if text.chars().any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t')) {
+        return None;
+    }
+    let mut preview: String = text.chars().take(PREVIEW_CHARS).collect();
+    if cut || preview.len() < text.len() {
+        preview.push('…');
+    }
+    Some(preview)
+}
+
+// Either alphabet, without padding; None unless all of it is base64
+fn base64_decode(text: &str) -> Option<Vec<u8>> {
+    if text.len() % 4 == 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            _ => return None,
+        };
+        acc = (acc << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+// The inflated content of a gzip member, and whether it was cut at MAX_DECODED
+fn gunzip(data: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let mut inflater = Inflater::new(data.iter());
+    inflater.gzip_header()?;
+    let cut = inflater.inflate(MAX_DECODED)?;
+    Some((inflater.out, cut))
+}
@@ -0,0 +1,223 @@
+// Text in encodings other than UTF-8: a file that starts with a byte order mark (or,
+// without one, looks like UTF-16: every other byte 0, as Windows services' logs tend to)
+// is decoded, and printed as UTF-8. `--encoding utf-8|utf-16le|utf-16be|latin-1|cp1252`
+// names it instead, for standard input too. A UTF-8 BOM is left out.
+//
+// Decoding is done as lines are printed, per thread (each file followed has its own), on
+// bytes split into lines as usual. A UTF-16 newline is two bytes, so lines are read
+// with read_line, which keeps them whole code units long and the newline in the line;
+// an offset rail seeks to is then always at the start of a code unit. With
+// --binary-safe, output is the file's bytes as they are.
+
+use std::cell::{Cell, RefCell};
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::open::{STDIN, open_log};
+use crate::output;
+
+// The code points of cp1252's bytes 0x80 to 0x9f; the five it leaves undefined stay
+// as the C1 controls latin-1 has there
+const CP1252: [char; 32] = [
+    '€', '\u{81}', '‚', 'ƒ', '„', '…', '†', '‡', 'ˆ', '‰', 'Š', '‹', 'Œ', '\u{8d}', 'Ž', '\u{8f}',
+    '\u{90}', '‘', '’', '“', '”', '•', '–', '—', '˜', '™', 'š', '›', 'œ', '\u{9d}', 'ž', 'Ÿ',
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+    Cp1252,
+}
+
+static FORCED: OnceLock<Encoding> = OnceLock::new();
+
+thread_local! {
+    // This thread's file's encoding, if it needs decoding
+    static ACTIVE: Cell<Option<Encoding>> = const { Cell::new(None) };
+    // The start of a code unit or character split off by the last read
+    static LEFTOVER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+// --encoding
+pub fn set_forced(name: &str) -> Result<(), String> {
+    let encoding = match name.to_ascii_lowercase().as_str() {
+        "utf-8" | "utf8" => Encoding::Utf8,
+        "utf-16le" | "utf16le" => Encoding::Utf16Le,
+        "utf-16be" | "utf16be" => Encoding::Utf16Be,
+        "latin-1" | "latin1" | "iso-8859-1" => Encoding::Latin1,
+        "cp1252" | "windows-1252" => Encoding::Cp1252,
+        _ => return Err(format!("expected utf-8, utf-16le, utf-16be, latin-1 or cp1252, got '{}'", name)),
+    };
+    let _ = FORCED.set(encoding);
+    Ok(())
+}
+
+pub fn forced() -> bool {
+    FORCED.get().is_some()
+}
+
+// What `filename` needs decoding from: as --encoding says, or as the first bytes of a
+// regular file do; None for UTF-8 without a BOM
+pub fn detect(filename: &str) -> Option<Encoding> {
+    if let Some(&forced) = FORCED.get() {
+        return Some(forced);
+    }
+    if filename == STDIN || !Path::new(filename).is_file() {
+        return None;
+    }
+    let mut start = Vec::new();
+    open_log(filename).and_then(|file| file.take(4).read_to_end(&mut start)).ok()?;
+    match start.as_slice() {
+        [0xef, 0xbb, 0xbf, ..] => Some(Encoding::Utf8),
+        [0xff, 0xfe, ..] => Some(Encoding::Utf16Le),
+        [0xfe, 0xff, ..] => Some(Encoding::Utf16Be),
+        [a, 0, b, 0] if *a != 0 && *b != 0 => Some(Encoding::Utf16Le),
+        [0, a, 0, b] if *a != 0 && *b != 0 => Some(Encoding::Utf16Be),
+        _ => None,
+    }
+}
+
+// Decode what this thread reads from now on as `filename` needs
+pub fn use_for(filename: &str) {
+    set(detect(filename));
+}
+
+pub fn current() -> Option<Encoding> {
+    ACTIVE.with(Cell::get)
+}
+
+pub fn set(encoding: Option<Encoding>) {
+    ACTIVE.with(|active| active.set(encoding));
+    LEFTOVER.with(|leftover| leftover.borrow_mut().clear());
+}
+
+// read_until for a line, with a UTF-16 newline (0a 00 or 00 0a, at the start of a code
+// unit) read whole and a 0a byte of another character read past
+pub fn read_line<R: BufRead>(reader: &mut R, line: &mut Vec<u8>) -> io::Result<usize> {
+    let delimiter = output::delimiter();
+    let little_endian = match current() {
+        Some(Encoding::Utf16Le) if delimiter == b'\n' => true,
+        Some(Encoding::Utf16Be) if delimiter == b'\n' => false,
+        _ => return reader.read_until(delimiter, line),
+    };
+    let start = line.len();
+    loop {
+        if reader.read_until(delimiter, line)? == 0 || line.last() != Some(&delimiter) {
+            break;
+        }
+        let len = line.len() - start;
+        if little_endian && !len.is_multiple_of(2) {
+            match reader.fill_buf()?.first() {
+                Some(0) => {
+                    reader.consume(1);
+                    line.push(0);
+                    break;
+                }
+                // Not written yet
+                None => break,
+                Some(_) => {}
+            }
+        } else if !little_endian && len.is_multiple_of(2) && line[line.len() - 2] == 0 {
+            break;
+        }
+    }
+    Ok(line.len() - start)
+}
+
+// How many bytes a code unit is; offsets rail seeks to are multiples of it
+pub fn unit() -> u64 {
+    match current() {
+        Some(Encoding::Utf16Le | Encoding::Utf16Be) if output::delimiter() == b'\n' => 2,
+        _ => 1,
+    }
+}
+
+// If a newline starts at `pos`, where the line after it starts; `byte` is the one at
+// `pos` and `next` the one after it
+pub fn newline_at(pos: u64, byte: u8, next: Option<u8>) -> Option<u64> {
+    match current().filter(|_| output::delimiter() == b'\n') {
+        Some(Encoding::Utf16Le) => (pos.is_multiple_of(2) && byte == b'\n' && next == Some(0)).then_some(pos + 2),
+        Some(Encoding::Utf16Be) => (pos.is_multiple_of(2) && byte == 0 && next == Some(b'\n')).then_some(pos + 2),
+        _ => (byte == output::delimiter()).then_some(pos + 1),
+    }
+}
+
+// How many lines end in `bytes`, read from offset `pos`
+pub fn newlines(bytes: &[u8], pos: u64) -> u64 {
+    let delimiter = output::delimiter();
+    let newline = match current().filter(|_| delimiter == b'\n') {
+        Some(Encoding::Utf16Le) => [b'\n', 0],
+        Some(Encoding::Utf16Be) => [0, b'\n'],
+        _ => return bytes.iter().filter(|&&b| b == delimiter).count() as u64,
+    };
+    let units = &bytes[(pos % 2) as usize..];
+    units.chunks_exact(2).filter(|&unit| unit == newline).count() as u64
+}
+
+// `bytes` as UTF-8, and whether part of a character is held back for the next call; None
+// if this thread's input needs no decoding
+pub fn decode(bytes: &[u8]) -> Option<(Vec<u8>, bool)> {
+    let encoding = current()?;
+    let mut text = String::with_capacity(bytes.len());
+    let held = LEFTOVER.with(|leftover| {
+        let mut leftover = leftover.borrow_mut();
+        let mut input = std::mem::take(&mut *leftover);
+        input.extend_from_slice(bytes);
+        let used = match encoding {
+            Encoding::Utf8 => utf8(&input, &mut text),
+            Encoding::Utf16Le => utf16(&input, &mut text, u16::from_le_bytes),
+            Encoding::Utf16Be => utf16(&input, &mut text, u16::from_be_bytes),
+            Encoding::Latin1 => {
+                text.extend(input.iter().map(|&b| b as char));
+                input.len()
+            }
+            Encoding::Cp1252 => {
+                text.extend(input.iter().map(|&b| if (0x80..0xa0).contains(&b) { CP1252[b as usize - 0x80] } else { b as char }));
+                input.len()
+            }
+        };
+        *leftover = input.split_off(used);
+        !leftover.is_empty()
+    });
+    Some((text.replace('\u{feff}', "").into_bytes(), held))
+}
+
+// Valid UTF-8 as it is, and what isn't as U+FFFD; how much of `input` was used (all but
+// a character cut off at its end)
+fn utf8(input: &[u8], text: &mut String) -> usize {
+    let mut rest = input;
+    loop {
+        match std::str::from_utf8(rest) {
+            Ok(valid) => {
+                text.push_str(valid);
+                return input.len();
+            }
+            Err(e) => {
+                let (valid, after) = rest.split_at(e.valid_up_to());
+                text.push_str(std::str::from_utf8(valid).unwrap_or_default());
+                let Some(len) = e.error_len() else {
+                    return input.len() - after.len();
+                };
+                text.push(char::REPLACEMENT_CHARACTER);
+                rest = &after[len..];
+            }
+        }
+    }
+}
+
+// UTF-16 code units, surrogate pairs joined and unpaired ones as U+FFFD; how much of
+// `input` was used
+fn utf16(input: &[u8], text: &mut String, unit: fn([u8; 2]) -> u16) -> usize {
+    let units: Vec<u16> = input.chunks_exact(2).map(|pair| unit([pair[0], pair[1]])).collect();
+    // A high surrogate at the end waits for its low one
+    let complete = match units.last() {
+        Some(0xd800..=0xdbff) => units.len() - 1,
+        _ => units.len(),
+    };
+    text.extend(char::decode_utf16(units[..complete].iter().copied()).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)));
+    complete * 2
+}
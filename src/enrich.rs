@@ -0,0 +1,350 @@
+// Built-in enrichers that add decoded/summarized copies of common access-log fields:
+//
+//   url:   percent-decode a request path into <field>_decoded (only when decoding
+//          changes it); a '+' is a space only in a query string after a '?'
+//   query: the same for a query string on its own, where every '+' is a space
+//   ua:    summarize a user-agent string as <field>_browser, <field>_os and <field>_bot
+//
+// Presets turn on the ones that fit their fields (iis-w3c: url for cs-uri-stem, query
+// for cs-uri-query, ua for cs(User-Agent)); `--enrich url:<field>,ua:<field>` adds more
+// and `--no-enrich` turns the preset's off.
+//
+// Others stamp every record with where it was read, so lines gathered from many
+// machines still say where they came from:
+//
+//   host        host=<the machine's name>
+//   env[:VAR]   env=<$RAIL_ENV, or $VAR>; rail has no config file to keep a label in
+//   az          az=<cloud availability zone>, asked of the instance metadata service
+//   version     rail_version=<this rail's version>
+//
+// They add fields with --format, and with --forward resource attributes
+// (deployment.environment, cloud.availability_zone, service.version; host.name is
+// always there). Each is worked
+// out once, when first needed; one that can't be (no $RAIL_ENV, not on a cloud) is
+// left out.
+
+use std::env;
+use std::sync::OnceLock;
+
+use crate::fields::{Format, Record};
+use crate::imds;
+use crate::otlp;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Url,
+    Query,
+    UserAgent,
+    Host,
+    Env,
+    Zone,
+    Version,
+}
+
+#[derive(Debug)]
+pub struct Enricher {
+    kind: Kind,
+    // The field enriched; for env, the variable holding the label
+    field: String,
+    stamp: OnceLock<Option<String>>,
+}
+
+pub fn parse_enrichers(spec: &str) -> Result<Vec<Enricher>, String> {
+    let mut enrichers = Vec::new();
+    for item in spec.split(',') {
+        let (kind, field) = match item {
+            "host" => (Kind::Host, ""),
+            "env" => (Kind::Env, "RAIL_ENV"),
+            "az" => (Kind::Zone, ""),
+            "version" => (Kind::Version, ""),
+            _ => {
+                let (kind, field) = item
+                    .split_once(':')
+                    .ok_or(format!("expected <url|query|ua>:<field>, host, env[:VAR], az or version, got '{}'", item))?;
+                let kind = match kind {
+                    "url" => Kind::Url,
+                    "query" => Kind::Query,
+                    "ua" => Kind::UserAgent,
+                    "env" => Kind::Env,
+                    _ => return Err(format!("unknown enricher '{}' (expected url, query, ua, host, env, az or version)", kind)),
+                };
+                if field.is_empty() {
+                    return Err(format!("missing field name in '{}'", item));
+                }
+                (kind, field)
+            }
+        };
+        enrichers.push(Enricher::new(kind, field));
+    }
+    Ok(enrichers)
+}
+
+// The enrichers a format preset turns on by default
+pub fn preset(format: Format) -> Vec<Enricher> {
+    let defaults: &[(Kind, &str)] = match format {
+        Format::IisW3c => &[(Kind::Url, "cs-uri-stem"), (Kind::Query, "cs-uri-query"), (Kind::UserAgent, "cs(User-Agent)")],
+        _ => &[],
+    };
+    defaults.iter().map(|&(kind, field)| Enricher::new(kind, field)).collect()
+}
+
+impl Enricher {
+    fn new(kind: Kind, field: &str) -> Enricher {
+        Enricher { kind, field: field.to_string(), stamp: OnceLock::new() }
+    }
+
+    // Whether this one only makes sense on parsed fields (url, query, ua)
+    pub fn needs_fields(&self) -> bool {
+        matches!(self.kind, Kind::Url | Kind::Query | Kind::UserAgent)
+    }
+
+    // The resource attribute and value a stamping enricher gives forwarded records
+    pub fn resource_attribute(&self) -> Option<(&'static str, String)> {
+        let name = match self.kind {
+            Kind::Url | Kind::Query | Kind::UserAgent | Kind::Host => return None,
+            Kind::Env => "deployment.environment",
+            Kind::Zone => "cloud.availability_zone",
+            Kind::Version => "service.version",
+        };
+        self.stamp().map(|value| (name, value.to_string()))
+    }
+
+    fn stamp(&self) -> Option<&str> {
+        self.stamp
+            .get_or_init(|| {
+                let value = match self.kind {
+                    Kind::Url | Kind::Query | Kind::UserAgent => None,
+                    Kind::Host => Some(otlp::host_name()),
+                    Kind::Env => env::var(&self.field).ok(),
+                    Kind::Zone => imds::availability_zone(),
+                    Kind::Version => Some(env!("CARGO_PKG_VERSION").to_string()),
+                };
+                value.filter(|v| !v.is_empty())
+            })
+            .as_deref()
+    }
+
+    pub fn enrich(&self, record: &mut Record) {
+        let name = match self.kind {
+            Kind::Url | Kind::Query | Kind::UserAgent => None,
+            Kind::Host => Some("host"),
+            Kind::Env => Some("env"),
+            Kind::Zone => Some("az"),
+            Kind::Version => Some("rail_version"),
+        };
+        if let Some(name) = name {
+            if let Some(value) = self.stamp() {
+                record.fields.push((name.to_string(), value.to_string()));
+            }
+            return;
+        }
+        let Some(value) = record.get(&self.field) else {
+            return;
+        };
+        let mut added = Vec::new();
+        match self.kind {
+            Kind::Host | Kind::Env | Kind::Zone | Kind::Version => {}
+            Kind::Url | Kind::Query => {
+                let decoded = if self.kind == Kind::Query {
+                    url_decode(value, true)
+                } else if let Some((path, query)) = value.split_once('?') {
+                    format!("{}?{}", url_decode(path, false), url_decode(query, true))
+                } else {
+                    url_decode(value, false)
+                };
+                if decoded != value {
+                    added.push((format!("{}_decoded", self.field), decoded));
+                }
+            }
+            Kind::UserAgent => {
+                // W3C logs write spaces in the user agent as '+'
+                let agent = url_decode(value, true);
+                let summary = summarize_agent(&agent);
+                if let Some(browser) = summary.browser {
+                    added.push((format!("{}_browser", self.field), browser));
+                }
+                if let Some(os) = summary.os {
+                    added.push((format!("{}_os", self.field), os));
+                }
+                if summary.bot {
+                    added.push((format!("{}_bot", self.field), "true".to_string()));
+                }
+            }
+        }
+        record.fields.extend(added);
+    }
+}
+
+// %XX escapes, and with `plus_as_space` (query strings) '+' as a space; invalid or cut
+// off escapes are kept as written
+fn url_decode(text: &str, plus_as_space: bool) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' if plus_as_space => out.push(b' '),
+            b'%' if i + 2 < bytes.len() && bytes[i + 1].is_ascii_hexdigit() && bytes[i + 2].is_ascii_hexdigit() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap();
+                out.push(u8::from_str_radix(hex, 16).unwrap());
+                i += 3;
+                continue;
+            }
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[derive(Default)]
+struct Agent {
+    browser: Option<String>,
+    os: Option<String>,
+    bot: bool,
+}
+
+const BOT_MARKERS: &[&str] = &["bot", "crawler", "spider", "slurp", "curl/", "wget/", "python-requests", "go-http-client", "httpclient", "okhttp"];
+
+// Checked in order: several browsers claim to be the ones after them
+const BROWSERS: &[(&str, &str)] = &[
+    ("Edg/", "Edge"), ("Edge/", "Edge"), ("OPR/", "Opera"), ("SamsungBrowser/", "Samsung Internet"),
+    ("Firefox/", "Firefox"), ("FxiOS/", "Firefox"), ("CriOS/", "Chrome"), ("Chrome/", "Chrome"),
+    ("Version/", "Safari"), ("MSIE ", "Internet Explorer"), ("Trident/", "Internet Explorer"),
+];
+
+fn summarize_agent(agent: &str) -> Agent {
+    let mut summary = Agent::default();
+    let lower = agent.to_ascii_lowercase();
+    summary.bot = BOT_MARKERS.iter().any(|m| lower.contains(m));
+
+    if summary.bot {
+        // Name the bot by its product token, e.g. "Googlebot/2.1" or "curl/8.4.0"
+        summary.browser = agent
+            .split([' ', ';', '(', ')', '+'])
+            .find(|token| {
+                let t = token.to_ascii_lowercase();
+                BOT_MARKERS.iter().any(|m| t.contains(m.trim_end_matches('/')))
+            })
+            .map(|token| token.trim_end_matches(',').to_string());
+    } else {
+        for (marker, name) in BROWSERS {
+            if let Some(at) = agent.find(marker) {
+                let version: String = agent[at + marker.len()..].chars().take_while(|c| c.is_ascii_digit()).collect();
+                // Trident/7.0 is IE 11
+                let version = if *marker == "Trident/" { "11".to_string() } else { version };
+                summary.browser = Some(if version.is_empty() { name.to_string() } else { format!("{} {}", name, version) });
+                break;
+            }
+        }
+    }
+
+    summary.os = if let Some(at) = agent.find("Windows NT ") {
+        let version: String = agent[at + 11..].chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+        Some(match version.as_str() {
+            "10.0" => "Windows 10/11".to_string(),
+            "6.3" => "Windows 8.1".to_string(),
+            "6.2" => "Windows 8".to_string(),
+            "6.1" => "Windows 7".to_string(),
+            v => format!("Windows NT {}", v),
+        })
+    } else if let Some(at) = agent.find("Android") {
+        let version: String = agent[at + 7..].trim_start().chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+        Some(if version.is_empty() { "Android".to_string() } else { format!("Android {}", version) })
+    } else if let Some(at) = agent.find("iPhone OS ").or_else(|| agent.find("CPU OS ")) {
+        let skip = if agent[at..].starts_with("iPhone") { 10 } else { 7 };
+        let version: String = agent[at + skip..].chars().take_while(|c| c.is_ascii_digit()).collect();
+        Some(format!("iOS {}", version))
+    } else if agent.contains("Mac OS X") {
+        Some("macOS".to_string())
+    } else if agent.contains("CrOS") {
+        Some("ChromeOS".to_string())
+    } else if agent.contains("Linux") {
+        Some("Linux".to_string())
+    } else {
+        None
+    };
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_decode_escapes_and_plus() {
+        assert_eq!(url_decode("/a%20b/%C3%A9", false), "/a b/é");
+        assert_eq!(url_decode("%2f%2F", false), "//");
+        assert_eq!(url_decode("/c++/x", false), "/c++/x");
+        assert_eq!(url_decode("q=c%2B%2B+rocks", true), "q=c++ rocks");
+        assert_eq!(url_decode("%41", false), "A");
+    }
+
+    #[test]
+    fn url_decode_keeps_invalid_and_cut_off_escapes() {
+        assert_eq!(url_decode("100%", false), "100%");
+        assert_eq!(url_decode("%4", false), "%4");
+        assert_eq!(url_decode("a%zzb", false), "a%zzb");
+        assert_eq!(url_decode("%+1", false), "%+1");
+        assert_eq!(url_decode("%%41", false), "%A");
+        assert_eq!(url_decode("%ff", false), "\u{fffd}");
+    }
+
+    fn enriched(spec: &str, field: &str, value: &str) -> Option<String> {
+        let mut record = Record { fields: vec![(field.to_string(), value.to_string())] };
+        parse_enrichers(spec).unwrap().iter().for_each(|enricher| enricher.enrich(&mut record));
+        record.get(&format!("{}_decoded", field)).map(str::to_string)
+    }
+
+    #[test]
+    fn plus_is_a_space_only_in_query_strings() {
+        assert_eq!(enriched("url:path", "path", "/c++/a%20b"), Some("/c++/a b".to_string()));
+        assert_eq!(enriched("url:request", "request", "/c++/x?q=a+b"), Some("/c++/x?q=a b".to_string()));
+        assert_eq!(enriched("query:qs", "qs", "q=a+b&lang=c%2B%2B"), Some("q=a b&lang=c++".to_string()));
+        assert_eq!(enriched("url:path", "path", "/c++/x"), None);
+    }
+
+    fn agent(text: &str) -> (Option<String>, Option<String>, bool) {
+        let summary = summarize_agent(text);
+        (summary.browser, summary.os, summary.bot)
+    }
+
+    fn some(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test]
+    fn browsers() {
+        let chrome = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36";
+        assert_eq!(agent(chrome), (some("Chrome 124"), some("Windows 10/11"), false));
+        let edge = "Mozilla/5.0 (Windows NT 6.1) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/109.0 Safari/537.36 Edg/109.0.1518.78";
+        assert_eq!(agent(edge), (some("Edge 109"), some("Windows 7"), false));
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:125.0) Gecko/20100101 Firefox/125.0";
+        assert_eq!(agent(firefox), (some("Firefox 125"), some("Linux"), false));
+        let safari = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1";
+        assert_eq!(agent(safari), (some("Safari 17"), some("iOS 17"), false));
+        let ie = "Mozilla/5.0 (Windows NT 6.3; Trident/7.0; rv:11.0) like Gecko";
+        assert_eq!(agent(ie), (some("Internet Explorer 11"), some("Windows 8.1"), false));
+    }
+
+    #[test]
+    fn operating_systems() {
+        let android = "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0 Mobile Safari/537.36";
+        assert_eq!(agent(android).1, some("Android 14"));
+        let mac = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Safari/605.1.15";
+        assert_eq!(agent(mac).1, some("macOS"));
+        assert_eq!(agent("Mozilla/5.0 (X11; CrOS x86_64 14541.0.0)").1, some("ChromeOS"));
+        assert_eq!(agent("SomeClient/1.0").1, None);
+    }
+
+    #[test]
+    fn bots() {
+        let google = "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+        assert_eq!(agent(google), (some("Googlebot/2.1"), None, true));
+        assert_eq!(agent("curl/8.4.0"), (some("curl/8.4.0"), None, true));
+        assert!(agent("python-requests/2.31.0").2);
+        // W3C logs write the spaces as '+'
+        let w3c = url_decode("Mozilla/5.0+(compatible;+bingbot/2.0;++http://www.bing.com/bingbot.htm)", true);
+        assert_eq!(agent(&w3c), (some("bingbot/2.0"), None, true));
+    }
+}
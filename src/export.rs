@@ -0,0 +1,203 @@
+// `rail export <file> --output <dir> [--since <time>] [--until <time>] [--chunk <size>]
+// [--sha256]`: copy the lines logged in a time range into a new directory, for handing
+// to legal or compliance. Lines are copied byte for byte, into files of at most --chunk
+// bytes (cut at line ends; a longer line gets a file of its own) named after the log:
+// app.log.0001, app.log.0002, ...
+//
+// A line's time is the timestamp it starts with, as for `rail diff --by time`; a line
+// without one (a stack trace's) goes with the line before it. --since is inclusive and
+// --until isn't; both take 2024-05-01T10:00:00Z, 2024-05-01 10:00:00 (local time) or a
+// date. Lines anywhere in the file that fall in the range are exported, so a few logged
+// out of order aren't lost.
+//
+// MANIFEST describes the export and each chunk: its size and which lines of the log it
+// holds. With --sha256 it also has each chunk's hash, and SHA256SUMS has them in the
+// form `sha256sum -c` checks. Nothing in either depends on when the export was made, so
+// exporting the same range of the same log again gives identical files. The directory
+// has to be new or empty; nothing is written over.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::open::open_log;
+use crate::sha256::Sha256;
+use crate::{backfill, compressed, humanize, otlp, since};
+
+struct Chunk {
+    name: String,
+    bytes: u64,
+    // Line numbers in the log of its first and last lines
+    first: u64,
+    last: u64,
+    sha256: Option<String>,
+}
+
+// The chunk being written, and those done
+struct Chunks {
+    dir: PathBuf,
+    base: String,
+    max: Option<u64>,
+    hash: bool,
+    file: Option<(BufWriter<File>, Option<Sha256>)>,
+    done: Vec<Chunk>,
+}
+
+// `rail export <file> [options]`
+pub fn command(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        eprintln!("Usage: rail export <file> --output <dir> [--since <time>] [--until <time>] [--chunk <size>] [--sha256]");
+        process::exit(1);
+    };
+    let mut filename = None;
+    let mut output = None;
+    let (mut since, mut until) = (None, None);
+    let mut chunk = None;
+    let mut hash = false;
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1).map(String::as_str);
+        match (args[i].as_str(), value) {
+            ("--sha256", _) => {
+                hash = true;
+                i += 1;
+                continue;
+            }
+            ("--output" | "-o", Some(dir)) => output = Some(dir.to_string()),
+            ("--since", Some(time)) => since = Some((time.to_string(), parse_time("--since", time))),
+            ("--until", Some(time)) => until = Some((time.to_string(), parse_time("--until", time))),
+            ("--chunk", Some(size)) => match backfill::parse_size(size) {
+                Ok(size) => chunk = Some(size),
+                Err(e) => {
+                    eprintln!("Error: Invalid --chunk: {}", e);
+                    process::exit(1);
+                }
+            },
+            (arg, _) if !arg.starts_with('-') && filename.is_none() => {
+                filename = Some(arg.to_string());
+                i += 1;
+                continue;
+            }
+            _ => usage(),
+        }
+        i += 2;
+    }
+    let (Some(filename), Some(output)) = (filename, output) else {
+        usage();
+        return Ok(());
+    };
+    if let Some(compression) = compressed::detect(&filename) {
+        eprintln!("Error: '{}' is {}-compressed; export copies a log's bytes as they are, so decompress it first", filename, compression.name());
+        process::exit(1);
+    }
+    let dir = Path::new(&output);
+    if fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some()) {
+        eprintln!("Error: '{}' already has files in it; export only writes to a new or empty directory", output);
+        process::exit(1);
+    }
+    fs::create_dir_all(dir).map_err(|e| io::Error::new(e.kind(), format!("Could not create '{}': {}", output, e)))?;
+
+    let file = open_log(&filename).map_err(|e| io::Error::new(e.kind(), format!("Could not open '{}': {}", filename, e)))?;
+    let base = Path::new(&filename).file_name().map_or("log".into(), |name| name.to_string_lossy().into_owned());
+    let mut chunks = Chunks { dir: dir.to_path_buf(), base, max: chunk, hash, file: None, done: Vec::new() };
+    let range = (since.as_ref().map(|s| s.1), until.as_ref().map(|u| u.1));
+    let mut reader = BufReader::new(file);
+    let mut line = Vec::new();
+    let (mut number, mut time, mut lines, mut bytes) = (0u64, 0u128, 0u64, 0u64);
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        number += 1;
+        if range != (None, None) {
+            if let Some(t) = since::line_time(&line) {
+                time = t;
+            }
+            if range.0.is_some_and(|since| time < since) || range.1.is_some_and(|until| time >= until) {
+                continue;
+            }
+        }
+        chunks.write(&line, number)?;
+        lines += 1;
+        bytes += line.len() as u64;
+    }
+    let done = chunks.finish()?;
+
+    let shown = |bound: &Option<(String, u128)>, none: &str| bound.as_ref().map_or(none.to_string(), |b| b.0.clone());
+    let mut manifest = format!("rail export of {}\n", filename);
+    manifest += &format!("since {}, until {}\n", shown(&since, "the start"), shown(&until, "the end"));
+    manifest += &format!("{} lines, {} bytes, {} chunk(s)\n\n", lines, bytes, done.len());
+    let width = done.iter().map(|c| c.bytes.to_string().len()).max().unwrap_or(0);
+    for c in &done {
+        manifest += &format!("{}  {:>width$} bytes  lines {}-{}", c.name, c.bytes, c.first, c.last);
+        if let Some(sha256) = &c.sha256 {
+            manifest += &format!("  sha256 {}", sha256);
+        }
+        manifest.push('\n');
+    }
+    fs::write(dir.join("MANIFEST"), manifest)?;
+    if hash {
+        let sums: String = done.iter().map(|c| format!("{}  {}\n", c.sha256.as_deref().unwrap_or(""), c.name)).collect();
+        fs::write(dir.join("SHA256SUMS"), sums)?;
+    }
+    if lines == 0 {
+        eprintln!("Warning: no lines of '{}' fall in the range; only the manifest was written", filename);
+    } else {
+        println!("Exported {} lines ({}) of '{}' to '{}' in {} chunk(s)", lines, humanize::bytes(bytes as f64), filename, output, done.len());
+    }
+    Ok(())
+}
+
+// --since and --until: a timestamp, or a date for its midnight
+pub fn parse_time(flag: &str, text: &str) -> u128 {
+    let time = match text.len() {
+        10 => otlp::parse_timestamp(&format!("{} 00:00:00", text)),
+        16 => otlp::parse_timestamp(&format!("{}:00", text)),
+        _ => otlp::parse_timestamp(text),
+    };
+    time.unwrap_or_else(|| {
+        eprintln!("Error: Invalid {}: expected a time like 2024-05-01T10:00:00Z, 2024-05-01 10:00 or 2024-05-01, got '{}'", flag, text);
+        process::exit(1);
+    })
+}
+
+impl Chunks {
+    // Add line `number` of the log, starting a new chunk if it wouldn't fit
+    fn write(&mut self, line: &[u8], number: u64) -> io::Result<()> {
+        let full = self.done.last().zip(self.max).is_some_and(|(c, max)| c.bytes > 0 && c.bytes + line.len() as u64 > max);
+        if self.file.is_none() || full {
+            self.close()?;
+            let name = format!("{}.{:04}", self.base, self.done.len() + 1);
+            let file = File::create(self.dir.join(&name))?;
+            self.file = Some((BufWriter::new(file), self.hash.then(Sha256::new)));
+            self.done.push(Chunk { name, bytes: 0, first: number, last: number, sha256: None });
+        }
+        let (file, sha256) = self.file.as_mut().unwrap();
+        file.write_all(line)?;
+        if let Some(sha256) = sha256 {
+            sha256.update(line);
+        }
+        let chunk = self.done.last_mut().unwrap();
+        chunk.bytes += line.len() as u64;
+        chunk.last = number;
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        if let Some((mut file, sha256)) = self.file.take() {
+            file.flush()?;
+            file.get_ref().sync_all()?;
+            if let Some(chunk) = self.done.last_mut() {
+                chunk.sha256 = sha256.map(Sha256::finish);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<Vec<Chunk>> {
+        self.close()?;
+        Ok(self.done)
+    }
+}
@@ -0,0 +1,40 @@
+// `--fail-on <regex>`: remember whether any emitted line matched, so rail can exit
+// non-zero once it stops and CI jobs can gate on log content. With --report every line
+// is matched, so the report can say how many did.
+
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::regex::Regex;
+
+static PATTERN: OnceLock<Regex> = OnceLock::new();
+static MATCHED: AtomicBool = AtomicBool::new(false);
+static MATCHES: AtomicU64 = AtomicU64::new(0);
+static COUNTING: AtomicBool = AtomicBool::new(false);
+
+pub fn set_pattern(re: Regex) {
+    let _ = PATTERN.set(re);
+}
+
+pub fn observe(line: &str) {
+    if let Some(re) = PATTERN.get()
+        && (!MATCHED.load(Ordering::Relaxed) || COUNTING.load(Ordering::Relaxed))
+        && re.is_match(line.trim_end_matches('\n'))
+    {
+        MATCHED.store(true, Ordering::Relaxed);
+        MATCHES.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+pub fn matched() -> bool {
+    MATCHED.load(Ordering::Relaxed)
+}
+
+pub fn count_matches() {
+    COUNTING.store(true, Ordering::Relaxed);
+}
+
+// The pattern and how many lines matched it
+pub fn summary() -> Option<(&'static str, u64)> {
+    PATTERN.get().map(|re| (re.as_str(), MATCHES.load(Ordering::Relaxed)))
+}
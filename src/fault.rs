@@ -0,0 +1,93 @@
+// Classification of read errors that mean "this handle went bad" rather than "this file
+// is unreadable", e.g. a stale NFS handle or a disk that was pulled. Those are worth
+// reopening the file for instead of giving up.
+
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadFault {
+    StaleHandle,
+    DeviceGone,
+    Io,
+}
+
+impl fmt::Display for ReadFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadFault::StaleHandle => write!(f, "stale file handle"),
+            ReadFault::DeviceGone => write!(f, "device removed"),
+            ReadFault::Io => write!(f, "I/O error"),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+const ESTALE: i32 = 116;
+#[cfg(all(unix, not(target_os = "linux")))]
+const ESTALE: i32 = 70;
+
+// Returns None for errors that reopening won't fix
+#[cfg(unix)]
+pub fn classify(e: &io::Error) -> Option<ReadFault> {
+    const EIO: i32 = 5;
+    const ENXIO: i32 = 6;
+    const ENODEV: i32 = 19;
+
+    match e.raw_os_error()? {
+        ESTALE => Some(ReadFault::StaleHandle),
+        ENXIO | ENODEV => Some(ReadFault::DeviceGone),
+        EIO => Some(ReadFault::Io),
+        _ => None,
+    }
+}
+
+#[cfg(windows)]
+pub fn classify(e: &io::Error) -> Option<ReadFault> {
+    const ERROR_NOT_READY: i32 = 21;
+    const ERROR_CRC: i32 = 23;
+    const ERROR_BAD_NETPATH: i32 = 53;
+    const ERROR_DEV_NOT_EXIST: i32 = 55;
+    const ERROR_UNEXP_NET_ERR: i32 = 59;
+    const ERROR_NETNAME_DELETED: i32 = 64;
+
+    match e.raw_os_error()? {
+        ERROR_NETNAME_DELETED | ERROR_UNEXP_NET_ERR | ERROR_BAD_NETPATH => Some(ReadFault::StaleHandle),
+        ERROR_DEV_NOT_EXIST | ERROR_NOT_READY => Some(ReadFault::DeviceGone),
+        ERROR_CRC => Some(ReadFault::Io),
+        _ => None,
+    }
+}
+
+// A run of permission errors on the tailed file. The first error of a run is reported,
+// later ones only grow the retry delay, so a revoked permission doesn't spam one
+// identical error line per poll.
+pub struct Denied {
+    delay: Option<Duration>,
+}
+
+impl Denied {
+    pub fn new() -> Denied {
+        Denied { delay: None }
+    }
+
+    // Returns how long to wait before trying again
+    pub fn hit(&mut self, filename: &str, e: &io::Error) -> Duration {
+        let delay = match self.delay {
+            None => {
+                eprintln!("\n--- Permission denied on '{}': {}; retrying ---\n", filename, e);
+                Duration::from_secs(1)
+            }
+            Some(d) => (d * 2).min(Duration::from_secs(30)),
+        };
+        self.delay = Some(delay);
+        delay
+    }
+
+    pub fn clear(&mut self, filename: &str) {
+        if self.delay.take().is_some() {
+            eprintln!("--- Access to '{}' restored ---", filename);
+        }
+    }
+}
@@ -0,0 +1,568 @@
+// Field extraction for known log formats (`--format`). Each record is split into named
+// fields and printed as key=value pairs, or as one JSON object per record with --json.
+// Lines that don't parse as the format are passed through unchanged.
+//
+// iis-w3c: space-delimited W3C extended log lines whose columns are named by the most
+// recent `#Fields:` directive. Directive lines themselves are consumed, and the header
+// at the top of the file is read up front so tailing from the middle still knows the
+// columns.
+//
+// csvlog-postgres: PostgreSQL's log_destination=csvlog output. Quoted fields may span
+// lines (multi-line messages and queries), so lines are collected until the record's
+// quotes balance.
+//
+// logcat: Android `logcat -v threadtime` lines, printed back in the same layout.
+//
+// logfmt (also `--logfmt`): key=value pairs as Heroku, Grafana and Go's slog write them,
+// values quoted when they contain spaces. A key on its own has an empty value. Mostly
+// useful with --fields and --where (see select.rs).
+//
+// kmsg: Linux kernel log records (see kmsg.rs), printed dmesg-style rather than as
+// key=value pairs.
+//
+// xml (`--xml-record <element>`): one record per <element>...</element>, which may span
+// lines; see xml.rs for how it maps to fields.
+
+use std::borrow::Cow;
+use std::io::{self, BufRead, BufReader};
+use std::sync::{Mutex, OnceLock};
+
+use crate::a11y;
+use crate::columns::Table;
+use crate::dns;
+use crate::encoded;
+use crate::enrich::{self, Enricher};
+use crate::geoip::GeoIp;
+use crate::humanize;
+use crate::kmsg;
+use crate::open::{STDIN, open_log};
+use crate::otlp;
+use crate::reclassify;
+use crate::select::{self, Condition};
+use crate::xml::{self, Scan};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    IisW3c,
+    CsvlogPostgres,
+    Kmsg,
+    Logcat,
+    Logfmt,
+    Xml,
+}
+
+pub fn parse_format(s: &str) -> Result<Format, String> {
+    match s {
+        "iis-w3c" => Ok(Format::IisW3c),
+        "csvlog-postgres" => Ok(Format::CsvlogPostgres),
+        "kmsg" => Ok(Format::Kmsg),
+        "logcat" => Ok(Format::Logcat),
+        "logfmt" => Ok(Format::Logfmt),
+        _ => Err(format!("expected iis-w3c, csvlog-postgres, kmsg, logcat or logfmt, got '{}'", s)),
+    }
+}
+
+// Columns IIS logs by default, used until a #Fields directive says otherwise
+const IIS_DEFAULT_FIELDS: &[&str] = &[
+    "date", "time", "s-ip", "cs-method", "cs-uri-stem", "cs-uri-query", "s-port",
+    "cs-username", "c-ip", "cs(User-Agent)", "cs(Referer)", "sc-status", "sc-substatus",
+    "sc-win32-status", "time-taken",
+];
+
+// csvlog columns as of PostgreSQL 14; older servers simply write fewer of them
+const POSTGRES_FIELDS: &[&str] = &[
+    "log_time", "user_name", "database_name", "process_id", "connection_from",
+    "session_id", "session_line_num", "command_tag", "session_start_time",
+    "virtual_transaction_id", "transaction_id", "error_severity", "sql_state_code",
+    "message", "detail", "hint", "internal_query", "internal_query_pos", "context",
+    "query", "query_pos", "location", "application_name", "backend_type", "leader_pid",
+    "query_id",
+];
+
+// Anything shorter isn't a csvlog line (every version writes at least these columns)
+const POSTGRES_MIN_FIELDS: usize = 22;
+
+// Give up on a record whose quotes or tags never balance rather than buffering forever
+const MAX_PENDING: usize = 1024 * 1024;
+
+#[derive(Clone, Debug, Default)]
+pub struct Record {
+    pub fields: Vec<(String, String)>,
+}
+
+impl Record {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+    }
+}
+
+pub enum Parsed {
+    Record(Record),
+    // Not a record; print this text as it is
+    Raw(String),
+    // Consumed without output (a directive, or part of a record still being collected)
+    Nothing,
+}
+
+pub struct Parser {
+    format: Format,
+    table: Option<Table>,
+    humanize: Vec<humanize::Rule>,
+    reclassify: Vec<reclassify::Rule>,
+    geoip: Option<GeoIp>,
+    enrichers: Vec<Enricher>,
+    // --fields and --where
+    shown: Vec<String>,
+    conditions: Vec<Condition>,
+    iis_fields: Vec<String>,
+    xml_element: String,
+    pending: String,
+}
+
+impl Parser {
+    pub fn new(format: Format) -> Parser {
+        Parser {
+            format,
+            table: None,
+            humanize: Vec::new(),
+            reclassify: Vec::new(),
+            geoip: None,
+            enrichers: Vec::new(),
+            shown: Vec::new(),
+            conditions: Vec::new(),
+            iis_fields: IIS_DEFAULT_FIELDS.iter().map(|s| s.to_string()).collect(),
+            xml_element: String::new(),
+            pending: String::new(),
+        }
+    }
+
+    // Pick up the directives at the top of the file, so the columns are known even when
+    // output starts after them
+    pub fn prime(&mut self, filename: &str) -> io::Result<()> {
+        // Standard input can only be read once, so its directives are picked up as they pass
+        if self.format != Format::IisW3c || filename == STDIN {
+            return Ok(());
+        }
+        let reader = BufReader::new(open_log(filename)?);
+        for line in reader.lines() {
+            let line = line?;
+            if !line.starts_with('#') {
+                break;
+            }
+            self.directive(&line);
+        }
+        Ok(())
+    }
+
+    // `line` includes its trailing newline
+    pub fn push(&mut self, line: &str) -> Parsed {
+        match self.format {
+            Format::IisW3c => self.push_iis(line),
+            Format::CsvlogPostgres => self.push_csvlog(line),
+            Format::Kmsg => self.push_kmsg(line),
+            Format::Xml => self.push_xml(line),
+            Format::Logcat => match parse_logcat(line) {
+                Some(record) => Parsed::Record(record),
+                None => Parsed::Raw(line.to_string()),
+            },
+            Format::Logfmt => match parse_logfmt(line) {
+                Some(record) => Parsed::Record(record),
+                None => Parsed::Raw(line.to_string()),
+            },
+        }
+    }
+
+    // Add the fields enrichers derive from the parsed ones
+    fn enrich(&self, record: &mut Record) {
+        for enricher in &self.enrichers {
+            enricher.enrich(record);
+        }
+        if let Some(geoip) = &self.geoip {
+            geoip.enrich(record);
+        }
+        encoded::expand(record);
+        dns::annotate(record);
+    }
+
+    fn render(&mut self, mut record: Record) -> String {
+        // The kmsg and logcat layouts need their fields, so a selection is key=value
+        let picked = !self.shown.is_empty();
+        if picked {
+            record = select::pick(&self.shown, record);
+        }
+        if JSON.get() == Some(&true) {
+            return render_json(&record);
+        }
+        humanize::apply(&self.humanize, &mut record);
+        if let Some(table) = &mut self.table {
+            table.render(&record)
+        } else if self.format == Format::Kmsg && !picked {
+            kmsg::render(&record)
+        } else if self.format == Format::Logcat && !picked {
+            render_logcat(&record)
+        } else {
+            render_logfmt(&record)
+        }
+    }
+
+    fn directive(&mut self, line: &str) {
+        if let Some(names) = line.trim_end().strip_prefix("#Fields:") {
+            self.iis_fields = names.split_whitespace().map(String::from).collect();
+        }
+    }
+
+    fn push_iis(&mut self, line: &str) -> Parsed {
+        let text = line.trim_end_matches(['\r', '\n']);
+        if text.starts_with('#') {
+            self.directive(text);
+            return Parsed::Nothing;
+        }
+        let values: Vec<&str> = text.split(' ').collect();
+        if values.len() != self.iis_fields.len() {
+            return Parsed::Raw(line.to_string());
+        }
+        let mut record = Record::default();
+        for (name, value) in self.iis_fields.iter().zip(values) {
+            // "-" is W3C for "no value"
+            if value != "-" {
+                record.fields.push((name.clone(), value.to_string()));
+            }
+        }
+        Parsed::Record(record)
+    }
+
+    fn push_csvlog(&mut self, line: &str) -> Parsed {
+        // A record starts with its timestamp; anything else arriving outside a record
+        // (e.g. the tail of one we started reading halfway through) isn't ours to parse
+        if self.pending.is_empty() && !line.starts_with(|c: char| c.is_ascii_digit()) {
+            return Parsed::Raw(line.to_string());
+        }
+        self.pending.push_str(line);
+        if self.pending.matches('"').count() % 2 == 1 {
+            if self.pending.len() > MAX_PENDING {
+                return Parsed::Raw(std::mem::take(&mut self.pending));
+            }
+            return Parsed::Nothing;
+        }
+
+        let text = std::mem::take(&mut self.pending);
+        let values = split_csv(text.trim_end_matches(['\r', '\n']));
+        if values.len() < POSTGRES_MIN_FIELDS {
+            return Parsed::Raw(text);
+        }
+        let mut record = Record::default();
+        for (i, value) in values.into_iter().enumerate() {
+            if value.is_empty() {
+                continue;
+            }
+            let name = match POSTGRES_FIELDS.get(i) {
+                Some(name) => name.to_string(),
+                None => format!("field{}", i + 1),
+            };
+            record.fields.push((name, value));
+        }
+        Parsed::Record(record)
+    }
+
+    fn push_kmsg(&mut self, line: &str) -> Parsed {
+        // Dictionary lines (" SUBSYSTEM=...") belong to the record before them; like
+        // dmesg, don't print them
+        if line.starts_with(' ') {
+            return Parsed::Nothing;
+        }
+        match kmsg::parse(line) {
+            Some(record) => Parsed::Record(record),
+            None => Parsed::Raw(line.to_string()),
+        }
+    }
+
+    fn push_xml(&mut self, line: &str) -> Parsed {
+        if self.pending.is_empty() {
+            let Some(start) = xml::find_start(line, &self.xml_element) else {
+                return Parsed::Raw(line.to_string());
+            };
+            self.pending.push_str(&line[start..]);
+        } else {
+            self.pending.push_str(line);
+        }
+        match xml::scan(&self.pending) {
+            Scan::Complete(record) => {
+                self.pending.clear();
+                Parsed::Record(record)
+            }
+            Scan::Incomplete if self.pending.len() <= MAX_PENDING => Parsed::Nothing,
+            Scan::Incomplete | Scan::Invalid => Parsed::Raw(std::mem::take(&mut self.pending)),
+        }
+    }
+}
+
+const LOGCAT_LEVELS: &[(&str, &str)] = &[
+    ("V", "verbose"), ("D", "debug"), ("I", "info"), ("W", "warn"), ("E", "error"),
+    ("F", "fatal"), ("A", "assert"),
+];
+
+// "MM-DD HH:MM:SS.mmm  PID  TID L TAG     : message"
+fn parse_logcat(line: &str) -> Option<Record> {
+    let text = line.trim_end_matches(['\r', '\n']);
+    let mut rest = text;
+    let mut next = || {
+        let trimmed = rest.trim_start();
+        let end = trimmed.find(' ').unwrap_or(trimmed.len());
+        let (word, tail) = trimmed.split_at(end);
+        rest = tail;
+        word
+    };
+    let (date, time, pid, tid, level) = (next(), next(), next(), next(), next());
+    let (tag, message) = rest.trim_start().split_once(": ").or_else(|| {
+        // An empty message leaves only the colon
+        rest.trim_start().strip_suffix(':').map(|tag| (tag, ""))
+    })?;
+    let level = LOGCAT_LEVELS.iter().find(|(c, _)| *c == level)?.1;
+    if date.len() != 5 || !time.contains(':') || pid.parse::<u32>().is_err() || tid.parse::<u32>().is_err() {
+        return None;
+    }
+
+    let mut record = Record::default();
+    for (name, value) in [("date", date), ("time", time), ("pid", pid), ("tid", tid), ("level", level)] {
+        record.fields.push((name.to_string(), value.to_string()));
+    }
+    record.fields.push(("tag".to_string(), tag.trim_end().to_string()));
+    record.fields.push(("message".to_string(), message.to_string()));
+    Some(record)
+}
+
+fn render_logcat(record: &Record) -> String {
+    let field = |name| record.get(name).unwrap_or("");
+    let level = LOGCAT_LEVELS.iter().find(|(_, l)| *l == field("level")).map_or("?", |(c, _)| c);
+    let text = format!(
+        "{} {} {:>5} {:>5} {} {}: {}",
+        field("date"), field("time"), field("pid"), field("tid"), level, field("tag"), field("message")
+    );
+    text.trim_end().to_string()
+}
+
+// key=value key2="quoted \"value\"" flag; None unless there is at least one key=value
+fn parse_logfmt(line: &str) -> Option<Record> {
+    let text = line.trim_end_matches(['\r', '\n']);
+    let mut record = Record::default();
+    let mut has_value = false;
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| !matches!(c, ' ' | '\t' | '=' | '"')) {
+            key.push(c);
+        }
+        if key.is_empty() {
+            // Nothing left, or something that isn't logfmt
+            return (chars.peek().is_none() && has_value).then_some(record);
+        }
+        let mut value = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            has_value = true;
+            if chars.next_if_eq(&'"').is_some() {
+                loop {
+                    match chars.next()? {
+                        '"' => break,
+                        '\\' => match chars.next()? {
+                            'n' => value.push('\n'),
+                            'r' => value.push('\r'),
+                            't' => value.push('\t'),
+                            c => value.push(c),
+                        },
+                        c => value.push(c),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| *c != ' ' && *c != '\t') {
+                    value.push(c);
+                }
+            }
+        }
+        record.fields.push((key, value));
+    }
+}
+
+// Split one CSV record; quoted fields may contain commas, newlines and "" escapes
+fn split_csv(text: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut value = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                value.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => values.push(std::mem::take(&mut value)),
+            _ => value.push(c),
+        }
+    }
+    values.push(value);
+    values
+}
+
+// key=value pairs, quoting values that would otherwise be ambiguous
+pub fn render_logfmt(record: &Record) -> String {
+    let mut out = String::new();
+    for (key, value) in &record.fields {
+        if !out.is_empty() {
+            out.push(' ');
+        }
+        out.push_str(key);
+        out.push('=');
+        if value.is_empty() || value.contains([' ', '"', '=', '\\']) || value.contains(char::is_control) {
+            out.push('"');
+            for c in value.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    '\n' => out.push_str("\\n"),
+                    '\r' => out.push_str("\\r"),
+                    '\t' => out.push_str("\\t"),
+                    _ => out.push(c),
+                }
+            }
+            out.push('"');
+        } else {
+            out.push_str(value);
+        }
+    }
+    out
+}
+
+pub fn render_json(record: &Record) -> String {
+    let mut out = String::from("{");
+    for (i, (key, value)) in record.fields.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        push_json_string(&mut out, key);
+        out.push(':');
+        push_json_string(&mut out, value);
+    }
+    out.push('}');
+    out
+}
+
+pub fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+static PARSER: Mutex<Option<Parser>> = Mutex::new(None);
+static JSON: OnceLock<bool> = OnceLock::new();
+
+pub fn set_format(format: Format) {
+    *PARSER.lock().unwrap() = Some(Parser::new(format));
+}
+
+// Read what the format needs from the start of the file (see Parser::prime)
+pub fn prime(filename: &str) {
+    if let Some(parser) = PARSER.lock().unwrap().as_mut()
+        && let Err(e) = parser.prime(filename)
+    {
+        eprintln!("Warning: Could not read the log header of '{}': {}", filename, e);
+    }
+}
+
+// Print records as an aligned table (see columns.rs)
+pub fn set_columns(max_width: Option<usize>) {
+    if let Some(parser) = PARSER.lock().unwrap().as_mut() {
+        parser.table = Some(Table::new(max_width));
+    }
+}
+
+pub fn add_humanize(rules: Vec<humanize::Rule>) {
+    if let Some(parser) = PARSER.lock().unwrap().as_mut() {
+        parser.humanize.extend(rules);
+    }
+}
+
+pub fn add_reclassify(rules: Vec<reclassify::Rule>) {
+    if let Some(parser) = PARSER.lock().unwrap().as_mut() {
+        parser.reclassify.extend(rules);
+    }
+}
+
+// `preset` keeps the format's default enrichers; `extra` are added after them
+pub fn set_enrichers(preset: bool, extra: Vec<Enricher>) {
+    if let Some(parser) = PARSER.lock().unwrap().as_mut() {
+        if preset {
+            parser.enrichers = enrich::preset(parser.format);
+        }
+        parser.enrichers.extend(extra);
+    }
+}
+
+// --fields: show only these, in this order
+pub fn set_shown(names: Vec<String>) {
+    if let Some(parser) = PARSER.lock().unwrap().as_mut() {
+        parser.shown = names;
+    }
+}
+
+// --where: show only records that meet all of these
+pub fn add_conditions(conditions: Vec<Condition>) {
+    if let Some(parser) = PARSER.lock().unwrap().as_mut() {
+        parser.conditions.extend(conditions);
+    }
+}
+
+// The element whose occurrences are the records of Format::Xml
+pub fn set_xml_element(element: &str) {
+    if let Some(parser) = PARSER.lock().unwrap().as_mut() {
+        parser.xml_element = element.to_string();
+    }
+}
+
+pub fn set_geoip(geoip: GeoIp) {
+    if let Some(parser) = PARSER.lock().unwrap().as_mut() {
+        parser.geoip = Some(geoip);
+    }
+}
+
+pub fn set_json(json: bool) {
+    let _ = JSON.set(json);
+}
+
+// The text to print for `line`, or None if it produced no output (yet). While lines are
+// forwarded (--forward), also the record the text was rendered from
+pub fn transform(line: &str) -> Option<(Cow<'_, str>, Option<Record>)> {
+    let mut parser = PARSER.lock().unwrap();
+    let Some(parser) = parser.as_mut() else {
+        return Some((Cow::Borrowed(line), None));
+    };
+    match parser.push(line) {
+        Parsed::Record(mut record) => {
+            reclassify::apply(&parser.reclassify, &mut record);
+            parser.enrich(&mut record);
+            if !select::meets(&parser.conditions, &record) {
+                return None;
+            }
+            let forwarded = otlp::enabled().then(|| record.clone());
+            let tag = a11y::enabled().then(|| a11y::level_tag(&record)).flatten();
+            let mut text = parser.render(record);
+            if let Some(tag) = tag {
+                text.insert_str(0, &tag);
+            }
+            text.push('\n');
+            Some((Cow::Owned(text), forwarded))
+        }
+        Parsed::Raw(text) => Some((Cow::Owned(text), None)),
+        Parsed::Nothing => None,
+    }
+}
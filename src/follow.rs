@@ -0,0 +1,293 @@
+// The decisions the follow loop makes from what it observes -- the file was rotated, it
+// was truncated, our handle stalled -- kept apart from the I/O that feeds them, so
+// `rail debug-replay` can run the same logic again over a --trace-events recording.
+//
+// The loop itself reaches the filesystem and the clock only through the Fs and Clock
+// traits below, so `rail simulate` can drive it against a simulated file whose writes,
+// rotations and truncations happen at exact points in simulated time.
+
+use std::fmt;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::open;
+use crate::report;
+use crate::watch::Watch;
+use crate::watchdog::Watchdog;
+
+// What the follow loop needs to know about the file at a path
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Stat {
+    pub len: u64,
+    // Nanoseconds since the epoch
+    pub modified: u128,
+}
+
+// Which file a path or handle refers to, for -F: device and inode on Unix, volume serial
+// number and file index on Windows, packed into one number
+pub type FileId = u128;
+
+pub trait Fs {
+    type File: Read + Seek;
+
+    fn open(&self, path: &str) -> io::Result<Self::File>;
+    fn stat(&self, path: &str) -> io::Result<Stat>;
+    // The size of the file behind an open handle, which may no longer be at the path
+    fn handle_len(&self, file: &Self::File) -> io::Result<u64>;
+    fn id(&self, path: &str) -> io::Result<FileId>;
+    fn handle_id(&self, file: &Self::File) -> io::Result<FileId>;
+    // Something to wait on for changes to the file, where the OS can tell us
+    fn watch(&self, _path: &str) -> Option<Watch> {
+        None
+    }
+}
+
+pub trait Clock {
+    // Time since some fixed start
+    fn now(&self) -> Duration;
+    fn sleep(&self, duration: Duration);
+    // True once the follow loop should return (the end of a simulation, or Ctrl+C with --report)
+    fn stopped(&self) -> bool {
+        false
+    }
+}
+
+pub struct RealFs;
+
+impl Fs for RealFs {
+    type File = File;
+
+    fn open(&self, path: &str) -> io::Result<File> {
+        open::open_log(path)
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Stat> {
+        let metadata = fs::metadata(path)?;
+        Ok(Stat { len: metadata.len(), modified: modified_ns(&metadata) })
+    }
+
+    fn handle_len(&self, file: &File) -> io::Result<u64> {
+        Ok(file.metadata()?.len())
+    }
+
+    #[cfg(unix)]
+    fn id(&self, path: &str) -> io::Result<FileId> {
+        Ok(unix_id(&fs::metadata(path)?))
+    }
+
+    #[cfg(windows)]
+    fn id(&self, path: &str) -> io::Result<FileId> {
+        self.handle_id(&open::open_log(path)?)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn id(&self, _path: &str) -> io::Result<FileId> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    #[cfg(unix)]
+    fn handle_id(&self, file: &File) -> io::Result<FileId> {
+        Ok(unix_id(&file.metadata()?))
+    }
+
+    #[cfg(windows)]
+    fn handle_id(&self, file: &File) -> io::Result<FileId> {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::fileapi::{BY_HANDLE_FILE_INFORMATION, GetFileInformationByHandle};
+
+        let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+        if unsafe { GetFileInformationByHandle(file.as_raw_handle() as _, &mut info) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let index = ((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64;
+        Ok(((info.dwVolumeSerialNumber as u128) << 64) | index as u128)
+    }
+
+    #[cfg(not(any(unix, windows)))]
+    fn handle_id(&self, _file: &File) -> io::Result<FileId> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn watch(&self, path: &str) -> Option<Watch> {
+        Watch::new(path)
+    }
+}
+
+#[cfg(unix)]
+fn unix_id(metadata: &Metadata) -> FileId {
+    use std::os::unix::fs::MetadataExt;
+    ((metadata.dev() as u128) << 64) | metadata.ino() as u128
+}
+
+pub struct RealClock;
+
+static START: OnceLock<Instant> = OnceLock::new();
+// -s: how long to wait between checks where the file is polled, in microseconds (0
+// until set); `rail ctl` can change it while following
+static POLL_INTERVAL: AtomicU64 = AtomicU64::new(0);
+
+impl Clock for RealClock {
+    // Since rail started, to the millisecond, so a trace records exactly what the
+    // decisions saw
+    fn now(&self) -> Duration {
+        Duration::from_millis(START.get_or_init(Instant::now).elapsed().as_millis() as u64)
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    // Interrupted while --report is on
+    fn stopped(&self) -> bool {
+        report::interrupted()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Decision {
+    Rotation,
+    Truncation,
+    Stall,
+    // -F: the path names a different file than our handle
+    Replaced,
+}
+
+impl Decision {
+    pub fn name(self) -> &'static str {
+        match self {
+            Decision::Rotation => "rotation",
+            Decision::Truncation => "truncation",
+            Decision::Stall => "stall",
+            Decision::Replaced => "replaced",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Decision> {
+        [Decision::Rotation, Decision::Truncation, Decision::Stall, Decision::Replaced]
+            .into_iter().find(|d| d.name() == name)
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+pub struct Follow {
+    // Where the next read starts
+    pub pos: u64,
+    // Nanoseconds since the epoch, as compared by the rotation check
+    last_modified: u128,
+    watchdog: Watchdog,
+}
+
+// A file's modification time in the form Follow compares; unknown times count as now
+fn modified_ns(metadata: &Metadata) -> u128 {
+    ns_since_epoch(metadata.modified().unwrap_or(SystemTime::now()))
+}
+
+// Seconds, fractions allowed ("0.5", "2")
+pub fn parse_interval(s: &str) -> Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs > 0.0 => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(format!("expected a number of seconds, got '{}'", s)),
+    }
+}
+
+pub fn set_poll_interval(interval: Duration) {
+    POLL_INTERVAL.store(interval.as_micros().max(1) as u64, Ordering::Relaxed);
+}
+
+pub fn poll_interval() -> Duration {
+    match POLL_INTERVAL.load(Ordering::Relaxed) {
+        0 => Duration::from_millis(100),
+        micros => Duration::from_micros(micros),
+    }
+}
+
+pub fn modified_ns_now() -> u128 {
+    ns_since_epoch(SystemTime::now())
+}
+
+fn ns_since_epoch(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos())
+}
+
+impl Follow {
+    pub fn new(pos: u64, modified: u128) -> Follow {
+        Follow { pos, last_modified: modified, watchdog: Watchdog::new() }
+    }
+
+    // Before reading, with the size and mtime of the file at the path. If the file
+    // changed and is now smaller than our position, it was probably rotated: start over
+    // on the new one
+    pub fn stat(&mut self, len: u64, modified: u128) -> Option<Decision> {
+        let changed = modified != self.last_modified;
+        self.last_modified = modified;
+        if changed && len < self.pos {
+            self.pos = 0;
+            return Some(Decision::Rotation);
+        }
+        None
+    }
+
+    // With -F, before reading after having caught up: the identity of the file at the path
+    // and of the one behind our handle. If they differ the path was given to a new file
+    // (once what is left behind the handle has been read, reading starts over on it)
+    pub fn identity(&mut self, path_id: FileId, handle_id: FileId) -> Option<Decision> {
+        if path_id != handle_id {
+            self.pos = 0;
+            return Some(Decision::Replaced);
+        }
+        None
+    }
+
+    pub fn read(&mut self, bytes: u64) {
+        self.pos += bytes;
+        if bytes > 0 {
+            self.watchdog.clear();
+        }
+    }
+
+    // After a read found nothing and we slept, with the size of the file at the path,
+    // the size of the file behind our handle and the clock's time. A path that shrank
+    // below our position was truncated if our handle shrank with it; if the handle still
+    // has everything we read, the path names a new file (a rename rotation)
+    pub fn idle(&mut self, len: u64, handle_len: u64, now: Duration) -> Option<Decision> {
+        if len < self.pos {
+            let decision = if handle_len >= self.pos { Decision::Rotation } else { Decision::Truncation };
+            self.pos = 0;
+            return Some(decision);
+        }
+        if self.watchdog.idle(self.pos, len, now) {
+            return Some(Decision::Stall);
+        }
+        None
+    }
+
+    // After reopening for a stall, with the size of the file behind the old handle: if
+    // nothing was left behind it, the path names a different file now. True if reading
+    // starts over
+    pub fn stall_reopened(&mut self, old_len: u64) -> bool {
+        let restart = old_len <= self.pos;
+        if restart {
+            self.pos = 0;
+        }
+        restart
+    }
+
+    // After reopening for a read fault, with the size of the reopened file. True if
+    // reading starts over
+    pub fn fault_reopened(&mut self, len: u64) -> bool {
+        let restart = len < self.pos;
+        if restart {
+            self.pos = 0;
+        }
+        restart
+    }
+}
@@ -0,0 +1,292 @@
+// `--geoip <file.mmdb> --geoip-field <field>`: look IP address fields up in MaxMind
+// databases (GeoLite2/GeoIP2 Country, City or ASN) and add what's found to the record
+// as <field>_country, <field>_city, <field>_asn and <field>_as_org.
+//
+// The reader implements just enough of the MaxMind DB format to do lookups: a binary
+// search tree over the address bits whose leaves point into a data section of typed,
+// self-describing values. See https://maxmind.github.io/MaxMind-DB/ for the spec.
+
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+
+use crate::fields::Record;
+
+const METADATA_MARKER: &[u8] = b"\xab\xcd\xefMaxMind.com";
+
+// Between the search tree and the data section
+const DATA_SEPARATOR: usize = 16;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Double(f64),
+    Bytes(Vec<u8>),
+    Uint(u128),
+    Int(i32),
+    Map(Vec<(String, Value)>),
+    Array(Vec<Value>),
+    Bool(bool),
+    Float(f32),
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    // Follow a path of map keys
+    fn path(&self, keys: &[&str]) -> Option<&Value> {
+        keys.iter().try_fold(self, |value, key| value.get(key))
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn as_uint(&self) -> Option<u128> {
+        match self {
+            Value::Uint(n) => Some(*n),
+            _ => None,
+        }
+    }
+}
+
+pub struct Database {
+    data: Vec<u8>,
+    node_count: usize,
+    record_size: usize,
+    ip_version: u128,
+    // Start of the data section
+    data_start: usize,
+    // Node reached after the 96 leading zero bits of an IPv4-mapped address
+    ipv4_start: usize,
+}
+
+impl Database {
+    pub fn open(path: &str) -> io::Result<Database> {
+        let data = fs::read(path)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, msg));
+
+        let marker = data
+            .windows(METADATA_MARKER.len())
+            .rposition(|w| w == METADATA_MARKER)
+            .ok_or_else(|| invalid("not a MaxMind DB file"))?;
+        let (metadata, _) = Decoder { data: &data, base: marker + METADATA_MARKER.len() }
+            .decode(marker + METADATA_MARKER.len())
+            .ok_or_else(|| invalid("unreadable metadata"))?;
+        let field = |name: &str| metadata.get(name).and_then(Value::as_uint);
+        let node_count = field("node_count").ok_or_else(|| invalid("metadata has no node_count"))? as usize;
+        let record_size = field("record_size").ok_or_else(|| invalid("metadata has no record_size"))? as usize;
+        let ip_version = field("ip_version").unwrap_or(6);
+        if ![24, 28, 32].contains(&record_size) {
+            return Err(invalid(&format!("unsupported record size {}", record_size)));
+        }
+
+        let tree_size = node_count * record_size * 2 / 8;
+        if tree_size + DATA_SEPARATOR > marker {
+            return Err(invalid("search tree runs past the end of the file"));
+        }
+        let mut db = Database {
+            data,
+            node_count,
+            record_size,
+            ip_version,
+            data_start: tree_size + DATA_SEPARATOR,
+            ipv4_start: 0,
+        };
+        if ip_version == 6 {
+            let mut node = 0;
+            for _ in 0..96 {
+                if node >= node_count {
+                    break;
+                }
+                node = db.record(node, 0);
+            }
+            db.ipv4_start = node;
+        }
+        Ok(db)
+    }
+
+    // The data record for `ip`, if the database has one
+    pub fn lookup(&self, ip: IpAddr) -> Option<Value> {
+        let (bits, mut node) = match ip {
+            IpAddr::V4(v4) => (u32::from(v4) as u128, self.ipv4_start),
+            IpAddr::V6(v6) if self.ip_version == 6 => (u128::from(v6), 0),
+            IpAddr::V6(v6) => ((v6.to_ipv4_mapped()?.to_bits()) as u128, 0),
+        };
+        let width = match ip {
+            IpAddr::V6(_) if self.ip_version == 6 => 128,
+            _ => 32,
+        };
+
+        for i in (0..width).rev() {
+            if node >= self.node_count {
+                break;
+            }
+            node = self.record(node, ((bits >> i) & 1) as usize);
+        }
+        if node <= self.node_count {
+            // node_count itself means "no data"
+            return None;
+        }
+        let offset = self.data_start + (node - self.node_count - DATA_SEPARATOR);
+        Decoder { data: &self.data, base: self.data_start }.decode(offset).map(|(value, _)| value)
+    }
+
+    // The left (0) or right (1) record of a search tree node
+    fn record(&self, node: usize, side: usize) -> usize {
+        let bytes = self.record_size * 2 / 8;
+        let at = node * bytes;
+        let b = |i: usize| self.data.get(at + i).copied().unwrap_or(0) as usize;
+        match (self.record_size, side) {
+            (24, 0) => (b(0) << 16) | (b(1) << 8) | b(2),
+            (24, _) => (b(3) << 16) | (b(4) << 8) | b(5),
+            (28, 0) => ((b(3) & 0xf0) << 20) | (b(0) << 16) | (b(1) << 8) | b(2),
+            (28, _) => ((b(3) & 0x0f) << 24) | (b(4) << 16) | (b(5) << 8) | b(6),
+            (_, 0) => (b(0) << 24) | (b(1) << 16) | (b(2) << 8) | b(3),
+            (_, _) => (b(4) << 24) | (b(5) << 16) | (b(6) << 8) | b(7),
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    // Where pointers are counted from
+    base: usize,
+}
+
+impl Decoder<'_> {
+    // The value at `offset` and the offset just past it
+    fn decode(&self, offset: usize) -> Option<(Value, usize)> {
+        let ctrl = *self.data.get(offset)?;
+        let mut pos = offset + 1;
+        let mut kind = ctrl >> 5;
+        if kind == 1 {
+            // Pointer: decode the value it points at, but carry on after the pointer
+            let size = ((ctrl >> 3) & 0x3) as usize;
+            let low = (ctrl & 0x7) as usize;
+            let bytes = self.data.get(pos..pos + size + 1)?;
+            let n = bytes.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize);
+            let target = match size {
+                0 => (low << 8) | n,
+                1 => ((low << 16) | n) + 2048,
+                2 => ((low << 24) | n) + 526336,
+                _ => n,
+            };
+            // The spec doesn't allow pointers to pointers; refusing them also rules out loops
+            if self.data.get(self.base + target)? >> 5 == 1 {
+                return None;
+            }
+            let (value, _) = self.decode(self.base + target)?;
+            return Some((value, pos + size + 1));
+        }
+        if kind == 0 {
+            kind = 7 + *self.data.get(pos)?;
+            pos += 1;
+        }
+
+        let mut size = (ctrl & 0x1f) as usize;
+        if size >= 29 {
+            let extra = size - 28;
+            let bytes = self.data.get(pos..pos + extra)?;
+            let n = bytes.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize);
+            size = match extra {
+                1 => 29 + n,
+                2 => 285 + n,
+                _ => 65821 + n,
+            };
+            pos += extra;
+        }
+
+        let take = |pos: usize, len: usize| self.data.get(pos..pos + len);
+        let uint = |pos: usize, len: usize| -> Option<u128> {
+            Some(take(pos, len)?.iter().fold(0u128, |acc, &b| (acc << 8) | b as u128))
+        };
+        match kind {
+            2 => Some((Value::String(String::from_utf8_lossy(take(pos, size)?).into_owned()), pos + size)),
+            3 => Some((Value::Double(f64::from_be_bytes(take(pos, 8)?.try_into().ok()?)), pos + 8)),
+            4 => Some((Value::Bytes(take(pos, size)?.to_vec()), pos + size)),
+            5 | 6 | 9 | 10 => Some((Value::Uint(uint(pos, size)?), pos + size)),
+            8 => Some((Value::Int(uint(pos, size)? as u32 as i32), pos + size)),
+            7 => {
+                let mut entries = Vec::with_capacity(size.min(64));
+                for _ in 0..size {
+                    let (key, next) = self.decode(pos)?;
+                    let (value, next) = self.decode(next)?;
+                    entries.push((key.as_str()?.to_string(), value));
+                    pos = next;
+                }
+                Some((Value::Map(entries), pos))
+            }
+            11 => {
+                let mut items = Vec::with_capacity(size.min(64));
+                for _ in 0..size {
+                    let (value, next) = self.decode(pos)?;
+                    items.push(value);
+                    pos = next;
+                }
+                Some((Value::Array(items), pos))
+            }
+            14 => Some((Value::Bool(size != 0), pos)),
+            15 => Some((Value::Float(f32::from_be_bytes(take(pos, 4)?.try_into().ok()?)), pos + 4)),
+            _ => None,
+        }
+    }
+}
+
+pub struct GeoIp {
+    databases: Vec<Database>,
+    fields: Vec<String>,
+}
+
+impl GeoIp {
+    pub fn new(databases: Vec<Database>, fields: Vec<String>) -> GeoIp {
+        GeoIp { databases, fields }
+    }
+
+    pub fn enrich(&self, record: &mut Record) {
+        for field in &self.fields {
+            let Some(ip) = record.get(field).and_then(parse_ip) else {
+                continue;
+            };
+            let mut added = Vec::new();
+            for db in &self.databases {
+                let Some(data) = db.lookup(ip) else {
+                    continue;
+                };
+                let country = data.path(&["country", "iso_code"]).or_else(|| data.path(&["registered_country", "iso_code"]));
+                if let Some(code) = country.and_then(Value::as_str) {
+                    added.push((format!("{}_country", field), code.to_string()));
+                }
+                if let Some(city) = data.path(&["city", "names", "en"]).and_then(Value::as_str) {
+                    added.push((format!("{}_city", field), city.to_string()));
+                }
+                if let Some(asn) = data.get("autonomous_system_number").and_then(Value::as_uint) {
+                    added.push((format!("{}_asn", field), format!("AS{}", asn)));
+                }
+                if let Some(org) = data.get("autonomous_system_organization").and_then(Value::as_str) {
+                    added.push((format!("{}_as_org", field), org.to_string()));
+                }
+            }
+            record.fields.extend(added);
+        }
+    }
+}
+
+// Accepts bare addresses and the "1.2.3.4:port" / "[::1]:port" forms access logs use
+fn parse_ip(text: &str) -> Option<IpAddr> {
+    if let Ok(ip) = text.parse() {
+        return Some(ip);
+    }
+    if let Some(rest) = text.strip_prefix('[') {
+        return rest.split_once(']')?.0.parse().ok();
+    }
+    text.rsplit_once(':')?.0.parse().ok()
+}
@@ -0,0 +1,128 @@
+// Glob patterns among the file arguments: `rail -f "logs/*.log"` follows every match,
+// the pattern quoted so the shell leaves it to rail (cmd.exe never expands one). * and ?
+// match within a path component, [abc], [a-z] and [!a-z] one character of a set; a
+// name starting with a dot is only matched by a pattern that does too, as in the shell.
+// Any component may have wildcards (logs/*/app.log). Matches are files, in name order.
+//
+// With -f the patterns are expanded again every RESCAN, and files that have appeared
+// since are followed too, from their first line. An argument that names an existing
+// file is that file, wildcards or not.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+pub const RESCAN: Duration = Duration::from_secs(1);
+
+pub fn is_pattern(arg: &str) -> bool {
+    has_wildcards(arg) && !Path::new(arg).exists()
+}
+
+fn has_wildcards(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+// The files `pattern` matches, sorted
+pub fn expand(pattern: &str) -> Vec<String> {
+    let mut paths = vec![PathBuf::new()];
+    for component in Path::new(pattern).components() {
+        match component {
+            Component::Normal(name) if has_wildcards(&name.to_string_lossy()) => {
+                let name: Vec<char> = name.to_string_lossy().chars().collect();
+                paths = paths.iter().flat_map(|dir| matching(dir, &name)).collect();
+            }
+            _ => paths.iter_mut().for_each(|path| path.push(component)),
+        }
+    }
+    let mut files: Vec<String> = paths
+        .into_iter()
+        .filter(|path| path.is_file())
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    files
+}
+
+// Every pattern's matches, each file once
+pub fn expand_all(patterns: &[String]) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    for file in patterns.iter().flat_map(|pattern| expand(pattern)) {
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+    files
+}
+
+// The entries of `dir` whose name matches `pattern`
+fn matching(dir: &Path, pattern: &[char]) -> Vec<PathBuf> {
+    let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let Ok(entries) = fs::read_dir(listed) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| {
+            let name: Vec<char> = entry.file_name().to_string_lossy().chars().collect();
+            (name.first() != Some(&'.') || pattern.first() == Some(&'.')) && matches(pattern, &name)
+        })
+        .map(|entry| dir.join(entry.file_name()))
+        .collect()
+}
+
+fn matches(pattern: &[char], name: &[char]) -> bool {
+    // Where to go back to when what follows the last * stops matching
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(1),
+            Some('[') => set(&pattern[p..], name[n]),
+            Some(&c) => (c == name[n]).then_some(1),
+            None => None,
+        };
+        match (step, star) {
+            (Some(len), _) => {
+                p += len;
+                n += 1;
+            }
+            // Let the last * take one more character and try again
+            (None, Some((after, taken))) => {
+                star = Some((after, taken + 1));
+                p = after;
+                n = taken + 1;
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+// How long the [...] set at the start of `pattern` is, if `c` is in it; a [ without a
+// closing ] is just a [
+fn set(pattern: &[char], c: char) -> Option<usize> {
+    let negated = matches!(pattern.get(1), Some('!' | '^'));
+    let first = if negated { 2 } else { 1 };
+    // A ] right after the [ is one of the set
+    let Some(close) = pattern.iter().skip(first + 1).position(|ch| *ch == ']').map(|i| i + first + 1) else {
+        return (c == '[').then_some(1);
+    };
+    let items = &pattern[first..close];
+    let mut found = false;
+    let mut i = 0;
+    while i < items.len() {
+        if items.get(i + 1) == Some(&'-') && i + 2 < items.len() {
+            found |= (items[i]..=items[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= items[i] == c;
+            i += 1;
+        }
+    }
+    (found != negated).then_some(close + 1)
+}
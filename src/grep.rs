@@ -0,0 +1,38 @@
+// `--grep <regex>`: only show lines that match; given more than once, lines that match
+// any of the patterns. Filtering happens inside rail, so rotation notices and the
+// --flush policy work as usual, which piping into grep would upset.
+//
+// `--exclude <regex>` (or --grep-v) is the opposite: lines that match are dropped, e.g.
+// health checks in an access log. It's repeatable too, and wins over --grep.
+//
+// As with `tail | grep`, the starting lines (-n) are picked first and then filtered.
+// Lines are matched as they would be printed (after --sub, --format, ...), and lines
+// left out aren't seen by --fail-on, --forward or the other options downstream either.
+
+use std::sync::Mutex;
+
+use crate::regex::Regex;
+
+static PATTERNS: Mutex<Vec<Regex>> = Mutex::new(Vec::new());
+static EXCLUDED: Mutex<Vec<Regex>> = Mutex::new(Vec::new());
+
+pub fn add(pattern: Regex) {
+    PATTERNS.lock().unwrap().push(pattern);
+}
+
+pub fn exclude(pattern: Regex) {
+    EXCLUDED.lock().unwrap().push(pattern);
+}
+
+pub fn enabled() -> bool {
+    !PATTERNS.lock().unwrap().is_empty() || !EXCLUDED.lock().unwrap().is_empty()
+}
+
+pub fn matches(line: &str) -> bool {
+    let text = line.trim_end_matches('\n');
+    if EXCLUDED.lock().unwrap().iter().any(|p| p.is_match(text)) {
+        return false;
+    }
+    let patterns = PATTERNS.lock().unwrap();
+    patterns.is_empty() || patterns.iter().any(|p| p.is_match(text))
+}
@@ -0,0 +1,92 @@
+// `--highlight <regex>`: show the matches in colour, the rest of the line as it is; given
+// more than once, every pattern's matches are. Colour is only used on a terminal (and
+// not with NO_COLOR or --a11y), so a highlighted run piped into a file stays plain.
+//
+// `--highlight-color` picks the colour: black, red, green, yellow, blue, magenta, cyan
+// or white, bright-<name>, bold-<name>, or a 256-colour palette number. The default is
+// bold red, as in grep. Matches are found in the line as printed (after --compact and
+// --json-pretty).
+
+use std::sync::{Mutex, OnceLock};
+
+use crate::output;
+use crate::regex::Regex;
+
+const NAMES: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+const DEFAULT: &str = "1;31";
+
+static PATTERNS: Mutex<Vec<Regex>> = Mutex::new(Vec::new());
+static COLOR: OnceLock<String> = OnceLock::new();
+
+// The SGR parameters for a colour, e.g. "bright-cyan" -> "96"
+pub fn parse_color(s: &str) -> Result<String, String> {
+    if let Ok(n) = s.parse::<u8>() {
+        return Ok(format!("38;5;{}", n));
+    }
+    let (base, name) = match s.split_once('-') {
+        Some(("bright", name)) => (90, name),
+        Some(("bold", name)) => (30, name),
+        _ => (30, s),
+    };
+    let Some(index) = NAMES.iter().position(|n| *n == name) else {
+        return Err(format!("expected a colour name like red, bright-red or bold-red, or 0-255, got '{}'", s));
+    };
+    let bold = if s.starts_with("bold-") { "1;" } else { "" };
+    Ok(format!("{}{}", bold, base + index))
+}
+
+pub fn add(pattern: Regex) {
+    PATTERNS.lock().unwrap().push(pattern);
+}
+
+pub fn enabled() -> bool {
+    !PATTERNS.lock().unwrap().is_empty()
+}
+
+pub fn set_color(sgr: String) {
+    let _ = COLOR.set(sgr);
+}
+
+pub fn transform(line: String) -> String {
+    let patterns = PATTERNS.lock().unwrap();
+    if patterns.is_empty() || !output::color_wanted() {
+        return line;
+    }
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    for pattern in patterns.iter() {
+        let mut pos = 0;
+        while pos < line.len() {
+            let Some(groups) = pattern.captures_at(&line, pos) else {
+                break;
+            };
+            let (start, end) = groups[0].unwrap();
+            if end > start && !line[start..end].contains('\n') {
+                spans.push((start, end));
+            }
+            // Step past an empty match, to the next character
+            pos = if end > start { end } else { end + line[end..].chars().next().map_or(1, char::len_utf8) };
+        }
+    }
+    if spans.is_empty() {
+        return line;
+    }
+    // Where patterns' matches overlap, colour their union once
+    spans.sort();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    let color = COLOR.get().map_or(DEFAULT, |c| c.as_str());
+    let mut out = String::with_capacity(line.len() + merged.len() * 12);
+    let mut copied = 0;
+    for (start, end) in merged {
+        out.push_str(&line[copied..start]);
+        out.push_str(&format!("\x1b[{}m{}\x1b[0m", color, &line[start..end]));
+        copied = end;
+    }
+    out.push_str(&line[copied..]);
+    out
+}
@@ -0,0 +1,99 @@
+// `--humanize <kind>:<field>,...`: show raw numbers in parsed records as sizes and
+// durations ("1.4 MiB", "2.3 s") in the human-readable output. JSON output keeps the
+// original values, since whatever reads it wants numbers it can compute with.
+//
+// Kinds: bytes, duration_s, duration_ms, duration_us. Values that aren't numbers are
+// left as they are.
+
+use crate::fields::Record;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Bytes,
+    // Duration, with the number of microseconds per unit
+    Duration(f64),
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    kind: Kind,
+    field: String,
+}
+
+pub fn parse_rules(spec: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    for item in spec.split(',') {
+        let (kind, field) = item
+            .split_once(':')
+            .ok_or(format!("expected <kind>:<field>, got '{}'", item))?;
+        let kind = match kind {
+            "bytes" => Kind::Bytes,
+            "duration_s" => Kind::Duration(1_000_000.0),
+            "duration_ms" => Kind::Duration(1_000.0),
+            "duration_us" => Kind::Duration(1.0),
+            _ => {
+                return Err(format!(
+                    "unknown kind '{}' (expected bytes, duration_s, duration_ms or duration_us)",
+                    kind
+                ))
+            }
+        };
+        if field.is_empty() {
+            return Err(format!("missing field name in '{}'", item));
+        }
+        rules.push(Rule { kind, field: field.to_string() });
+    }
+    Ok(rules)
+}
+
+pub fn apply(rules: &[Rule], record: &mut Record) {
+    for (name, value) in &mut record.fields {
+        let Some(rule) = rules.iter().find(|r| &r.field == name) else {
+            continue;
+        };
+        let Ok(n) = value.parse::<f64>() else {
+            continue;
+        };
+        *value = match rule.kind {
+            Kind::Bytes => bytes(n),
+            Kind::Duration(us_per_unit) => duration(n * us_per_unit),
+        };
+    }
+}
+
+pub fn bytes(n: f64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = n;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", n)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn duration(us: f64) -> String {
+    let secs = us / 1_000_000.0;
+    if us.abs() < 1_000.0 {
+        format!("{} µs", round(us))
+    } else if us.abs() < 1_000_000.0 {
+        format!("{} ms", round(us / 1_000.0))
+    } else if secs.abs() < 60.0 {
+        format!("{:.1} s", secs)
+    } else if secs.abs() < 3600.0 {
+        let whole = secs.round() as i64;
+        format!("{}m {:02}s", whole / 60, whole % 60)
+    } else {
+        let minutes = (secs / 60.0).round() as i64;
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    }
+}
+
+// Whole numbers as they are, others to one decimal place
+fn round(n: f64) -> String {
+    if n.fract() == 0.0 { format!("{}", n) } else { format!("{:.1}", n) }
+}
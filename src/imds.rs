@@ -0,0 +1,86 @@
+// The cloud availability zone rail runs in, from the instance metadata service the
+// big clouds serve at 169.254.169.254 (link-local, so it never leaves the machine):
+//
+//   AWS    PUT /latest/api/token, then GET /latest/meta-data/placement/availability-zone
+//   GCP    GET /computeMetadata/v1/instance/zone (projects/<n>/zones/<zone>)
+//   Azure  GET /metadata/instance/compute/location and .../zone, as "<location>-<zone>"
+//
+// Off a cloud nothing answers at that address, and the connect gives up after
+// CONNECT_TIMEOUT; the zone is then unknown.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+const ADDRESS: ([u8; 4], u16) = ([169, 254, 169, 254], 80);
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(300);
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+pub fn availability_zone() -> Option<String> {
+    aws().or_else(gcp).or_else(azure)
+}
+
+fn aws() -> Option<String> {
+    let token = request("PUT", "/latest/api/token", &["X-aws-ec2-metadata-token-ttl-seconds: 60"]).ok()?;
+    let header = format!("X-aws-ec2-metadata-token: {}", token);
+    request("GET", "/latest/meta-data/placement/availability-zone", &[&header]).ok()
+}
+
+fn gcp() -> Option<String> {
+    let zone = request("GET", "/computeMetadata/v1/instance/zone", &["Metadata-Flavor: Google"]).ok()?;
+    zone.rsplit('/').next().map(str::to_string)
+}
+
+fn azure() -> Option<String> {
+    let get = |what: &str| {
+        let path = format!("/metadata/instance/compute/{}?api-version=2021-02-01&format=text", what);
+        request("GET", &path, &["Metadata: true"]).ok()
+    };
+    let location = get("location")?;
+    // VMs not placed in a zone have an empty one
+    match get("zone") {
+        Some(zone) if !zone.is_empty() => Some(format!("{}-{}", location, zone)),
+        _ => Some(location),
+    }
+}
+
+// The body of a 200 response, trimmed
+fn request(method: &str, path: &str, headers: &[&str]) -> io::Result<String> {
+    let mut stream = TcpStream::connect_timeout(&SocketAddr::from(ADDRESS), CONNECT_TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut request = format!("{} {} HTTP/1.1\r\nHost: 169.254.169.254\r\nConnection: close\r\nContent-Length: 0\r\n", method, path);
+    for header in headers {
+        request.push_str(header);
+        request.push_str("\r\n");
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n").unwrap_or((&response, ""));
+    if head.split(' ').nth(1) != Some("200") {
+        return Err(io::Error::other(format!("metadata service answered '{}'", head.lines().next().unwrap_or(""))));
+    }
+    if head.to_ascii_lowercase().contains("transfer-encoding: chunked") {
+        return Ok(unchunk(body).trim().to_string());
+    }
+    Ok(body.trim().to_string())
+}
+
+fn unchunk(body: &str) -> String {
+    let mut out = String::new();
+    let mut rest = body;
+    while let Some((size, after)) = rest.split_once("\r\n") {
+        let Ok(size) = usize::from_str_radix(size.trim(), 16) else {
+            break;
+        };
+        if size == 0 || after.len() < size {
+            break;
+        }
+        out.push_str(&after[..size]);
+        rest = after[size..].trim_start_matches("\r\n");
+    }
+    out
+}
@@ -0,0 +1,300 @@
+// Sidecar line index (`<file>.railidx`) so `-n` and `--since` on huge files don't need a
+// full scan.
+//
+// The index records the byte offset of every STRIDE-th line, and with it the latest time
+// a line before it starts with (see since.rs), so that times only go up from checkpoint
+// to checkpoint even if a few lines are logged out of order. On open it is validated
+// against the log (head fingerprint + checkpoint sanity) and then extended by scanning
+// only the bytes appended since the last checkpoint. In follow mode new checkpoints are
+// appended to the sidecar as lines arrive.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+
+use crate::open::open_log;
+use crate::since;
+
+const MAGIC: &str = "railidx 2";
+const STRIDE: u64 = 1024;
+const FINGERPRINT_LEN: u64 = 4096;
+
+pub struct LineIndex {
+    index_path: String,
+    // checkpoints[k] is the byte offset where line k * STRIDE starts, and times[k] the
+    // latest time of the lines before it
+    checkpoints: Vec<u64>,
+    times: Vec<Option<u128>>,
+    // The latest time scanned so far, and the start of the line being scanned
+    latest: Option<u128>,
+    head: Vec<u8>,
+    // Bytes of the log that have been scanned so far
+    scanned_len: u64,
+    // Complete (newline-terminated) lines within scanned_len
+    complete_lines: u64,
+    // Offset just past the last newline scanned
+    newline_end: u64,
+    fingerprint_len: u64,
+    fingerprint: u64,
+    // Number of checkpoints already written to the sidecar
+    saved: usize,
+}
+
+pub fn index_path(filename: &str) -> String {
+    format!("{}.railidx", filename)
+}
+
+impl LineIndex {
+    // Load the sidecar for `filename` (rebuilding it if missing or stale) and bring it
+    // up to date with the current end of the file.
+    pub fn open(filename: &str) -> io::Result<LineIndex> {
+        let mut file = open_log(filename)?;
+        let file_len = file.metadata()?.len();
+        let index_path = index_path(filename);
+
+        let mut index = match Self::load(&index_path) {
+            Some(index) if index.is_valid_for(&mut file, file_len)? => index,
+            _ => Self::empty(index_path, &mut file, file_len)?,
+        };
+
+        // Resume scanning from the last checkpoint
+        let last = *index.checkpoints.last().unwrap();
+        index.complete_lines = (index.checkpoints.len() as u64 - 1) * STRIDE;
+        index.scanned_len = last;
+        index.newline_end = last;
+        index.latest = *index.times.last().unwrap();
+        index.head.clear();
+        file.seek(SeekFrom::Start(last))?;
+        let mut reader = BufReader::with_capacity(64 * 1024, file);
+        loop {
+            let chunk = reader.fill_buf()?;
+            if chunk.is_empty() {
+                break;
+            }
+            let len = chunk.len();
+            index.advance(chunk);
+            reader.consume(len);
+        }
+
+        // Upgrade a fingerprint taken while the file was still short
+        if index.fingerprint_len < FINGERPRINT_LEN && file_len > index.fingerprint_len {
+            let mut file = open_log(filename)?;
+            let (len, hash) = fingerprint(&mut file, file_len)?;
+            index.fingerprint_len = len;
+            index.fingerprint = hash;
+            index.saved = 0;
+        }
+
+        index.save()?;
+        Ok(index)
+    }
+
+    fn empty(index_path: String, file: &mut File, file_len: u64) -> io::Result<LineIndex> {
+        let (fingerprint_len, fingerprint) = fingerprint(file, file_len)?;
+        Ok(LineIndex {
+            index_path,
+            checkpoints: vec![0],
+            times: vec![None],
+            latest: None,
+            head: Vec::new(),
+            scanned_len: 0,
+            complete_lines: 0,
+            newline_end: 0,
+            fingerprint_len,
+            fingerprint,
+            saved: 0,
+        })
+    }
+
+    fn load(index_path: &str) -> Option<LineIndex> {
+        let contents = fs::read_to_string(index_path).ok()?;
+        let mut lines = contents.lines();
+        if lines.next()? != MAGIC {
+            return None;
+        }
+        let stride: u64 = lines.next()?.strip_prefix("stride ")?.parse().ok()?;
+        if stride != STRIDE {
+            return None;
+        }
+        let mut parts = lines.next()?.strip_prefix("fingerprint ")?.split(' ');
+        let fingerprint_len = parts.next()?.parse().ok()?;
+        let fingerprint = u64::from_str_radix(parts.next()?, 16).ok()?;
+
+        let mut checkpoints = Vec::new();
+        let mut times = Vec::new();
+        for line in lines {
+            let (offset, time) = line.split_once(' ')?;
+            let offset: u64 = offset.parse().ok()?;
+            let time = if time == "-" { None } else { Some(time.parse::<u128>().ok()?) };
+            if checkpoints.last().is_some_and(|&prev| offset <= prev) || times.last().is_some_and(|&prev| time < prev) {
+                return None;
+            }
+            checkpoints.push(offset);
+            times.push(time);
+        }
+        if checkpoints.first() != Some(&0) {
+            return None;
+        }
+
+        let saved = checkpoints.len();
+        Some(LineIndex {
+            index_path: index_path.to_string(),
+            checkpoints,
+            times,
+            latest: None,
+            head: Vec::new(),
+            scanned_len: 0,
+            complete_lines: 0,
+            newline_end: 0,
+            fingerprint_len,
+            fingerprint,
+            saved,
+        })
+    }
+
+    fn is_valid_for(&self, file: &mut File, file_len: u64) -> io::Result<bool> {
+        if file_len < self.fingerprint_len {
+            return Ok(false);
+        }
+        if fingerprint(file, self.fingerprint_len)? != (self.fingerprint_len, self.fingerprint) {
+            return Ok(false);
+        }
+
+        // The last checkpoint must still be inside the file and start a line
+        let last = *self.checkpoints.last().unwrap();
+        if last > file_len {
+            return Ok(false);
+        }
+        if last > 0 {
+            let mut byte = [0u8; 1];
+            file.seek(SeekFrom::Start(last - 1))?;
+            file.read_exact(&mut byte)?;
+            if byte[0] != b'\n' {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Feed bytes that follow everything scanned so far
+    pub fn advance(&mut self, data: &[u8]) {
+        let mut pos = self.scanned_len;
+        let mut rest = data;
+        while !rest.is_empty() {
+            let end = rest.iter().position(|&b| b == b'\n');
+            let (piece, after) = rest.split_at(end.map_or(rest.len(), |i| i + 1));
+            let wanted = since::TIME_PREFIX.saturating_sub(self.head.len()).min(piece.len());
+            self.head.extend_from_slice(&piece[..wanted]);
+            pos += piece.len() as u64;
+            rest = after;
+            if end.is_none() {
+                break;
+            }
+            self.latest = self.latest.max(since::line_time(&self.head));
+            self.head.clear();
+            self.complete_lines += 1;
+            self.newline_end = pos;
+            if self.complete_lines.is_multiple_of(STRIDE) {
+                self.checkpoints.push(self.newline_end);
+                self.times.push(self.latest);
+            }
+        }
+        self.scanned_len += data.len() as u64;
+    }
+
+    pub fn scanned_len(&self) -> u64 {
+        self.scanned_len
+    }
+
+    // Offset of the first of the last `num_lines` lines of the file
+    pub fn offset_of_last(&self, num_lines: usize, file: &mut File) -> io::Result<u64> {
+        let partial = self.scanned_len > self.newline_end;
+        let total = self.complete_lines + partial as u64;
+        self.offset_of_line(total.saturating_sub(num_lines as u64), file)
+    }
+
+    // Offset where line `start` (0-based) begins, or the end of the file if there are
+    // fewer lines
+    pub fn offset_of_line(&self, start: u64, file: &mut File) -> io::Result<u64> {
+        let k = ((start / STRIDE) as usize).min(self.checkpoints.len() - 1);
+        let mut offset = self.checkpoints[k];
+        let mut to_skip = start - k as u64 * STRIDE;
+
+        file.seek(SeekFrom::Start(offset))?;
+        let mut reader = BufReader::new(file);
+        let mut line = Vec::new();
+        while to_skip > 0 {
+            line.clear();
+            let n = reader.read_until(b'\n', &mut line)?;
+            if n == 0 {
+                break;
+            }
+            offset += n as u64;
+            to_skip -= 1;
+        }
+        Ok(offset)
+    }
+
+    // Offset of the first line with a time of `since` or later (see since.rs), or the end
+    // of the file
+    pub fn offset_since(&self, since: u128, file: &mut File) -> io::Result<u64> {
+        // Every line before checkpoint k is older than `since`
+        let k = self.times.partition_point(|t| t.is_none_or(|t| t < since)) - 1;
+        since::find(file, self.checkpoints[k], self.times[k], since)
+    }
+
+    // Forget everything (the file was truncated or replaced) and start over
+    pub fn reset(&mut self, filename: &str) -> io::Result<()> {
+        let mut file = open_log(filename)?;
+        *self = Self::empty(self.index_path.clone(), &mut file, 0)?;
+        self.save()
+    }
+
+    // Write any checkpoints not yet in the sidecar, rewriting it when the header changed
+    pub fn save(&mut self) -> io::Result<()> {
+        if self.saved > 0 && self.saved == self.checkpoints.len() {
+            return Ok(());
+        }
+
+        if self.saved == 0 {
+            let tmp_path = format!("{}.tmp", self.index_path);
+            let mut out = io::BufWriter::new(File::create(&tmp_path)?);
+            writeln!(out, "{}", MAGIC)?;
+            writeln!(out, "stride {}", STRIDE)?;
+            writeln!(out, "fingerprint {} {:016x}", self.fingerprint_len, self.fingerprint)?;
+            for (offset, time) in self.checkpoints.iter().zip(&self.times) {
+                writeln!(out, "{} {}", offset, time_text(*time))?;
+            }
+            out.flush()?;
+            drop(out);
+            fs::rename(&tmp_path, &self.index_path)?;
+        } else {
+            let mut out = OpenOptions::new().append(true).open(&self.index_path)?;
+            let mut new = String::new();
+            for (offset, time) in self.checkpoints.iter().zip(&self.times).skip(self.saved) {
+                new.push_str(&format!("{} {}\n", offset, time_text(*time)));
+            }
+            out.write_all(new.as_bytes())?;
+        }
+
+        self.saved = self.checkpoints.len();
+        Ok(())
+    }
+}
+
+fn time_text(time: Option<u128>) -> String {
+    time.map_or("-".to_string(), |time| time.to_string())
+}
+
+// FNV-1a over the first (up to) FINGERPRINT_LEN bytes, so a replaced file is detected
+fn fingerprint(file: &mut File, limit: u64) -> io::Result<(u64, u64)> {
+    let mut head = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.take(limit.min(FINGERPRINT_LEN)).read_to_end(&mut head)?;
+
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for &b in &head {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    Ok((head.len() as u64, hash))
+}
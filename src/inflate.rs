@@ -0,0 +1,306 @@
+// DEFLATE (RFC 1951) and the gzip header around it, which std doesn't have:
+// --expand-encoded inflates gzip blobs in a field with it, and gzip input is read
+// through it.
+//
+// Compressed bytes come from an Input one at a time, so a source can be a slice or a
+// file that is still being written. Output goes to `out`, which back-references read
+// from; whoever drains it has to leave the last WINDOW bytes.
+
+use crate::compressed::Input;
+
+const WINDOW: usize = 32 * 1024;
+// Trailer of a gzip member: CRC-32 and size
+const TRAILER: usize = 8;
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+    131, 163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// The order code length code lengths are sent in
+const CLEN_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+// Gzip members one after another, as zcat reads them
+pub fn gunzip<I: Input>(input: &mut I, out: &mut Vec<u8>) {
+    input.window(WINDOW);
+    let mut inflater = Inflater { input: &mut *input, out: std::mem::take(out), acc: 0, count: 0 };
+    while inflater.gzip_header().is_some()
+        && inflater.inflate(usize::MAX).is_some()
+        && (0..TRAILER).all(|_| inflater.byte().is_some())
+    {
+        inflater.input.boundary();
+    }
+    *out = inflater.out;
+}
+
+pub struct Inflater<I> {
+    pub input: I,
+    pub out: Vec<u8>,
+    acc: u32,
+    count: u32,
+}
+
+impl<I: Input> Inflater<I> {
+    pub fn new(input: I) -> Inflater<I> {
+        Inflater { input, out: Vec::new(), acc: 0, count: 0 }
+    }
+
+    // The next whole byte, the rest of a partly read one dropped: what follows a stream
+    // (gzip's trailer) starts on a byte boundary
+    pub fn byte(&mut self) -> Option<u8> {
+        self.acc = 0;
+        self.count = 0;
+        self.input.byte(&mut self.out)
+    }
+
+    // The header of a gzip member (RFC 1952); None if it isn't one
+    pub fn gzip_header(&mut self) -> Option<()> {
+        const FHCRC: u8 = 2;
+        const FEXTRA: u8 = 4;
+        const FNAME: u8 = 8;
+        const FCOMMENT: u8 = 16;
+
+        let mut header = [0u8; 10];
+        for b in &mut header {
+            *b = self.byte()?;
+        }
+        if header[..3] != [0x1f, 0x8b, 8] {
+            return None;
+        }
+        let flags = header[3];
+        if flags & FEXTRA != 0 {
+            let len = u16::from_le_bytes([self.byte()?, self.byte()?]);
+            for _ in 0..len {
+                self.byte()?;
+            }
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                while self.byte()? != 0 {}
+            }
+        }
+        if flags & FHCRC != 0 {
+            self.byte()?;
+            self.byte()?;
+        }
+        Some(())
+    }
+
+    fn take(&mut self, n: u32) -> Option<u32> {
+        while self.count < n {
+            self.acc |= (self.input.byte(&mut self.out)? as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.acc & ((1 << n) - 1);
+        self.acc >>= n;
+        self.count -= n;
+        Some(value)
+    }
+
+    // One DEFLATE stream, stopping once `out` holds `limit` bytes; true if it was cut there
+    pub fn inflate(&mut self, limit: usize) -> Option<bool> {
+        loop {
+            let last = self.take(1)? == 1;
+            match self.take(2)? {
+                0 => {
+                    let len = u16::from_le_bytes([self.byte()?, self.input.byte(&mut self.out)?]);
+                    self.input.byte(&mut self.out)?;
+                    self.input.byte(&mut self.out)?;
+                    for _ in 0..len {
+                        let b = self.input.byte(&mut self.out)?;
+                        self.out.push(b);
+                    }
+                }
+                1 => {
+                    let mut lengths = [0u8; 288];
+                    lengths[..144].fill(8);
+                    lengths[144..256].fill(9);
+                    lengths[256..280].fill(7);
+                    lengths[280..].fill(8);
+                    let (lit, dist) = (Huffman::new(&lengths), Huffman::new(&[5; 30]));
+                    self.block(&lit, &dist, limit)?;
+                }
+                2 => {
+                    let (lit, dist) = self.dynamic_codes()?;
+                    self.block(&lit, &dist, limit)?;
+                }
+                _ => return None,
+            }
+            if self.out.len() >= limit {
+                self.out.truncate(limit);
+                return Some(true);
+            }
+            if last {
+                return Some(false);
+            }
+        }
+    }
+
+    fn dynamic_codes(&mut self) -> Option<(Huffman, Huffman)> {
+        let nlen = self.take(5)? as usize + 257;
+        let ndist = self.take(5)? as usize + 1;
+        let ncode = self.take(4)? as usize + 4;
+        let mut clens = [0u8; 19];
+        for &i in &CLEN_ORDER[..ncode] {
+            clens[i] = self.take(3)? as u8;
+        }
+        let clen = Huffman::new(&clens);
+
+        let mut lengths = Vec::with_capacity(nlen + ndist);
+        while lengths.len() < nlen + ndist {
+            let (len, repeat) = match clen.decode(self)? {
+                sym @ 0..=15 => (sym as u8, 1),
+                16 => (*lengths.last()?, 3 + self.take(2)?),
+                17 => (0, 3 + self.take(3)?),
+                18 => (0, 11 + self.take(7)?),
+                _ => return None,
+            };
+            lengths.extend(std::iter::repeat_n(len, repeat as usize));
+        }
+        if lengths.len() > nlen + ndist {
+            return None;
+        }
+        Some((Huffman::new(&lengths[..nlen]), Huffman::new(&lengths[nlen..])))
+    }
+
+    fn block(&mut self, lit: &Huffman, dist: &Huffman, limit: usize) -> Option<()> {
+        while self.out.len() < limit {
+            let sym = lit.decode(self)? as usize;
+            if sym < 256 {
+                self.out.push(sym as u8);
+                continue;
+            }
+            if sym == 256 {
+                return Some(());
+            }
+            let sym = sym - 257;
+            let len = *LEN_BASE.get(sym)? as usize + self.take(LEN_EXTRA[sym] as u32)? as usize;
+            let dsym = dist.decode(self)? as usize;
+            let back = *DIST_BASE.get(dsym)? as usize + self.take(DIST_EXTRA[dsym] as u32)? as usize;
+            if back > self.out.len() {
+                return None;
+            }
+            for _ in 0..len {
+                self.out.push(self.out[self.out.len() - back]);
+            }
+        }
+        Some(())
+    }
+}
+
+// A canonical Huffman code: how many codes there are of each length, and the symbols in
+// code order
+struct Huffman {
+    count: [u16; 16],
+    symbol: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Huffman {
+        let mut count = [0u16; 16];
+        for &len in lengths {
+            count[len as usize] += 1;
+        }
+        count[0] = 0;
+        let mut offset = [0u16; 16];
+        for len in 1..15 {
+            offset[len + 1] = offset[len] + count[len];
+        }
+        let mut symbol = vec![0; lengths.len()];
+        for (sym, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbol[offset[len as usize] as usize] = sym as u16;
+                offset[len as usize] += 1;
+            }
+        }
+        Huffman { count, symbol }
+    }
+
+    fn decode<I: Input>(&self, bits: &mut Inflater<I>) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.take(1)? as i32;
+            let count = self.count[len] as i32;
+            if code - first < count {
+                return self.symbol.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressed::fixtures::{Units, binary, text};
+
+    // `gzip -9 -n` of fixtures::text() and fixtures::binary()
+    const TEXT: [u8; 159] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x75, 0xd1, 0x3b, 0x0a, 0x02, 0x51,
+        0x0c, 0x85, 0xe1, 0xde, 0x55, 0xdc, 0x0d, 0x0c, 0x24, 0x99, 0xdc, 0x97, 0x0b, 0x10, 0xa6, 0xd1,
+        0x55, 0x4c, 0x65, 0x21, 0xbe, 0xf6, 0x2f, 0xda, 0xe5, 0x98, 0x03, 0x29, 0x7f, 0xf8, 0x20, 0xc7,
+        0xc4, 0x7c, 0x91, 0xba, 0x88, 0x16, 0x95, 0xa3, 0x7c, 0xaf, 0x6c, 0xe7, 0xd3, 0xa5, 0x3c, 0xf6,
+        0xfb, 0x7b, 0x7f, 0xbe, 0x8a, 0x94, 0xdb, 0xf5, 0x60, 0x7f, 0x95, 0xc6, 0x6a, 0xed, 0x79, 0x66,
+        0x31, 0xeb, 0x9e, 0x67, 0x6b, 0xcc, 0x94, 0xa0, 0x1e, 0x33, 0x27, 0x68, 0x8d, 0xd9, 0x20, 0x68,
+        0x8b, 0x99, 0x11, 0xb4, 0xc7, 0xac, 0x12, 0x74, 0xc4, 0x6c, 0x12, 0x74, 0xc2, 0xdf, 0x72, 0x54,
+        0x61, 0x84, 0x96, 0xa3, 0x8a, 0x2b, 0xe4, 0x15, 0x8c, 0xe0, 0xc4, 0x84, 0x11, 0x3a, 0x31, 0x61,
+        0x04, 0x25, 0x28, 0x8c, 0x50, 0x09, 0x0a, 0x23, 0x0c, 0x82, 0xc2, 0x08, 0x46, 0x50, 0x18, 0xa1,
+        0x11, 0x14, 0x46, 0x98, 0x3f, 0xf4, 0x03, 0x20, 0x6e, 0xed, 0x2a, 0x0a, 0x03, 0x00, 0x00,
+    ];
+    const BINARY: [u8; 87] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x01, 0x40, 0x00, 0xbf, 0xff, 0x29,
+        0x72, 0xbb, 0x09, 0x52, 0x9b, 0xe4, 0x32, 0x7b, 0xc4, 0x12, 0x5b, 0xa4, 0xed, 0x3b, 0x84, 0xcd,
+        0x1b, 0x64, 0xad, 0xf6, 0x44, 0x8d, 0xd6, 0x24, 0x6d, 0xb6, 0x04, 0x4d, 0x96, 0xdf, 0x2d, 0x76,
+        0xbf, 0x0d, 0x56, 0x9f, 0xe8, 0x36, 0x7f, 0xc8, 0x16, 0x5f, 0xa8, 0xf1, 0x3f, 0x88, 0xd1, 0x1f,
+        0x68, 0xb1, 0xfa, 0x48, 0x91, 0xda, 0x28, 0x71, 0xba, 0x08, 0x51, 0x9a, 0xe3, 0x31, 0x7a, 0x4a,
+        0xea, 0x32, 0xf7, 0x40, 0x00, 0x00, 0x00,
+    ];
+
+    fn decompress(data: &[u8]) -> (Vec<u8>, usize) {
+        let mut input = Units::new(data);
+        let mut out = Vec::new();
+        gunzip(&mut input, &mut out);
+        (out, input.units)
+    }
+
+    #[test]
+    fn decodes_what_the_reference_tool_wrote() {
+        assert_eq!(decompress(&TEXT), (text(), 1));
+        assert_eq!(decompress(&BINARY), (binary(), 1));
+    }
+
+    #[test]
+    fn decodes_concatenated_units() {
+        let (out, units) = decompress(&[&TEXT[..], &BINARY[..], &TEXT[..]].concat());
+        assert_eq!(out, [text(), binary(), text()].concat());
+        assert_eq!(units, 3);
+    }
+
+    #[test]
+    fn stops_where_input_is_cut_short() {
+        for len in [TEXT.len() - 1, TEXT.len() / 2, 3] {
+            let (out, units) = decompress(&TEXT[..len]);
+            assert_eq!(units, 0);
+            assert!(text().starts_with(&out));
+        }
+    }
+
+    #[test]
+    fn survives_corrupt_input() {
+        for i in 0..TEXT.len() {
+            let mut data = TEXT;
+            data[i] ^= 0x55;
+            decompress(&data);
+        }
+    }
+}
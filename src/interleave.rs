@@ -0,0 +1,135 @@
+// `--reassemble` (and `--writer-atomicity <bytes>`): undo the damage several processes
+// appending to one file can do. O_APPEND makes each write() land whole, but a writer
+// that puts out a long line in pieces (a full stdio buffer, then the rest) can have
+// another writer's line land between the pieces:
+//
+//   2024-05-01 10:00:00 worker-1 started a very lo2024-05-01 10:00:00 worker-2 ready
+//   ng request
+//
+// Record starts are learned from the first LEARN_LINES lines: the most common "shape"
+// of a line's first SHAPE_LEN characters (digits and letters generalised), if at least
+// 80% of lines share it. After that, a record start found inside a line cuts it in two;
+// the head is held back until a line that doesn't start a record arrives to complete
+// it, and the joined line goes out marked "[reassembled] ". A line that doesn't start a
+// record with nothing held back to join, and a head nothing completed, go out marked
+// "[suspect] ". Until a shape is learned (or if none dominates) lines pass unchanged.
+//
+// An embedded timestamp could look like another writer's line, so without a hint a cut
+// is only made where the record start is glued to the text before it. With
+// `--writer-atomicity <bytes>` (the writers' buffer size, e.g. 4096) a head must also be
+// a whole number of such writes long.
+//
+// Text without a newline yet (a write still in progress) is held until the line ends.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+const SHAPE_LEN: usize = 8;
+const LEARN_LINES: usize = 20;
+// Heads waiting for their rest; older ones are given up on
+const MAX_HEADS: usize = 16;
+
+struct Reassembler {
+    atomicity: Option<usize>,
+    shapes: HashMap<String, usize>,
+    learned: usize,
+    signature: Option<String>,
+    unterminated: String,
+    heads: VecDeque<String>,
+}
+
+fn shape(text: &str) -> Option<String> {
+    let shape: String = text
+        .chars()
+        .take(SHAPE_LEN)
+        .map(|c| if c.is_ascii_digit() { '9' } else if c.is_alphabetic() { 'a' } else { c })
+        .collect();
+    (shape.chars().count() == SHAPE_LEN).then_some(shape)
+}
+
+impl Reassembler {
+    fn new(atomicity: Option<usize>) -> Reassembler {
+        Reassembler {
+            atomicity,
+            shapes: HashMap::new(),
+            learned: 0,
+            signature: None,
+            unterminated: String::new(),
+            heads: VecDeque::new(),
+        }
+    }
+
+    // The lines to pass on for `line`
+    fn push(&mut self, line: &str) -> Vec<String> {
+        self.unterminated.push_str(line);
+        if !self.unterminated.ends_with('\n') {
+            return Vec::new();
+        }
+        let line = std::mem::take(&mut self.unterminated);
+
+        if self.learned < LEARN_LINES {
+            self.learn(&line);
+            return vec![line];
+        }
+        let Some(signature) = self.signature.clone() else {
+            return vec![line];
+        };
+        let mut out = Vec::new();
+        if shape(&line).as_ref() != Some(&signature) {
+            match self.heads.pop_front() {
+                Some(head) => out.push(format!("[reassembled] {}{}", head, line)),
+                None => out.push(format!("[suspect] {}", line)),
+            }
+            return out;
+        }
+
+        // Cut off heads of other writers' lines for as long as record starts are found
+        let mut rest = line.as_str();
+        while let Some(cut) = self.find_cut(rest, &signature) {
+            self.heads.push_back(rest[..cut].to_string());
+            rest = &rest[cut..];
+        }
+        while self.heads.len() > MAX_HEADS {
+            let head = self.heads.pop_front().unwrap();
+            out.push(format!("[suspect] {}\n", head));
+        }
+        out.push(rest.to_string());
+        out
+    }
+
+    fn learn(&mut self, line: &str) {
+        if let Some(shape) = shape(line) {
+            *self.shapes.entry(shape).or_default() += 1;
+        }
+        self.learned += 1;
+        if self.learned == LEARN_LINES {
+            let shapes = std::mem::take(&mut self.shapes);
+            self.signature = shapes
+                .into_iter()
+                .find(|(_, count)| count * 10 >= LEARN_LINES * 8)
+                .map(|(shape, _)| shape);
+        }
+    }
+
+    // Where another writer's line starts inside `line`, after its own record start
+    fn find_cut(&self, line: &str, signature: &str) -> Option<usize> {
+        line.char_indices().skip(1).find_map(|(i, _)| {
+            let glued = line[..i].chars().next_back().is_some_and(|c| !c.is_whitespace() && !"[(\"'=,:".contains(c));
+            let whole_writes = self.atomicity.is_none_or(|n| i % n == 0);
+            (glued && whole_writes && shape(&line[i..]).as_deref() == Some(signature)).then_some(i)
+        })
+    }
+}
+
+static REASSEMBLER: Mutex<Option<Reassembler>> = Mutex::new(None);
+
+pub fn enable(atomicity: Option<usize>) {
+    *REASSEMBLER.lock().unwrap() = Some(Reassembler::new(atomicity));
+}
+
+// The lines to pass on for `line`: usually just it, none while it is incomplete, more
+// than one when it held another writer's line or completed an earlier one
+pub fn transform(line: &str) -> Option<Vec<String>> {
+    let mut reassembler = REASSEMBLER.lock().unwrap();
+    reassembler.as_mut().map(|r| r.push(line))
+}
@@ -0,0 +1,116 @@
+// `--compact-json`: services that pretty-print their JSON write one document over many
+// lines. Lines are collected from one that starts with '{' until its braces balance,
+// and the document goes down the rest of the pipeline (--format, --fail-on, the output)
+// as a single line with the whitespace between tokens removed.
+//
+// Anything else passes through as it is: lines outside a document, text after a
+// closing brace (then the whole collection goes out unchanged), and a document that
+// never balances once MAX_PENDING bytes have piled up.
+
+use std::borrow::Cow;
+use std::sync::Mutex;
+
+// Give up on a document that never closes rather than buffering forever
+const MAX_PENDING: usize = 1024 * 1024;
+
+struct Assembler {
+    pending: String,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Assembler {
+    fn new() -> Assembler {
+        Assembler { pending: String::new(), depth: 0, in_string: false, escaped: false }
+    }
+
+    // The text to pass on for `line` (with its newline), or None while a document is
+    // still open
+    fn push<'a>(&mut self, line: &'a str) -> Option<Cow<'a, str>> {
+        if self.pending.is_empty() && !line.trim_start().starts_with('{') {
+            return Some(Cow::Borrowed(line));
+        }
+        self.pending.push_str(line);
+        let mut closed_at = None;
+        for (i, c) in line.char_indices() {
+            if self.in_string {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' => self.in_string = true,
+                '{' | '[' => self.depth += 1,
+                '}' | ']' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    if self.depth == 0 {
+                        closed_at = Some(i + 1);
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        match closed_at {
+            Some(end) if line[end..].trim().is_empty() => Some(Cow::Owned(self.finish(true))),
+            Some(_) => Some(Cow::Owned(self.finish(false))),
+            None if self.pending.len() > MAX_PENDING => Some(Cow::Owned(self.finish(false))),
+            None => None,
+        }
+    }
+
+    // Hand back what was collected, compacted if it was a whole document
+    fn finish(&mut self, complete: bool) -> String {
+        let text = std::mem::take(&mut self.pending);
+        *self = Assembler::new();
+        if !complete {
+            return text;
+        }
+        let mut out = compact(&text);
+        out.push('\n');
+        out
+    }
+}
+
+// Drop whitespace outside strings
+fn compact(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let (mut in_string, mut escaped) = (false, false);
+    for c in text.chars() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if c.is_whitespace() {
+            continue;
+        }
+        out.push(c);
+    }
+    out
+}
+
+static ASSEMBLER: Mutex<Option<Assembler>> = Mutex::new(None);
+
+pub fn enable() {
+    *ASSEMBLER.lock().unwrap() = Some(Assembler::new());
+}
+
+// The text to pass on for `line`, or None if it is part of a document still being read
+pub fn transform(line: &str) -> Option<Cow<'_, str>> {
+    let mut assembler = ASSEMBLER.lock().unwrap();
+    match assembler.as_mut() {
+        Some(assembler) => assembler.push(line),
+        None => Some(Cow::Borrowed(line)),
+    }
+}
@@ -0,0 +1,261 @@
+// `--json-pretty`: show lines that are a JSON object (JSON Lines, as structured loggers
+// write them) indented over several lines, two spaces a level, with keys, strings,
+// numbers and literals in colour on a terminal. Any other line, including one with text
+// before or after its object, is shown as it is. Strings and numbers are printed as
+// written, escapes and all.
+//
+// Only what is shown changes: --grep, --fail-on, --forward and the rest see the line as
+// read. With --compact-json, documents spread over several lines are collected first,
+// so they come out indented the same way whatever the writer did.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::output;
+
+const INDENT: &str = "  ";
+// Deeper than this is left alone rather than risk the stack
+const MAX_DEPTH: usize = 128;
+const KEY: &str = "1;34";
+const STRING: &str = "32";
+const NUMBER: &str = "36";
+const LITERAL: &str = "33";
+const NULL: &str = "90";
+
+enum Value<'a> {
+    Object(Vec<(&'a str, Value<'a>)>),
+    Array(Vec<Value<'a>>),
+    // As written: strings with their quotes, numbers, true, false and null
+    String(&'a str),
+    Number(&'a str),
+    Literal(&'a str),
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn transform(line: String) -> String {
+    if !enabled() {
+        return line;
+    }
+    let (text, newline) = match line.strip_suffix('\n') {
+        Some(text) => (text.strip_suffix('\r').unwrap_or(text), "\n"),
+        None => (line.as_str(), ""),
+    };
+    let text = text.trim();
+    if !text.starts_with('{') {
+        return line;
+    }
+    let mut parser = Parser { text, pos: 0, depth: 0 };
+    let value = match parser.value() {
+        Some(value @ Value::Object(_)) if parser.at_end() => value,
+        _ => return line,
+    };
+    let mut out = String::with_capacity(text.len() * 2);
+    render(&value, 0, output::color_wanted(), &mut out);
+    out.push_str(newline);
+    out
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        let rest = &self.text[self.pos..];
+        self.pos += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_space();
+        self.pos == self.text.len()
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_space();
+        self.text.as_bytes().get(self.pos).copied()
+    }
+
+    fn eat(&mut self, b: u8) -> bool {
+        if self.peek() == Some(b) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn value(&mut self) -> Option<Value<'a>> {
+        match self.peek()? {
+            b'{' => self.nested(|parser| parser.object()),
+            b'[' => self.nested(|parser| parser.array()),
+            b'"' => self.string().map(Value::String),
+            b'-' | b'0'..=b'9' => self.number().map(Value::Number),
+            _ => ["true", "false", "null"].into_iter().find(|word| self.text[self.pos..].starts_with(word)).map(|word| {
+                self.pos += word.len();
+                Value::Literal(word)
+            }),
+        }
+    }
+
+    fn nested(&mut self, parse: impl FnOnce(&mut Self) -> Option<Value<'a>>) -> Option<Value<'a>> {
+        if self.depth == MAX_DEPTH {
+            return None;
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Option<Value<'a>> {
+        self.pos += 1;
+        let mut members = Vec::new();
+        if self.eat(b'}') {
+            return Some(Value::Object(members));
+        }
+        loop {
+            self.peek()?;
+            let key = self.string()?;
+            if !self.eat(b':') {
+                return None;
+            }
+            members.push((key, self.value()?));
+            if self.eat(b'}') {
+                return Some(Value::Object(members));
+            }
+            if !self.eat(b',') {
+                return None;
+            }
+        }
+    }
+
+    fn array(&mut self) -> Option<Value<'a>> {
+        self.pos += 1;
+        let mut items = Vec::new();
+        if self.eat(b']') {
+            return Some(Value::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            if self.eat(b']') {
+                return Some(Value::Array(items));
+            }
+            if !self.eat(b',') {
+                return None;
+            }
+        }
+    }
+
+    // A string, quotes included
+    fn string(&mut self) -> Option<&'a str> {
+        let bytes = self.text.as_bytes();
+        if bytes.get(self.pos) != Some(&b'"') {
+            return None;
+        }
+        let start = self.pos;
+        let mut i = start + 1;
+        loop {
+            match *bytes.get(i)? {
+                b'"' => break,
+                b'\\' => i += 2,
+                b if b < 0x20 => return None,
+                _ => i += 1,
+            }
+        }
+        self.pos = i + 1;
+        Some(&self.text[start..self.pos])
+    }
+
+    fn number(&mut self) -> Option<&'a str> {
+        let bytes = self.text.as_bytes();
+        let start = self.pos;
+        let digits = |i: &mut usize| {
+            let from = *i;
+            while bytes.get(*i).is_some_and(u8::is_ascii_digit) {
+                *i += 1;
+            }
+            *i > from
+        };
+        let mut i = start;
+        if bytes[i] == b'-' {
+            i += 1;
+        }
+        if !digits(&mut i) {
+            return None;
+        }
+        if bytes.get(i) == Some(&b'.') {
+            i += 1;
+            if !digits(&mut i) {
+                return None;
+            }
+        }
+        if matches!(bytes.get(i), Some(b'e' | b'E')) {
+            i += 1;
+            if matches!(bytes.get(i), Some(b'+' | b'-')) {
+                i += 1;
+            }
+            if !digits(&mut i) {
+                return None;
+            }
+        }
+        self.pos = i;
+        Some(&self.text[start..i])
+    }
+}
+
+fn render(value: &Value, depth: usize, color: bool, out: &mut String) {
+    let paint = |out: &mut String, sgr: &str, text: &str| {
+        if color {
+            out.push_str(&format!("\x1b[{}m{}\x1b[0m", sgr, text));
+        } else {
+            out.push_str(text);
+        }
+    };
+    let newline = |out: &mut String, depth: usize| {
+        out.push('\n');
+        (0..depth).for_each(|_| out.push_str(INDENT));
+    };
+    match value {
+        Value::Object(members) if members.is_empty() => out.push_str("{}"),
+        Value::Array(items) if items.is_empty() => out.push_str("[]"),
+        Value::Object(members) => {
+            out.push('{');
+            for (i, (key, value)) in members.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, depth + 1);
+                paint(out, KEY, key);
+                out.push_str(": ");
+                render(value, depth + 1, color, out);
+            }
+            newline(out, depth);
+            out.push('}');
+        }
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, depth + 1);
+                render(item, depth + 1, color, out);
+            }
+            newline(out, depth);
+            out.push(']');
+        }
+        Value::String(text) => paint(out, STRING, text),
+        Value::Number(text) => paint(out, NUMBER, text),
+        Value::Literal(text) => paint(out, if *text == "null" { NULL } else { LITERAL }, text),
+    }
+}
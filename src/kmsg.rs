@@ -0,0 +1,140 @@
+// The Linux kernel log, read from /dev/kmsg like `dmesg -w` does.
+//
+// Every read() of /dev/kmsg returns exactly one record:
+//
+//     <priority>,<sequence>,<microseconds since boot>,<flags>;<message>
+//      KEY=value             (optional dictionary lines, indented by one space)
+//
+// Reading starts at the oldest record still in the ring buffer. The device is opened
+// non-blocking so "caught up" can be told apart from "waiting for the next message":
+// that's where the last N records are printed, and where -f starts polling. If the
+// kernel overwrites records before we read them the read fails with EPIPE and the next
+// read continues with the oldest record that's left.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::thread;
+
+use crate::fields::Record;
+use crate::follow;
+use crate::output;
+use crate::pid;
+use crate::report;
+
+// Larger than any record the kernel writes; a smaller buffer makes read() fail
+const RECORD_BUF: usize = 8192;
+
+const EPIPE: i32 = 32;
+
+const LEVELS: [&str; 8] = ["emerg", "alert", "crit", "err", "warn", "notice", "info", "debug"];
+
+const FACILITIES: [&str; 24] = [
+    "kern", "user", "mail", "daemon", "auth", "syslog", "lpr", "news", "uucp", "cron",
+    "authpriv", "ftp", "ntp", "security", "console", "solaris-cron", "local0", "local1",
+    "local2", "local3", "local4", "local5", "local6", "local7",
+];
+
+pub fn is_kmsg(filename: &str) -> bool {
+    cfg!(target_os = "linux")
+        && std::fs::canonicalize(filename).is_ok_and(|p| p.as_os_str() == "/dev/kmsg")
+}
+
+// Print the last `num_lines` records, then with `follow` keep printing new ones
+pub fn tail(filename: &str, num_lines: usize, follow: bool) -> io::Result<()> {
+    let mut file = open_nonblocking(filename)?;
+    let mut buf = vec![0u8; RECORD_BUF];
+
+    let mut backlog = VecDeque::new();
+    while let Some(record) = read_record(&mut file, &mut buf)? {
+        backlog.push_back(record);
+        if backlog.len() > num_lines {
+            backlog.pop_front();
+        }
+    }
+    for record in backlog {
+        output::emit(&record);
+    }
+    output::flush();
+
+    if !follow {
+        return Ok(());
+    }
+    output::following(filename);
+    while !report::interrupted() {
+        match read_record(&mut file, &mut buf)? {
+            Some(record) => output::emit(&record),
+            None => {
+                // Caught up; what the --pid process logged as it exited is read by now
+                if pid::exited() {
+                    break;
+                }
+                output::flush();
+                thread::sleep(follow::poll_interval());
+            }
+        }
+    }
+    output::flush();
+    Ok(())
+}
+
+// The next record's first line (dictionary lines dropped), or None when caught up
+fn read_record(file: &mut File, buf: &mut [u8]) -> io::Result<Option<String>> {
+    loop {
+        match file.read(buf) {
+            Ok(0) => return Ok(None),
+            Ok(n) => {
+                let text = String::from_utf8_lossy(&buf[..n]);
+                let mut line = text.split_inclusive('\n').next().unwrap_or("").to_string();
+                if !line.ends_with('\n') {
+                    line.push('\n');
+                }
+                return Ok(Some(line));
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) if e.raw_os_error() == Some(EPIPE) => {
+                output::flush();
+                output::status(format_args!("\n--- Kernel log wrapped before it was read; some messages were lost ---\n"));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn open_nonblocking(filename: &str) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    const O_NONBLOCK: i32 = 0o4000;
+    std::fs::OpenOptions::new().read(true).custom_flags(O_NONBLOCK).open(filename)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_nonblocking(filename: &str) -> io::Result<File> {
+    crate::open::open_log(filename)
+}
+
+// Split "<prio>,<seq>,<usec>,<flags>;<message>" into fields
+pub fn parse(line: &str) -> Option<Record> {
+    let (header, message) = line.trim_end_matches('\n').split_once(';')?;
+    let mut parts = header.split(',');
+    let priority: usize = parts.next()?.parse().ok()?;
+    let seq: u64 = parts.next()?.parse().ok()?;
+    let usec: u64 = parts.next()?.parse().ok()?;
+
+    let mut record = Record::default();
+    record.fields.push(("level".to_string(), LEVELS[priority & 7].to_string()));
+    if let Some(facility) = FACILITIES.get(priority >> 3) {
+        record.fields.push(("facility".to_string(), facility.to_string()));
+    }
+    record.fields.push(("seq".to_string(), seq.to_string()));
+    record.fields.push(("timestamp".to_string(), format!("{}.{:06}", usec / 1_000_000, usec % 1_000_000)));
+    record.fields.push(("message".to_string(), message.to_string()));
+    Some(record)
+}
+
+// dmesg-style: "[    1.234567] err: message"
+pub fn render(record: &Record) -> String {
+    let field = |name| record.get(name).unwrap_or("");
+    format!("[{:>12}] {}: {}", field("timestamp"), field("level"), field("message"))
+}
@@ -0,0 +1,79 @@
+// `--per-source-limit <N>/s`: with several files, show at most N lines a second from each
+// one, so a file that starts logging in a loop can't bury the others in the merged
+// output. What goes over the budget is left out of the output only (--fail-on, --forward
+// and crash reports still see it) and summarized under the file's header once its
+// second is over:
+//
+//   --- 1830 lines from 'app.log' over the limit of 100/s not shown ---
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+const WINDOW: Duration = Duration::from_secs(1);
+
+struct Budget {
+    started: Instant,
+    shown: u64,
+    dropped: u64,
+}
+
+static LIMIT: OnceLock<u64> = OnceLock::new();
+static BUDGETS: Mutex<Option<HashMap<String, Budget>>> = Mutex::new(None);
+
+pub fn parse(s: &str) -> Result<u64, String> {
+    match s.strip_suffix("/s").unwrap_or(s).parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("expected a number of lines per second like 100/s, got '{}'", s)),
+    }
+}
+
+pub fn set(per_second: u64) {
+    let _ = LIMIT.set(per_second);
+}
+
+pub fn enabled() -> bool {
+    LIMIT.get().is_some()
+}
+
+fn summary(source: &str, dropped: u64) -> String {
+    format!("--- {} lines from '{}' over the limit of {}/s not shown ---\n", dropped, source, LIMIT.get().unwrap_or(&0))
+}
+
+// Whether a line from `source` fits in its budget (it's counted if so), and the summary
+// of what was left out in its previous second, if that has just ended
+pub fn admit(source: &str) -> (bool, Option<String>) {
+    let Some(&limit) = LIMIT.get() else {
+        return (true, None);
+    };
+    let mut budgets = BUDGETS.lock().unwrap();
+    let budget = budgets
+        .get_or_insert_with(HashMap::new)
+        .entry(source.to_string())
+        .or_insert_with(|| Budget { started: Instant::now(), shown: 0, dropped: 0 });
+    let mut ended = None;
+    if budget.started.elapsed() >= WINDOW {
+        ended = (budget.dropped > 0).then(|| summary(source, budget.dropped));
+        *budget = Budget { started: Instant::now(), shown: 0, dropped: 0 };
+    }
+    if budget.shown < limit {
+        budget.shown += 1;
+        (true, ended)
+    } else {
+        budget.dropped += 1;
+        (false, ended)
+    }
+}
+
+// The summary for `source` once its second is over, for when no further line comes to
+// carry it
+pub fn expired(source: &str) -> Option<String> {
+    let mut budgets = BUDGETS.lock().unwrap();
+    let budget = budgets.as_mut()?.get_mut(source)?;
+    if budget.dropped == 0 || budget.started.elapsed() < WINDOW {
+        return None;
+    }
+    let ended = summary(source, budget.dropped);
+    budget.dropped = 0;
+    Some(ended)
+}
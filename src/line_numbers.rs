@@ -0,0 +1,107 @@
+// `--line-numbers`: start each output line with its line number in the file, as
+// `cat -n` does, to find it again in an editor. Numbers count from the top of the file
+// (so the last lines of a large file are counted up to, which reads the file once) and
+// carry on through what is appended while following; after a truncation or rotation
+// they start again at 1. Numbers are of lines read, so lines --grep leaves out still
+// count, and with options that join lines the number is that of the last one joined.
+
+use std::cell::Cell;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::open::{STDIN, open_log};
+use crate::{encoding, output};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// The last place a file's lines were counted up to: (file, offset, lines before it)
+static COUNTED: Mutex<Vec<(String, u64, u64)>> = Mutex::new(Vec::new());
+
+thread_local! {
+    // The number of the line the next bytes read belong to
+    static NEXT: Cell<u64> = const { Cell::new(1) };
+    // The line being emitted, or None if it is the rest of a line read in part before
+    static CURRENT: Cell<Option<u64>> = const { Cell::new(None) };
+    // Whether the last read ended mid-line
+    static PARTIAL: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Lines this thread reads from now on are numbered from `next`
+pub fn start_at(next: u64) {
+    NEXT.with(|n| n.set(next));
+    PARTIAL.with(|p| p.set(false));
+}
+
+pub fn next() -> u64 {
+    NEXT.with(Cell::get)
+}
+
+// The file started over
+pub fn restart() {
+    start_at(1);
+}
+
+// The number of lines (delimiters) in `filename` before `offset`
+pub fn lines_before(filename: &str, offset: u64) -> io::Result<u64> {
+    if offset == 0 || filename == STDIN {
+        return Ok(0);
+    }
+    let known = COUNTED.lock().unwrap().iter().find(|(f, at, _)| f == filename && *at <= offset).map(|&(_, at, lines)| (at, lines));
+    let (mut at, mut lines) = known.unwrap_or((0, 0));
+    let mut file = open_log(filename)?;
+    file.seek(SeekFrom::Start(at))?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    while at < offset {
+        let want = buffer.len().min((offset - at) as usize);
+        let n = file.read(&mut buffer[..want])?;
+        if n == 0 {
+            break;
+        }
+        lines += encoding::newlines(&buffer[..n], at);
+        at += n as u64;
+    }
+    mark(filename, at, lines);
+    Ok(lines)
+}
+
+// Remember that `filename` has `lines` lines before `offset`, so counting can go on from
+// there
+pub fn mark(filename: &str, offset: u64, lines: u64) {
+    if !enabled() {
+        return;
+    }
+    let mut counted = COUNTED.lock().unwrap();
+    counted.retain(|(f, _, _)| f != filename);
+    counted.push((filename.to_string(), offset, lines));
+}
+
+// A line (or part of one) as read from the input
+pub fn read(line: &[u8]) {
+    if !enabled() {
+        return;
+    }
+    let continued = PARTIAL.with(Cell::get);
+    let number = next();
+    CURRENT.with(|c| c.set((!continued).then_some(number)));
+    let complete = line.last() == Some(&output::delimiter());
+    PARTIAL.with(|p| p.set(!complete));
+    if complete {
+        NEXT.with(|n| n.set(number + 1));
+    }
+}
+
+// `text` with the current line's number in front (of its first line)
+pub fn prefix(text: String) -> String {
+    match CURRENT.with(Cell::get) {
+        Some(number) if enabled() => format!("{:>6}\t{}", number, text),
+        _ => text,
+    }
+}
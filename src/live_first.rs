@@ -0,0 +1,141 @@
+// `--live-first`: with -f, show new lines from the moment rail starts, and read the
+// history asked for (-n N, -n +N, the --state-file position or a bookmark) in the
+// background. It is printed between two markers once it has been read and the live lines
+// are caught up, within a second of that:
+//
+//   --- History: 10000 lines from before rail went live ---
+//   ...
+//   --- End of history; live lines go on below ---
+//
+// so during an incident the first new line isn't held up behind a scan of a large file.
+// The history ends exactly where following began, so no line is shown twice or missed.
+// It is held in memory until printed; --backfill-max and --backfill-max-bytes bound it.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+
+use crate::open::open_log;
+use crate::{backfill, encoding, line_numbers, output};
+
+#[derive(Clone, Copy)]
+pub enum History {
+    // The recorded position or a bookmark
+    From(u64),
+    // -n N
+    Last(usize),
+    // -n +N
+    FromLine(usize),
+}
+
+struct Block {
+    lines: Vec<Vec<u8>>,
+    // The number of its first line, for --line-numbers
+    first: u64,
+    // Bytes left out for --backfill-max
+    skipped: u64,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// Tells the reader where following began
+static LIVE_AT: Mutex<Option<Sender<u64>>> = Mutex::new(None);
+static READY: Mutex<Option<Block>> = Mutex::new(None);
+static PENDING: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// Start reading `history` of `filename`; it waits for went_live to say where it ends
+pub fn spawn(filename: &str, history: History) {
+    let (sender, receiver) = mpsc::channel();
+    *LIVE_AT.lock().unwrap() = Some(sender);
+    let filename = filename.to_string();
+    let encoding = encoding::current();
+    thread::spawn(move || {
+        encoding::set(encoding);
+        let Ok(end) = receiver.recv() else {
+            return;
+        };
+        match read(&filename, history, end) {
+            Ok(block) if block.lines.is_empty() => {}
+            Ok(block) => {
+                *READY.lock().unwrap() = Some(block);
+                PENDING.store(true, Ordering::Relaxed);
+            }
+            Err(e) => eprintln!("Warning: Could not read the history of '{}': {}", filename, e),
+        }
+    });
+}
+
+// Following began at `pos`
+pub fn went_live(pos: u64) {
+    if let Some(sender) = LIVE_AT.lock().unwrap().take() {
+        let _ = sender.send(pos);
+    }
+}
+
+// Print the history if it's ready; called where the live lines are caught up
+pub fn deliver() -> io::Result<()> {
+    if !PENDING.swap(false, Ordering::Relaxed) {
+        return Ok(());
+    }
+    let Some(block) = READY.lock().unwrap().take() else {
+        return Ok(());
+    };
+    output::flush();
+    output::status(format_args!("--- History: {} lines from before rail went live ---", block.lines.len()));
+    backfill::note_skipped(block.skipped);
+    let next = line_numbers::next();
+    line_numbers::start_at(block.first);
+    for line in block.lines {
+        output::emit_bytes(line, true)?;
+    }
+    output::flush();
+    line_numbers::start_at(next);
+    output::status(format_args!("--- End of history; live lines go on below ---"));
+    Ok(())
+}
+
+fn read(filename: &str, history: History, end: u64) -> io::Result<Block> {
+    let mut file = open_log(filename)?;
+    let from = match history {
+        History::From(offset) => offset.min(end),
+        History::Last(num_lines) => crate::start_of_last_before(&mut file, end, num_lines)?,
+        History::FromLine(line) => offset_of_line(&mut file, line, end)?,
+    };
+    let start = backfill::bound(&mut file, from, end)?.min(end);
+    let first = if line_numbers::enabled() { line_numbers::lines_before(filename, start)? + 1 } else { 1 };
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = BufReader::new(file.take(end - start));
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    while encoding::read_line(&mut reader, &mut line)? > 0 {
+        lines.push(std::mem::take(&mut line));
+    }
+    Ok(Block { lines, first, skipped: start - from })
+}
+
+// Where line `line` starts, or `end` if that comes first
+fn offset_of_line(file: &mut File, line: usize, end: u64) -> io::Result<u64> {
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::new(file.by_ref().take(end));
+    let mut buffer = Vec::new();
+    let mut offset = 0;
+    for _ in 1..line {
+        buffer.clear();
+        let n = encoding::read_line(&mut reader, &mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        offset += n as u64;
+    }
+    Ok(offset)
+}
@@ -1,12 +1,90 @@
 use std::env;
 use std::fs::File;
-use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::io::{self, BufReader, IsTerminal, Read, Seek, SeekFrom};
 use std::path::Path;
 use std::thread;
 use std::time::Duration;
 use std::process;
-use std::fs;
-use std::time::SystemTime;
+
+mod a11y;
+mod active_hours;
+mod adb;
+mod append_only;
+mod backfill;
+mod bzip2;
+mod columns;
+mod compact;
+mod compat;
+mod compressed;
+mod control;
+mod crash;
+mod digest;
+mod fail_on;
+mod fault;
+mod diff;
+mod dns;
+mod encoded;
+mod encoding;
+mod enrich;
+mod export;
+mod fields;
+mod follow;
+mod geoip;
+mod glob;
+mod grep;
+mod highlight;
+mod humanize;
+mod imds;
+mod index;
+mod inflate;
+mod interleave;
+mod json;
+mod json_pretty;
+mod kmsg;
+mod limit;
+mod line_numbers;
+mod live_first;
+mod mirror;
+mod mute;
+mod open;
+mod otlp;
+mod output;
+mod passthrough;
+mod pid;
+mod prefilter;
+mod pseudo;
+mod reclassify;
+mod regex;
+mod report;
+mod rotated;
+mod select;
+mod sequence;
+mod sha256;
+mod since;
+mod sim;
+mod sound;
+mod state;
+mod sub;
+mod timestamps;
+mod trace;
+mod trace_context;
+mod transport;
+mod tz;
+#[cfg(windows)]
+mod usn;
+mod watch;
+mod watchdog;
+mod xml;
+mod xz;
+mod zstd;
+
+use append_only::Verifier;
+use encoding::Encoding;
+use follow::{Clock, Decision, Follow, Fs, RealClock, RealFs};
+use index::LineIndex;
+use open::open_log;
+use pseudo::FileKind;
+use state::StateFile;
 
 // Windows-specific imports for console handling
 #[cfg(windows)]
@@ -47,207 +125,2269 @@ fn main() -> io::Result<()> {
     // Set up Windows console for better terminal handling
     setup_windows_console()?;
     
-    let args: Vec<String> = env::args().collect();
+    let mut args: Vec<String> = env::args().collect();
+    
+    // Stand-ins for tailf, logtail and multitail, when run under their names or as
+    // subcommands
+    let tool = Path::new(&args[0])
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .filter(|stem| compat::NAMES.contains(stem))
+        .map(|stem| (stem.to_string(), 1))
+        .or_else(|| args.get(1).filter(|arg| compat::NAMES.contains(&arg.as_str())).map(|arg| (arg.clone(), 2)));
+    if let Some((name, skip)) = tool {
+        match compat::translate(&name, &args[skip..]) {
+            Ok(rest) => args = std::iter::once(args[0].clone()).chain(rest).collect(),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        }
+    }
     
-    if args.len() < 2 {
-        eprintln!("Usage: {} <filename> [-f] [-n lines]", args[0]);
+    // With no arguments, a terminal on stdin means the user wants help, a pipe means input
+    if args.len() < 2 && io::stdin().is_terminal() {
+        eprintln!("Usage: {} [<filename>...] [-f] [-n lines]", args[0]);
+        eprintln!("       With no filename, or with -, read standard input (with -f, until it ends)");
+        eprintln!("       A quoted pattern (\"logs/*.log\") is expanded by rail; with -f, files that match it later are followed too");
+        eprintln!("       A compressed file (gzip, zstd, bzip2 or xz, told by its first bytes) is read decompressed; with -f, what is appended to it is shown as it arrives");
+        eprintln!("       {} adb [device] [--buffer main|system|crash|...] [--json] [--fail-on regex]", args[0]);
+        eprintln!("       {} diff <a> <b> [-f] [--by line|time] [--ignore <regex>] [--unified]  Compare two logs side by side, without their timestamps", args[0]);
+        eprintln!("       {} export <file> --output <dir> [--since <time>] [--until <time>] [--chunk 100MB] [--sha256]  Copy a time range of a log into chunks, with a manifest", args[0]);
+        eprintln!("       {} debug-replay <trace.jsonl>  Re-run the follow decisions recorded with --trace-events", args[0]);
+        eprintln!("       {} state show|reset|bookmark|unbookmark ... --state-file <path>  Inspect or drop recorded positions, set bookmarks", args[0]);
+        eprintln!("       {} tailf|logtail|multitail <their arguments>  Behave like these tools (also when rail is run under their names)", args[0]);
+        eprintln!("       {} simulate <scenario.toml>...  Follow a simulated file through a scripted rotation/truncation/slow-write scenario", args[0]);
+        eprintln!("           [--reconnect-max n] [--reconnect-backoff 500ms|2s]  Reconnect policy for remote sources (default: 5, 1s)");
         eprintln!("  -f              Follow mode: output appended data as the file grows");
-        eprintln!("  -n <num_lines>  Output the last NUM lines (default: 10)");
+        eprintln!("  -F              Follow the name: when the path is renamed away or replaced, finish the old file and switch to the new one (implies --retry)");
+        eprintln!("  --follow=name   Like -F, without --retry");
+        eprintln!("  -n <num_lines>  Output the last NUM lines (default: 10); -n +NUM starts at line NUM instead");
+        eprintln!("  --since <time>  Start at the first line logged at or after TIME (2024-05-01T10:00:00Z, 2024-05-01 10:00 or a date) instead; fast on huge files with --index");
+        eprintln!("  -c, --bytes <num_bytes>  Output the last NUM bytes instead, as they are; with -f, appended bytes go out raw too");
+        eprintln!("  --pid <PID>     With -f, stop once process PID has exited");
+        eprintln!("  -z, --zero-terminated  Lines end in NUL, not newline, in the input and the output");
+        eprintln!("  --a11y          Screen reader friendly output: plain status sentences, spelled-out level tags, no colour or tables");
+        eprintln!("  --line-numbers  Start each line with its line number in the file, counting on while following");
+        eprintln!("  --timestamps[=FORMAT]  Start each line with the time rail read it, strftime-style (default: %Y-%m-%d %H:%M:%S.%3N)");
+        eprintln!("  --compact[=time,level,names]  Shorter lines for narrow panes: times as HH:MM:SS, one-letter levels, c.e.a.ClassName");
+        eprintln!("  -q, --quiet     Never print \"==> name <==\" headers");
+        eprintln!("  -v, --verbose   Always print headers, even for one file");
+        eprintln!("  --prefix=filename  Start every line with the name of its file instead of printing headers (=basename for the name without its directory)");
+        eprintln!("  -s, --sleep-interval <secs>  With -f, how long to wait between checks where the file is polled rather than watched (default: 0.1)");
+        eprintln!("  --per-source-limit <N>/s  With several files, show at most N lines a second from each; the rest are counted and summarized");
         eprintln!("  --retry         Keep trying to open the file if it's not accessible");
+        eprintln!("  --index         Keep a sidecar line index (<filename>.railidx) for fast -n on large files");
+        eprintln!("  --state-file <path>  Record the read position and resume from it on the next run");
+        eprintln!("  --goto-bookmark <name>  With --state-file, start at a bookmark set with `rail ctl <socket> bookmark <name>` or `rail state bookmark`");
+        eprintln!("  --backfill-max <N>  Print at most the last N lines of the history before going live (resuming, -n +N, a large -n); a note says how much was skipped");
+        eprintln!("  --backfill-max-bytes <size>  The same, in bytes (e.g. 50MB; K, M and G are powers of 1024)");
+        eprintln!("  --compression <auto|none|gzip|zstd|bzip2|xz>  How the input is compressed, when its first bytes don't say (or can't: standard input); none shows it as it is");
+        eprintln!("  --encoding <utf-8|utf-16le|utf-16be|latin-1|cp1252>  What the input is written in, when it has no byte order mark to say; it's printed as UTF-8");
+        eprintln!("  --live-first    With -f, show new lines at once and print the history (-n, the recorded position) above a marker when it has been read");
+        eprintln!("  --rotated       When the file has fewer than -n lines, take the rest from its rotated files (app.log.1, app.log.2.gz, ...)");
+        eprintln!("  --rebase        With --state-file, find the recorded position by content (e.g. after a copy or move)");
+        eprintln!("  --usn-journal   On Windows, with -f, watch files through the NTFS change journal, one reader per volume (needs administrator rights)");
+        eprintln!("  --share-mode <read,write,delete>  Windows share mode for rail's handle (default: all three)");
+        eprintln!("  --reopen-each-poll  Close the file while idle and reopen it each poll, for writers that need exclusive access");
+        eprintln!("  --reopen-on-eacces  Reopen the file when reads start failing with permission errors");
+        eprintln!("  --sequence-field <name>  Point out where a counter field (seq=N) skips, repeats or goes back, e.g. lost shipper batches");
+        eprintln!("  --fail-on <regex>  Exit with status 1 if any output line matched");
+        eprintln!("  --digest <regex> --email <address>  Mail a summary of matching lines every --digest-interval (default: 1h); --email is repeatable");
+        eprintln!("  --sound '<regex> => bell[*N][@ms]|system:error|warning|info'  Play a cue when a line matches; --sound severity for level words; repeatable");
+        eprintln!("  --smtp <host[:port]>  Mail relay for --digest, plain SMTP (default: localhost:25)");
+        eprintln!("  --control <socket>  With -f, take `rail ctl <socket> <command>` commands: status, offsets, pause/resume <file|all>, sleep-interval <secs>, grep/exclude <regex>, bookmark <name>");
+        eprintln!("  --mirror unix://<path>  Copy the output to whoever attaches with `rail attach unix://<path>`, read-only (tcp://127.0.0.1:<port> on Windows)");
+        eprintln!("  --report <file.json>  On exit, write a JSON summary: bytes and lines read, follow decisions, matches, mutes, errors");
+        eprintln!("  --flush <line|block|interval:ms>  Output buffering (default: line on a terminal, block otherwise)");
+        eprintln!("  --format <iis-w3c|csvlog-postgres|kmsg|logcat|logfmt>  Parse lines of a known log format into fields (kmsg is detected for /dev/kmsg)");
+        eprintln!("  --logfmt        Same as --format logfmt");
+        eprintln!("  --fields <a,b,c>  With --format, show only these fields, in this order");
+        eprintln!("  --where <field><op><value>  With --format, show only records where a field is = != ~ !~ > >= < <= a value; repeatable");
+        eprintln!("  --binary-safe, --raw  Write the file's bytes exactly as read, even if they aren't UTF-8, with status messages on stderr");
+        eprintln!("  --normalize     Turn CRLF line endings into LF and end the last line with a newline (options that rewrite lines do this anyway)");
+        eprintln!("  --verify-passthrough  With --binary-safe, checksum what was read against what was written");
+        eprintln!("  --reassemble    Rejoin lines split by other writers' lines landing mid-line, and mark suspect ones");
+        eprintln!("  --writer-atomicity <bytes>  With --reassemble, the size the writers write in (e.g. 4096), for fewer false cuts");
+        eprintln!("  --json-pretty   Show lines that are JSON objects indented (and in colour on a terminal); other lines as they are");
+        eprintln!("  --compact-json  Collect pretty-printed JSON documents spread over several lines and print each on one line");
+        eprintln!("  --xml-record <element>  Parse each <element>...</element> (which may span lines) into fields, like --format");
+        eprintln!("  --json          With --format, print each record as a JSON object");
+        eprintln!("  --columns       With --format, print records as an aligned table");
+        eprintln!("  --column-max <n>  With --columns, cut values longer than N characters");
+        eprintln!("  --humanize <kind:field,...>  Show fields as sizes/durations (bytes, duration_s, duration_ms, duration_us)");
+        eprintln!("  --reclassify '<regex> => <level>'  With --format, set the level of records with a field matching regex; repeatable");
+        eprintln!("  --enrich <url|query|ua:field,...>  With --format, add URL-decoded / user-agent summary fields");
+        eprintln!("  --enrich <host,env[:VAR],az,version>  Stamp --format records and --forward resources with the host, $RAIL_ENV, cloud zone and rail version");
+        eprintln!("  --expand-encoded  With --format, add a decoded preview of base64 (and base64 gzip) blobs in fields");
+        eprintln!("  --no-enrich     Turn off the enrichers the --format preset enables by default");
+        eprintln!("  --geoip <file.mmdb>  With --format, add country/city/ASN of --geoip-field addresses; repeatable");
+        eprintln!("  --geoip-field <field>  Field holding an IP address to look up; repeatable");
+        eprintln!("  --resolve-ips   With --format, add the reverse DNS name of IP address fields (looked up in the background)");
+        eprintln!("  --grep <regex>  Only show lines that match; repeatable (any may match)");
+        eprintln!("  --exclude <regex>  Leave out lines that match, e.g. health checks; repeatable (also --grep-v)");
+        eprintln!("  --mute 'regex[@08:00-18:00 Mon-Fri]'  Hide matching lines (during those hours), but count them; repeatable");
+        eprintln!("  --mute-file <file>  Read --mute rules from a file, one per line");
+        eprintln!("  --sub 's/regex/replacement/[gi]'  Rewrite matching text in each line; repeatable, applied in order");
+        eprintln!("  --redact <secrets,emails,hostnames,ips>  Mask common kinds of sensitive text");
+        eprintln!("  --demo-safe     Mask everything --redact knows about, for showing live logs to an audience");
+        eprintln!("  --verify-append-only <warn|exit>  With -f, report (or exit 1) if content already read changes or the file is truncated");
+        eprintln!("  --forward otlp://host[:port][/path]  Also send each line to an OpenTelemetry collector (OTLP/HTTP JSON, default port 4318)");
+        eprintln!("  --trace <id>    Only show lines of this distributed trace (W3C traceparent or a trace_id field)");
+        eprintln!("  --highlight <regex>  On a terminal, show matches in colour; repeatable");
+        eprintln!("  --highlight-color <colour>  red, bright-red, bold-red (default), other ANSI colour names, or 0-255");
+        eprintln!("  --color-traces  On a terminal, colour each line by the trace it belongs to");
+        eprintln!("  --trace-link <url>  Print a link the first time a trace is seen; {{trace_id}} and {{span_id}} are filled in");
+        eprintln!("  --trace-events <file.jsonl>  Record what the follow loop sees and decides, for `rail debug-replay`");
+        eprintln!("  --crash-dir <dir>  If rail crashes, write a report with its recent output and state here");
+        eprintln!("  --crash-lines <n>  Lines of output kept for the crash report (default: 100)");
+        eprintln!("  --active-hours '08:00-18:00 Mon-Fri'  With -f, only read during these local-time windows (';' separates groups)");
+        return Ok(());
+    }
+    
+    let command = args.get(1).map_or("", String::as_str);
+    if command == "adb" {
+        return adb::run(&args[2..]);
+    }
+    
+    if command == "state" {
+        return state::command(&args[2..]);
+    }
+    
+    if command == "ctl" {
+        return control::command(&args[2..]);
+    }
+    
+    if command == "attach" {
+        return mirror::command(&args[2..]);
+    }
+    
+    if command == "diff" {
+        return diff::command(&args[2..]);
+    }
+    
+    if command == "export" {
+        return export::command(&args[2..]);
+    }
+    
+    if command == "debug-replay" {
+        let Some(path) = args.get(2) else {
+            eprintln!("Error: debug-replay requires a trace file");
+            process::exit(1);
+        };
+        match trace::replay(path) {
+            Ok(true) => return Ok(()),
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("Error: Could not replay '{}': {}", path, e);
+                process::exit(1);
+            }
+        }
+    }
+    
+    if command == "simulate" {
+        if args.len() < 3 {
+            eprintln!("Error: simulate requires a scenario file");
+            process::exit(1);
+        }
+        let mut passed = true;
+        for path in &args[2..] {
+            match sim::run(path) {
+                Ok(ok) => passed &= ok,
+                Err(e) => {
+                    eprintln!("Error: Could not simulate '{}': {}", path, e);
+                    passed = false;
+                }
+            }
+        }
+        if !passed {
+            process::exit(1);
+        }
         return Ok(());
     }
     
-    let filename = &args[1];
+    let mut filenames = Vec::new();
     let mut follow_mode = false;
-    let mut num_lines = 10;
+    let mut follow_name = false;
+    let mut pid = None;
+    // -q / -v: None is GNU tail's default, headers only with several files
+    let mut headers = None;
+    let mut zero_terminated = false;
+    let mut report_path: Option<String> = None;
+    let mut control_address = None;
+    let mut mirror_address = None;
+    let mut digest_pattern = None;
+    let mut digest_interval = Duration::from_secs(3600);
+    let mut emails = Vec::new();
+    let mut smtp = "localhost:25".to_string();
+    let mut start = Start::Last(10);
+    let mut start_given = false;
+    let mut since = None;
     let mut retry_mode = false;
+    let mut use_index = false;
+    let mut state_path: Option<String> = None;
+    let mut goto_bookmark: Option<String> = None;
+    let mut rebase_mode = false;
+    let mut reopen_each_poll = false;
+    let mut reopen_on_eacces = false;
+    let mut append_only = None;
+    let mut format: Option<fields::Format> = None;
+    let mut xml_element: Option<String> = None;
+    let mut demo_safe = false;
+    let mut compact_json = false;
+    let mut forward = None;
+    let mut reassemble = false;
+    let mut writer_atomicity = None;
+    let mut binary_safe = false;
+    let mut normalize = false;
+    let mut verify_passthrough = false;
+    let mut json = false;
+    let mut columns = false;
+    let mut column_max: Option<usize> = None;
+    let mut humanize_rules = Vec::new();
+    let mut reclassify_rules = Vec::new();
+    let mut shown_fields = None;
+    let mut conditions = Vec::new();
+    let mut enrichers = Vec::new();
+    let mut preset_enrich = true;
+    let mut resolve_ips = false;
+    let mut expand_encoded = false;
+    let mut geoip_dbs = Vec::new();
+    let mut geoip_fields = Vec::new();
+    let mut active_hours = None;
+    let mut crash_dir: Option<String> = None;
+    let mut crash_lines = crash::DEFAULT_LINES;
     
-    let mut i = 2;
+    let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
             "-f" => {
                 follow_mode = true;
                 i += 1;
             }
+            // As in tail, -F is --follow=name --retry
+            "-F" | "--follow=name" => {
+                follow_mode = true;
+                follow_name = true;
+                retry_mode |= args[i] == "-F";
+                i += 1;
+            }
+            "-z" | "--zero-terminated" => {
+                zero_terminated = true;
+                i += 1;
+            }
+            "--a11y" => {
+                a11y::enable();
+                i += 1;
+            }
+            arg if arg.starts_with("--prefix=") => {
+                match output::parse_prefix(&arg["--prefix=".len()..]) {
+                    Ok(prefix) => output::set_prefix(prefix),
+                    Err(e) => {
+                        eprintln!("Error: Invalid --prefix: {}", e);
+                        process::exit(1);
+                    }
+                }
+                i += 1;
+            }
+            "--line-numbers" => {
+                line_numbers::enable();
+                i += 1;
+            }
+            arg if arg == "--timestamps" || arg.starts_with("--timestamps=") => {
+                let format = arg.strip_prefix("--timestamps=").unwrap_or(timestamps::DEFAULT);
+                if let Err(e) = timestamps::set_format(format) {
+                    eprintln!("Error: --timestamps: {}", e);
+                    process::exit(1);
+                }
+                i += 1;
+            }
+            arg if arg == "--compact" || arg.starts_with("--compact=") => {
+                if let Err(e) = compact::enable(arg.strip_prefix("--compact=").unwrap_or("")) {
+                    eprintln!("Error: --compact: {}", e);
+                    process::exit(1);
+                }
+                i += 1;
+            }
+            "-q" | "--quiet" | "--silent" => {
+                headers = Some(false);
+                i += 1;
+            }
+            "-v" | "--verbose" => {
+                headers = Some(true);
+                i += 1;
+            }
+            "-s" | "--sleep-interval" => {
+                if i + 1 < args.len() {
+                    match follow::parse_interval(&args[i + 1]) {
+                        Ok(interval) => follow::set_poll_interval(interval),
+                        Err(e) => {
+                            eprintln!("Error: Invalid {}: {}", args[i], e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: {} requires a number of seconds", args[i]);
+                    process::exit(1);
+                }
+            }
+            "--pid" => {
+                if i + 1 < args.len() {
+                    match args[i + 1].parse::<u32>() {
+                        Ok(n) => pid = Some(n),
+                        Err(_) => {
+                            eprintln!("Error: Invalid --pid: {}", args[i + 1]);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --pid requires a process ID");
+                    process::exit(1);
+                }
+            }
             "--retry" => {
                 retry_mode = true;
                 i += 1;
             }
-            "-n" => {
+            "--index" => {
+                use_index = true;
+                i += 1;
+            }
+            "--rebase" => {
+                rebase_mode = true;
+                i += 1;
+            }
+            "--reopen-each-poll" => {
+                reopen_each_poll = true;
+                i += 1;
+            }
+            "--reopen-on-eacces" => {
+                reopen_on_eacces = true;
+                i += 1;
+            }
+            "--compact-json" => {
+                compact_json = true;
+                i += 1;
+            }
+            "--sequence-field" => {
+                if i + 1 < args.len() {
+                    sequence::set_field(&args[i + 1]);
+                    i += 2;
+                } else {
+                    eprintln!("Error: --sequence-field requires a field name");
+                    process::exit(1);
+                }
+            }
+            "--json-pretty" => {
+                json_pretty::enable();
+                i += 1;
+            }
+            "--reassemble" => {
+                reassemble = true;
+                i += 1;
+            }
+            "--writer-atomicity" => {
                 if i + 1 < args.len() {
                     match args[i + 1].parse::<usize>() {
-                        Ok(n) => num_lines = n,
-                        Err(_) => {
-                            eprintln!("Error: Invalid number of lines: {}", args[i + 1]);
+                        Ok(n) if n > 0 => writer_atomicity = Some(n),
+                        _ => {
+                            eprintln!("Error: Invalid --writer-atomicity: expected a number of bytes, got '{}'", args[i + 1]);
                             process::exit(1);
                         }
                     }
                     i += 2;
                 } else {
-                    eprintln!("Error: -n requires a number argument");
+                    eprintln!("Error: --writer-atomicity requires an argument");
                     process::exit(1);
                 }
             }
-            _ => {
-                eprintln!("Unknown option: {}", args[i]);
-                process::exit(1);
+            "--binary-safe" | "--raw" => {
+                binary_safe = true;
+                i += 1;
             }
-        }
-    }
-
-    // Check if file exists first
-    let path = Path::new(filename);
-    if !path.exists() && !retry_mode {
-        eprintln!("Error: File '{}' not found", filename);
-        process::exit(1);
-    }
-
-    if retry_mode {
-        while !path.exists() {
-            println!("Waiting for file '{}' to appear...", filename);
-            thread::sleep(Duration::from_secs(1));
-        }
-    }
-
-    // Print last N lines
-    match tail_file(filename, num_lines) {
-        Ok(_) => {}
-        Err(e) => {
-            eprintln!("Error reading file: {}", e);
-            if retry_mode {
-                println!("Retrying in 1 second...");
-                thread::sleep(Duration::from_secs(1));
-            } else {
-                process::exit(1);
+            "--normalize" => {
+                normalize = true;
+                i += 1;
             }
-        }
-    }
-
-    // If follow mode, monitor file for changes
-    if follow_mode {
-        println!("Following file '{}'. Press Ctrl+C to stop.", filename);
-        follow_file(filename, retry_mode)?;
-    }
-
-    Ok(())
-}
-
-fn tail_file(filename: &str, num_lines: usize) -> io::Result<()> {
-    let file = File::open(filename)?;
-    let mut reader = BufReader::new(file);
-    
-    let mut lines = Vec::new();
-    let mut line = String::new();
-    
-    while reader.read_line(&mut line)? > 0 {
-        // Handle Windows CRLF line endings
-        if line.ends_with("\r\n") {
-            line.pop();
-            line.pop();
-            line.push('\n');
-        } else if line.ends_with('\n') {
-            // Leave Unix-style line endings as is
-        } else {
-            line.push('\n'); // Add newline if missing
-        }
-        
-        lines.push(line.clone());
-        if lines.len() > num_lines {
-            lines.remove(0);
-        }
-        line.clear();
-    }
-    
-    for line in &lines {
-        print!("{}", line);
-    }
-    
-    io::stdout().flush().unwrap();
-    Ok(())
-}
-
-fn follow_file(filename: &str, retry_mode: bool) -> io::Result<()> {
-    let mut file = match File::open(filename) {
-        Ok(f) => BufReader::new(f),
-        Err(e) => {
-            if retry_mode {
-                println!("Error opening file: {}. Retrying...", e);
-                thread::sleep(Duration::from_secs(1));
-                return follow_file(filename, retry_mode);
-            } else {
-                return Err(e);
+            "--verify-passthrough" => {
+                verify_passthrough = true;
+                i += 1;
             }
-        }
-    };
-    
-    // Seek to the end
-    let mut pos = file.seek(SeekFrom::End(0))?;
-    
-    let mut last_modified = match fs::metadata(filename) {
-        Ok(metadata) => metadata.modified().unwrap_or(SystemTime::now()),
-        Err(_) => SystemTime::now(),
-    };
-    
-    loop {
-        // Check if file has been rotated (common in Windows logs)
-        match fs::metadata(filename) {
-            Ok(metadata) => {
-                let current_modified = metadata.modified().unwrap_or(SystemTime::now());
-                
-                // If the file's modified time changed and it's smaller than before, it was probably rotated
-                let current_size = metadata.len();
-                if current_modified != last_modified && current_size < pos as u64 {
-                    println!("\n--- Log file rotation detected ---\n");
-                    // Reopen the file
-                    drop(file);
-                    file = BufReader::new(File::open(filename)?);
-                    pos = 0;
-                }
-                
-                last_modified = current_modified;
-            },
-            Err(e) => {
-                if retry_mode {
-                    println!("File access error: {}. Retrying...", e);
-                    thread::sleep(Duration::from_secs(1));
-                    continue;
+            "--json" => {
+                json = true;
+                i += 1;
+            }
+            "--columns" => {
+                columns = true;
+                i += 1;
+            }
+            "--humanize" => {
+                if i + 1 < args.len() {
+                    match humanize::parse_rules(&args[i + 1]) {
+                        Ok(rules) => humanize_rules.extend(rules),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --humanize: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
                 } else {
-                    return Err(e);
+                    eprintln!("Error: --humanize requires an argument");
+                    process::exit(1);
                 }
             }
-        }
-        
-        // Seek to where we were before
-        file.seek(SeekFrom::Start(pos))?;
-        
-        let mut buffer = String::new();
-        let bytes_read = file.read_line(&mut buffer)?;
-        
-        if bytes_read > 0 {
-            // Handle Windows CRLF line endings
-            if buffer.ends_with("\r\n") {
-                buffer.pop();
-                buffer.pop();
-                buffer.push('\n');
+            "--mute" => {
+                if i + 1 < args.len() {
+                    match mute::parse_rule(&args[i + 1]) {
+                        Ok(rule) => mute::add_rules(vec![rule]),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --mute: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --mute requires an argument");
+                    process::exit(1);
+                }
             }
-            
-            print!("{}", buffer);
-            io::stdout().flush().unwrap();
-            pos += bytes_read as u64;
-        } else {
-            // No new data, wait a bit before checking again
-            // Windows file locking might prevent access, so we use a shorter interval
-            thread::sleep(Duration::from_millis(100));
-            
-            // Handle the case where the file was truncated (common in log rotation)
-            let metadata = fs::metadata(filename)?;
-            let size = metadata.len();
-            if size < pos {
-                println!("\n--- File was truncated or rotated ---\n");
-                // Start from the beginning
-                file.seek(SeekFrom::Start(0))?;
-                pos = 0;
+            "--mute-file" => {
+                if i + 1 < args.len() {
+                    match mute::read_rules(&args[i + 1]) {
+                        Ok(rules) => mute::add_rules(rules),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --mute-file: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --mute-file requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--logfmt" => {
+                format = Some(fields::Format::Logfmt);
+                i += 1;
+            }
+            "--fields" => {
+                if i + 1 < args.len() {
+                    match select::parse_fields(&args[i + 1]) {
+                        Ok(names) => shown_fields = Some(names),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --fields: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --fields requires a list of field names");
+                    process::exit(1);
+                }
+            }
+            "--where" => {
+                if i + 1 < args.len() {
+                    match select::parse_condition(&args[i + 1]) {
+                        Ok(condition) => conditions.push(condition),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --where: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --where requires a condition");
+                    process::exit(1);
+                }
+            }
+            "--reclassify" => {
+                if i + 1 < args.len() {
+                    match reclassify::parse_rule(&args[i + 1]) {
+                        Ok(rule) => reclassify_rules.push(rule),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --reclassify: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --reclassify requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--resolve-ips" => {
+                resolve_ips = true;
+                i += 1;
+            }
+            "--expand-encoded" => {
+                expand_encoded = true;
+                i += 1;
+            }
+            "--no-enrich" => {
+                preset_enrich = false;
+                i += 1;
+            }
+            "--enrich" => {
+                if i + 1 < args.len() {
+                    match enrich::parse_enrichers(&args[i + 1]) {
+                        Ok(list) => enrichers.extend(list),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --enrich: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --enrich requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--geoip" => {
+                if i + 1 < args.len() {
+                    match geoip::Database::open(&args[i + 1]) {
+                        Ok(db) => geoip_dbs.push(db),
+                        Err(e) => {
+                            eprintln!("Error: Could not load GeoIP database: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --geoip requires a path argument");
+                    process::exit(1);
+                }
             }
+            "--geoip-field" => {
+                if i + 1 < args.len() {
+                    geoip_fields.push(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    eprintln!("Error: --geoip-field requires a field name");
+                    process::exit(1);
+                }
+            }
+            "--column-max" => {
+                if i + 1 < args.len() {
+                    match args[i + 1].parse::<usize>() {
+                        Ok(n) if n > 0 => column_max = Some(n),
+                        _ => {
+                            eprintln!("Error: Invalid column width: {}", args[i + 1]);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --column-max requires a number argument");
+                    process::exit(1);
+                }
+            }
+            "--format" => {
+                if i + 1 < args.len() {
+                    match fields::parse_format(&args[i + 1]) {
+                        Ok(f) => format = Some(f),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --format: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --format requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--xml-record" => {
+                if i + 1 < args.len() {
+                    xml_element = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    eprintln!("Error: --xml-record requires an element name");
+                    process::exit(1);
+                }
+            }
+            "--usn-journal" => {
+                if cfg!(windows) {
+                    watch::set_usn_journal();
+                } else {
+                    eprintln!("Warning: --usn-journal is only available on Windows; ignored");
+                }
+                i += 1;
+            }
+            "--share-mode" => {
+                if i + 1 < args.len() {
+                    match open::parse_share_mode(&args[i + 1]) {
+                        Ok(mode) => open::set_share_mode(mode),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --share-mode: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --share-mode requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--control" => {
+                if i + 1 < args.len() {
+                    match control::parse_address(&args[i + 1]) {
+                        Ok(address) => control_address = Some(address),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --control: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --control requires a socket path");
+                    process::exit(1);
+                }
+            }
+            "--mirror" => {
+                if i + 1 < args.len() {
+                    match mirror::parse_address(&args[i + 1]) {
+                        Ok(address) => mirror_address = Some(address),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --mirror: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --mirror requires a socket (unix://<path> or tcp://127.0.0.1:<port>)");
+                    process::exit(1);
+                }
+            }
+            "--report" => {
+                if i + 1 < args.len() {
+                    report_path = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    eprintln!("Error: --report requires a file path");
+                    process::exit(1);
+                }
+            }
+            "--grep" => {
+                if i + 1 < args.len() {
+                    match regex::Regex::new(&args[i + 1]) {
+                        Ok(re) => grep::add(re),
+                        Err(e) => {
+                            eprintln!("Error: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --grep requires a regex argument");
+                    process::exit(1);
+                }
+            }
+            "--exclude" | "--grep-v" => {
+                if i + 1 < args.len() {
+                    match regex::Regex::new(&args[i + 1]) {
+                        Ok(re) => grep::exclude(re),
+                        Err(e) => {
+                            eprintln!("Error: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: {} requires a regex argument", args[i]);
+                    process::exit(1);
+                }
+            }
+            "--digest" => {
+                if i + 1 < args.len() {
+                    match regex::Regex::new(&args[i + 1]) {
+                        Ok(re) => digest_pattern = Some(re),
+                        Err(e) => {
+                            eprintln!("Error: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --digest requires a regex argument");
+                    process::exit(1);
+                }
+            }
+            "--digest-interval" => {
+                if i + 1 < args.len() {
+                    match digest::parse_interval(&args[i + 1]) {
+                        Ok(interval) => digest_interval = interval,
+                        Err(e) => {
+                            eprintln!("Error: Invalid --digest-interval: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --digest-interval requires a duration argument");
+                    process::exit(1);
+                }
+            }
+            "--email" => {
+                if i + 1 < args.len() {
+                    emails.push(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    eprintln!("Error: --email requires an address");
+                    process::exit(1);
+                }
+            }
+            "--smtp" => {
+                if i + 1 < args.len() {
+                    smtp = digest::parse_smtp(&args[i + 1]);
+                    i += 2;
+                } else {
+                    eprintln!("Error: --smtp requires host[:port]");
+                    process::exit(1);
+                }
+            }
+            "--sound" => {
+                if i + 1 < args.len() {
+                    match sound::parse_rules(&args[i + 1]) {
+                        Ok(rules) => sound::add_rules(rules),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --sound: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --sound requires '<regex> => <cue>' or 'severity'");
+                    process::exit(1);
+                }
+            }
+            "--per-source-limit" => {
+                if i + 1 < args.len() {
+                    match limit::parse(&args[i + 1]) {
+                        Ok(n) => limit::set(n),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --per-source-limit: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --per-source-limit requires a rate like 100/s");
+                    process::exit(1);
+                }
+            }
+            "--fail-on" => {
+                if i + 1 < args.len() {
+                    match regex::Regex::new(&args[i + 1]) {
+                        Ok(re) => fail_on::set_pattern(re),
+                        Err(e) => {
+                            eprintln!("Error: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --fail-on requires a regex argument");
+                    process::exit(1);
+                }
+            }
+            "--sub" => {
+                if i + 1 < args.len() {
+                    match sub::parse_rule(&args[i + 1]) {
+                        Ok(rule) => sub::add_rule(rule),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --sub: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --sub requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--redact" | "--demo-safe" => {
+                let presets = if args[i] == "--demo-safe" {
+                    demo_safe = true;
+                    i += 1;
+                    sub::DEMO_SAFE
+                } else if i + 1 < args.len() {
+                    i += 2;
+                    &args[i - 1]
+                } else {
+                    eprintln!("Error: --redact requires an argument");
+                    process::exit(1);
+                };
+                match sub::preset_rules(presets) {
+                    Ok(rules) => rules.into_iter().for_each(sub::add_rule),
+                    Err(e) => {
+                        eprintln!("Error: Invalid --redact: {}", e);
+                        process::exit(1);
+                    }
+                }
+            }
+            "--verify-append-only" => {
+                if i + 1 < args.len() {
+                    match append_only::parse_mode(&args[i + 1]) {
+                        Ok(mode) => append_only = Some(mode),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --verify-append-only: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --verify-append-only requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--forward" => {
+                if i + 1 < args.len() {
+                    match otlp::parse_target(&args[i + 1]) {
+                        Ok(target) => forward = Some(target),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --forward: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --forward requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--trace" => {
+                if i + 1 < args.len() {
+                    if let Err(e) = trace_context::set_only(&args[i + 1]) {
+                        eprintln!("Error: Invalid --trace: {}", e);
+                        process::exit(1);
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --trace requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--highlight" => {
+                if i + 1 < args.len() {
+                    match regex::Regex::new(&args[i + 1]) {
+                        Ok(re) => highlight::add(re),
+                        Err(e) => {
+                            eprintln!("Error: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --highlight requires a regex argument");
+                    process::exit(1);
+                }
+            }
+            "--highlight-color" => {
+                if i + 1 < args.len() {
+                    match highlight::parse_color(&args[i + 1]) {
+                        Ok(sgr) => highlight::set_color(sgr),
+                        Err(e) => {
+                            eprintln!("Error: --highlight-color: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --highlight-color requires a colour");
+                    process::exit(1);
+                }
+            }
+            "--color-traces" => {
+                trace_context::set_color();
+                i += 1;
+            }
+            "--trace-link" => {
+                if i + 1 < args.len() {
+                    if let Err(e) = trace_context::set_link(&args[i + 1]) {
+                        eprintln!("Error: Invalid --trace-link: {}", e);
+                        process::exit(1);
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --trace-link requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--trace-events" => {
+                if i + 1 < args.len() {
+                    if let Err(e) = trace::enable(&args[i + 1]) {
+                        eprintln!("Error: Could not create trace file '{}': {}", args[i + 1], e);
+                        process::exit(1);
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --trace-events requires a path argument");
+                    process::exit(1);
+                }
+            }
+            "--crash-dir" => {
+                if i + 1 < args.len() {
+                    crash_dir = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    eprintln!("Error: --crash-dir requires a path argument");
+                    process::exit(1);
+                }
+            }
+            "--crash-lines" => {
+                if i + 1 < args.len() {
+                    match args[i + 1].parse::<usize>() {
+                        Ok(n) => crash_lines = n,
+                        Err(_) => {
+                            eprintln!("Error: Invalid number of lines: {}", args[i + 1]);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --crash-lines requires a number argument");
+                    process::exit(1);
+                }
+            }
+            "--active-hours" => {
+                if i + 1 < args.len() {
+                    match active_hours::parse_schedule(&args[i + 1]) {
+                        Ok(schedule) => active_hours = Some(schedule),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --active-hours: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --active-hours requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--flush" => {
+                if i + 1 < args.len() {
+                    match output::parse_flush(&args[i + 1]) {
+                        Ok(policy) => output::set_flush(policy),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --flush: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --flush requires an argument");
+                    process::exit(1);
+                }
+            }
+            "--compression" => {
+                if i + 1 < args.len() {
+                    if let Err(e) = compressed::set_forced(&args[i + 1]) {
+                        eprintln!("Error: Invalid --compression: {}", e);
+                        process::exit(1);
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --compression requires a format");
+                    process::exit(1);
+                }
+            }
+            "--encoding" => {
+                if i + 1 < args.len() {
+                    if let Err(e) = encoding::set_forced(&args[i + 1]) {
+                        eprintln!("Error: Invalid --encoding: {}", e);
+                        process::exit(1);
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --encoding requires an encoding");
+                    process::exit(1);
+                }
+            }
+            "--since" => {
+                if i + 1 < args.len() {
+                    since = Some(export::parse_time("--since", &args[i + 1]));
+                    i += 2;
+                } else {
+                    eprintln!("Error: --since requires a time");
+                    process::exit(1);
+                }
+            }
+            "--live-first" => {
+                live_first::enable();
+                i += 1;
+            }
+            "--rotated" => {
+                rotated::enable();
+                i += 1;
+            }
+            "--goto-bookmark" => {
+                if i + 1 < args.len() {
+                    goto_bookmark = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    eprintln!("Error: --goto-bookmark requires a bookmark name");
+                    process::exit(1);
+                }
+            }
+            "--backfill-max" => {
+                if i + 1 < args.len() {
+                    match args[i + 1].parse::<usize>() {
+                        Ok(n) => backfill::set_max_lines(n),
+                        Err(_) => {
+                            eprintln!("Error: Invalid --backfill-max: expected a number of lines, got '{}'", args[i + 1]);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --backfill-max requires a number of lines");
+                    process::exit(1);
+                }
+            }
+            "--backfill-max-bytes" => {
+                if i + 1 < args.len() {
+                    match backfill::parse_size(&args[i + 1]) {
+                        Ok(n) => backfill::set_max_bytes(n),
+                        Err(e) => {
+                            eprintln!("Error: Invalid --backfill-max-bytes: {}", e);
+                            process::exit(1);
+                        }
+                    }
+                    i += 2;
+                } else {
+                    eprintln!("Error: --backfill-max-bytes requires a size");
+                    process::exit(1);
+                }
+            }
+            "--state-file" => {
+                if i + 1 < args.len() {
+                    state_path = Some(args[i + 1].clone());
+                    i += 2;
+                } else {
+                    eprintln!("Error: --state-file requires a path argument");
+                    process::exit(1);
+                }
+            }
+            "-n" => {
+                if i + 1 < args.len() {
+                    let (from_start, count) = match args[i + 1].strip_prefix('+') {
+                        Some(count) => (true, count),
+                        None => (false, args[i + 1].as_str()),
+                    };
+                    match count.parse::<usize>() {
+                        Ok(n) if from_start => start = Start::FromLine(n),
+                        Ok(n) => start = Start::Last(n),
+                        Err(_) => {
+                            eprintln!("Error: Invalid number of lines: {}", args[i + 1]);
+                            process::exit(1);
+                        }
+                    }
+                    start_given = true;
+                    i += 2;
+                } else {
+                    eprintln!("Error: -n requires a number argument");
+                    process::exit(1);
+                }
+            }
+            "-c" | "--bytes" => {
+                if i + 1 < args.len() {
+                    match args[i + 1].parse::<u64>() {
+                        Ok(n) => start = Start::LastBytes(n),
+                        Err(_) => {
+                            eprintln!("Error: Invalid number of bytes: {}", args[i + 1]);
+                            process::exit(1);
+                        }
+                    }
+                    start_given = true;
+                    i += 2;
+                } else {
+                    eprintln!("Error: {} requires a number argument", args[i]);
+                    process::exit(1);
+                }
+            }
+            name if name == open::STDIN || !name.starts_with('-') => {
+                filenames.push(name.to_string());
+                i += 1;
+            }
+            _ => {
+                eprintln!("Unknown option: {}", args[i]);
+                process::exit(1);
+            }
+        }
+    }
+
+    // Patterns the shell left alone (quoted, or on Windows) are rail's to expand
+    let patterns: Vec<String> = filenames.iter().filter(|name| glob::is_pattern(name)).cloned().collect();
+    if !patterns.is_empty() {
+        let mut expanded: Vec<String> = Vec::new();
+        for name in filenames.drain(..) {
+            let matches = if patterns.contains(&name) { glob::expand(&name) } else { vec![name] };
+            for name in matches {
+                if !expanded.contains(&name) {
+                    expanded.push(name);
+                }
+            }
+        }
+        filenames = expanded;
+        if filenames.is_empty() && !follow_mode {
+            eprintln!("Error: No files match '{}'", patterns.join("' or '"));
+            process::exit(1);
+        }
+        if filenames.is_empty() {
+            output::status(format_args!("Waiting for files matching '{}' to appear...", patterns.join("' or '")));
+            while filenames.is_empty() {
+                thread::sleep(glob::RESCAN);
+                filenames = glob::expand_all(&patterns);
+            }
+        }
+    }
+    if filenames.is_empty() {
+        filenames.push(open::STDIN.to_string());
+    }
+    let filename = &filenames[0].clone();
+    // With -f, a pattern may match more files later, so it's followed as several files
+    let several = filenames.len() > 1 || (follow_mode && !patterns.is_empty());
+
+    if active_hours.is_some() && !follow_mode {
+        eprintln!("Error: --active-hours requires -f");
+        process::exit(1);
+    }
+    if append_only.is_some() && !follow_mode {
+        eprintln!("Error: --verify-append-only requires -f");
+        process::exit(1);
+    }
+    if let Some(pid) = pid {
+        if !follow_mode {
+            eprintln!("Error: --pid requires -f");
+            process::exit(1);
+        }
+        if let Err(e) = pid::set(pid) {
+            eprintln!("Error: Can't watch process {}: {}", pid, e);
+            process::exit(1);
+        }
+    }
+
+    // Options that keep per-file state in one place, or whose output must be the file's
+    if several
+        && (state_path.is_some() || format.is_some() || xml_element.is_some() || compact_json || reassemble || binary_safe || forward.is_some() || live_first::enabled())
+    {
+        eprintln!("Error: --state-file, --format, --xml-record, --compact-json, --reassemble, --binary-safe, --forward and --live-first work with one file only");
+        process::exit(1);
+    }
+    if limit::enabled() && !several {
+        eprintln!("Error: --per-source-limit is for merging several files");
+        process::exit(1);
+    }
+
+    match digest_pattern {
+        Some(pattern) if !emails.is_empty() => {
+            digest::enable(digest::Digest { pattern, interval: digest_interval, to: emails, smtp }, &filenames.join(", "));
+        }
+        Some(_) => {
+            eprintln!("Error: --digest requires --email");
+            process::exit(1);
+        }
+        None if !emails.is_empty() => {
+            eprintln!("Error: --email requires --digest");
+            process::exit(1);
+        }
+        None => {}
+    }
+    if let Some(path) = &report_path {
+        report::enable(path, &filenames);
+    }
+    if let Some(address) = control_address {
+        if !follow_mode {
+            eprintln!("Error: --control requires -f");
+            process::exit(1);
+        }
+        if let Err(e) = control::listen(address) {
+            eprintln!("Error: Could not open the control socket: {}", e);
+            process::exit(1);
+        }
+    }
+    if let Some(address) = mirror_address
+        && let Err(e) = mirror::listen(address)
+    {
+        eprintln!("Error: Could not open the mirror socket: {}", e);
+        process::exit(1);
+    }
+    if let Some(dir) = &crash_dir
+        && let Err(e) = crash::enable(dir, crash_lines, &filenames.join(", "))
+    {
+        eprintln!("Error: Could not set up crash reports in '{}': {}", dir, e);
+        process::exit(1);
+    }
+
+    if rebase_mode && state_path.is_none() {
+        eprintln!("Error: --rebase requires --state-file");
+        process::exit(1);
+    }
+    if live_first::enabled() && !follow_mode {
+        eprintln!("Error: --live-first requires -f");
+        process::exit(1);
+    }
+    if rotated::enabled() && !matches!(start, Start::Last(_)) {
+        eprintln!("Error: --rotated makes up the lines -n N asks for, and can't be combined with -c or -n +N");
+        process::exit(1);
+    }
+    if rotated::enabled() && live_first::enabled() {
+        eprintln!("Error: --rotated can't be combined with --live-first");
+        process::exit(1);
+    }
+    if since.is_some() && (start_given || state_path.is_some() || rotated::enabled() || live_first::enabled() || several) {
+        eprintln!("Error: --since says where one file's output starts, and can't be combined with -n, -c, --state-file, --rotated, --live-first or several files");
+        process::exit(1);
+    }
+    if goto_bookmark.is_some() && state_path.is_none() {
+        eprintln!("Error: --goto-bookmark requires --state-file");
+        process::exit(1);
+    }
+
+    let mut state = match &state_path {
+        Some(p) => match StateFile::load(p) {
+            Ok(st) => Some(st),
+            Err(e) => {
+                eprintln!("Error: Could not read state file '{}': {}", p, e);
+                process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    // Check if the files exist first
+    for filename in filenames.iter().filter(|name| *name != open::STDIN) {
+        let path = Path::new(filename);
+        if !path.exists() && !retry_mode {
+            eprintln!("Error: File '{}' not found", filename);
+            process::exit(1);
+        }
+
+        if retry_mode {
+            while !path.exists() {
+                output::status(format_args!("Waiting for file '{}' to appear...", filename));
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    }
+
+    // The kernel log is always in kmsg format; other formats make no sense for it
+    let is_kmsg = kmsg::is_kmsg(filename);
+    if is_kmsg {
+        format = Some(fields::Format::Kmsg);
+    }
+    if let Some(element) = &xml_element {
+        if format.is_some() {
+            eprintln!("Error: --xml-record can't be combined with --format");
+            process::exit(1);
+        }
+        if !element.chars().all(|c| c.is_alphanumeric() || "_-.:".contains(c)) || element.is_empty() {
+            eprintln!("Error: Invalid --xml-record: '{}' is not an element name", element);
+            process::exit(1);
+        }
+        format = Some(fields::Format::Xml);
+    }
+    if verify_passthrough && !binary_safe {
+        eprintln!("Error: --verify-passthrough requires --binary-safe");
+        process::exit(1);
+    }
+    if writer_atomicity.is_some() && !reassemble {
+        eprintln!("Error: --writer-atomicity requires --reassemble");
+        process::exit(1);
+    }
+    let bytes_mode = matches!(start, Start::LastBytes(_));
+    if bytes_mode && live_first::enabled() {
+        eprintln!("Error: -c can't be combined with --live-first, whose history is marked off in lines");
+        process::exit(1);
+    }
+    if bytes_mode && backfill::enabled() {
+        eprintln!("Error: -c already bounds what is printed; --backfill-max and --backfill-max-bytes are for line modes");
+        process::exit(1);
+    }
+    // Options that work on lines, and need them as UTF-8 ending in LF
+    let rewrites_lines = format.is_some() || xml_element.is_some() || compact_json || reassemble || sub::has_rules() || mute::has_rules() || trace_context::enabled() || grep::enabled() || compact::enabled() || highlight::enabled() || timestamps::enabled() || line_numbers::enabled() || output::prefix_enabled() || json_pretty::enabled() || sequence::enabled();
+    if bytes_mode && rewrites_lines {
+        eprintln!("Error: -c passes bytes through as they are; it can't be combined with options that work on lines (--format, --xml-record, --compact-json, --reassemble, --sub, --redact, --mute, --trace options, --grep, --exclude, --compact, --highlight, --timestamps, --line-numbers, --prefix, --json-pretty, --sequence-field)");
+        process::exit(1);
+    }
+    if binary_safe && rewrites_lines {
+        eprintln!("Error: --binary-safe can't be combined with options that rewrite lines (--format, --xml-record, --compact-json, --reassemble, --sub, --redact, --mute, --trace options, --grep, --exclude, --compact, --highlight, --timestamps, --line-numbers, --prefix, --json-pretty, --sequence-field)");
+        process::exit(1);
+    }
+    if encoding::forced() && (binary_safe || bytes_mode) {
+        eprintln!("Error: --encoding has lines printed as UTF-8, and -c and --binary-safe pass bytes through as they are");
+        process::exit(1);
+    }
+    if zero_terminated {
+        if use_index || reassemble {
+            eprintln!("Error: -z can't be combined with --index or --reassemble, which look for newlines");
+            process::exit(1);
+        }
+        output::set_zero_terminated();
+    }
+    if normalize && (binary_safe || bytes_mode) {
+        eprintln!("Error: --normalize changes line endings, and -c and --binary-safe pass bytes through as they are");
+        process::exit(1);
+    }
+    // Bytes from the middle of a line or character go out exactly like --binary-safe's
+    if binary_safe || bytes_mode {
+        output::set_binary_safe();
+    } else if normalize || rewrites_lines {
+        output::set_normalize();
+    }
+    if verify_passthrough {
+        passthrough::enable();
+    }
+    if compact_json {
+        json::enable();
+    }
+    if reassemble {
+        interleave::enable(writer_atomicity);
+    }
+    if let Some(target) = forward {
+        let attributes: Vec<_> = enrichers.iter().filter_map(|e| e.resource_attribute()).collect();
+        otlp::enable(target, filename, &attributes);
+    }
+    if json && format.is_none() {
+        eprintln!("Error: --json requires --format");
+        process::exit(1);
+    }
+    if (columns || column_max.is_some()) && a11y::enabled() {
+        eprintln!("Warning: --columns is a visual layout; with --a11y records stay key=value");
+        columns = false;
+        column_max = None;
+    }
+    if (columns || column_max.is_some()) && format.is_none() {
+        eprintln!("Error: --columns requires --format");
+        process::exit(1);
+    }
+    if !humanize_rules.is_empty() && format.is_none() {
+        eprintln!("Error: --humanize requires --format");
+        process::exit(1);
+    }
+    if !reclassify_rules.is_empty() && format.is_none() {
+        eprintln!("Error: --reclassify requires --format");
+        process::exit(1);
+    }
+    if (shown_fields.is_some() || !conditions.is_empty()) && format.is_none() {
+        eprintln!("Error: --fields and --where require --format");
+        process::exit(1);
+    }
+    if enrichers.iter().any(|e| e.needs_fields()) && format.is_none() {
+        eprintln!("Error: --enrich url/ua require --format");
+        process::exit(1);
+    }
+    if !enrichers.is_empty() && format.is_none() && !otlp::enabled() {
+        eprintln!("Error: --enrich host/env/az/version require --format or --forward");
+        process::exit(1);
+    }
+    if expand_encoded && format.is_none() {
+        eprintln!("Error: --expand-encoded requires --format");
+        process::exit(1);
+    }
+    if resolve_ips && demo_safe {
+        eprintln!("Error: --resolve-ips would print real hostnames; it can't be used with --demo-safe");
+        process::exit(1);
+    }
+    if resolve_ips && format.is_none() {
+        eprintln!("Error: --resolve-ips requires --format");
+        process::exit(1);
+    }
+    if geoip_dbs.is_empty() != geoip_fields.is_empty() {
+        eprintln!("Error: --geoip and --geoip-field must be used together");
+        process::exit(1);
+    }
+    if !geoip_dbs.is_empty() && format.is_none() {
+        eprintln!("Error: --geoip requires --format");
+        process::exit(1);
+    }
+    if let Some(format) = format {
+        fields::set_format(format);
+        fields::set_json(json);
+        if let Some(element) = &xml_element {
+            fields::set_xml_element(element);
+        }
+        if columns || column_max.is_some() {
+            fields::set_columns(column_max);
+        }
+        fields::add_humanize(humanize_rules);
+        fields::add_reclassify(reclassify_rules);
+        if let Some(names) = shown_fields {
+            fields::set_shown(names);
+        }
+        fields::add_conditions(conditions);
+        fields::set_enrichers(preset_enrich, enrichers);
+        if expand_encoded {
+            encoded::enable();
+        }
+        if resolve_ips {
+            dns::enable();
+        }
+        if !geoip_dbs.is_empty() {
+            fields::set_geoip(geoip::GeoIp::new(geoip_dbs, geoip_fields));
+        }
+        fields::prime(filename);
+    }
+
+    // Pipes, devices and generated files can't be indexed or resumed by offset
+    let kind = pseudo::file_kind(filename).unwrap_or(FileKind::Regular);
+    if kind == FileKind::Pseudo && binary_safe && follow_mode {
+        eprintln!("Error: --binary-safe can't follow '{}': it is regenerated, not appended to", filename);
+        process::exit(1);
+    }
+    if kind != FileKind::Regular {
+        if use_index || state.is_some() || active_hours.is_some() {
+            eprintln!("Warning: '{}' is not a regular file; ignoring --index, --state-file and --active-hours", filename);
+        }
+        use_index = false;
+        state = None;
+        active_hours = None;
+    }
+    let compression = compressed::detect(filename);
+    if let Some(compression) = compression
+        && (bytes_mode || use_index || state.is_some())
+    {
+        eprintln!("Error: '{}' is {}-compressed; -c, --index and --state-file work on positions in the file and can't be used with it", filename, compression.name());
+        process::exit(1);
+    }
+    if since.is_some() && (kind != FileKind::Regular || compression.is_some()) {
+        eprintln!("Error: --since needs a regular, uncompressed file, and '{}' is not one", filename);
+        process::exit(1);
+    }
+    if live_first::enabled() && (kind != FileKind::Regular || compression.is_some() || is_kmsg) {
+        eprintln!("Error: --live-first needs a regular, uncompressed file, and '{}' is not one", filename);
+        process::exit(1);
+    }
+    encoding::use_for(filename);
+    if use_index && encoding::unit() == 2 {
+        eprintln!("Error: '{}' is UTF-16; --index counts lines by their bytes and can't be used with it", filename);
+        process::exit(1);
+    }
+    if let Some(schedule) = active_hours {
+        active_hours::set_schedule(schedule);
+        active_hours::wait();
+    }
+
+    match headers {
+        Some(false) => output::set_quiet(),
+        Some(true) if !several => {
+            output::set_source(filename);
+            output::announce_source();
+        }
+        None if output::prefix_enabled() => output::set_quiet(),
+        _ => {}
+    }
+    if output::prefix_enabled() && !several {
+        output::set_source(filename);
+    }
+    if several {
+        let opts = FollowOptions { retry_mode, use_index, reopen_each_poll, reopen_on_eacces, follow_name, append_only };
+        tail_many(&filenames, &patterns, start, follow_mode, &opts)?;
+        return finish();
+    }
+    
+    // A pipe's end is the end of what there is to decompress
+    if let Some(compression) = compression {
+        compressed::tail(filename, compression, Some(start), follow_mode && kind == FileKind::Regular, true)?;
+        return finish();
+    }
+    
+    if is_kmsg && let Start::Last(num_lines) = start {
+        kmsg::tail(filename, num_lines, follow_mode)?;
+        return finish();
+    }
+
+    // A stream has no "last N lines" until it ends, so in follow mode just pass it through
+    if kind == FileKind::Stream && follow_mode {
+        output::following(filename);
+        let skip = if let Start::FromLine(line) = start { line.saturating_sub(1) } else { 0 };
+        pseudo::follow_stream(filename, skip)?;
+        return finish();
+    }
+
+    // Start at the bookmark asked for, or resume from the recorded position if there is
+    // one, otherwise print last N lines
+    if state.is_some() {
+        state::accept_bookmarks();
+    }
+    let resume = match (&state, &goto_bookmark) {
+        (Some(st), Some(name)) => match st.bookmark_offset(filename, name) {
+            Ok(offset) => Some((offset, None)),
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        },
+        (Some(st), None) => st.resume_offset(filename, rebase_mode).unwrap_or(None),
+        (None, _) => None,
+    };
+    let result = match resume {
+        Some((offset, rebased_from)) => {
+            if let Some(old_path) = rebased_from {
+                output::status(format_args!("\n--- Rebased onto position recorded for '{}' ---\n", old_path));
+            }
+            if live_first::enabled() {
+                live_first::spawn(filename, live_first::History::From(offset));
+                Ok(offset)
+            } else {
+                print_from(filename, offset)
+            }
+        }
+        // Following starts at the end as it is now, and the history is read in the
+        // background up to there
+        None if live_first::enabled() => {
+            // -c has been refused
+            let history = match start {
+                Start::FromLine(line) => live_first::History::FromLine(line),
+                Start::Last(num_lines) => live_first::History::Last(num_lines),
+                Start::LastBytes(_) => live_first::History::Last(0),
+            };
+            live_first::spawn(filename, history);
+            open_log(filename).and_then(|mut file| file.seek(SeekFrom::End(0)))
+        }
+        // Streams and generated files are read through, as they can't seek
+        None => match (since, kind, start) {
+            (Some(since), _, _) => offset_since(filename, since, use_index).and_then(|offset| print_from(filename, offset)),
+            (None, FileKind::Regular, _) | (None, _, Start::Last(_)) => print_start(filename, start, use_index),
+            (None, _, Start::FromLine(line)) => pseudo::print_from_line(filename, line),
+            (None, _, Start::LastBytes(num_bytes)) => pseudo::tail_bytes(filename, num_bytes),
+        },
+    };
+    match result {
+        Ok(end) => {
+            crash::set_offset(end);
+            save_state(&mut state, filename, end);
+        }
+        Err(e) => {
+            eprintln!("Error reading file: {}", e);
+            report::error(format!("{}: {}", filename, e));
+            if retry_mode {
+                output::status(format_args!("Retrying in 1 second..."));
+                thread::sleep(Duration::from_secs(1));
+            } else {
+                exit_with_report(1);
+            }
+        }
+    }
+
+    // If follow mode, monitor file for changes
+    if follow_mode {
+        output::following(filename);
+        if kind == FileKind::Pseudo {
+            pseudo::follow_snapshots(filename)?;
+            return finish();
+        }
+        let opts = FollowOptions { retry_mode, use_index, reopen_each_poll, reopen_on_eacces, follow_name, append_only };
+        follow_file(&RealFs, &RealClock, filename, &opts, &mut state)?;
+    }
+
+    finish()
+}
+
+// Reports and the exit status once rail is done with its input
+fn finish() -> io::Result<()> {
+    output::finishing();
+    control::finish();
+    otlp::finish();
+    digest::finish();
+    sound::finish();
+    if let Some(report) = mute::report() {
+        output::status(format_args!("{}", report));
+    }
+    if let Some(report) = sequence::report() {
+        output::status(format_args!("{}", report));
+    }
+    if let Some(report) = passthrough::report() {
+        eprintln!("{}", report);
+    }
+    output::flush();
+    mirror::finish();
+    exit_with_report(fail_on::matched() as i32);
+    Ok(())
+}
+
+// Write the --report (if any) and exit unless the status is 0
+fn exit_with_report(status: i32) {
+    if let Err(e) = report::write(status) {
+        eprintln!("Error: Could not write report: {}", e);
+    }
+    if status != 0 {
+        process::exit(status);
+    }
+}
+
+// Several files, GNU tail style: the last lines of each under a "==> name <==" header,
+// then with -f all of them followed at once, one thread each, with a header whenever
+// the output switches to another file
+fn tail_many(
+    filenames: &[String],
+    patterns: &[String],
+    start: Start,
+    follow_mode: bool,
+    opts: &FollowOptions,
+) -> io::Result<()> {
+    for filename in filenames {
+        if pseudo::file_kind(filename).unwrap_or(FileKind::Regular) != FileKind::Regular {
+            eprintln!("Error: '{}' is not a regular file; it can only be tailed on its own", filename);
+            process::exit(1);
+        }
+        if let Some(compression) = compressed::detect(filename)
+            && (matches!(start, Start::LastBytes(_)) || opts.use_index)
+        {
+            eprintln!("Error: '{}' is {}-compressed; -c and --index work on positions in the file and can't be used with it", filename, compression.name());
+            process::exit(1);
+        }
+        if opts.use_index && matches!(encoding::detect(filename), Some(Encoding::Utf16Le | Encoding::Utf16Be)) {
+            eprintln!("Error: '{}' is UTF-16; --index counts lines by their bytes and can't be used with it", filename);
+            process::exit(1);
+        }
+    }
+    for filename in filenames {
+        output::set_source(filename);
+        report::set_input(filename);
+        encoding::use_for(filename);
+        output::announce_source();
+        let result = match compressed::detect(filename) {
+            Some(compression) => compressed::tail(filename, compression, Some(start), false, false),
+            None => print_start(filename, start, opts.use_index).map(|_| ()),
+        };
+        if let Err(e) = result {
+            output::flush();
+            eprintln!("Error reading '{}': {}", filename, e);
+            report::error(format!("{}: {}", filename, e));
+            if !opts.retry_mode {
+                exit_with_report(1);
+            }
+        }
+    }
+    if !follow_mode {
+        return Ok(());
+    }
+
+    let files = if filenames.len() == 1 { "file" } else { "files" };
+    output::status(format_args!("Following {} {}. Press Ctrl+C to stop.", filenames.len(), files));
+    thread::scope(|scope| {
+        for filename in filenames {
+            scope.spawn(move || {
+                output::set_source(filename);
+                report::set_input(filename);
+                encoding::use_for(filename);
+                let result = match compressed::detect(filename) {
+                    // Decompressed again from the start, to know where appended data begins
+                    Some(compression) => compressed::tail(filename, compression, None, true, false),
+                    None => follow_file(&RealFs, &RealClock, filename, opts, &mut None),
+                };
+                if let Err(e) = result {
+                    output::flush();
+                    eprintln!("Error following '{}': {}", filename, e);
+                    report::error(format!("{}: {}", filename, e));
+                }
+            });
+        }
+        // Files that match a pattern from now on are new, so all of each is shown
+        let mut known = filenames.to_vec();
+        while !patterns.is_empty() && !report::interrupted() && !pid::exited() {
+            thread::sleep(glob::RESCAN);
+            for filename in glob::expand_all(patterns) {
+                if known.contains(&filename) {
+                    continue;
+                }
+                known.push(filename.clone());
+                scope.spawn(move || {
+                    output::set_source(&filename);
+                    report::set_input(&filename);
+                    encoding::use_for(&filename);
+                    let result = match compressed::detect(&filename) {
+                        Some(compression) => compressed::tail(&filename, compression, Some(Start::FromLine(1)), true, false),
+                        None => print_from(&filename, 0).and_then(|_| follow_file(&RealFs, &RealClock, &filename, opts, &mut None)),
+                    };
+                    if let Err(e) = result {
+                        output::flush();
+                        eprintln!("Error following '{}': {}", filename, e);
+                        report::error(format!("{}: {}", filename, e));
+                    }
+                });
+            }
+        }
+    });
+    Ok(())
+}
+
+// Open (or build) the sidecar index; failures only cost us the speedup, so just warn
+fn open_index(filename: &str) -> Option<LineIndex> {
+    match LineIndex::open(filename) {
+        Ok(index) => Some(index),
+        Err(e) => {
+            eprintln!("Warning: Could not update index for '{}': {}", filename, e);
+            None
+        }
+    }
+}
+
+// --since: where the first line logged at or after `since` starts
+fn offset_since(filename: &str, since: u128, use_index: bool) -> io::Result<u64> {
+    let mut file = open_log(filename)?;
+    if use_index && let Some(index) = open_index(filename) {
+        return index.offset_since(since, &mut file);
+    }
+    since::find(&mut file, 0, None, since)
+}
+
+// Where the output of a file starts, before following it
+#[derive(Clone, Copy)]
+enum Start {
+    // -n N
+    Last(usize),
+    // -n +N (1-based, as in tail)
+    FromLine(usize),
+    // -c N
+    LastBytes(u64),
+}
+
+// Print the start of the output; returns the offset reading stopped at
+fn print_start(filename: &str, start: Start, use_index: bool) -> io::Result<u64> {
+    match start {
+        Start::Last(num_lines) => tail_file(filename, num_lines, use_index),
+        Start::FromLine(line) => print_from_line(filename, line, use_index),
+        Start::LastBytes(num_bytes) => tail_bytes(filename, num_bytes),
+    }
+}
+
+// Print the last `num_lines` lines; returns the offset reading stopped at
+fn tail_file(filename: &str, num_lines: usize, use_index: bool) -> io::Result<u64> {
+    let mut file = open_log(filename)?;
+    let mut offset = 0;
+    
+    // With an index we can jump straight to the first line we need; without one, find
+    // it by scanning back from the end
+    if use_index && let Some(index) = open_index(filename) {
+        offset = index.offset_of_last(num_lines, &mut file)?;
+        file.seek(SeekFrom::Start(offset))?;
+    } else if let Some(start) = start_of_last(&mut file, num_lines)? {
+        offset = start;
+        file.seek(SeekFrom::Start(offset))?;
+    }
+    offset = backfill::limit(&mut file, offset)?;
+    let whole = offset == 0;
+    
+    let before = if line_numbers::enabled() { line_numbers::lines_before(filename, offset)? } else { 0 };
+    let mut reader = BufReader::new(file);
+    
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    let mut read = 0;
+    
+    while encoding::read_line(&mut reader, &mut line)? > 0 {
+        offset += line.len() as u64;
+        read += 1;
+        lines.push(std::mem::take(&mut line));
+        if lines.len() > num_lines {
+            lines.remove(0);
+        }
+    }
+    if rotated::enabled() && whole && read < num_lines {
+        rotated::print_older(filename, num_lines, read, offset)?;
+    }
+    line_numbers::start_at(before + (read - lines.len()) as u64 + 1);
+    
+    // With --normalize, CRLF line endings become LF and a missing final newline is added
+    for line in lines {
+        output::emit_bytes(line, true)?;
+    }
+    line_numbers::mark(filename, offset, line_numbers::next() - 1);
+    
+    output::flush();
+    Ok(offset)
+}
+
+// Where the last `num_lines` lines start, found by reading back from the end a block at
+// a time, so a large file costs no more than its tail. None for files that have to be
+// read through: pipes can't seek, and generated files report a size of 0.
+fn start_of_last(file: &mut File, num_lines: usize) -> io::Result<Option<u64>> {
+    let Ok(len) = file.seek(SeekFrom::End(0)) else {
+        return Ok(None);
+    };
+    if len == 0 {
+        return Ok(None);
+    }
+    start_of_last_before(file, len, num_lines).map(Some)
+}
+
+// Where the last `num_lines` lines before `len` start
+fn start_of_last_before(file: &mut File, len: u64, num_lines: usize) -> io::Result<u64> {
+    const BLOCK: u64 = 64 * 1024;
+    
+    if num_lines == 0 {
+        return Ok(len);
+    }
+    let mut block = vec![0u8; BLOCK as usize];
+    let mut newlines = 0;
+    let mut end = len;
+    // The byte after the one looked at, as a UTF-16 newline is two
+    let mut next = None;
+    while end > 0 {
+        let start = end.saturating_sub(BLOCK);
+        let chunk = &mut block[..(end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(chunk)?;
+        for (i, &byte) in chunk.iter().enumerate().rev() {
+            let pos = start + i as u64;
+            let after = next.replace(byte);
+            // A final delimiter ends the last line rather than starting another
+            let Some(line_start) = encoding::newline_at(pos, byte, after).filter(|&s| s < len) else {
+                continue;
+            };
+            newlines += 1;
+            if newlines == num_lines {
+                return Ok(line_start);
+            }
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+// Print everything from line `line` on; returns the offset reading stopped at
+fn print_from_line(filename: &str, line: usize, use_index: bool) -> io::Result<u64> {
+    let skip = line.saturating_sub(1) as u64;
+    let mut file = open_log(filename)?;
+    let mut offset = 0;
+    if use_index && let Some(index) = open_index(filename) {
+        offset = index.offset_of_line(skip, &mut file)?;
+    } else {
+        let mut reader = BufReader::new(file);
+        let mut buffer = Vec::new();
+        let mut skipped = 0;
+        for _ in 0..skip {
+            buffer.clear();
+            let n = encoding::read_line(&mut reader, &mut buffer)?;
+            if n == 0 {
+                break;
+            }
+            offset += n as u64;
+            skipped += 1;
+        }
+        line_numbers::mark(filename, offset, skipped);
+    }
+    print_from(filename, offset)
+}
+
+// Print the last `num_bytes` bytes (set_binary_safe has made output byte-exact); returns
+// the offset reading stopped at
+fn tail_bytes(filename: &str, num_bytes: u64) -> io::Result<u64> {
+    let len = open_log(filename)?.seek(SeekFrom::End(0))?;
+    print_from(filename, len.saturating_sub(num_bytes))
+}
+
+// Print everything from `offset` to the end; returns the offset reading stopped at
+fn print_from(filename: &str, mut offset: u64) -> io::Result<u64> {
+    let mut file = open_log(filename)?;
+    offset = backfill::limit(&mut file, offset)?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut reader = BufReader::new(file);
+    if line_numbers::enabled() {
+        line_numbers::start_at(line_numbers::lines_before(filename, offset)? + 1);
+    }
+    
+    let mut line = Vec::new();
+    while encoding::read_line(&mut reader, &mut line)? > 0 {
+        offset += line.len() as u64;
+        output::emit_bytes(std::mem::take(&mut line), true)?;
+    }
+    line_numbers::mark(filename, offset, line_numbers::next() - 1);
+    
+    output::flush();
+    Ok(offset)
+}
+
+struct FollowOptions {
+    retry_mode: bool,
+    use_index: bool,
+    reopen_each_poll: bool,
+    reopen_on_eacces: bool,
+    // -F: switch to whatever file the path names, once the old one is read to the end
+    follow_name: bool,
+    append_only: Option<append_only::Mode>,
+}
+
+fn follow_file<F: Fs, C: Clock>(
+    fs: &F,
+    clock: &C,
+    filename: &str,
+    opts: &FollowOptions,
+    state: &mut Option<StateFile>,
+) -> io::Result<()> {
+    let retry_mode = opts.retry_mode;
+    let mut file = match fs.open(filename) {
+        Ok(f) => BufReader::new(f),
+        Err(e) => {
+            if retry_mode {
+                output::status(format_args!("Error opening file: {}. Retrying...", e));
+                clock.sleep(Duration::from_secs(1));
+                return follow_file(fs, clock, filename, opts, state);
+            } else {
+                return Err(e);
+            }
+        }
+    };
+    
+    let mut index = if opts.use_index { open_index(filename) } else { None };
+    
+    // Seek to the end, or to where the index stopped scanning so no line is counted twice
+    let pos = match &index {
+        Some(idx) => file.seek(SeekFrom::Start(idx.scanned_len()))?,
+        None => file.seek(SeekFrom::End(0))?,
+    };
+    
+    if line_numbers::enabled() {
+        line_numbers::start_at(line_numbers::lines_before(filename, pos)? + 1);
+    }
+    control::follow(filename, pos);
+    live_first::went_live(pos);
+    let mut saved_pos = pos;
+    let mut denied = fault::Denied::new();
+    let mut in_burst = false;
+    let mut producer_gone = false;
+    let mut watcher = fs.watch(filename);
+    
+    let modified = match fs.stat(filename) {
+        Ok(stat) => stat.modified,
+        Err(_) => follow::modified_ns_now(),
+    };
+    let mut follow = Follow::new(pos, modified);
+    let mut verifier = match opts.append_only {
+        Some(_) => Some(Verifier::new(fs, filename, pos)?),
+        None => None,
+    };
+    trace::event(clock.now(), "start", &[("pos", pos as u128), ("modified", modified)]);
+    
+    loop {
+        if clock.stopped() {
+            output::flush();
+            return Ok(());
+        }
+        
+        // While a burst of lines is being read, keep going from the reader's buffer;
+        // the rotation checks and re-seek only happen once we've caught up
+        let burst = std::mem::take(&mut in_burst);
+        
+        // Outside --active-hours, hold our position until the next window
+        if !burst {
+            active_hours::wait();
+        }
+        control::wait_if_paused(filename);
+        
+        // Check if file has been rotated (common in Windows logs)
+        if !burst {
+            match fs.stat(filename) {
+                Ok(stat) => {
+                    trace::event(clock.now(), "stat", &[("len", stat.len as u128), ("modified", stat.modified)]);
+                    
+                    // If the file's modified time changed and it's smaller than before, it was probably rotated
+                    if let Some(decision) = follow.stat(stat.len, stat.modified) {
+                        trace::event(clock.now(), decision.name(), &[]);
+                        report::decision(decision.name());
+                        output::flush();
+                        output::status(format_args!("\n--- Log file rotation detected ---\n"));
+                        // Reopen the file
+                        drop(file);
+                        file = BufReader::new(fs.open(filename)?);
+                        reset_index(&mut index, filename);
+                        line_numbers::restart();
+                    }
+                },
+                Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                    trace::event(clock.now(), "denied", &[]);
+                    clock.sleep(denied.hit(filename, &e));
+                    continue;
+                }
+                Err(e) => {
+                    trace::event(clock.now(), "stat_error", &[]);
+                    if retry_mode {
+                        output::status(format_args!("File access error: {}. Retrying...", e));
+                        clock.sleep(Duration::from_secs(1));
+                        continue;
+                    } else {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        
+        // Seek to where we were before and read the next line
+        let mut buffer = Vec::new();
+        let read = if burst {
+            encoding::read_line(&mut file, &mut buffer)
+        } else {
+            file.seek(SeekFrom::Start(follow.pos)).and_then(|_| encoding::read_line(&mut file, &mut buffer))
+        };
+        
+        let bytes_read = match read {
+            Ok(n) => {
+                trace::event(clock.now(), "read", &[("pos", follow.pos as u128), ("bytes", n as u128)]);
+                denied.clear(filename);
+                n
+            }
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                trace::event(clock.now(), "denied", &[]);
+                clock.sleep(denied.hit(filename, &e));
+                // Our handle may keep failing even once access is granted again
+                if opts.reopen_on_eacces
+                    && let Ok(f) = fs.open(filename)
+                {
+                    file = BufReader::new(f);
+                }
+                continue;
+            }
+            Err(e) => match fault::classify(&e) {
+                // The handle went bad under us (NFS, removable media): reopen, don't die
+                Some(fault) => {
+                    trace::event(clock.now(), "read_error", &[]);
+                    eprintln!("\n--- Read error on '{}' ({}): {}; reopening ---\n", filename, fault, e);
+                    report::error(format!("{}: read error ({}): {}", filename, fault, e));
+                    file = reopen_after_fault(fs, clock, filename, retry_mode)?;
+                    let size = fs.handle_len(file.get_ref())?;
+                    trace::event(clock.now(), "fault_reopen", &[("len", size as u128)]);
+                    if follow.fault_reopened(size) {
+                        reset_index(&mut index, filename);
+                        line_numbers::restart();
+                    }
+                    continue;
+                }
+                None => return Err(e),
+            },
+        };
+        
+        if bytes_read > 0 {
+            if let Some(v) = verifier.as_mut() {
+                v.read(follow.pos, &buffer);
+            }
+            if let Some(idx) = index.as_mut() {
+                idx.advance(&buffer);
+                save_index(&mut index);
+            }
+            
+            // With --normalize, CRLF line endings become LF
+            output::emit_bytes(buffer, false)?;
+            follow.read(bytes_read as u64);
+            crash::set_offset(follow.pos);
+            control::read(filename, follow.pos);
+            in_burst = true;
+        } else {
+            output::flush();
+            trace::flush();
+            mute::notice();
+            output::notice_dropped();
+
+            // --pid: the process is gone and what it wrote last has been read
+            if producer_gone {
+                return Ok(());
+            }
+            if pid::exited() {
+                producer_gone = true;
+                continue;
+            }
+            
+            live_first::deliver()?;
+            
+            // Caught up: a good moment to persist where we are (and bookmarks set since)
+            if follow.pos != saved_pos || state::bookmarks_pending() {
+                save_state(state, filename, follow.pos);
+                saved_pos = follow.pos;
+            }
+            
+            // No new data: wait for the OS to report a change, or poll where it can't
+            if opts.reopen_each_poll {
+                // Don't hold a handle while idle, so writers that briefly need
+                // exclusive access can get it
+                drop(file);
+                clock.sleep(follow::poll_interval());
+                file = reopen_when_released(fs, clock, filename)?;
+            } else if let Some(watcher) = watcher.as_mut() {
+                watcher.wait(watch::MAX_WAIT);
+            } else {
+                clock.sleep(follow::poll_interval());
+            }
+            
+            // With -F, notice the path being given to another file: finish reading the old
+            // one (its writer may have added to it since we caught up), then switch
+            if opts.follow_name
+                && let (Ok(path_id), Ok(handle_id)) = (fs.id(filename), fs.handle_id(file.get_ref()))
+            {
+                trace::event(clock.now(), "identity", &[("path", path_id), ("handle", handle_id)]);
+                let old_pos = follow.pos;
+                if let Some(decision) = follow.identity(path_id, handle_id) {
+                    file.seek(SeekFrom::Start(old_pos))?;
+                    let mut line = Vec::new();
+                    while encoding::read_line(&mut file, &mut line)? > 0 {
+                        output::emit_bytes(std::mem::take(&mut line), true)?;
+                    }
+                    trace::event(clock.now(), decision.name(), &[]);
+                    report::decision(decision.name());
+                    output::flush();
+                    output::status(format_args!("\n--- '{}' now names a different file; following that ---\n", filename));
+                    file = BufReader::new(fs.open(filename)?);
+                    reset_index(&mut index, filename);
+                    line_numbers::restart();
+                    continue;
+                }
+            }
+
+            // Handle the case where the file was truncated or renamed away (log rotation),
+            // and reads that keep finding nothing although the file at the path has grown
+            // past us: our handle is wedged or no longer points at that file. Stat errors
+            // are dealt with at the top of the loop
+            if let Ok(stat) = fs.stat(filename) {
+                let now = clock.now();
+                let handle_len = fs.handle_len(file.get_ref()).unwrap_or(0);
+                trace::event(
+                    now,
+                    "idle",
+                    &[("len", stat.len as u128), ("handle_len", handle_len as u128), ("now", now.as_millis())],
+                );
+                match follow.idle(stat.len, handle_len, now) {
+                    Some(decision @ Decision::Rotation) => {
+                        trace::event(clock.now(), decision.name(), &[]);
+                        report::decision(decision.name());
+                        output::flush();
+                        output::status(format_args!("\n--- Log file rotation detected ---\n"));
+                        drop(file);
+                        file = BufReader::new(fs.open(filename)?);
+                        reset_index(&mut index, filename);
+                        line_numbers::restart();
+                    }
+                    Some(decision @ Decision::Truncation) => {
+                        trace::event(clock.now(), decision.name(), &[]);
+                        report::decision(decision.name());
+                        output::flush();
+                        if let Some(v) = verifier.as_mut()
+                            && v.len() > 0
+                        {
+                            tampered(opts, filename, "was truncated");
+                            *v = Verifier::new(fs, filename, 0)?;
+                        }
+                        output::status(format_args!("\n--- File was truncated or rotated ---\n"));
+                        // Start from the beginning
+                        file.seek(SeekFrom::Start(0))?;
+                        reset_index(&mut index, filename);
+                        line_numbers::restart();
+                    }
+                    Some(decision) => {
+                        trace::event(clock.now(), decision.name(), &[]);
+                        report::decision(decision.name());
+                        output::flush();
+                        output::status(format_args!(
+                            "\n--- No progress on '{}' for {}s although it grew to {} bytes; reopening ---\n",
+                            filename,
+                            watchdog::STALL_TIMEOUT.as_secs(),
+                            stat.len
+                        ));
+                        let old_len = fs.handle_len(file.get_ref()).unwrap_or(0);
+                        file = BufReader::new(fs.open(filename)?);
+                        trace::event(clock.now(), "stall_reopen", &[("old_len", old_len as u128)]);
+                        if follow.stall_reopened(old_len) {
+                            reset_index(&mut index, filename);
+                            line_numbers::restart();
+                        }
+                    }
+                    None => {
+                        if let Some(v) = verifier.as_mut()
+                            && let Some((start, end)) = v.check(fs, filename, follow.pos)?
+                        {
+                            tampered(opts, filename, &format!("changed in bytes {}..{} after they were read", start, end));
+                            *v = Verifier::new(fs, filename, follow.pos)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Report a --verify-append-only violation, and stop if asked to
+fn tampered(opts: &FollowOptions, filename: &str, what: &str) {
+    output::flush();
+    eprintln!("\n--- Append-only violation: '{}' {} ---\n", filename, what);
+    report::error(format!("{}: append-only violation: {}", filename, what));
+    if opts.append_only == Some(append_only::Mode::Exit) {
+        exit_with_report(1);
+    }
+}
+
+// Reopen after a read fault, backing off between attempts; gives up after a few tries
+// unless --retry was given
+fn reopen_after_fault<F: Fs, C: Clock>(fs: &F, clock: &C, filename: &str, retry_mode: bool) -> io::Result<BufReader<F::File>> {
+    let mut delay = Duration::from_secs(1);
+    let mut attempts = 0;
+    loop {
+        clock.sleep(delay);
+        match fs.open(filename) {
+            Ok(f) => {
+                eprintln!("--- Reopened '{}' ---", filename);
+                return Ok(BufReader::new(f));
+            }
+            Err(e) => {
+                attempts += 1;
+                if !retry_mode && attempts >= 5 {
+                    return Err(e);
+                }
+                eprintln!("Error reopening '{}': {}. Retrying...", filename, e);
+                delay = (delay * 2).min(Duration::from_secs(30));
+            }
+        }
+    }
+}
+
+// Reopen for --reopen-each-poll, waiting out writers that currently hold the file exclusively
+fn reopen_when_released<F: Fs, C: Clock>(fs: &F, clock: &C, filename: &str) -> io::Result<BufReader<F::File>> {
+    let mut waiting = false;
+    loop {
+        match fs.open(filename) {
+            Ok(f) => return Ok(BufReader::new(f)),
+            Err(e) if open::is_sharing_violation(&e) => {
+                if !waiting {
+                    eprintln!("Waiting for '{}' to be released by its writer...", filename);
+                    waiting = true;
+                }
+                clock.sleep(follow::poll_interval());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn save_index(index: &mut Option<LineIndex>) {
+    if let Some(idx) = index
+        && let Err(e) = idx.save()
+    {
+        eprintln!("Warning: Could not update index: {}", e);
+        *index = None;
+    }
+}
+
+fn reset_index(index: &mut Option<LineIndex>, filename: &str) {
+    if let Some(idx) = index
+        && let Err(e) = idx.reset(filename)
+    {
+        eprintln!("Warning: Could not update index: {}", e);
+        *index = None;
+    }
+}
+
+// Record how far `filename` has been read; a failing state file shouldn't stop tailing
+fn save_state(state: &mut Option<StateFile>, filename: &str, offset: u64) {
+    if let Some(st) = state {
+        let result = open_log(filename)
+            .and_then(|mut f| state::read_context(&mut f, offset))
+            .and_then(|context| {
+                st.record(filename, offset, context);
+                st.save()
+            });
+        if let Err(e) = result {
+            eprintln!("Warning: Could not update state file: {}", e);
+            *state = None;
         }
     }
 }
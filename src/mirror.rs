@@ -0,0 +1,167 @@
+// `--mirror unix:///tmp/rail.sock`: send a copy of everything rail writes to stdout to
+// whoever connects to a local socket, so another terminal (or a colleague over ssh) can
+// watch exactly what is on screen, filters, colours and all, e.g. while debugging
+// together. Viewers attach with `rail attach unix:///tmp/rail.sock` (or `nc -U`), see
+// the output from then on and can't send anything back.
+//
+// The Unix socket is only accessible to rail's user; to let someone else attach, change
+// its permissions. tcp://127.0.0.1:<port> (e.g. on Windows) listens on the loopback
+// interface, where any local user can connect.
+//
+// A viewer that can't keep up is disconnected rather than allowed to slow rail down.
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::control::Address;
+
+// Writes a viewer may be behind by before it is dropped
+const QUEUE_SIZE: usize = 1000;
+// How long a viewer gets to take a write, and what is left when rail finishes
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Viewer {
+    queue: SyncSender<Arc<[u8]>>,
+    writer: JoinHandle<()>,
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SOCKET: OnceLock<Address> = OnceLock::new();
+static VIEWERS: Mutex<Vec<Viewer>> = Mutex::new(Vec::new());
+
+// unix://<path> or tcp://127.0.0.1:<port>
+pub fn parse_address(s: &str) -> Result<Address, String> {
+    if let Some(addr) = s.strip_prefix("tcp://") {
+        let addr: SocketAddr = addr.parse().map_err(|_| format!("expected tcp://127.0.0.1:<port>, got '{}'", s))?;
+        if !addr.ip().is_loopback() {
+            return Err(format!("'{}' is not a loopback address; anyone who can reach it could read the output", s));
+        }
+        return Ok(Address::Tcp(addr));
+    }
+    match s.strip_prefix("unix://") {
+        #[cfg(unix)]
+        Some(path) if !path.is_empty() => Ok(Address::Unix(std::path::PathBuf::from(path))),
+        #[cfg(not(unix))]
+        Some(_) => Err(format!("Unix domain sockets aren't available here; use tcp://127.0.0.1:<port>, not '{}'", s)),
+        _ => Err(format!("expected unix://<path> or tcp://127.0.0.1:<port>, got '{}'", s)),
+    }
+}
+
+// Start taking viewers on `address`
+pub fn listen(address: Address) -> io::Result<()> {
+    match &address {
+        #[cfg(unix)]
+        Address::Unix(path) => {
+            let listener = crate::control::bind_unix(path)?;
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let _ = stream.set_write_timeout(Some(TIMEOUT));
+                    attach(stream);
+                }
+            });
+        }
+        Address::Tcp(addr) => {
+            let listener = TcpListener::bind(addr)?;
+            thread::spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let _ = stream.set_write_timeout(Some(TIMEOUT));
+                    attach(stream);
+                }
+            });
+        }
+    }
+    let _ = SOCKET.set(address);
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+fn attach<S: Write + Send + 'static>(stream: S) {
+    let (queue, writes) = mpsc::sync_channel(QUEUE_SIZE);
+    let writer = thread::spawn(move || send(stream, writes));
+    VIEWERS.lock().unwrap().push(Viewer { queue, writer });
+}
+
+fn send<S: Write>(mut stream: S, writes: Receiver<Arc<[u8]>>) {
+    for bytes in writes {
+        if stream.write_all(&bytes).is_err() {
+            return;
+        }
+    }
+}
+
+// What was just written to stdout
+pub fn write(lines: &[Vec<u8>]) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let mut viewers = VIEWERS.lock().unwrap();
+    if viewers.is_empty() {
+        return;
+    }
+    let bytes: Arc<[u8]> = lines.concat().into();
+    // A viewer that has gone or fallen too far behind is dropped, which ends its writer
+    viewers.retain(|viewer| viewer.queue.try_send(bytes.clone()).is_ok());
+}
+
+// Let viewers have what is still queued for them and remove the socket; call before
+// exiting
+pub fn finish() {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let viewers = std::mem::take(&mut *VIEWERS.lock().unwrap());
+    for Viewer { queue, writer } in viewers {
+        drop(queue);
+        let _ = writer.join();
+    }
+    #[cfg(unix)]
+    if let Some(Address::Unix(path)) = SOCKET.get() {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+// `rail attach <unix://path | tcp://127.0.0.1:port>`: show another rail's output
+pub fn command(args: &[String]) -> io::Result<()> {
+    let Some(socket) = args.first() else {
+        eprintln!("Error: usage: rail attach <unix://path|tcp://127.0.0.1:port>");
+        process::exit(1);
+    };
+    let address = match parse_address(socket) {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
+    let result = match address {
+        #[cfg(unix)]
+        Address::Unix(path) => std::os::unix::net::UnixStream::connect(&path).and_then(copy),
+        Address::Tcp(addr) => TcpStream::connect(addr).and_then(copy),
+    };
+    if let Err(e) = result {
+        eprintln!("Error: Could not attach to rail at '{}': {}", socket, e);
+        process::exit(1);
+    }
+    Ok(())
+}
+
+fn copy<S: Read>(mut stream: S) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let n = match stream.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        stdout.write_all(&buffer[..n])?;
+        stdout.flush()?;
+    }
+}
@@ -0,0 +1,127 @@
+// `--mute '<regex>[@<hours>]'`: hide lines that are known, benign noise, optionally only
+// during some local-time windows (same syntax as --active-hours), e.g.
+//
+//   --mute 'GET /healthz'  --mute 'backup job (started|finished)@22:00-06:00'
+//
+// `--mute-file <file>` reads a list of such rules, one per line (blank lines and lines
+// starting with # are skipped), so a team can keep its noise list next to the logs.
+//
+// Unlike a filter, muting keeps the evidence: every muted line is counted against the
+// rule that hid it, the counts are printed while following (at most once a minute, when
+// they changed) and again when rail finishes. Muted lines aren't seen by --fail-on or
+// --forward either, since they're known not to matter.
+
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::active_hours::{self, Schedule};
+use crate::output;
+use crate::regex::Regex;
+use crate::tz;
+
+const NOTICE_EVERY: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub struct Rule {
+    spec: String,
+    regex: Regex,
+    hours: Option<Schedule>,
+    muted: u64,
+}
+
+struct Mutes {
+    rules: Vec<Rule>,
+    // The total when counts were last printed, and when
+    noticed: u64,
+    last_notice: Option<Instant>,
+}
+
+static MUTES: Mutex<Mutes> = Mutex::new(Mutes { rules: Vec::new(), noticed: 0, last_notice: None });
+
+// A regex may itself contain '@', so the part after the last one is only taken as hours
+// if it starts like a time (and then it must parse as hours)
+pub fn parse_rule(spec: &str) -> Result<Rule, String> {
+    let (pattern, hours) = match spec.rsplit_once('@') {
+        Some((pattern, hours)) if hours.starts_with(|c: char| c.is_ascii_digit()) && hours.contains(':') => {
+            (pattern, Some(active_hours::parse_schedule(hours)?))
+        }
+        _ => (spec, None),
+    };
+    let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+    Ok(Rule { spec: spec.to_string(), regex, hours, muted: 0 })
+}
+
+pub fn read_rules(path: &str) -> Result<Vec<Rule>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("could not read '{}': {}", path, e))?;
+    text.lines()
+        .enumerate()
+        .map(|(n, line)| (n, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(n, line)| parse_rule(line).map_err(|e| format!("{}:{}: {}", path, n + 1, e)))
+        .collect()
+}
+
+pub fn add_rules(rules: Vec<Rule>) {
+    MUTES.lock().unwrap().rules.extend(rules);
+}
+
+pub fn has_rules() -> bool {
+    !MUTES.lock().unwrap().rules.is_empty()
+}
+
+// Whether `line` is muted now; if so it is counted
+pub fn muted(line: &str) -> bool {
+    let mut mutes = MUTES.lock().unwrap();
+    if mutes.rules.is_empty() {
+        return false;
+    }
+    let text = line.trim_end_matches('\n');
+    let mut now = None;
+    for rule in &mut mutes.rules {
+        if !rule.regex.is_match(text) {
+            continue;
+        }
+        if let Some(hours) = &rule.hours
+            && !hours.covers(*now.get_or_insert_with(tz::now))
+        {
+            continue;
+        }
+        rule.muted += 1;
+        return true;
+    }
+    false
+}
+
+fn describe(rules: &[Rule]) -> String {
+    rules
+        .iter()
+        .filter(|r| r.muted > 0)
+        .map(|r| format!("{} x '{}'", r.muted, r.spec))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// While following and idle: print the counts if they changed and it's been a while
+pub fn notice() {
+    let mut mutes = MUTES.lock().unwrap();
+    let total = mutes.rules.iter().map(|r| r.muted).sum();
+    if total == mutes.noticed || mutes.last_notice.is_some_and(|t| t.elapsed() < NOTICE_EVERY) {
+        return;
+    }
+    mutes.noticed = total;
+    mutes.last_notice = Some(Instant::now());
+    output::status(format_args!("\n--- Muted {} lines so far: {} ---\n", total, describe(&mutes.rules)));
+}
+
+// Each rule and how many lines it muted
+pub fn counts() -> Vec<(String, u64)> {
+    MUTES.lock().unwrap().rules.iter().map(|r| (r.spec.clone(), r.muted)).collect()
+}
+
+// The summary to print when rail is done
+pub fn report() -> Option<String> {
+    let mutes = MUTES.lock().unwrap();
+    let total: u64 = mutes.rules.iter().map(|r| r.muted).sum();
+    (total > 0).then(|| format!("Muted {} lines: {}", total, describe(&mutes.rules)))
+}
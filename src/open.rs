@@ -0,0 +1,76 @@
+// Opening the tailed file. On Windows the share mode decides which other opens (by the
+// writer, or by logrotate-style tools renaming the file) are allowed while rail holds a
+// handle; `--share-mode` lets that be narrowed or widened for picky applications.
+//
+// The name "-" stands for standard input. Opening it gives a new handle to the same
+// stream, so everything that reads a File can read a pipe into rail as well.
+
+use std::fs::File;
+use std::io;
+use std::sync::OnceLock;
+
+pub const SHARE_READ: u32 = 0x1;
+pub const SHARE_WRITE: u32 = 0x2;
+pub const SHARE_DELETE: u32 = 0x4;
+
+pub const STDIN: &str = "-";
+
+static SHARE_MODE: OnceLock<u32> = OnceLock::new();
+
+// Parse a comma separated list like "read,write,delete" (or "none")
+pub fn parse_share_mode(s: &str) -> Result<u32, String> {
+    let mut mode = 0;
+    for part in s.split(',') {
+        match part.trim() {
+            "read" => mode |= SHARE_READ,
+            "write" => mode |= SHARE_WRITE,
+            "delete" => mode |= SHARE_DELETE,
+            "none" => {}
+            other => return Err(format!("unknown share mode '{}'", other)),
+        }
+    }
+    Ok(mode)
+}
+
+pub fn set_share_mode(mode: u32) {
+    let _ = SHARE_MODE.set(mode);
+}
+
+#[cfg(windows)]
+pub fn open_log(path: &str) -> io::Result<File> {
+    use std::fs::OpenOptions;
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::AsHandle;
+
+    if path == STDIN {
+        return Ok(File::from(io::stdin().as_handle().try_clone_to_owned()?));
+    }
+    let mode = *SHARE_MODE.get().unwrap_or(&(SHARE_READ | SHARE_WRITE | SHARE_DELETE));
+    OpenOptions::new().read(true).share_mode(mode).open(path)
+}
+
+#[cfg(not(windows))]
+pub fn open_log(path: &str) -> io::Result<File> {
+    use std::os::fd::AsFd;
+
+    if path == STDIN {
+        return Ok(File::from(io::stdin().as_fd().try_clone_to_owned()?));
+    }
+    // Unix has no share modes; opens never block other processes
+    File::open(path)
+}
+
+// True if the open failed because another process holds the file in a conflicting mode
+pub fn is_sharing_violation(e: &io::Error) -> bool {
+    #[cfg(windows)]
+    {
+        const ERROR_SHARING_VIOLATION: i32 = 32;
+        const ERROR_LOCK_VIOLATION: i32 = 33;
+        matches!(e.raw_os_error(), Some(ERROR_SHARING_VIOLATION) | Some(ERROR_LOCK_VIOLATION))
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = e;
+        false
+    }
+}
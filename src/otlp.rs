@@ -0,0 +1,326 @@
+// `--forward otlp://collector:4318`: ship every output line to an OpenTelemetry
+// collector as a LogRecord, alongside printing it. rail speaks OTLP/HTTP with the JSON
+// encoding (POST <path, default /v1/logs>); collectors take that on 4318. gRPC, usually
+// on 4317, isn't spoken.
+//
+// Each record carries the line as its body (a parsed record's "message" field, if it
+// has one), the --format fields as attributes, a severity from a level-like field, and
+// a timestamp from a time-like field when it reads as ISO 8601 / RFC 3339 (fields with
+// no zone are taken as local time). The resource names the host and the file.
+//
+// Sending happens on a background thread, batched by BATCH_SIZE lines or FLUSH_INTERVAL.
+// If the collector can't keep up the queue fills and lines are dropped from the export
+// (never from the output); a batch the collector keeps refusing is dropped after a few
+// tries. Both are reported on stderr.
+
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::fields::{Record, push_json_string};
+use crate::tz;
+
+const QUEUE_SIZE: usize = 10_000;
+const BATCH_SIZE: usize = 500;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(5);
+const ATTEMPTS: u32 = 3;
+
+pub const LEVEL_FIELDS: &[&str] = &["level", "severity", "error_severity", "log_level", "loglevel"];
+const TIME_FIELDS: &[&str] = &["timestamp", "@timestamp", "time", "log_time", "ts", "datetime"];
+const MESSAGE_FIELDS: &[&str] = &["message", "msg"];
+
+#[derive(Clone, Debug)]
+pub struct Target {
+    // host:port, to connect to and for the Host header
+    authority: String,
+    path: String,
+}
+
+pub fn parse_target(s: &str) -> Result<Target, String> {
+    let rest = s.strip_prefix("otlp://").ok_or(format!("expected otlp://host[:port][/path], got '{}'", s))?;
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/v1/logs"),
+    };
+    if authority.is_empty() {
+        return Err(format!("missing host in '{}'", s));
+    }
+    let has_port = authority.rsplit_once(':').is_some_and(|(_, port)| port.parse::<u16>().is_ok());
+    let authority = if has_port { authority.to_string() } else { format!("{}:4318", authority) };
+    Ok(Target { authority, path: path.to_string() })
+}
+
+enum Message {
+    // One LogRecord, already JSON
+    Record(String),
+    // Send what is queued, then acknowledge
+    Finish(SyncSender<()>),
+}
+
+static QUEUE: OnceLock<SyncSender<Message>> = OnceLock::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// `attributes` are added to the resource (see enrich.rs)
+pub fn enable(target: Target, filename: &str, attributes: &[(&str, String)]) {
+    let resource = resource(filename, attributes);
+    QUEUE.get_or_init(|| {
+        let (queue, messages) = mpsc::sync_channel(QUEUE_SIZE);
+        thread::spawn(move || worker(messages, target, resource));
+        queue
+    });
+}
+
+pub fn enabled() -> bool {
+    QUEUE.get().is_some()
+}
+
+// Queue `line` (as printed) and the record it came from, if any
+pub fn forward(line: &str, record: Option<&Record>) {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    if let Err(TrySendError::Full(_)) = queue.try_send(Message::Record(log_record(line, record))) {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+// Wait (a little) for what is queued to be sent; call before exiting
+pub fn finish() {
+    let Some(queue) = QUEUE.get() else {
+        return;
+    };
+    let (ack, done) = mpsc::sync_channel(1);
+    if queue.send(Message::Finish(ack)).is_ok() {
+        let _ = done.recv_timeout(TIMEOUT * ATTEMPTS);
+    }
+    let dropped = DROPPED.load(Ordering::Relaxed);
+    if dropped > 0 {
+        eprintln!("Warning: {} line(s) were not forwarded to the OTLP collector", dropped);
+    }
+}
+
+fn worker(messages: Receiver<Message>, target: Target, resource: String) {
+    let mut batch = Vec::new();
+    let mut deadline = Instant::now() + FLUSH_INTERVAL;
+    loop {
+        let message = messages.recv_timeout(deadline.saturating_duration_since(Instant::now()));
+        let finish = match message {
+            Ok(Message::Record(record)) => {
+                batch.push(record);
+                if batch.len() < BATCH_SIZE {
+                    continue;
+                }
+                None
+            }
+            Ok(Message::Finish(ack)) => Some(ack),
+            Err(RecvTimeoutError::Timeout) => None,
+            Err(RecvTimeoutError::Disconnected) => return,
+        };
+        if !batch.is_empty() {
+            send_batch(&target, &resource, &batch);
+            batch.clear();
+        }
+        deadline = Instant::now() + FLUSH_INTERVAL;
+        if let Some(ack) = finish {
+            let _ = ack.send(());
+        }
+    }
+}
+
+fn send_batch(target: &Target, resource: &str, batch: &[String]) {
+    let body = format!(
+        "{{\"resourceLogs\":[{{\"resource\":{{\"attributes\":[{}]}},\"scopeLogs\":[{{\"scope\":{{\"name\":\"rail\",\"version\":\"{}\"}},\"logRecords\":[{}]}}]}}]}}",
+        resource,
+        env!("CARGO_PKG_VERSION"),
+        batch.join(",")
+    );
+    let mut delay = Duration::from_millis(500);
+    for attempt in 1..=ATTEMPTS {
+        match post(target, &body) {
+            Ok(()) => return,
+            Err(e) if attempt == ATTEMPTS => {
+                eprintln!("Warning: OTLP export to {} failed: {}; dropped {} line(s)", target.authority, e, batch.len());
+                DROPPED.fetch_add(batch.len() as u64, Ordering::Relaxed);
+            }
+            Err(_) => {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+        }
+    }
+}
+
+fn post(target: &Target, body: &str) -> io::Result<()> {
+    let addr = target
+        .authority
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address for collector"))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        target.path,
+        target.authority,
+        body.len(),
+        body
+    )?;
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(&response);
+    let status = response.split(' ').nth(1).unwrap_or("");
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        let line = response.lines().next().unwrap_or("no response");
+        Err(io::Error::other(format!("collector answered '{}'", line)))
+    }
+}
+
+fn attribute(out: &mut String, key: &str, value: &str) {
+    if !out.is_empty() {
+        out.push(',');
+    }
+    out.push_str("{\"key\":");
+    push_json_string(out, key);
+    out.push_str(",\"value\":{\"stringValue\":");
+    push_json_string(out, value);
+    out.push_str("}}");
+}
+
+// The machine's name, or "" if it can't be found
+pub fn host_name() -> String {
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn resource(filename: &str, attributes: &[(&str, String)]) -> String {
+    let host = host_name();
+    let path = fs::canonicalize(filename).map_or(filename.to_string(), |p| p.display().to_string());
+    let name = std::path::Path::new(&path).file_name().map_or(path.clone(), |n| n.to_string_lossy().into_owned());
+
+    let mut out = String::new();
+    attribute(&mut out, "service.name", "rail");
+    if !host.is_empty() {
+        attribute(&mut out, "host.name", &host);
+    }
+    attribute(&mut out, "log.file.path", &path);
+    attribute(&mut out, "log.file.name", &name);
+    for (key, value) in attributes {
+        attribute(&mut out, key, value);
+    }
+    out
+}
+
+fn log_record(line: &str, record: Option<&Record>) -> String {
+    let observed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos());
+    let find = |names: &[&str]| record.and_then(|r| names.iter().find_map(|n| r.get(n)));
+
+    let mut out = format!("{{\"observedTimeUnixNano\":\"{}\"", observed);
+    if let Some(time) = find(TIME_FIELDS).and_then(parse_timestamp) {
+        out.push_str(&format!(",\"timeUnixNano\":\"{}\"", time));
+    }
+    if let Some(level) = find(LEVEL_FIELDS) {
+        if let Some(number) = severity_number(level) {
+            out.push_str(&format!(",\"severityNumber\":{}", number));
+        }
+        out.push_str(",\"severityText\":");
+        push_json_string(&mut out, level);
+    }
+    out.push_str(",\"body\":{\"stringValue\":");
+    push_json_string(&mut out, find(MESSAGE_FIELDS).unwrap_or(line.trim_end_matches(['\r', '\n'])));
+    out.push('}');
+    if let Some(record) = record {
+        let mut attributes = String::new();
+        for (key, value) in &record.fields {
+            attribute(&mut attributes, key, value);
+        }
+        out.push_str(&format!(",\"attributes\":[{}]", attributes));
+    }
+    out.push('}');
+    out
+}
+
+// The OpenTelemetry SeverityNumber of the first of its range for a level name
+fn severity_number(level: &str) -> Option<u32> {
+    Some(match level.to_ascii_lowercase().as_str() {
+        "trace" | "verbose" | "finest" => 1,
+        "debug" | "debug1" | "debug2" | "debug3" | "debug4" | "debug5" | "fine" => 5,
+        "info" | "information" | "notice" | "log" => 9,
+        "warn" | "warning" => 13,
+        "error" | "err" => 17,
+        "fatal" | "panic" | "crit" | "critical" | "alert" | "emerg" | "assert" => 21,
+        _ => return None,
+    })
+}
+
+// "YYYY-MM-DD[T ]HH:MM:SS[.fraction][Z | ±HH:MM | ±HHMM | UTC]" as nanoseconds since the
+// epoch; without a zone, local time
+pub fn parse_timestamp(s: &str) -> Option<u128> {
+    let s = s.trim();
+    let num = |range: std::ops::Range<usize>| s.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if separators.iter().any(|&(i, c)| s.as_bytes()[i] != c) || !matches!(s.as_bytes()[10], b'T' | b' ') {
+        return None;
+    }
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let mut rest = &s[19..];
+    let mut nanos = 0u128;
+    if let Some(fraction) = rest.strip_prefix(['.', ',']) {
+        let digits = fraction.find(|c: char| !c.is_ascii_digit()).unwrap_or(fraction.len());
+        let padded = format!("{:0<9}", &fraction[..digits.min(9)]);
+        nanos = padded.parse().ok()?;
+        rest = &fraction[digits..];
+    }
+
+    let local = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    let rest = rest.trim();
+    let offset = match rest {
+        "" => tz::local_zone().offset_at(local) as i64,
+        "Z" | "z" | "UTC" | "GMT" => 0,
+        zone => {
+            let sign = match zone.as_bytes()[0] {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            let digits: String = zone[1..].chars().filter(|c| *c != ':').collect();
+            if digits.len() != 4 {
+                return None;
+            }
+            let (h, m) = (digits[..2].parse::<i64>().ok()?, digits[2..].parse::<i64>().ok()?);
+            sign * (h * 3600 + m * 60)
+        }
+    };
+    let utc = local - offset;
+    (utc >= 0).then(|| utc as u128 * 1_000_000_000 + nanos)
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let doy = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
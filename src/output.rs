@@ -0,0 +1,494 @@
+// Everything rail prints from the tailed content goes through emit(), so options that
+// look at the output stream see every line exactly once.
+//
+// Lines are collected and written according to the --flush policy: every time the
+// reader catches up (the default on a terminal), in large blocks (the default when
+// piping), or at most every N milliseconds. Whatever the policy, callers flush() before
+// going idle so a quiet stream never leaves lines sitting in the buffer.
+//
+// With --binary-safe, lines reach emit_bytes() exactly as read and are written exactly
+// as they came; rail's own status messages go to stderr instead of being mixed in.
+//
+// With -z, records end in NUL instead of a newline, on the way in and on the way out.
+// Inside rail a record still ends in '\n' like any line (so every option works on it
+// unchanged), and push() turns that back into a NUL.
+//
+// With several files, each line is attributed to the file its thread is reading
+// (set_source), and a GNU tail style "==> name <==" header goes out whenever the file
+// the output comes from changes (-q leaves the headers out, and -v has main() name even
+// a single file). --per-source-limit (limit.rs) is applied there too.
+// `--prefix=filename` (or =basename) puts the name in front of every line instead, for
+// output that is grepped afterwards; the headers are then left out unless -v asks.
+//
+// A flush hands all pending lines to a single write_vectored call on the locked stdout,
+// so a burst of thousands of lines costs a handful of syscalls, and whole lines are
+// never split between writes of ours and other output.
+
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{self, IoSlice, Write};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use crate::a11y;
+use crate::compact;
+use crate::crash;
+use crate::digest;
+use crate::encoding;
+use crate::fail_on;
+use crate::fields;
+use crate::grep;
+use crate::highlight;
+use crate::interleave;
+use crate::json;
+use crate::json_pretty;
+use crate::limit;
+use crate::line_numbers;
+use crate::mirror;
+use crate::mute;
+use crate::open::STDIN;
+use crate::otlp;
+use crate::passthrough;
+use crate::sound;
+use crate::report;
+use crate::sequence;
+use crate::sub;
+use crate::timestamps;
+use crate::trace_context;
+
+const BLOCK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prefix {
+    Filename,
+    Basename,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Flush {
+    Line,
+    Block,
+    Interval(Duration),
+}
+
+struct Buffer {
+    lines: Vec<Vec<u8>>,
+    len: usize,
+    last_flush: Option<Instant>,
+    // The file whose header went out last
+    source: Option<String>,
+    headers: usize,
+}
+
+static POLICY: OnceLock<Flush> = OnceLock::new();
+static BUFFER: Mutex<Buffer> =
+    Mutex::new(Buffer { lines: Vec::new(), len: 0, last_flush: None, source: None, headers: 0 });
+// While set, flushed lines and status messages are collected here instead of written
+// (`rail simulate`)
+static CAPTURE: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+static BINARY_SAFE: AtomicBool = AtomicBool::new(false);
+static NORMALIZE: AtomicBool = AtomicBool::new(false);
+// The last line written had no newline (the file's last line, with no --normalize)
+static LINE_OPEN: AtomicBool = AtomicBool::new(false);
+static QUIET: AtomicBool = AtomicBool::new(false);
+// Whoever read stdout stopped (`rail ... | head`); CLOSING once rail is wrapping up
+static CLOSED: AtomicBool = AtomicBool::new(false);
+static CLOSING: AtomicBool = AtomicBool::new(false);
+static ZERO_TERMINATED: AtomicBool = AtomicBool::new(false);
+static PREFIX: OnceLock<Prefix> = OnceLock::new();
+
+thread_local! {
+    static SOURCE: RefCell<Option<String>> = const { RefCell::new(None) };
+    // With -z: whether the record being emitted is complete, so its final '\n' stands
+    // for the NUL it ended in (and isn't a newline inside a record read in part)
+    static RECORD_END: Cell<bool> = const { Cell::new(false) };
+}
+
+pub fn parse_flush(s: &str) -> Result<Flush, String> {
+    match s {
+        "line" => Ok(Flush::Line),
+        "block" => Ok(Flush::Block),
+        _ => match s.strip_prefix("interval:").map(|ms| ms.parse::<u64>()) {
+            Some(Ok(ms)) => Ok(Flush::Interval(Duration::from_millis(ms))),
+            _ => Err(format!("expected line, block or interval:<ms>, got '{}'", s)),
+        },
+    }
+}
+
+pub fn set_flush(policy: Flush) {
+    let _ = POLICY.set(policy);
+}
+
+fn policy() -> Flush {
+    *POLICY.get_or_init(|| {
+        use std::io::IsTerminal;
+        if io::stdout().is_terminal() { Flush::Line } else { Flush::Block }
+    })
+}
+
+// Colour on a terminal, unless NO_COLOR is set or --a11y asks for none
+pub fn color_wanted() -> bool {
+    use std::io::IsTerminal;
+    static TERMINAL: OnceLock<bool> = OnceLock::new();
+    *TERMINAL.get_or_init(|| io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()) && !a11y::enabled()
+}
+
+pub fn emit(line: &str) {
+    match interleave::transform(line) {
+        Some(lines) => lines.iter().for_each(|line| emit_one(line)),
+        None => emit_one(line),
+    }
+}
+
+fn emit_one(line: &str) {
+    let line = sub::transform(line);
+    let Some(line) = json::transform(&line) else {
+        return;
+    };
+    let Some((line, record)) = fields::transform(&line) else {
+        return;
+    };
+    let line = line.as_ref();
+    if sequence::enabled() {
+        let source = SOURCE.with(|source| source.borrow().clone()).unwrap_or_default();
+        if let Some(note) = sequence::check(&source, line) {
+            notice(note);
+        }
+    }
+    if !grep::matches(line) {
+        return;
+    }
+    let Some(shown) = trace_context::transform(line) else {
+        return;
+    };
+    if mute::muted(line) {
+        return;
+    }
+    fail_on::observe(line);
+    digest::observe(line);
+    sound::observe(line);
+    crash::record(line);
+    otlp::forward(line, record.as_ref());
+    let shown = highlight::transform(json_pretty::transform(compact::transform(shown.into_owned())));
+    push(tag(timestamps::prefix(line_numbers::prefix(shown))).into_bytes());
+}
+
+pub fn set_binary_safe() {
+    BINARY_SAFE.store(true, Ordering::Relaxed);
+}
+
+pub fn set_normalize() {
+    NORMALIZE.store(true, Ordering::Relaxed);
+}
+
+pub fn set_zero_terminated() {
+    ZERO_TERMINATED.store(true, Ordering::Relaxed);
+}
+
+// What ends a line in the input: NUL with -z, else a newline
+pub fn delimiter() -> u8 {
+    if ZERO_TERMINATED.load(Ordering::Relaxed) { b'\0' } else { b'\n' }
+}
+
+// A line as read from the input, with its newline if it had one. With --binary-safe it
+// goes out byte for byte; otherwise it's decoded if it isn't UTF-8 (see encoding.rs) and
+// then must be. With --normalize, CRLF becomes LF and, with `terminate`, a missing final
+// newline is added before it goes to emit()
+pub fn emit_bytes(line: Vec<u8>, terminate: bool) -> io::Result<()> {
+    let (line, terminate) = if BINARY_SAFE.load(Ordering::Relaxed) {
+        (line, terminate)
+    } else {
+        match encoding::decode(&line) {
+            Some((text, _)) if text.is_empty() => return Ok(()),
+            // Not the end of the line while part of a character is still to come
+            Some((text, held)) => (text, terminate && !held),
+            None => (line, terminate),
+        }
+    };
+    report::read(&line);
+    line_numbers::read(&line);
+    if BINARY_SAFE.load(Ordering::Relaxed) {
+        let text = String::from_utf8_lossy(&line);
+        fail_on::observe(&text);
+        digest::observe(&text);
+        sound::observe(&text);
+        crash::record(&text);
+        otlp::forward(&text, None);
+        passthrough::read(&line);
+        push(line);
+        return Ok(());
+    }
+    let mut line = String::from_utf8(line)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "stream did not contain valid UTF-8"))?;
+    if ZERO_TERMINATED.load(Ordering::Relaxed) {
+        let complete = line.ends_with('\0') || terminate;
+        if line.ends_with('\0') {
+            line.pop();
+        }
+        if complete {
+            line.push('\n');
+        }
+        RECORD_END.with(|end| end.set(complete));
+        emit(&line);
+        RECORD_END.with(|end| end.set(false));
+        return Ok(());
+    }
+    if NORMALIZE.load(Ordering::Relaxed) {
+        if line.ends_with("\r\n") {
+            line.pop();
+            line.pop();
+            line.push('\n');
+        } else if terminate && !line.ends_with('\n') {
+            line.push('\n');
+        }
+    }
+    emit(&line);
+    Ok(())
+}
+
+// One of rail's own status messages; on stderr with --binary-safe, so stdout carries
+// nothing but the file's bytes
+pub fn status(message: fmt::Arguments) {
+    let message = if a11y::enabled() { a11y::status(&message.to_string()) } else { message.to_string() };
+    if BINARY_SAFE.load(Ordering::Relaxed) {
+        eprintln!("{}", message);
+    } else {
+        // On a line of its own, even after a last line without a newline
+        let message = if LINE_OPEN.swap(false, Ordering::Relaxed) { format!("\n{}", message) } else { message };
+        if let Some(captured) = CAPTURE.lock().unwrap().as_mut() {
+            captured.extend(format!("{}\n", message).into_bytes());
+            return;
+        }
+        if !CLOSED.load(Ordering::Relaxed)
+            && let Err(e) = writeln!(io::stdout(), "{}", message)
+        {
+            write_failed(e);
+        }
+        mirror::write(&[format!("{}\n", message).into_bytes()]);
+        stop_if_closed();
+    }
+}
+
+// The status line when following `filename` starts
+pub fn following(filename: &str) {
+    if filename == STDIN {
+        status(format_args!("Following standard input. Press Ctrl+C to stop."));
+    } else {
+        status(format_args!("Following file '{}'. Press Ctrl+C to stop.", filename));
+    }
+}
+
+// -q: no "==> name <==" headers
+pub fn set_quiet() {
+    QUIET.store(true, Ordering::Relaxed);
+}
+
+pub fn parse_prefix(s: &str) -> Result<Prefix, String> {
+    match s {
+        "filename" => Ok(Prefix::Filename),
+        "basename" => Ok(Prefix::Basename),
+        _ => Err(format!("expected filename or basename, got '{}'", s)),
+    }
+}
+
+pub fn set_prefix(prefix: Prefix) {
+    let _ = PREFIX.set(prefix);
+}
+
+pub fn prefix_enabled() -> bool {
+    PREFIX.get().is_some()
+}
+
+// `text` (one or more lines) with this thread's file name in front of each line
+fn tag(text: String) -> String {
+    let Some(&prefix) = PREFIX.get() else {
+        return text;
+    };
+    let name = SOURCE.with(|source| match source.borrow().as_deref() {
+        Some(STDIN) | None => "standard input".to_string(),
+        Some(name) if prefix == Prefix::Basename => {
+            std::path::Path::new(name).file_name().map_or(name.to_string(), |n| n.to_string_lossy().into_owned())
+        }
+        Some(name) => name.to_string(),
+    });
+    let mut out = String::with_capacity(text.len() + name.len() + 2);
+    for line in text.split_inclusive('\n') {
+        out.push_str(&name);
+        out.push_str(": ");
+        out.push_str(line);
+    }
+    out
+}
+
+// Lines this thread emits from now on come from `name`
+pub fn set_source(name: &str) {
+    SOURCE.with(|source| *source.borrow_mut() = Some(name.to_string()));
+}
+
+// Put out the header of this thread's file now, not just before its next line (so a
+// file with nothing to show still gets one)
+pub fn announce_source() {
+    let mut buffer = BUFFER.lock().unwrap();
+    switch_source(&mut buffer);
+}
+
+fn switch_source(buffer: &mut Buffer) {
+    SOURCE.with(|source| {
+        let source = source.borrow();
+        if source.is_none() || *source == buffer.source || QUIET.load(Ordering::Relaxed) {
+            return;
+        }
+        let name = match source.as_deref() {
+            Some(STDIN) => "standard input",
+            name => name.unwrap_or(""),
+        };
+        let header = if buffer.headers == 0 { format!("==> {} <==\n", name) } else { format!("\n==> {} <==\n", name) };
+        buffer.len += header.len();
+        buffer.lines.push(header.into_bytes());
+        buffer.source = source.clone();
+        buffer.headers += 1;
+    });
+}
+
+// While idle: the summary of what --per-source-limit left out of this thread's file, if
+// it's due
+pub fn notice_dropped() {
+    if let Some(summary) = SOURCE.with(|source| source.borrow().as_deref().and_then(limit::expired)) {
+        notice(summary);
+    }
+}
+
+// A note about this thread's file, which no limit holds back
+fn notice(text: String) {
+    let mut buffer = BUFFER.lock().unwrap();
+    switch_source(&mut buffer);
+    append(&mut buffer, text.into_bytes());
+    drop(buffer);
+    stop_if_closed();
+}
+
+fn push(mut line: Vec<u8>) {
+    if RECORD_END.with(Cell::get) && line.last() == Some(&b'\n') {
+        *line.last_mut().unwrap() = b'\0';
+    }
+    let mut buffer = BUFFER.lock().unwrap();
+    let (shown, dropped) = SOURCE.with(|source| match source.borrow().as_deref() {
+        Some(name) => limit::admit(name),
+        None => (true, None),
+    });
+    if let Some(summary) = dropped {
+        switch_source(&mut buffer);
+        append(&mut buffer, summary.into_bytes());
+    }
+    if shown {
+        switch_source(&mut buffer);
+        append(&mut buffer, line);
+    }
+    drop(buffer);
+    stop_if_closed();
+}
+
+fn append(buffer: &mut Buffer, line: Vec<u8>) {
+    buffer.len += line.len();
+    buffer.lines.push(line);
+    // Line mode writes once the reader has caught up (see flush); until then only the
+    // size cap applies, which keeps a long catch-up from holding everything in memory
+    let due = match policy() {
+        Flush::Line | Flush::Block => buffer.len >= BLOCK_SIZE,
+        Flush::Interval(every) => {
+            buffer.len >= BLOCK_SIZE || buffer.last_flush.is_none_or(|t| t.elapsed() >= every)
+        }
+    };
+    if due {
+        write_out(buffer);
+    }
+}
+
+// Write out anything buffered; call when caught up, before sleeping or blocking, and
+// before printing a status line
+pub fn flush() {
+    let mut buffer = BUFFER.lock().unwrap();
+    write_out(&mut buffer);
+    drop(buffer);
+    stop_if_closed();
+}
+
+// Collect output instead of writing it, until take_captured()
+pub fn capture() {
+    *CAPTURE.lock().unwrap() = Some(Vec::new());
+}
+
+pub fn take_captured() -> Vec<u8> {
+    flush();
+    CAPTURE.lock().unwrap().take().unwrap_or_default()
+}
+
+fn write_out(buffer: &mut Buffer) {
+    if let Some(captured) = CAPTURE.lock().unwrap().as_mut() {
+        buffer.lines.drain(..).for_each(|line| captured.extend(line));
+        buffer.len = 0;
+    }
+    if !buffer.lines.is_empty() {
+        let mut stdout = io::stdout().lock();
+        if !CLOSED.load(Ordering::Relaxed)
+            && let Err(e) = write_all_vectored(&mut stdout, &buffer.lines).and_then(|_| stdout.flush())
+        {
+            write_failed(e);
+        }
+        if let Some(&last) = buffer.lines.iter().rev().find_map(|line| line.last()) {
+            LINE_OPEN.store(last != b'\n', Ordering::Relaxed);
+        }
+        mirror::write(&buffer.lines);
+        buffer.lines.iter().for_each(|line| passthrough::written(line));
+        buffer.lines.clear();
+        buffer.len = 0;
+    }
+    buffer.last_flush = Some(Instant::now());
+    if let Err(e) = passthrough::check() {
+        eprintln!("Error: --verify-passthrough: {}", e);
+        process::exit(1);
+    }
+}
+
+// Whoever reads stdout stopped (`rail ... | head`): nothing more goes to stdout, and
+// rail wraps up (see stop_if_closed); any other failure to write is an error
+fn write_failed(e: io::Error) {
+    if e.kind() == io::ErrorKind::BrokenPipe {
+        CLOSED.store(true, Ordering::Relaxed);
+        return;
+    }
+    eprintln!("Error: Could not write output: {}", e);
+    process::exit(1);
+}
+
+// rail is finishing anyway; a closed stdout from now on needs nothing more done
+pub fn finishing() {
+    CLOSING.store(true, Ordering::Relaxed);
+}
+
+// Once stdout is closed, finish as at the end of the input (reports, --fail-on's exit
+// status) and exit; called without the buffer locked, as finishing flushes it
+fn stop_if_closed() {
+    if CLOSED.load(Ordering::Relaxed) && !CLOSING.swap(true, Ordering::Relaxed) {
+        let _ = crate::finish();
+        process::exit(0);
+    }
+}
+
+// Like Write::write_all_vectored (not yet stable): keep writing until every slice is out
+fn write_all_vectored(out: &mut impl Write, lines: &[Vec<u8>]) -> io::Result<()> {
+    let mut slices: Vec<IoSlice> = lines.iter().map(|l| IoSlice::new(l)).collect();
+    let mut remaining = &mut slices[..];
+    IoSlice::advance_slices(&mut remaining, 0);
+    while !remaining.is_empty() {
+        match out.write_vectored(remaining) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => IoSlice::advance_slices(&mut remaining, n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+// `--verify-passthrough`: with --binary-safe, keep a CRC-32 of the bytes read from the
+// file and of the bytes written to stdout. Every flush writes out all that was read, so
+// after each one the two must agree; if they ever don't, rail stops with an error
+// rather than keep feeding a pipeline altered data. When rail finishes on its own it
+// reports the byte count and CRC, which can be checked against the source (the CRC is
+// the common one of gzip, zip and `crc32`).
+
+use std::sync::Mutex;
+
+#[derive(Clone, Copy, PartialEq)]
+struct Sum {
+    crc: u32,
+    len: u64,
+}
+
+struct Sums {
+    read: Sum,
+    written: Sum,
+}
+
+static SUMS: Mutex<Option<Sums>> = Mutex::new(None);
+
+// CRC-32 (IEEE 802.3, reflected), one table lookup per byte
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+impl Sum {
+    fn add(&mut self, bytes: &[u8]) {
+        let mut crc = !self.crc;
+        for &b in bytes {
+            crc = TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+        }
+        self.crc = !crc;
+        self.len += bytes.len() as u64;
+    }
+}
+
+pub fn enable() {
+    let empty = Sum { crc: 0, len: 0 };
+    *SUMS.lock().unwrap() = Some(Sums { read: empty, written: empty });
+}
+
+pub fn read(bytes: &[u8]) {
+    if let Some(sums) = SUMS.lock().unwrap().as_mut() {
+        sums.read.add(bytes);
+    }
+}
+
+pub fn written(bytes: &[u8]) {
+    if let Some(sums) = SUMS.lock().unwrap().as_mut() {
+        sums.written.add(bytes);
+    }
+}
+
+// Once everything read has been written: an error describing the difference, if any
+pub fn check() -> Result<(), String> {
+    match SUMS.lock().unwrap().as_ref() {
+        Some(sums) if sums.read != sums.written => Err(format!(
+            "read {} bytes (CRC-32 {:08x}) but wrote {} bytes (CRC-32 {:08x})",
+            sums.read.len, sums.read.crc, sums.written.len, sums.written.crc
+        )),
+        _ => Ok(()),
+    }
+}
+
+// The summary to print when rail is done
+pub fn report() -> Option<String> {
+    let sums = SUMS.lock().unwrap();
+    let sums = sums.as_ref()?;
+    Some(format!("Passed through {} bytes unchanged (CRC-32 {:08x})", sums.written.len, sums.written.crc))
+}
@@ -0,0 +1,79 @@
+// `--pid <PID>`: stop following once process PID has exited, as in GNU tail, so a script
+// tailing the log of a job it started doesn't hang after the job is done.
+//
+// The process is checked whenever the follow loop has caught up. Once it is gone, the
+// file is read one more time (the process may have written just before exiting) and
+// rail finishes normally.
+
+use std::io;
+use std::sync::OnceLock;
+
+static PID: OnceLock<u32> = OnceLock::new();
+
+pub fn set(pid: u32) -> io::Result<()> {
+    if !alive(pid)? {
+        return Err(io::Error::new(io::ErrorKind::NotFound, "no such process"));
+    }
+    let _ = PID.set(pid);
+    Ok(())
+}
+
+pub fn watching() -> bool {
+    PID.get().is_some()
+}
+
+// True once the --pid process (if any) has exited
+pub fn exited() -> bool {
+    PID.get().is_some_and(|&pid| !alive(pid).unwrap_or(true))
+}
+
+// Signal 0 checks that the process exists without sending anything; EPERM means it
+// exists but belongs to someone else
+#[cfg(unix)]
+fn alive(pid: u32) -> io::Result<bool> {
+    use std::os::raw::c_int;
+
+    const EPERM: i32 = 1;
+    const ESRCH: i32 = 3;
+
+    unsafe extern "C" {
+        fn kill(pid: c_int, sig: c_int) -> c_int;
+    }
+    let pid = c_int::try_from(pid).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "PID out of range"))?;
+    if unsafe { kill(pid, 0) } == 0 {
+        return Ok(true);
+    }
+    let e = io::Error::last_os_error();
+    match e.raw_os_error() {
+        Some(EPERM) => Ok(true),
+        Some(ESRCH) => Ok(false),
+        _ => Err(e),
+    }
+}
+
+#[cfg(windows)]
+fn alive(pid: u32) -> io::Result<bool> {
+    use winapi::um::handleapi::CloseHandle;
+    use winapi::um::minwinbase::STILL_ACTIVE;
+    use winapi::um::processthreadsapi::{GetExitCodeProcess, OpenProcess};
+    use winapi::um::winnt::PROCESS_QUERY_LIMITED_INFORMATION;
+
+    let handle = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+    if handle.is_null() {
+        // ERROR_INVALID_PARAMETER: no process has that ID
+        let e = io::Error::last_os_error();
+        return if e.raw_os_error() == Some(87) { Ok(false) } else { Err(e) };
+    }
+    let mut code = 0;
+    let ok = unsafe { GetExitCodeProcess(handle, &mut code) };
+    unsafe { CloseHandle(handle) };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(code == STILL_ACTIVE)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn alive(_pid: u32) -> io::Result<bool> {
+    Err(io::ErrorKind::Unsupported.into())
+}
@@ -0,0 +1,150 @@
+// Aho-Corasick matcher for a set of literal byte strings.
+//
+// The regex engine extracts literals that every match must contain and checks for them
+// with this automaton first. It is a full DFA (256 transitions per state), so scanning
+// is one table lookup per byte, and while in the start state it jumps straight to the
+// next byte that can begin a literal. Far faster than the VM and no UTF-8 decoding, so
+// the common case of a line that can't possibly match is rejected cheaply.
+
+#[derive(Debug)]
+pub struct Prefilter {
+    trans: Vec<u32>,
+    accept: Vec<bool>,
+    start_bytes: [bool; 256],
+    fold_case: bool,
+}
+
+impl Prefilter {
+    // With `fold_case`, literals and input are compared ASCII case-insensitively
+    pub fn new(literals: &[Vec<u8>], fold_case: bool) -> Prefilter {
+        let fold = |b: u8| if fold_case { b.to_ascii_lowercase() } else { b };
+
+        // Build the trie
+        let mut children: Vec<Vec<(u8, u32)>> = vec![Vec::new()];
+        let mut accept = vec![false];
+        for literal in literals {
+            let mut state = 0usize;
+            for &b in literal {
+                let b = fold(b);
+                state = match children[state].iter().find(|&&(c, _)| c == b) {
+                    Some(&(_, next)) => next as usize,
+                    None => {
+                        let next = children.len();
+                        children.push(Vec::new());
+                        accept.push(false);
+                        children[state].push((b, next as u32));
+                        next
+                    }
+                };
+            }
+            accept[state] = true;
+        }
+
+        // Breadth-first: fill in missing transitions from each state's failure link so
+        // the result is a DFA with no backtracking at match time
+        let mut trans = vec![0u32; children.len() * 256];
+        let mut fail = vec![0usize; children.len()];
+        let mut queue = std::collections::VecDeque::new();
+        for &(b, next) in &children[0] {
+            trans[b as usize] = next;
+            queue.push_back(next as usize);
+        }
+        while let Some(state) = queue.pop_front() {
+            accept[state] |= accept[fail[state]];
+            for b in 0..256 {
+                trans[state * 256 + b] = trans[fail[state] * 256 + b];
+            }
+            for &(b, next) in &children[state] {
+                fail[next as usize] = trans[fail[state] * 256 + b as usize] as usize;
+                trans[state * 256 + b as usize] = next;
+                queue.push_back(next as usize);
+            }
+        }
+
+        let mut start_bytes = [false; 256];
+        for &(b, _) in &children[0] {
+            start_bytes[b as usize] = true;
+            if fold_case {
+                start_bytes[b.to_ascii_uppercase() as usize] = true;
+            }
+        }
+
+        Prefilter { trans, accept, start_bytes, fold_case }
+    }
+
+    // True if any of the literals occurs in `haystack`
+    pub fn is_match(&self, haystack: &[u8]) -> bool {
+        let mut state = 0usize;
+        let mut i = 0;
+        while i < haystack.len() {
+            if state == 0 {
+                match haystack[i..].iter().position(|&b| self.start_bytes[b as usize]) {
+                    Some(skip) => i += skip,
+                    None => return false,
+                }
+            }
+            let mut b = haystack[i];
+            if self.fold_case {
+                b = b.to_ascii_lowercase();
+            }
+            state = self.trans[state * 256 + b as usize] as usize;
+            if self.accept[state] {
+                return true;
+            }
+            i += 1;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefilter(literals: &[&str], fold_case: bool) -> Prefilter {
+        let literals: Vec<Vec<u8>> = literals.iter().map(|l| l.as_bytes().to_vec()).collect();
+        Prefilter::new(&literals, fold_case)
+    }
+
+    #[test]
+    fn overlapping_literals_follow_failure_links() {
+        let classic = prefilter(&["he", "she", "his", "hers"], false);
+        for text in ["ushers", "ahishe", "she", "xxhis", "hhe"] {
+            assert!(classic.is_match(text.as_bytes()), "{}", text);
+        }
+        for text in ["", "hi", "sh", "hs", "eh"] {
+            assert!(!classic.is_match(text.as_bytes()), "{}", text);
+        }
+        // "bc" is found only through the failure link out of "abc"
+        let nested = prefilter(&["abcd", "bc"], false);
+        assert!(nested.is_match(b"abce"));
+        assert!(!nested.is_match(b"abdc"));
+        // A failed match that is itself a prefix of the literal
+        let repeated = prefilter(&["aab"], false);
+        assert!(repeated.is_match(b"aaab"));
+        assert!(repeated.is_match(b"aaaaab"));
+        assert!(!repeated.is_match(b"abab"));
+    }
+
+    #[test]
+    fn fold_case() {
+        let folded = prefilter(&["error", "Warn"], true);
+        for text in ["An ERROR here", "Error", "eRrOr", "WARN", "warning"] {
+            assert!(folded.is_match(text.as_bytes()), "{}", text);
+        }
+        assert!(!folded.is_match(b"err0r"));
+        let exact = prefilter(&["error"], false);
+        assert!(exact.is_match(b"an error"));
+        assert!(!exact.is_match(b"an ERROR"));
+        assert!(!exact.is_match(b"Error"));
+    }
+
+    #[test]
+    fn bytes_beyond_ascii_and_no_literals() {
+        let utf8 = prefilter(&["é", "\u{2192}"], false);
+        assert!(utf8.is_match("café".as_bytes()));
+        assert!(utf8.is_match("a → b".as_bytes()));
+        assert!(!utf8.is_match("cafe".as_bytes()));
+        assert!(!prefilter(&[], false).is_match(b"anything"));
+    }
+}
@@ -0,0 +1,179 @@
+// Following things that aren't ordinary, growing files.
+//
+// FIFOs, character devices and sockets can't be seeked or stat'ed for growth, so they
+// are read as a plain stream. Files under /proc and /sys (and other regular files that
+// report a zero size but still have content) are regenerated on every read, so seeking
+// to "the end" finds nothing; those are re-read periodically and printed when they change.
+// Standard input is always a stream, even when redirected from a file, and following it
+// ends when it does. -n +N reads past the lines before N, and -c N keeps the last N bytes
+// read, as neither can seek.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::thread;
+
+use crate::follow;
+use crate::line_numbers;
+use crate::open::{STDIN, open_log};
+use crate::output;
+use crate::pid;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FileKind {
+    Regular,
+    Pseudo,
+    Stream,
+}
+
+pub fn file_kind(filename: &str) -> io::Result<FileKind> {
+    if filename == STDIN {
+        return Ok(FileKind::Stream);
+    }
+    let metadata = fs::metadata(filename)?;
+    let file_type = metadata.file_type();
+    if !file_type.is_file() {
+        return Ok(if file_type.is_dir() { FileKind::Regular } else { FileKind::Stream });
+    }
+
+    if filename.starts_with("/proc/") || filename.starts_with("/sys/") {
+        return Ok(FileKind::Pseudo);
+    }
+    if metadata.len() == 0 {
+        // An empty log stays empty when read; a generated file doesn't
+        let mut byte = [0u8; 1];
+        if open_log(filename)?.read(&mut byte)? > 0 {
+            return Ok(FileKind::Pseudo);
+        }
+    }
+    Ok(FileKind::Regular)
+}
+
+// -n +N: everything from line `line` on; returns the bytes read
+pub fn print_from_line(filename: &str, line: usize) -> io::Result<u64> {
+    let mut reader = BufReader::new(open_log(filename)?);
+    let skip = line.saturating_sub(1);
+    let mut buffer = Vec::new();
+    let mut read = 0;
+    let mut skipped = 0;
+    while reader.read_until(output::delimiter(), &mut buffer)? > 0 {
+        read += buffer.len() as u64;
+        if skipped < skip {
+            skipped += 1;
+            buffer.clear();
+            if skipped == skip {
+                line_numbers::start_at(skip as u64 + 1);
+            }
+            continue;
+        }
+        output::emit_bytes(std::mem::take(&mut buffer), true)?;
+    }
+    output::flush();
+    Ok(read)
+}
+
+// -c N: the last `num_bytes` bytes; returns the bytes read
+pub fn tail_bytes(filename: &str, num_bytes: u64) -> io::Result<u64> {
+    let mut file = open_log(filename)?;
+    let keep = usize::try_from(num_bytes).unwrap_or(usize::MAX);
+    let mut last = VecDeque::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut read = 0;
+    loop {
+        let n = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        read += n as u64;
+        last.extend(&buffer[..n]);
+        last.drain(..last.len().saturating_sub(keep));
+    }
+    if !last.is_empty() {
+        output::emit_bytes(last.into(), false)?;
+    }
+    output::flush();
+    Ok(read)
+}
+
+// Print lines as they arrive, after the first `skip` (-n +N). When a FIFO's writer goes
+// away, reopen and wait for the next one rather than spinning on EOF; with --pid, poll
+// instead, as opening would block until a writer came and the process couldn't be checked.
+pub fn follow_stream(filename: &str, mut skip: usize) -> io::Result<()> {
+    let mut reader = BufReader::new(open_log(filename)?);
+    let mut line = Vec::new();
+    if skip > 0 {
+        line_numbers::start_at(skip as u64 + 1);
+    }
+    loop {
+        // Nothing buffered means the next read may block, so don't sit on output
+        if reader.buffer().is_empty() {
+            output::flush();
+        }
+        // At the end of what has been written; with --pid, that's all there will be once
+        // the process has exited
+        if reader.read_until(output::delimiter(), &mut line)? == 0 {
+            if filename == STDIN || pid::exited() {
+                return Ok(());
+            }
+            if is_fifo(filename) && !pid::watching() {
+                reader = BufReader::new(open_log(filename)?);
+            } else {
+                thread::sleep(follow::poll_interval());
+            }
+            continue;
+        }
+        // A line being skipped is only done with once its end has been read
+        if skip > 0 {
+            if line.last() == Some(&output::delimiter()) {
+                skip -= 1;
+                line.clear();
+            }
+            continue;
+        }
+        output::emit_bytes(std::mem::take(&mut line), false)?;
+    }
+}
+
+// Re-read a generated file every poll interval (-s, or `rail ctl ... sleep-interval`) and
+// print the whole new content whenever it differs from the previous read
+pub fn follow_snapshots(filename: &str) -> io::Result<()> {
+    let mut last = read_snapshot(filename)?;
+    loop {
+        thread::sleep(follow::poll_interval());
+        let exited = pid::exited();
+        let current = read_snapshot(filename)?;
+        if current != last {
+            let mut text = String::from_utf8_lossy(&current).replace("\r\n", "\n");
+            if !text.ends_with('\n') {
+                text.push('\n');
+            }
+            for line in text.split_inclusive('\n') {
+                output::emit(line);
+            }
+            output::flush();
+            last = current;
+        }
+        if exited {
+            return Ok(());
+        }
+    }
+}
+
+fn read_snapshot(filename: &str) -> io::Result<Vec<u8>> {
+    let mut contents = Vec::new();
+    open_log(filename)?.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+#[cfg(unix)]
+fn is_fifo(filename: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    fs::metadata(filename).is_ok_and(|m| m.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_filename: &str) -> bool {
+    false
+}
@@ -0,0 +1,53 @@
+// `--reclassify '<regex> => <level>'`: override the level a --format record was parsed
+// with, for libraries that log routine conditions at ERROR. The first rule whose regex
+// matches any of the record's field values (the message, a logcat tag, ...) wins; its
+// level replaces the record's level field before anything else sees the record, so the
+// rendered line, --json and the severity sent by --forward all agree. The original
+// level is kept in `reclassified_from`.
+//
+// The level field is the first of LEVEL_FIELDS the record has ("level" is added if it
+// has none). The new level is written in the case the old one used, so `=> warn` on a
+// PostgreSQL record gives WARN; for logcat the level must be one logcat knows (verbose,
+// debug, info, warn, error, fatal, assert) to be printed as its letter.
+
+use crate::fields::Record;
+use crate::otlp::LEVEL_FIELDS;
+use crate::regex::Regex;
+
+#[derive(Debug)]
+pub struct Rule {
+    regex: Regex,
+    level: String,
+}
+
+pub fn parse_rule(spec: &str) -> Result<Rule, String> {
+    let (pattern, level) = spec
+        .rsplit_once("=>")
+        .ok_or(format!("expected '<regex> => <level>', got '{}'", spec))?;
+    let (pattern, level) = (pattern.trim(), level.trim());
+    if level.is_empty() || !level.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(format!("'{}' is not a level name", level));
+    }
+    let regex = Regex::new(pattern).map_err(|e| e.to_string())?;
+    Ok(Rule { regex, level: level.to_string() })
+}
+
+pub fn apply(rules: &[Rule], record: &mut Record) {
+    let Some(rule) = rules.iter().find(|r| record.fields.iter().any(|(_, v)| r.regex.is_match(v))) else {
+        return;
+    };
+    let field = LEVEL_FIELDS.iter().find(|name| record.get(name).is_some()).copied();
+    let Some((_, value)) = field.and_then(|name| record.fields.iter_mut().find(|(k, _)| k == name)) else {
+        record.fields.push(("level".to_string(), rule.level.clone()));
+        return;
+    };
+    let level = if value.chars().any(|c| c.is_ascii_lowercase()) {
+        rule.level.to_ascii_lowercase()
+    } else {
+        rule.level.to_ascii_uppercase()
+    };
+    if *value != level {
+        let original = std::mem::replace(value, level);
+        record.fields.push(("reclassified_from".to_string(), original));
+    }
+}
@@ -0,0 +1,988 @@
+// A small regular expression engine (rail has no dependencies beyond winapi).
+//
+// Patterns are parsed into an AST and compiled to a program for a Pike VM, which runs
+// all alternatives in lockstep: matching is linear in the length of the line no matter
+// how the pattern is written, and submatch positions follow Perl's leftmost-first rules.
+//
+// Supported syntax: literals and escapes (\t \n \r \xHH \. etc.), `.`, classes
+// ([a-z], [^...], \d \w \s and their negations), anchors (^ $ \b \B), groups
+// ((...), (?:...), (?P<name>...) / (?<name>...)), alternation, the `(?i)` flag, and
+// greedy or lazy `*`, `+`, `?`, `{n}`, `{n,}`, `{n,m}`.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use crate::prefilter::Prefilter;
+
+// Counted repetitions are expanded, so keep them from blowing up the program
+const MAX_REPEAT: u32 = 1000;
+
+// Limits on the literal sets considered for the prefilter
+const MAX_LITERALS: usize = 64;
+const MAX_LITERAL_LEN: usize = 64;
+
+#[derive(Clone, Debug)]
+enum Node {
+    Empty,
+    Char(char, bool),
+    Any,
+    Class(Class),
+    Look(Look),
+    Group(Box<Node>, Option<usize>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat { node: Box<Node>, min: u32, max: Option<u32>, greedy: bool },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Look {
+    Start,
+    End,
+    WordBoundary,
+    NotWordBoundary,
+}
+
+#[derive(Clone, Debug)]
+struct Class {
+    ranges: Vec<(char, char)>,
+    negated: bool,
+    case_insensitive: bool,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        let hit = |c: char| self.ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi);
+        let mut found = hit(c);
+        if !found && self.case_insensitive {
+            found = c.to_lowercase().any(hit) || c.to_uppercase().any(hit);
+        }
+        found != self.negated
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Inst {
+    Char(char, bool),
+    Any,
+    Class(Class),
+    Look(Look),
+    Split(usize, usize),
+    Jmp(usize),
+    Save(usize),
+    Match,
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pattern: String,
+    msg: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid regex '{}': {}", self.pattern, self.msg)
+    }
+}
+
+#[derive(Debug)]
+pub struct Regex {
+    pattern: String,
+    prog: Vec<Inst>,
+    // Capture group names by index; group 0 is the whole match
+    names: Vec<Option<String>>,
+    // Literals every match must contain, checked before running the VM
+    prefilter: Option<Prefilter>,
+    // Thread lists reused between searches so matching doesn't allocate
+    pool: Mutex<Vec<(Threads, Threads)>>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, Error> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            case_insensitive: false,
+            names: vec![None],
+        };
+        let ast = parser.parse_alt().and_then(|ast| {
+            if parser.pos < parser.chars.len() {
+                Err("unmatched ')'".to_string())
+            } else {
+                Ok(ast)
+            }
+        });
+        let ast = ast.map_err(|msg| Error { pattern: pattern.to_string(), msg })?;
+
+        let mut prog = vec![Inst::Save(0)];
+        compile(&ast, &mut prog);
+        prog.push(Inst::Save(1));
+        prog.push(Inst::Match);
+
+        let prefilter = required_literals(&ast).map(|(literals, fold_case)| Prefilter::new(&literals, fold_case));
+
+        Ok(Regex { pattern: pattern.to_string(), prog, names: parser.names, prefilter, pool: Mutex::new(Vec::new()) })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    // Number of capture groups, counting the whole match as group 0
+    pub fn groups(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn group_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n.as_deref() == Some(name))
+    }
+
+    // Leftmost match at or after byte offset `start`, with the (start, end) of every
+    // group that took part in it
+    pub fn captures_at(&self, text: &str, start: usize) -> Option<Vec<Option<(usize, usize)>>> {
+        if let Some(prefilter) = &self.prefilter
+            && !prefilter.is_match(&text.as_bytes()[start..])
+        {
+            return None;
+        }
+        let slots = self.exec(text.as_bytes(), start, self.names.len() * 2)?;
+        Some(slots.chunks(2).map(|pair| Some((pair[0]?, pair[1]?))).collect())
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        let text = text.as_bytes();
+        if let Some(prefilter) = &self.prefilter
+            && !prefilter.is_match(text)
+        {
+            return false;
+        }
+        self.exec(text, 0, 0).is_some()
+    }
+
+    // Pike VM: every live thread advances one character at a time, in priority order
+    fn exec(&self, text: &[u8], start: usize, nslots: usize) -> Option<Vec<Option<usize>>> {
+        let (mut clist, mut nlist) = self.pool.lock().unwrap().pop().unwrap_or_else(|| {
+            (Threads::new(self.prog.len()), Threads::new(self.prog.len()))
+        });
+        clist.clear();
+        nlist.clear();
+        let mut matched: Option<Vec<Option<usize>>> = None;
+        let mut scratch = vec![None; nslots];
+
+        let mut pos = start;
+        loop {
+            // A new attempt starting here has the lowest priority of all
+            if matched.is_none() {
+                scratch.iter_mut().for_each(|s| *s = None);
+                self.add_thread(&mut clist, 0, pos, text, &mut scratch);
+            }
+            if clist.is_empty() && (matched.is_some() || pos >= text.len()) {
+                break;
+            }
+
+            let (c, len) = decode(text, pos);
+            for i in 0..clist.threads.len() {
+                let (pc, ref slots) = clist.threads[i];
+                let step = match &self.prog[pc] {
+                    Inst::Match => {
+                        matched = Some(slots.clone());
+                        // Lower-priority threads can't win any more
+                        break;
+                    }
+                    Inst::Char(want, ci) => c.is_some_and(|c| c == *want || (*ci && eq_ignore_case(c, *want))),
+                    Inst::Any => c.is_some_and(|c| c != '\n'),
+                    Inst::Class(class) => c.is_some_and(|c| class.matches(c)),
+                    _ => false,
+                };
+                if step {
+                    let mut slots = slots.clone();
+                    self.add_thread(&mut nlist, pc + 1, pos + len, text, &mut slots);
+                }
+            }
+
+            std::mem::swap(&mut clist, &mut nlist);
+            nlist.clear();
+            if pos >= text.len() {
+                break;
+            }
+            pos += len;
+        }
+
+        self.pool.lock().unwrap().push((clist, nlist));
+        matched
+    }
+
+    // Follow epsilon transitions from `pc`, queueing the threads that consume input
+    fn add_thread(&self, list: &mut Threads, pc: usize, pos: usize, text: &[u8], slots: &mut Vec<Option<usize>>) {
+        if !list.mark(pc) {
+            return;
+        }
+        match &self.prog[pc] {
+            Inst::Jmp(target) => self.add_thread(list, *target, pos, text, slots),
+            Inst::Split(first, second) => {
+                self.add_thread(list, *first, pos, text, slots);
+                self.add_thread(list, *second, pos, text, slots);
+            }
+            Inst::Save(slot) => {
+                if *slot < slots.len() {
+                    let old = slots[*slot];
+                    slots[*slot] = Some(pos);
+                    self.add_thread(list, pc + 1, pos, text, slots);
+                    slots[*slot] = old;
+                } else {
+                    self.add_thread(list, pc + 1, pos, text, slots);
+                }
+            }
+            Inst::Look(look) => {
+                if look_holds(*look, text, pos) {
+                    self.add_thread(list, pc + 1, pos, text, slots);
+                }
+            }
+            _ => list.threads.push((pc, slots.clone())),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Threads {
+    threads: Vec<(usize, Vec<Option<usize>>)>,
+    // Sparse set of program counters already queued for this position
+    dense: Vec<usize>,
+    sparse: Vec<usize>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Threads {
+        Threads { threads: Vec::new(), dense: Vec::with_capacity(size), sparse: vec![0; size] }
+    }
+
+    fn mark(&mut self, pc: usize) -> bool {
+        let i = self.sparse[pc];
+        if i < self.dense.len() && self.dense[i] == pc {
+            return false;
+        }
+        self.sparse[pc] = self.dense.len();
+        self.dense.push(pc);
+        true
+    }
+
+    fn is_empty(&self) -> bool {
+        self.threads.is_empty()
+    }
+
+    fn clear(&mut self) {
+        self.threads.clear();
+        self.dense.clear();
+    }
+}
+
+// Decode the character at `pos`, treating invalid UTF-8 as one U+FFFD per byte
+fn decode(text: &[u8], pos: usize) -> (Option<char>, usize) {
+    if pos >= text.len() {
+        return (None, 0);
+    }
+    let b = text[pos];
+    let len = match b {
+        0x00..=0x7f => return (Some(b as char), 1),
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return (Some('\u{fffd}'), 1),
+    };
+    match text.get(pos..pos + len).and_then(|s| std::str::from_utf8(s).ok()) {
+        Some(s) => (s.chars().next(), len),
+        None => (Some('\u{fffd}'), 1),
+    }
+}
+
+fn decode_before(text: &[u8], pos: usize) -> Option<char> {
+    if pos == 0 {
+        return None;
+    }
+    let mut start = pos - 1;
+    while start > 0 && pos - start < 4 && (text[start] & 0xc0) == 0x80 {
+        start -= 1;
+    }
+    match decode(text, start) {
+        (c, len) if start + len == pos => c,
+        _ => Some('\u{fffd}'),
+    }
+}
+
+fn is_word(c: Option<char>) -> bool {
+    c.is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn look_holds(look: Look, text: &[u8], pos: usize) -> bool {
+    match look {
+        Look::Start => pos == 0,
+        Look::End => pos == text.len() || (pos + 1 == text.len() && text[pos] == b'\n'),
+        Look::WordBoundary | Look::NotWordBoundary => {
+            let boundary = is_word(decode_before(text, pos)) != is_word(decode(text, pos).0);
+            boundary == (look == Look::WordBoundary)
+        }
+    }
+}
+
+fn eq_ignore_case(a: char, b: char) -> bool {
+    a.to_lowercase().eq(b.to_lowercase())
+}
+
+fn compile(node: &Node, prog: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Char(c, ci) => prog.push(Inst::Char(*c, *ci)),
+        Node::Any => prog.push(Inst::Any),
+        Node::Class(class) => prog.push(Inst::Class(class.clone())),
+        Node::Look(look) => prog.push(Inst::Look(*look)),
+        Node::Group(inner, None) => compile(inner, prog),
+        Node::Group(inner, Some(index)) => {
+            prog.push(Inst::Save(index * 2));
+            compile(inner, prog);
+            prog.push(Inst::Save(index * 2 + 1));
+        }
+        Node::Concat(nodes) => nodes.iter().for_each(|n| compile(n, prog)),
+        Node::Alt(nodes) => {
+            let mut jumps = Vec::new();
+            for (i, alt) in nodes.iter().enumerate() {
+                if i + 1 < nodes.len() {
+                    let split = prog.len();
+                    prog.push(Inst::Split(split + 1, 0));
+                    compile(alt, prog);
+                    jumps.push(prog.len());
+                    prog.push(Inst::Jmp(0));
+                    let next = prog.len();
+                    prog[split] = Inst::Split(split + 1, next);
+                } else {
+                    compile(alt, prog);
+                }
+            }
+            let end = prog.len();
+            for j in jumps {
+                prog[j] = Inst::Jmp(end);
+            }
+        }
+        Node::Repeat { node, min, max, greedy } => {
+            for _ in 0..*min {
+                compile(node, prog);
+            }
+            let split = |body: usize, out: usize| if *greedy { Inst::Split(body, out) } else { Inst::Split(out, body) };
+            match max {
+                None => {
+                    let start = prog.len();
+                    prog.push(Inst::Split(0, 0));
+                    compile(node, prog);
+                    prog.push(Inst::Jmp(start));
+                    let out = prog.len();
+                    prog[start] = split(start + 1, out);
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(prog.len());
+                        prog.push(Inst::Split(0, 0));
+                        compile(node, prog);
+                    }
+                    let out = prog.len();
+                    for s in splits {
+                        prog[s] = split(s + 1, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    case_insensitive: bool,
+    names: Vec<Option<String>>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn parse_alt(&mut self) -> Result<Node, String> {
+        let mut alts = vec![self.parse_concat()?];
+        while self.eat('|') {
+            alts.push(self.parse_concat()?);
+        }
+        Ok(if alts.len() == 1 { alts.pop().unwrap() } else { Node::Alt(alts) })
+    }
+
+    fn parse_concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            let atom = self.parse_repeat(atom)?;
+            nodes.push(atom);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn parse_repeat(&mut self, atom: Node) -> Result<Node, String> {
+        let mut node = atom;
+        loop {
+            let (min, max) = match self.peek() {
+                Some('*') => {
+                    self.pos += 1;
+                    (0, None)
+                }
+                Some('+') => {
+                    self.pos += 1;
+                    (1, None)
+                }
+                Some('?') => {
+                    self.pos += 1;
+                    (0, Some(1))
+                }
+                Some('{') => match self.parse_counts()? {
+                    Some(counts) => counts,
+                    None => return Ok(node),
+                },
+                _ => return Ok(node),
+            };
+            if matches!(node, Node::Look(_)) {
+                return Err("repetition of an anchor".to_string());
+            }
+            let greedy = !self.eat('?');
+            node = Node::Repeat { node: Box::new(node), min, max, greedy };
+        }
+    }
+
+    // `{n}`, `{n,}` or `{n,m}`; a `{` that doesn't start one of those is a literal
+    fn parse_counts(&mut self) -> Result<Option<(u32, Option<u32>)>, String> {
+        let start = self.pos;
+        self.pos += 1;
+        let number = |p: &mut Parser| {
+            let from = p.pos;
+            while p.peek().is_some_and(|c| c.is_ascii_digit()) {
+                p.pos += 1;
+            }
+            p.chars[from..p.pos].iter().collect::<String>().parse::<u32>().ok()
+        };
+        let min = number(self);
+        let max = if self.eat(',') { number(self) } else { min };
+        if min.is_none() || !self.eat('}') {
+            self.pos = start;
+            return Ok(None);
+        }
+        let min = min.unwrap();
+        if max.is_some_and(|m| m < min) {
+            return Err("repetition range out of order".to_string());
+        }
+        if min > MAX_REPEAT || max.is_some_and(|m| m > MAX_REPEAT) {
+            return Err(format!("repetition count above {}", MAX_REPEAT));
+        }
+        Ok(Some((min, max)))
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        let c = self.peek().unwrap();
+        self.pos += 1;
+        match c {
+            '.' => Ok(Node::Any),
+            '^' => Ok(Node::Look(Look::Start)),
+            '$' => Ok(Node::Look(Look::End)),
+            '(' => self.parse_group(),
+            '[' => self.parse_class().map(Node::Class),
+            '\\' => self.parse_escape(),
+            '*' | '+' | '?' => Err(format!("nothing to repeat before '{}'", c)),
+            c => Ok(Node::Char(c, self.case_insensitive)),
+        }
+    }
+
+    fn parse_group(&mut self) -> Result<Node, String> {
+        let mut capture = true;
+        let mut name = None;
+        let outer_ci = self.case_insensitive;
+
+        if self.eat('?') {
+            if self.eat(':') {
+                capture = false;
+            } else if self.eat('P') || self.peek() == Some('<') {
+                if !self.eat('<') {
+                    return Err("expected '<' after '(?P'".to_string());
+                }
+                let from = self.pos;
+                while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+                    self.pos += 1;
+                }
+                if from == self.pos || !self.eat('>') {
+                    return Err("invalid group name".to_string());
+                }
+                name = Some(self.chars[from..self.pos - 1].iter().collect());
+            } else {
+                // Flags: (?i) applies to the rest of the enclosing group, (?i:...) to its body
+                let mut ci = self.case_insensitive;
+                let mut negate = false;
+                loop {
+                    match self.peek() {
+                        Some('i') => ci = !negate,
+                        Some('-') => negate = true,
+                        Some(')') => {
+                            self.pos += 1;
+                            self.case_insensitive = ci;
+                            return Ok(Node::Empty);
+                        }
+                        Some(':') => {
+                            self.pos += 1;
+                            self.case_insensitive = ci;
+                            capture = false;
+                            break;
+                        }
+                        _ => return Err("unsupported group flag".to_string()),
+                    }
+                    self.pos += 1;
+                }
+            }
+        }
+
+        let index = if capture {
+            self.names.push(name);
+            Some(self.names.len() - 1)
+        } else {
+            None
+        };
+        let inner = self.parse_alt()?;
+        if !self.eat(')') {
+            return Err("unclosed group".to_string());
+        }
+        self.case_insensitive = outer_ci;
+        Ok(Node::Group(Box::new(inner), index))
+    }
+
+    fn parse_escape(&mut self) -> Result<Node, String> {
+        let c = self.peek().ok_or("trailing backslash")?;
+        self.pos += 1;
+        let class = |ranges: &[(char, char)], negated| {
+            Node::Class(Class { ranges: ranges.to_vec(), negated, case_insensitive: false })
+        };
+        Ok(match c {
+            'd' => class(DIGIT, false),
+            'D' => class(DIGIT, true),
+            'w' => class(WORD, false),
+            'W' => class(WORD, true),
+            's' => class(SPACE, false),
+            'S' => class(SPACE, true),
+            'b' => Node::Look(Look::WordBoundary),
+            'B' => Node::Look(Look::NotWordBoundary),
+            'A' => Node::Look(Look::Start),
+            'z' => Node::Look(Look::End),
+            _ => Node::Char(self.escaped_char(c)?, self.case_insensitive),
+        })
+    }
+
+    fn escaped_char(&mut self, c: char) -> Result<char, String> {
+        Ok(match c {
+            't' => '\t',
+            'n' => '\n',
+            'r' => '\r',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            '0' => '\0',
+            'x' => {
+                let hex: String = self.chars.get(self.pos..self.pos + 2).ok_or("incomplete \\x escape")?.iter().collect();
+                self.pos += 2;
+                u8::from_str_radix(&hex, 16).map_err(|_| "invalid \\x escape")? as char
+            }
+            c if c.is_ascii_alphanumeric() => return Err(format!("unknown escape '\\{}'", c)),
+            c => c,
+        })
+    }
+
+    fn parse_class(&mut self) -> Result<Class, String> {
+        let negated = self.eat('^');
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.peek().ok_or("unclosed character class")?;
+            self.pos += 1;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let lo = if c == '\\' {
+                let e = self.peek().ok_or("unclosed character class")?;
+                self.pos += 1;
+                let predefined = match e {
+                    'd' => Some(DIGIT),
+                    'w' => Some(WORD),
+                    's' => Some(SPACE),
+                    _ => None,
+                };
+                if let Some(set) = predefined {
+                    ranges.extend_from_slice(set);
+                    continue;
+                }
+                self.escaped_char(e)?
+            } else {
+                c
+            };
+
+            // A '-' between two characters makes a range; elsewhere it's literal
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&n| n != ']') {
+                self.pos += 1;
+                let mut hi = self.peek().unwrap();
+                self.pos += 1;
+                if hi == '\\' {
+                    let e = self.peek().ok_or("unclosed character class")?;
+                    self.pos += 1;
+                    hi = self.escaped_char(e)?;
+                }
+                if hi < lo {
+                    return Err("character class range out of order".to_string());
+                }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+        Ok(Class { ranges, negated, case_insensitive: self.case_insensitive })
+    }
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')];
+const SPACE: &[(char, char)] = &[('\t', '\r'), (' ', ' ')];
+
+// Literal sets for the prefilter: at least one string of the returned set occurs in
+// every match, so a line containing none of them can be rejected without the VM.
+// Returns the set and whether it needs ASCII case folding.
+fn required_literals(node: &Node) -> Option<(Vec<Vec<u8>>, bool)> {
+    let (set, fold_case) = required(node)?;
+    if set.is_empty() || set.iter().any(|s| s.is_empty()) {
+        return None;
+    }
+    Some((set, fold_case))
+}
+
+// A set of strings, with a flag for "compare ASCII case-insensitively"
+type Literals = (Vec<Vec<u8>>, bool);
+
+fn required(node: &Node) -> Option<Literals> {
+    if let Some(set) = exact(node) {
+        return Some(set);
+    }
+    match node {
+        Node::Group(inner, _) => required(inner),
+        Node::Repeat { node, min, .. } if *min >= 1 => required(node),
+        Node::Alt(alts) => {
+            let mut union: Vec<Vec<u8>> = Vec::new();
+            let mut fold_case = false;
+            for alt in alts {
+                let (set, fold) = required(alt)?;
+                union.extend(set);
+                fold_case |= fold;
+            }
+            union.sort();
+            union.dedup();
+            (union.len() <= MAX_LITERALS).then_some((union, fold_case))
+        }
+        Node::Concat(nodes) => {
+            // Runs of exactly-known pieces join into longer literals; otherwise fall
+            // back on whatever a single piece requires. Keep the most selective.
+            let mut best: Option<Literals> = None;
+            let mut run: Option<Literals> = Some((vec![Vec::new()], false));
+            for n in nodes {
+                match exact(n) {
+                    Some(set) => {
+                        run = run.and_then(|r| cross(&r, &set));
+                        if let Some(r) = &run {
+                            best = better(best, r.clone());
+                        }
+                    }
+                    None => {
+                        if let Some(set) = required(n) {
+                            best = better(best, set);
+                        }
+                        run = Some((vec![Vec::new()], false));
+                    }
+                }
+            }
+            best
+        }
+        _ => None,
+    }
+}
+
+// Every string the node can match, if that's a small finite set
+fn exact(node: &Node) -> Option<Literals> {
+    match node {
+        Node::Char(c, ci) => {
+            let mut buf = [0u8; 4];
+            let bytes = c.encode_utf8(&mut buf).as_bytes().to_vec();
+            if !ci || !c.is_alphabetic() {
+                Some((vec![bytes], false))
+            } else if c.is_ascii() && !c.eq_ignore_ascii_case(&'k') {
+                // The VM's case folding is Unicode-aware; only plain ASCII letters with
+                // no non-ASCII case partners (KELVIN SIGN folds to k) are safe to fold here
+                Some((vec![bytes], true))
+            } else {
+                None
+            }
+        }
+        Node::Class(class) if !class.negated && !class.case_insensitive => {
+            let mut set = Vec::new();
+            for &(lo, hi) in &class.ranges {
+                for c in lo..=hi {
+                    if set.len() >= 4 {
+                        return None;
+                    }
+                    let mut buf = [0u8; 4];
+                    set.push(c.encode_utf8(&mut buf).as_bytes().to_vec());
+                }
+            }
+            Some((set, false))
+        }
+        Node::Group(inner, _) => exact(inner),
+        Node::Concat(nodes) => {
+            let mut acc: Literals = (vec![Vec::new()], false);
+            for n in nodes {
+                acc = cross(&acc, &exact(n)?)?;
+            }
+            Some(acc)
+        }
+        Node::Alt(alts) => {
+            let mut union = Vec::new();
+            let mut fold_case = false;
+            for alt in alts {
+                let (set, fold) = exact(alt)?;
+                union.extend(set);
+                fold_case |= fold;
+            }
+            (union.len() <= MAX_LITERALS).then_some((union, fold_case))
+        }
+        Node::Repeat { node, min, max: Some(max), .. } if min == max && *min <= 8 => {
+            let inner = exact(node)?;
+            let mut acc: Literals = (vec![Vec::new()], false);
+            for _ in 0..*min {
+                acc = cross(&acc, &inner)?;
+            }
+            Some(acc)
+        }
+        _ => None,
+    }
+}
+
+fn cross(a: &Literals, b: &Literals) -> Option<Literals> {
+    if a.0.len() * b.0.len() > MAX_LITERALS {
+        return None;
+    }
+    let mut out = Vec::new();
+    for x in &a.0 {
+        for y in &b.0 {
+            let mut s = x.clone();
+            s.extend_from_slice(y);
+            if s.len() > MAX_LITERAL_LEN {
+                return None;
+            }
+            out.push(s);
+        }
+    }
+    Some((out, a.1 || b.1))
+}
+
+// Prefer the set whose shortest member is longest: it rules out the most lines
+fn better(current: Option<Literals>, candidate: Literals) -> Option<Literals> {
+    let score = |set: &Literals| set.0.iter().map(|s| s.len()).min().unwrap_or(0);
+    match current {
+        Some(c) if score(&c) >= score(&candidate) => Some(c),
+        _ => Some(candidate),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The leftmost match of `pattern` in `text`
+    fn find<'a>(pattern: &str, text: &'a str) -> Option<&'a str> {
+        let groups = Regex::new(pattern).unwrap().captures_at(text, 0)?;
+        groups[0].map(|(start, end)| &text[start..end])
+    }
+
+    // Every group of the leftmost match
+    fn groups<'a>(pattern: &str, text: &'a str) -> Vec<Option<&'a str>> {
+        let groups = Regex::new(pattern).unwrap().captures_at(text, 0).unwrap();
+        groups.into_iter().map(|group| group.map(|(start, end)| &text[start..end])).collect()
+    }
+
+    #[test]
+    fn literals_classes_and_escapes() {
+        assert_eq!(find("b.d", "abcde"), Some("bcd"));
+        assert_eq!(find("[a-c]+", "xxabcabd"), Some("abcab"));
+        assert_eq!(find("[^a-c ]+", "abc def"), Some("def"));
+        assert_eq!(find(r"\d+\.\d+", "v 10.25s"), Some("10.25"));
+        assert_eq!(find(r"\w+\s\W", "ok, go !"), Some("go !"));
+        assert_eq!(find(r"\x41\t", "A\tB"), Some("A\t"));
+        assert_eq!(find(".", "é"), Some("é"));
+        assert_eq!(find("x", "abc"), None);
+    }
+
+    #[test]
+    fn empty_patterns_and_alternatives() {
+        assert_eq!(find("", "abc"), Some(""));
+        assert_eq!(find("", ""), Some(""));
+        assert_eq!(find("a|", "b"), Some(""));
+        assert_eq!(find("|a", "a"), Some(""));
+        assert_eq!(find("()", "a"), Some(""));
+        assert_eq!(find("(a|)*", "aab"), Some("aa"));
+        assert_eq!(find("(?:)*x", "x"), Some("x"));
+    }
+
+    #[test]
+    fn alternation_is_leftmost_first() {
+        assert_eq!(find("a|ab", "ab"), Some("a"));
+        assert_eq!(find("ab|a", "ab"), Some("ab"));
+        assert_eq!(find("cat|dog", "hotdog cat"), Some("dog"));
+        assert_eq!(find("x(a|b|c)y", "xcy"), Some("xcy"));
+        assert_eq!(find("foo|bar", "baz"), None);
+    }
+
+    #[test]
+    fn repetition() {
+        assert_eq!(find("a*", "aaab"), Some("aaa"));
+        assert_eq!(find("a+?", "aaa"), Some("a"));
+        assert_eq!(find("a*?b", "aaab"), Some("aaab"));
+        assert_eq!(find("a{2}", "aaaa"), Some("aa"));
+        assert_eq!(find("a{2,3}", "aaaa"), Some("aaa"));
+        assert_eq!(find("a{2,}", "aaaaa"), Some("aaaaa"));
+        assert_eq!(find("a{2,3}?", "aaaa"), Some("aa"));
+        assert_eq!(find("colou?r", "color"), Some("color"));
+    }
+
+    #[test]
+    fn anchors_and_word_boundaries() {
+        assert_eq!(find("^ab", "abab"), Some("ab"));
+        assert_eq!(find("^b", "ab"), None);
+        assert_eq!(find("b$", "abab"), Some("b"));
+        assert_eq!(find(r"\bis\b", "this is"), Some("is"));
+        assert_eq!(find(r"\Bis", "this"), Some("is"));
+        assert!(Regex::new("^$").unwrap().is_match(""));
+    }
+
+    #[test]
+    fn case_insensitivity() {
+        assert_eq!(find("(?i)error", "An ERROR here"), Some("ERROR"));
+        assert_eq!(find("(?i)[a-c]+", "xABCx"), Some("ABC"));
+        assert!(!Regex::new("error").unwrap().is_match("ERROR"));
+    }
+
+    #[test]
+    fn groups_and_names() {
+        assert_eq!(groups(r"(\w+)=(\d+)", "a=1 b=2"), [Some("a=1"), Some("a"), Some("1")]);
+        assert_eq!(groups("(a)|(b)", "b"), [Some("b"), None, Some("b")]);
+        assert_eq!(groups("(?:a)(b)", "ab"), [Some("ab"), Some("b")]);
+        assert_eq!(groups("(a*)+", "b"), [Some(""), Some("")]);
+        let regex = Regex::new(r"(?P<user>\w+)@(?<host>\w+)").unwrap();
+        assert_eq!(regex.groups(), 3);
+        assert_eq!(regex.group_index("user"), Some(1));
+        assert_eq!(regex.group_index("host"), Some(2));
+        assert_eq!(regex.group_index("port"), None);
+    }
+
+    #[test]
+    fn searching_from_an_offset() {
+        let regex = Regex::new(r"\d").unwrap();
+        assert_eq!(regex.captures_at("1a2", 1).unwrap()[0], Some((2, 3)));
+        assert_eq!(regex.captures_at("1a", 1), None);
+    }
+
+    // The prefilter's literals for `pattern`, sorted, and whether they fold case
+    fn literals(pattern: &str) -> Option<(Vec<String>, bool)> {
+        let mut parser = Parser { chars: pattern.chars().collect(), pos: 0, case_insensitive: false, names: vec![None] };
+        let (set, fold_case) = required_literals(&parser.parse_alt().unwrap())?;
+        let mut set: Vec<String> = set.into_iter().map(|l| String::from_utf8(l).unwrap()).collect();
+        set.sort();
+        Some((set, fold_case))
+    }
+
+    fn set(literals: &[&str], fold_case: bool) -> Option<(Vec<String>, bool)> {
+        Some((literals.iter().map(|l| l.to_string()).collect(), fold_case))
+    }
+
+    #[test]
+    fn required_literals_of_alternations_and_classes() {
+        assert_eq!(literals("timeout"), set(&["timeout"], false));
+        assert_eq!(literals("foo|bar"), set(&["bar", "foo"], false));
+        assert_eq!(literals("error (a|b)"), set(&["error a", "error b"], false));
+        assert_eq!(literals("x[abc]y"), set(&["xay", "xby", "xcy"], false));
+        assert_eq!(literals(r"id=\d+ (GET|POST)"), set(&[" GET", " POST"], false));
+        assert_eq!(literals("(ab){2}c"), set(&["ababc"], false));
+        assert_eq!(literals("(?i)error"), set(&["error"], true));
+        // The longer run wins over the shorter one
+        assert_eq!(literals(r"ab\d+cdef"), set(&["cdef"], false));
+    }
+
+    #[test]
+    fn patterns_without_a_usable_literal() {
+        for pattern in ["", ".*", "a*", "a?b?", "[a-z]+", "[^x]", r"\d+", "a|b*", "(?i)k", "(?i)é", "^$"] {
+            assert_eq!(literals(pattern), None, "{}", pattern);
+        }
+    }
+
+    // Whatever the VM matches, the prefilter lets through: patterns and lines are built
+    // from pieces chosen by a fixed pseudo-random sequence
+    #[test]
+    fn prefilter_never_rejects_a_match() {
+        const PIECES: [&str; 16] =
+            ["a", "b", "ab", "[ab]", "(a|bc)", "x*", "(?:ab)+", "b{2}", ".", r"\d", "c?", "(b|)", "[^a]", "A", r"\b", "k"];
+        const TEXT: &[u8] = b"abcxAB1K ";
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % n as u64) as usize
+        };
+        let mut checked = 0;
+        for _ in 0..2000 {
+            let mut pattern = if next(4) == 0 { "(?i)".to_string() } else { String::new() };
+            for i in 0..1 + next(2) {
+                if i > 0 {
+                    pattern.push('|');
+                }
+                (0..1 + next(4)).for_each(|_| pattern.push_str(PIECES[next(PIECES.len())]));
+            }
+            let regex = Regex::new(&pattern).unwrap();
+            let Some(prefilter) = &regex.prefilter else {
+                continue;
+            };
+            for _ in 0..40 {
+                let text: Vec<u8> = (0..next(12)).map(|_| TEXT[next(TEXT.len())]).collect();
+                if regex.exec(&text, 0, 0).is_some() {
+                    assert!(prefilter.is_match(&text), "{:?} rejected {:?}", pattern, String::from_utf8_lossy(&text));
+                    checked += 1;
+                }
+            }
+        }
+        assert!(checked > 1000, "only {} matches checked", checked);
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        for pattern in ["(", ")", "(a", "[a", "a{2,1}", "*", "(?P<x", r"\"] {
+            assert!(Regex::new(pattern).is_err(), "{} should be an error", pattern);
+        }
+    }
+}
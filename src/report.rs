@@ -0,0 +1,197 @@
+// `--report <file.json>`: when rail finishes, write a summary of the session for CI jobs
+// and batch pipelines to archive:
+//
+//   {"started_unix":1714557600,"duration_ms":93012,"exit_status":0,
+//    "inputs":[{"path":"app.log","bytes":48211,"lines":612}],
+//    "decisions":{"rotation":1},"fail_on":{"pattern":"FATAL","matches":0},
+//    "mutes":[{"rule":"GET /healthz","muted":140}],"errors":[]}
+//
+// Bytes and lines are what was read from each input. Decisions are what the follow loop
+// concluded (rotation, truncation, stall, replaced), errors what it reported on stderr
+// and carried on from (at most MAX_ERRORS are kept).
+//
+// While --report is on, SIGINT and SIGTERM (Ctrl+C and Ctrl+Break on Windows) stop
+// following as if the input had ended, so a session ended by hand or by a job runner
+// still gets its report.
+
+use std::cell::Cell;
+use std::fs;
+use std::io;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::fail_on;
+use crate::fields::push_json_string;
+use crate::mute;
+
+const MAX_ERRORS: usize = 100;
+
+struct Input {
+    path: String,
+    bytes: u64,
+    lines: u64,
+}
+
+struct Report {
+    path: String,
+    started: Instant,
+    started_unix: u64,
+    inputs: Vec<Input>,
+    decisions: Vec<(&'static str, u64)>,
+    errors: Vec<String>,
+}
+
+static REPORT: Mutex<Option<Report>> = Mutex::new(None);
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    // The input this thread reads, as an index into Report::inputs
+    static INPUT: Cell<usize> = const { Cell::new(0) };
+}
+
+pub fn enable(path: &str, inputs: &[String]) {
+    let started_unix = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    *REPORT.lock().unwrap() = Some(Report {
+        path: path.to_string(),
+        started: Instant::now(),
+        started_unix,
+        inputs: inputs.iter().map(|p| Input { path: p.clone(), bytes: 0, lines: 0 }).collect(),
+        decisions: Vec::new(),
+        errors: Vec::new(),
+    });
+    fail_on::count_matches();
+    interrupt::install();
+}
+
+// Bytes read from now on in this thread count for input `path`
+pub fn set_input(path: &str) {
+    if let Some(report) = REPORT.lock().unwrap().as_ref()
+        && let Some(i) = report.inputs.iter().position(|input| input.path == path)
+    {
+        INPUT.with(|input| input.set(i));
+    }
+}
+
+pub fn read(bytes: &[u8]) {
+    if let Some(report) = REPORT.lock().unwrap().as_mut()
+        && let Some(input) = report.inputs.get_mut(INPUT.with(Cell::get))
+    {
+        input.bytes += bytes.len() as u64;
+        input.lines += bytes.ends_with(b"\n") as u64;
+    }
+}
+
+pub fn decision(name: &'static str) {
+    if let Some(report) = REPORT.lock().unwrap().as_mut() {
+        match report.decisions.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, count)) => *count += 1,
+            None => report.decisions.push((name, 1)),
+        }
+    }
+}
+
+pub fn error(message: String) {
+    if let Some(report) = REPORT.lock().unwrap().as_mut()
+        && report.errors.len() < MAX_ERRORS
+    {
+        report.errors.push(message);
+    }
+}
+
+// True once SIGINT or SIGTERM asked rail to stop (only while --report is on)
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+pub fn write(exit_status: i32) -> io::Result<()> {
+    let report = REPORT.lock().unwrap();
+    let Some(report) = report.as_ref() else {
+        return Ok(());
+    };
+    let mut out = format!(
+        "{{\"started_unix\":{},\"duration_ms\":{},\"exit_status\":{},\"inputs\":[",
+        report.started_unix,
+        report.started.elapsed().as_millis(),
+        exit_status
+    );
+    for (i, input) in report.inputs.iter().enumerate() {
+        out.push_str(if i == 0 { "{\"path\":" } else { ",{\"path\":" });
+        push_json_string(&mut out, &input.path);
+        out.push_str(&format!(",\"bytes\":{},\"lines\":{}}}", input.bytes, input.lines));
+    }
+    out.push_str("],\"decisions\":{");
+    for (i, (name, count)) in report.decisions.iter().enumerate() {
+        out.push_str(&format!("{}\"{}\":{}", if i == 0 { "" } else { "," }, name, count));
+    }
+    out.push('}');
+    if let Some((pattern, matches)) = fail_on::summary() {
+        out.push_str(",\"fail_on\":{\"pattern\":");
+        push_json_string(&mut out, pattern);
+        out.push_str(&format!(",\"matches\":{}}}", matches));
+    }
+    out.push_str(",\"mutes\":[");
+    for (i, (rule, muted)) in mute::counts().iter().enumerate() {
+        out.push_str(if i == 0 { "{\"rule\":" } else { ",{\"rule\":" });
+        push_json_string(&mut out, rule);
+        out.push_str(&format!(",\"muted\":{}}}", muted));
+    }
+    out.push_str("],\"errors\":[");
+    for (i, error) in report.errors.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        push_json_string(&mut out, error);
+    }
+    out.push_str("]}\n");
+    fs::write(&report.path, out)
+}
+
+#[cfg(unix)]
+mod interrupt {
+    use std::os::raw::c_int;
+    use std::sync::atomic::Ordering;
+
+    use super::INTERRUPTED;
+
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+
+    unsafe extern "C" {
+        fn signal(sig: c_int, handler: usize) -> usize;
+    }
+
+    pub fn install() {
+        for sig in [SIGINT, SIGTERM] {
+            unsafe { signal(sig, on_signal as extern "C" fn(c_int) as usize) };
+        }
+    }
+
+    extern "C" fn on_signal(_sig: c_int) {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(windows)]
+mod interrupt {
+    use std::sync::atomic::Ordering;
+
+    use winapi::shared::minwindef::{BOOL, DWORD, TRUE};
+    use winapi::um::consoleapi::SetConsoleCtrlHandler;
+
+    use super::INTERRUPTED;
+
+    pub fn install() {
+        unsafe { SetConsoleCtrlHandler(Some(on_ctrl), TRUE) };
+    }
+
+    unsafe extern "system" fn on_ctrl(_event: DWORD) -> BOOL {
+        INTERRUPTED.store(true, Ordering::Relaxed);
+        TRUE
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod interrupt {
+    pub fn install() {}
+}
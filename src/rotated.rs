@@ -0,0 +1,136 @@
+// `--rotated`: when -n N asks for more lines than the log has (it was rotated not long
+// ago), take the rest from the files it was rotated to, newest first: app.log.1,
+// app.log.2.gz, ... as logrotate numbers them, or app.log-20240501.gz and the like with
+// dateext. Compressed ones are read decompressed. Their lines come before the log's,
+// each file's under a marker:
+//
+//   --- app.log.2.gz (rotated) ---
+//   ...
+//   --- app.log.1 (rotated) ---
+//   ...
+//   --- app.log ---
+//
+// --backfill-max and --backfill-max-bytes bound them along with the log's own lines.
+// A rotated file that can't be read ends the walk back, with a warning.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufReader, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::open::{STDIN, open_log};
+use crate::{backfill, compressed, encoding, line_numbers, output};
+
+const COMPRESSED_EXTENSIONS: [&str; 4] = [".gz", ".zst", ".bz2", ".xz"];
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+// `filename` had `lines` lines of `bytes` in all, short of the `wanted`: print what its
+// rotated files have to make up for that, before its own lines are printed
+pub fn print_older(filename: &str, wanted: usize, lines: usize, bytes: u64) -> io::Result<()> {
+    if filename == STDIN {
+        return Ok(());
+    }
+    let mut missing = wanted.min(backfill::max_lines().unwrap_or(usize::MAX)).saturating_sub(lines);
+    let mut budget = backfill::max_bytes().map_or(u64::MAX, |max| max.saturating_sub(bytes));
+    // Newest first
+    let mut found = Vec::new();
+    for sibling in siblings(filename) {
+        if missing == 0 || budget == 0 {
+            break;
+        }
+        let (mut older, mut before) = match last_lines(&sibling, missing) {
+            Ok(older) => older,
+            Err(e) => {
+                eprintln!("Warning: Could not read rotated file '{}': {}", sibling, e);
+                break;
+            }
+        };
+        missing -= older.len();
+        let mut size: u64 = older.iter().map(|line| line.len() as u64).sum();
+        while size > budget {
+            size -= older.pop_front().map_or(0, |line| line.len() as u64);
+            before += 1;
+        }
+        budget -= size;
+        found.push((sibling, older, before));
+    }
+    if found.is_empty() {
+        return Ok(());
+    }
+    let next = line_numbers::next();
+    for (sibling, older, before) in found.into_iter().rev() {
+        let name = Path::new(&sibling).file_name().map_or(sibling.clone(), |n| n.to_string_lossy().into_owned());
+        output::status(format_args!("--- {} (rotated) ---", name));
+        line_numbers::start_at(before + 1);
+        for line in older {
+            output::emit_bytes(line, true)?;
+        }
+        output::flush();
+    }
+    line_numbers::start_at(next);
+    let name = Path::new(filename).file_name().map_or(filename.into(), |n| n.to_string_lossy());
+    output::status(format_args!("--- {} ---", name));
+    Ok(())
+}
+
+// The files `filename` was rotated to, newest first: numbered ones in order, then dated
+// ones by name, latest first
+fn siblings(filename: &str) -> Vec<String> {
+    let path = Path::new(filename);
+    let (Some(base), Some(dir)) = (path.file_name().and_then(|n| n.to_str()), path.parent()) else {
+        return Vec::new();
+    };
+    let listed = if dir.as_os_str().is_empty() { Path::new(".") } else { dir };
+    let Ok(entries) = fs::read_dir(listed) else {
+        return Vec::new();
+    };
+    let mut numbered = Vec::new();
+    let mut dated = Vec::new();
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let Some(rest) = name.strip_prefix(base).and_then(|r| r.strip_prefix(['.', '-'])) else {
+            continue;
+        };
+        let suffix = COMPRESSED_EXTENSIONS.iter().find_map(|ext| rest.strip_suffix(ext)).unwrap_or(rest);
+        if !suffix.starts_with(|c: char| c.is_ascii_digit()) || !entry.path().is_file() {
+            continue;
+        }
+        let sibling = dir.join(&name).to_string_lossy().into_owned();
+        match suffix.parse::<u32>() {
+            Ok(n) if suffix.len() <= 4 => numbered.push((n, sibling)),
+            _ => dated.push(sibling),
+        }
+    }
+    numbered.sort();
+    dated.sort_by(|a, b| b.cmp(a));
+    numbered.into_iter().map(|(_, sibling)| sibling).chain(dated).collect()
+}
+
+// The last `n` lines of a rotated file, and how many lines come before them
+fn last_lines(filename: &str, n: usize) -> io::Result<(VecDeque<Vec<u8>>, u64)> {
+    if let Some(compression) = compressed::detect(filename) {
+        return compressed::last_lines(filename, compression, n);
+    }
+    let mut file = open_log(filename)?;
+    let len = file.seek(SeekFrom::End(0))?;
+    let start = crate::start_of_last_before(&mut file, len, n)?;
+    let before = if line_numbers::enabled() { line_numbers::lines_before(filename, start)? } else { 0 };
+    file.seek(SeekFrom::Start(start))?;
+    let mut reader = BufReader::new(file);
+    let mut lines = VecDeque::new();
+    let mut line = Vec::new();
+    while encoding::read_line(&mut reader, &mut line)? > 0 {
+        lines.push_back(std::mem::take(&mut line));
+    }
+    Ok((lines, before))
+}
@@ -0,0 +1,101 @@
+// `--fields` and `--where`: choose what of a --format record is shown. `--fields
+// ts,level,msg` prints just those fields, in that order (a record without one leaves
+// it out); `--where <condition>` shows only records that meet it, and when given more
+// than once, all of them. Conditions are
+//
+//   field=value    field!=value    exact text
+//   field~regex    field!~regex    a match anywhere in the value
+//   field>N  field>=N  field<N  field<=N    numbers (a value that isn't one fails)
+//
+// A record without the field fails the = ~ and comparison conditions and meets != and
+// !~. Conditions see the record after --reclassify and the enrichers; lines that aren't
+// records pass through as --format leaves them. --fields applies to the rendered line
+// and --json, not to what --forward sends.
+
+use crate::fields::Record;
+use crate::regex::Regex;
+
+// Longest first, so "!=" isn't taken for "!" and "="
+const OPERATORS: [&str; 8] = ["!=", "!~", ">=", "<=", "=", "~", ">", "<"];
+
+#[derive(Debug)]
+enum Test {
+    Equal(String),
+    NotEqual(String),
+    Matches(Regex),
+    NotMatches(Regex),
+    Greater(f64),
+    AtLeast(f64),
+    Less(f64),
+    AtMost(f64),
+}
+
+#[derive(Debug)]
+pub struct Condition {
+    field: String,
+    test: Test,
+}
+
+// "ts,level,msg"
+pub fn parse_fields(s: &str) -> Result<Vec<String>, String> {
+    let names: Vec<String> = s.split(',').map(|name| name.trim().to_string()).collect();
+    if names.iter().any(String::is_empty) {
+        return Err(format!("expected field names separated by commas, got '{}'", s));
+    }
+    Ok(names)
+}
+
+pub fn parse_condition(spec: &str) -> Result<Condition, String> {
+    let invalid = || format!("expected <field><op><value> with op one of = != ~ !~ > >= < <=, got '{}'", spec);
+    let at = spec.find(['=', '!', '~', '<', '>']).ok_or_else(invalid)?;
+    let field = spec[..at].trim();
+    let operator = OPERATORS.iter().find(|op| spec[at..].starts_with(*op)).ok_or_else(invalid)?;
+    let value = &spec[at + operator.len()..];
+    if field.is_empty() {
+        return Err(invalid());
+    }
+    let number = || value.trim().parse::<f64>().map_err(|_| format!("'{}' is not a number, as {} needs", value, operator));
+    let regex = || Regex::new(value).map_err(|e| e.to_string());
+    let test = match *operator {
+        "=" => Test::Equal(value.to_string()),
+        "!=" => Test::NotEqual(value.to_string()),
+        "~" => Test::Matches(regex()?),
+        "!~" => Test::NotMatches(regex()?),
+        ">" => Test::Greater(number()?),
+        ">=" => Test::AtLeast(number()?),
+        "<" => Test::Less(number()?),
+        _ => Test::AtMost(number()?),
+    };
+    Ok(Condition { field: field.to_string(), test })
+}
+
+pub fn meets(conditions: &[Condition], record: &Record) -> bool {
+    conditions.iter().all(|condition| condition.meets(record))
+}
+
+impl Condition {
+    fn meets(&self, record: &Record) -> bool {
+        let value = record.get(&self.field);
+        let number = || value.and_then(|v| v.trim().parse::<f64>().ok());
+        match &self.test {
+            Test::Equal(expected) => value == Some(expected.as_str()),
+            Test::NotEqual(expected) => value != Some(expected.as_str()),
+            Test::Matches(regex) => value.is_some_and(|v| regex.is_match(v)),
+            Test::NotMatches(regex) => !value.is_some_and(|v| regex.is_match(v)),
+            Test::Greater(n) => number().is_some_and(|v| v > *n),
+            Test::AtLeast(n) => number().is_some_and(|v| v >= *n),
+            Test::Less(n) => number().is_some_and(|v| v < *n),
+            Test::AtMost(n) => number().is_some_and(|v| v <= *n),
+        }
+    }
+}
+
+// The record with just `names`, in that order
+pub fn pick(names: &[String], record: Record) -> Record {
+    let mut fields = record.fields;
+    let picked = names
+        .iter()
+        .filter_map(|name| fields.iter().position(|(k, _)| k == name).map(|i| fields.swap_remove(i)))
+        .collect();
+    Record { fields: picked }
+}
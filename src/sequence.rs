@@ -0,0 +1,91 @@
+// `--sequence-field <name>`: for logs whose lines carry a sequence number or counter
+// that goes up by one each line (seq=41, "seq":41), point out where it doesn't, which is
+// where a shipper upstream lost, repeated or restarted something:
+//
+//   --- seq: 3 missing between 41 and 45 ---
+//   --- seq: 45 again ---
+//   --- seq: went back from 45 to 1 (reset or replay) ---
+//
+// The field is found in the line as written (after --format rendering), as name=N,
+// "name":N or name: N, with the number quoted or not. Every line read is checked, the
+// ones --grep or --mute leave out too; the notes go out where the line would. Each file
+// has its own count, and totals are printed when rail finishes.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Default)]
+struct Totals {
+    gaps: u64,
+    missing: u64,
+    duplicates: u64,
+    resets: u64,
+}
+
+static FIELD: OnceLock<String> = OnceLock::new();
+// The last number seen, per file
+static LAST: Mutex<Option<HashMap<String, u64>>> = Mutex::new(None);
+static TOTALS: Mutex<Totals> = Mutex::new(Totals { gaps: 0, missing: 0, duplicates: 0, resets: 0 });
+
+pub fn set_field(name: &str) {
+    let _ = FIELD.set(name.to_string());
+}
+
+pub fn enabled() -> bool {
+    FIELD.get().is_some()
+}
+
+// The note to show before `line` from `source`, if its number isn't the one expected
+pub fn check(source: &str, line: &str) -> Option<String> {
+    let name = FIELD.get()?;
+    let value = find(line, name)?;
+    let mut last = LAST.lock().unwrap();
+    let previous = last.get_or_insert_with(HashMap::new).insert(source.to_string(), value)?;
+    let mut totals = TOTALS.lock().unwrap();
+    if value == previous + 1 {
+        None
+    } else if value > previous {
+        totals.gaps += 1;
+        totals.missing += value - previous - 1;
+        Some(format!("--- {}: {} missing between {} and {} ---\n", name, value - previous - 1, previous, value))
+    } else if value == previous {
+        totals.duplicates += 1;
+        Some(format!("--- {}: {} again ---\n", name, value))
+    } else {
+        totals.resets += 1;
+        Some(format!("--- {}: went back from {} to {} (reset or replay) ---\n", name, previous, value))
+    }
+}
+
+// The summary to print when rail is done
+pub fn report() -> Option<String> {
+    let name = FIELD.get()?;
+    let totals = TOTALS.lock().unwrap();
+    Some(format!(
+        "Sequence '{}': {} missing in {} gaps, {} repeated, {} went back",
+        name, totals.missing, totals.gaps, totals.duplicates, totals.resets
+    ))
+}
+
+// The number after `name=`, `"name":` or `name:` where `name` starts a word
+fn find(line: &str, name: &str) -> Option<u64> {
+    let bytes = line.as_bytes();
+    let mut from = 0;
+    while let Some(i) = line[from..].find(name).map(|i| from + i) {
+        from = i + name.len();
+        let starts_word = i == 0 || !(bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'_');
+        if !starts_word {
+            continue;
+        }
+        let rest = line[from..].strip_prefix('"').unwrap_or(&line[from..]);
+        let Some(rest) = rest.strip_prefix(['=', ':']) else {
+            continue;
+        };
+        let rest = rest.trim_start_matches([' ', '"']);
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if let Ok(value) = rest[..digits].parse() {
+            return Some(value);
+        }
+    }
+    None
+}
@@ -0,0 +1,477 @@
+// `rail simulate <scenario.toml>...`: run the real follow loop against a simulated file
+// and clock (the Fs and Clock traits in follow.rs), so rotations, truncations and slow
+// writers happen at exact points in simulated time and a scenario finishes at once. Each
+// scenario then checks what rail printed (the file's lines and its own status lines) and
+// what the loop decided.
+//
+// A scenario:
+//
+//   name = "rename rotation"
+//   initial = "old\n"             # the file's content when following starts (not printed)
+//   duration = 3000               # ms to follow for (default: 1s after the last step)
+//   reopen_each_poll = true       # follow as with --reopen-each-poll
+//   follow_name = true            # follow as with -F
+//   expect_output = "a\nb\n"      # everything printed, status lines included
+//   expect_decisions = ["rotation"]   # rotation, truncation, stall or replaced, in order
+//
+//   [[step]]
+//   at = 200                      # ms since following started
+//   append = "a\n"                # write to the file at the path...
+//   every = 50                    # ...one byte every 50ms instead of all at once
+//
+//   [[step]]
+//   at = 500
+//   rotate = true                 # rename the file away; a new, empty one takes the path
+//   append_rotated = "late\n"     # a writer still holding the renamed file writes to it
+//
+//   [[step]]
+//   at = 900
+//   truncate = true               # cut the file at the path to nothing
+//
+// A step may do several things; they happen in the order rotate, append_rotated,
+// truncate, append. Only
+// this much TOML is understood: comments, top-level keys and [[step]] tables, with
+// strings, integers, booleans and arrays of strings as values.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::follow::{Clock, Decision, FileId, Fs, Stat};
+use crate::output;
+use crate::trace;
+
+const PATH: &str = "simulated.log";
+
+enum Value {
+    Str(String),
+    Int(u64),
+    Bool(bool),
+    List(Vec<String>),
+}
+
+type Table = Vec<(String, Value)>;
+
+enum Action {
+    Append(Vec<u8>),
+    AppendRotated(Vec<u8>),
+    Rotate,
+    Truncate,
+}
+
+struct Scenario {
+    name: String,
+    initial: Vec<u8>,
+    duration: Duration,
+    reopen_each_poll: bool,
+    follow_name: bool,
+    expect_output: Option<String>,
+    expect_decisions: Option<Vec<String>>,
+    // In time order
+    actions: Vec<(Duration, Action)>,
+}
+
+fn parse_scenario(text: &str, default_name: &str) -> Result<Scenario, String> {
+    let (top, steps) = parse_tables(text)?;
+    let mut scenario = Scenario {
+        name: default_name.to_string(),
+        initial: Vec::new(),
+        duration: Duration::ZERO,
+        reopen_each_poll: false,
+        follow_name: false,
+        expect_output: None,
+        expect_decisions: None,
+        actions: Vec::new(),
+    };
+    let mut duration = None;
+    for (key, value) in top {
+        match (key.as_str(), value) {
+            ("name", Value::Str(s)) => scenario.name = s,
+            ("initial", Value::Str(s)) => scenario.initial = s.into_bytes(),
+            ("duration", Value::Int(ms)) => duration = Some(Duration::from_millis(ms)),
+            ("reopen_each_poll", Value::Bool(b)) => scenario.reopen_each_poll = b,
+            ("follow_name", Value::Bool(b)) => scenario.follow_name = b,
+            ("expect_output", Value::Str(s)) => scenario.expect_output = Some(s),
+            ("expect_decisions", Value::List(names)) => {
+                if let Some(name) = names.iter().find(|n| Decision::from_name(n).is_none()) {
+                    return Err(format!("unknown decision '{}' in expect_decisions", name));
+                }
+                scenario.expect_decisions = Some(names);
+            }
+            (key, _) => return Err(format!("unknown key or wrong type of value: {}", key)),
+        }
+    }
+
+    for (n, step) in steps.into_iter().enumerate() {
+        let mut at = None;
+        let (mut append, mut every, mut rotate, mut truncate) = (None, None, false, false);
+        let mut append_rotated = None;
+        for (key, value) in step {
+            match (key.as_str(), value) {
+                ("at", Value::Int(ms)) => at = Some(Duration::from_millis(ms)),
+                ("append", Value::Str(s)) => append = Some(s.into_bytes()),
+                ("every", Value::Int(ms)) => every = Some(Duration::from_millis(ms)),
+                ("rotate", Value::Bool(b)) => rotate = b,
+                ("append_rotated", Value::Str(s)) => append_rotated = Some(s.into_bytes()),
+                ("truncate", Value::Bool(b)) => truncate = b,
+                (key, _) => return Err(format!("step {}: unknown key or wrong type of value: {}", n + 1, key)),
+            }
+        }
+        let at = at.ok_or(format!("step {}: missing 'at'", n + 1))?;
+        if rotate {
+            scenario.actions.push((at, Action::Rotate));
+        }
+        if let Some(bytes) = append_rotated {
+            scenario.actions.push((at, Action::AppendRotated(bytes)));
+        }
+        if truncate {
+            scenario.actions.push((at, Action::Truncate));
+        }
+        match (append, every) {
+            (Some(bytes), Some(every)) => {
+                for (i, byte) in bytes.into_iter().enumerate() {
+                    scenario.actions.push((at + every * i as u32, Action::Append(vec![byte])));
+                }
+            }
+            (Some(bytes), None) => scenario.actions.push((at, Action::Append(bytes))),
+            (None, Some(_)) => return Err(format!("step {}: 'every' needs 'append'", n + 1)),
+            (None, None) => {}
+        }
+    }
+    // Stable, so what one step does stays in order
+    scenario.actions.sort_by_key(|(at, _)| *at);
+
+    let last = scenario.actions.last().map_or(Duration::ZERO, |(at, _)| *at);
+    scenario.duration = duration.unwrap_or(last + Duration::from_secs(1));
+    Ok(scenario)
+}
+
+// Top-level keys, then one table per [[step]]
+fn parse_tables(text: &str) -> Result<(Table, Vec<Table>), String> {
+    let mut top = Vec::new();
+    let mut steps: Vec<Table> = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        let invalid = |e: String| format!("line {}: {}", n + 1, e);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            if strip_comment(line) != "[[step]]" {
+                return Err(invalid(format!("unknown table {}", line)));
+            }
+            steps.push(Vec::new());
+            continue;
+        }
+        let (key, value) = line.split_once('=').ok_or(invalid("expected key = value".to_string()))?;
+        let (value, rest) = parse_value(value.trim()).map_err(invalid)?;
+        if !strip_comment(rest).is_empty() {
+            return Err(invalid(format!("unexpected '{}' after value", rest.trim())));
+        }
+        steps.last_mut().unwrap_or(&mut top).push((key.trim().to_string(), value));
+    }
+    Ok((top, steps))
+}
+
+fn strip_comment(s: &str) -> &str {
+    s.split('#').next().unwrap_or("").trim()
+}
+
+// A value and whatever follows it on the line
+fn parse_value(s: &str) -> Result<(Value, &str), String> {
+    if s.starts_with('"') {
+        let (string, rest) = parse_string(s)?;
+        return Ok((Value::Str(string), rest));
+    }
+    if let Some(mut rest) = s.strip_prefix('[') {
+        let mut list = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::List(list), after));
+            }
+            let (string, after) = parse_string(rest)?;
+            list.push(string);
+            rest = after.trim_start();
+            rest = rest.strip_prefix(',').unwrap_or(rest);
+        }
+    }
+    let end = s.find(|c: char| c.is_whitespace() || c == '#').unwrap_or(s.len());
+    let value = match &s[..end] {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        word => Value::Int(word.replace('_', "").parse().map_err(|_| format!("unexpected value '{}'", word))?),
+    };
+    Ok((value, &s[end..]))
+}
+
+fn parse_string(s: &str) -> Result<(String, &str), String> {
+    let body = s.strip_prefix('"').ok_or(format!("expected a string, got '{}'", s))?;
+    let mut string = String::new();
+    let mut chars = body.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((string, &body[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => string.push('\n'),
+                Some('r') => string.push('\r'),
+                Some('t') => string.push('\t'),
+                Some('"') => string.push('"'),
+                Some('\\') => string.push('\\'),
+                other => return Err(format!("unknown escape \\{}", other.map_or(String::new(), String::from))),
+            },
+            c => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+struct Content {
+    bytes: Vec<u8>,
+    // Nanoseconds of simulated time at the last change
+    modified: u128,
+}
+
+// An open handle: keeps reading the file it was opened on, wherever that file went
+pub struct SimFile {
+    content: Rc<RefCell<Content>>,
+    pos: u64,
+}
+
+impl Read for SimFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let content = self.content.borrow();
+        let start = (self.pos as usize).min(content.bytes.len());
+        let n = buf.len().min(content.bytes.len() - start);
+        buf[..n].copy_from_slice(&content.bytes[start..start + n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for SimFile {
+    fn seek(&mut self, from: SeekFrom) -> io::Result<u64> {
+        let len = self.content.borrow().bytes.len() as i64;
+        let pos = match from {
+            SeekFrom::Start(pos) => pos as i64,
+            SeekFrom::End(delta) => len + delta,
+            SeekFrom::Current(delta) => self.pos as i64 + delta,
+        };
+        if pos < 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of file"));
+        }
+        self.pos = pos as u64;
+        Ok(self.pos)
+    }
+}
+
+// The simulated file at PATH and the clock, which applies the scenario's actions as
+// time passes
+struct Sim {
+    at_path: RefCell<Rc<RefCell<Content>>>,
+    // The file last renamed away
+    rotated: RefCell<Option<Rc<RefCell<Content>>>>,
+    now: Cell<Duration>,
+    end: Duration,
+    actions: RefCell<VecDeque<(Duration, Action)>>,
+}
+
+impl Sim {
+    fn new(scenario: Scenario) -> Sim {
+        let content = Content { bytes: scenario.initial, modified: 0 };
+        Sim {
+            at_path: RefCell::new(Rc::new(RefCell::new(content))),
+            rotated: RefCell::new(None),
+            now: Cell::new(Duration::ZERO),
+            end: scenario.duration,
+            actions: RefCell::new(scenario.actions.into()),
+        }
+    }
+
+    fn apply(&self, action: Action) {
+        let modified = self.now.get().as_nanos();
+        match action {
+            Action::Rotate => {
+                let content = Content { bytes: Vec::new(), modified };
+                let old = std::mem::replace(&mut *self.at_path.borrow_mut(), Rc::new(RefCell::new(content)));
+                *self.rotated.borrow_mut() = Some(old);
+            }
+            Action::AppendRotated(bytes) => {
+                if let Some(rotated) = self.rotated.borrow().as_ref() {
+                    let mut content = rotated.borrow_mut();
+                    content.bytes.extend(bytes);
+                    content.modified = modified;
+                }
+            }
+            Action::Truncate | Action::Append(_) => {
+                let at_path = self.at_path.borrow();
+                let mut content = at_path.borrow_mut();
+                match action {
+                    Action::Append(bytes) => content.bytes.extend(bytes),
+                    _ => content.bytes.clear(),
+                }
+                content.modified = modified;
+            }
+        }
+    }
+}
+
+impl Fs for Sim {
+    type File = SimFile;
+
+    fn open(&self, path: &str) -> io::Result<SimFile> {
+        if path != PATH {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such simulated file"));
+        }
+        Ok(SimFile { content: self.at_path.borrow().clone(), pos: 0 })
+    }
+
+    fn stat(&self, path: &str) -> io::Result<Stat> {
+        if path != PATH {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such simulated file"));
+        }
+        let at_path = self.at_path.borrow();
+        let content = at_path.borrow();
+        Ok(Stat { len: content.bytes.len() as u64, modified: content.modified })
+    }
+
+    fn handle_len(&self, file: &SimFile) -> io::Result<u64> {
+        Ok(file.content.borrow().bytes.len() as u64)
+    }
+
+    // Files are told apart by where their content lives
+    fn id(&self, path: &str) -> io::Result<FileId> {
+        if path != PATH {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such simulated file"));
+        }
+        Ok(Rc::as_ptr(&self.at_path.borrow()) as usize as FileId)
+    }
+
+    fn handle_id(&self, file: &SimFile) -> io::Result<FileId> {
+        Ok(Rc::as_ptr(&file.content) as usize as FileId)
+    }
+}
+
+impl Clock for Sim {
+    fn now(&self) -> Duration {
+        self.now.get()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let until = self.now.get() + duration;
+        loop {
+            let mut actions = self.actions.borrow_mut();
+            if actions.front().is_none_or(|(at, _)| *at > until) {
+                break;
+            }
+            let (at, action) = actions.pop_front().unwrap();
+            drop(actions);
+            self.now.set(self.now.get().max(at));
+            self.apply(action);
+        }
+        self.now.set(until);
+    }
+
+    fn stopped(&self) -> bool {
+        self.now.get() >= self.end
+    }
+}
+
+// Where the trace of a simulated run goes, to pick the decisions out of it afterwards
+#[derive(Clone, Default)]
+struct Recorded(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Recorded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Run one scenario file and report on it; true if it went as expected
+pub fn run(path: &str) -> io::Result<bool> {
+    let text = fs::read_to_string(path)?;
+    let scenario = parse_scenario(&text, path).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let name = scenario.name.clone();
+    let expect_output = scenario.expect_output.clone();
+    let expect_decisions = scenario.expect_decisions.clone();
+    let duration = scenario.duration;
+    let reopen_each_poll = scenario.reopen_each_poll;
+    let follow_name = scenario.follow_name;
+
+    let recorded = Recorded::default();
+    trace::set_output(Box::new(recorded.clone()));
+    output::capture();
+    let sim = Sim::new(scenario);
+    let opts = crate::FollowOptions {
+        retry_mode: false,
+        use_index: false,
+        reopen_each_poll,
+        reopen_on_eacces: false,
+        follow_name,
+        append_only: None,
+    };
+    let result = crate::follow_file(&sim, &sim, PATH, &opts, &mut None);
+    let printed = String::from_utf8_lossy(&output::take_captured()).into_owned();
+    trace::flush();
+    result?;
+
+    let trace = String::from_utf8_lossy(&recorded.0.lock().unwrap()).into_owned();
+    let mut decisions = Vec::new();
+    for line in trace.lines() {
+        let event = trace::parse_event(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if Decision::from_name(&event.name).is_some() {
+            decisions.push((event.t, event.name));
+        }
+    }
+
+    let mut failures = Vec::new();
+    if let Some(expected) = expect_output
+        && expected != printed
+    {
+        failures.push(format!("output: expected {:?}, got {:?}", expected, printed));
+    }
+    if let Some(expected) = expect_decisions
+        && !expected.iter().eq(decisions.iter().map(|(_, name)| name))
+    {
+        let got: Vec<String> = decisions.iter().map(|(t, name)| format!("{} at {}ms", name, t)).collect();
+        failures.push(format!("decisions: expected [{}], got [{}]", expected.join(", "), got.join(", ")));
+    }
+
+    if failures.is_empty() {
+        println!("ok      {} ({} decision(s) in {}ms simulated)", name, decisions.len(), duration.as_millis());
+    } else {
+        println!("FAILED  {}", name);
+        for failure in &failures {
+            println!("        {}", failure);
+        }
+    }
+    Ok(failures.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The scenarios shipped in scenarios/, one after the other: a run sets the output
+    // capture and trace output, which are global
+    #[test]
+    fn shipped_scenarios_pass() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/scenarios");
+        let mut paths: Vec<_> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        paths.retain(|path| path.extension().is_some_and(|e| e == "toml"));
+        paths.sort();
+        assert!(!paths.is_empty());
+        for path in paths {
+            assert!(run(path.to_str().unwrap()).unwrap(), "{} failed", path.display());
+        }
+    }
+}
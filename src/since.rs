@@ -0,0 +1,51 @@
+// `--since <time>`: start at the first line logged at or after a time instead of at the
+// last -n lines, e.g. `rail --since "2024-05-01 10:00" -f app.log`. The time is given as
+// for `rail export`, and a line's time is the timestamp it starts with; a line without
+// one (a stack trace's) goes with the line before it.
+//
+// Without --index the file is read from the start up to that line. With it, the index's
+// checkpoints say the latest time logged before each of them, so the search only reads
+// the lines between two checkpoints.
+
+use std::fs::File;
+use std::io::{self, BufReader, Seek, SeekFrom};
+
+use crate::diff;
+use crate::encoding::{self, Encoding};
+
+// How much of a line is looked at for its timestamp
+pub const TIME_PREFIX: usize = 64;
+
+// The time `line` starts with, if any; a UTF-16 line is decoded as this thread's file is
+pub fn line_time(line: &[u8]) -> Option<u128> {
+    let start = &line[..line.len().min(TIME_PREFIX)];
+    let unit: fn([u8; 2]) -> u16 = match encoding::current() {
+        Some(Encoding::Utf16Le) => u16::from_le_bytes,
+        Some(Encoding::Utf16Be) => u16::from_be_bytes,
+        _ => return diff::leading_time(&String::from_utf8_lossy(start)).map(|(time, _)| time),
+    };
+    let units: Vec<u16> = start.chunks_exact(2).map(|pair| unit([pair[0], pair[1]])).collect();
+    let prefix = String::from_utf16_lossy(&units);
+    diff::leading_time(prefix.trim_start_matches('\u{feff}')).map(|(time, _)| time)
+}
+
+// Where the first line at or after `from` with a time of `since` or later starts, or the
+// end of the file; `time` is that of the line before `from`
+pub fn find(file: &mut File, from: u64, mut time: Option<u128>, since: u128) -> io::Result<u64> {
+    file.seek(SeekFrom::Start(from))?;
+    let mut reader = BufReader::new(file);
+    let mut offset = from;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let n = encoding::read_line(&mut reader, &mut line)?;
+        if n == 0 {
+            return Ok(offset);
+        }
+        time = line_time(&line).or(time);
+        if time.is_some_and(|t| t >= since) {
+            return Ok(offset);
+        }
+        offset += n as u64;
+    }
+}
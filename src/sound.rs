@@ -0,0 +1,166 @@
+// `--sound '<regex> => <cue>'`: an audible cue when a shown line matches, so logs can be
+// monitored without watching them. The first matching rule's cue plays; rules are tried
+// in the order given. Cues are
+//
+//   bell[*N][@ms]   N terminal bells (default 1), ms apart (default 250), e.g. bell*3@120
+//   system:error    the system's error / warning / info sound (Windows); elsewhere
+//   system:warning  three, two and one bells, so each severity still sounds different
+//   system:info
+//
+// `--sound severity` adds rules for the usual level words: fatal and critical lines
+// sound system:error, errors bell*2@250 and warnings one bell.
+//
+// Bells go to stderr, which is normally the terminal even when stdout is piped. Cues
+// play on their own thread so output isn't held up; while one plays, further matches
+// are ignored, so a burst of errors is one cue rather than a minute of beeping.
+
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::regex::Regex;
+
+const SEVERITY_RULES: [&str; 3] = [
+    r"(?i)\b(fatal|critical|crit|emerg|emergency|alert|panic)\b => system:error",
+    r"(?i)\b(error|err)\b => bell*2@250",
+    r"(?i)\b(warn|warning)\b => bell",
+];
+
+#[derive(Clone, Copy, Debug)]
+enum Cue {
+    Bells { count: u32, gap: Duration },
+    System(Severity),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    regex: Regex,
+    cue: Cue,
+}
+
+static RULES: Mutex<Vec<Rule>> = Mutex::new(Vec::new());
+static PLAYING: AtomicBool = AtomicBool::new(false);
+
+// One rule, or the rules `severity` stands for
+pub fn parse_rules(spec: &str) -> Result<Vec<Rule>, String> {
+    if spec == "severity" {
+        return SEVERITY_RULES.iter().map(|rule| parse_rule(rule)).collect();
+    }
+    parse_rule(spec).map(|rule| vec![rule])
+}
+
+fn parse_rule(spec: &str) -> Result<Rule, String> {
+    let (pattern, cue) = spec
+        .rsplit_once("=>")
+        .ok_or(format!("expected '<regex> => <cue>' or 'severity', got '{}'", spec))?;
+    let cue = parse_cue(cue.trim())?;
+    let regex = Regex::new(pattern.trim()).map_err(|e| e.to_string())?;
+    Ok(Rule { regex, cue })
+}
+
+fn parse_cue(s: &str) -> Result<Cue, String> {
+    let invalid = || format!("'{}' is not a cue (bell[*N][@ms] or system:error|warning|info)", s);
+    if let Some(severity) = s.strip_prefix("system:") {
+        return match severity {
+            "error" => Ok(Cue::System(Severity::Error)),
+            "warning" => Ok(Cue::System(Severity::Warning)),
+            "info" => Ok(Cue::System(Severity::Info)),
+            _ => Err(invalid()),
+        };
+    }
+    let rest = s.strip_prefix("bell").ok_or_else(invalid)?;
+    let (count, gap) = match rest.split_once('@') {
+        Some((count, gap)) => (count, Some(gap)),
+        None => (rest, None),
+    };
+    let count = match count.strip_prefix('*') {
+        Some(n) => n.parse::<u32>().ok().filter(|n| (1..=10).contains(n)).ok_or_else(invalid)?,
+        None if count.is_empty() => 1,
+        None => return Err(invalid()),
+    };
+    let gap = match gap {
+        Some(ms) => Duration::from_millis(ms.parse::<u64>().map_err(|_| invalid())?),
+        None => Duration::from_millis(250),
+    };
+    Ok(Cue::Bells { count, gap })
+}
+
+pub fn add_rules(rules: Vec<Rule>) {
+    RULES.lock().unwrap().extend(rules);
+}
+
+pub fn observe(line: &str) {
+    let rules = RULES.lock().unwrap();
+    if rules.is_empty() || PLAYING.load(Ordering::Relaxed) {
+        return;
+    }
+    let text = line.trim_end_matches('\n');
+    if let Some(rule) = rules.iter().find(|rule| rule.regex.is_match(text)) {
+        let cue = rule.cue;
+        PLAYING.store(true, Ordering::Relaxed);
+        thread::spawn(move || {
+            play(cue);
+            PLAYING.store(false, Ordering::Relaxed);
+        });
+    }
+}
+
+// Let a cue that is playing finish; call before exiting
+pub fn finish() {
+    while PLAYING.load(Ordering::Relaxed) {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn play(cue: Cue) {
+    let (count, gap) = match cue {
+        Cue::Bells { count, gap } => (count, gap),
+        Cue::System(severity) => {
+            if system_sound(severity) {
+                return;
+            }
+            let count = match severity {
+                Severity::Error => 3,
+                Severity::Warning => 2,
+                Severity::Info => 1,
+            };
+            (count, Duration::from_millis(200))
+        }
+    };
+    for i in 0..count {
+        if i > 0 {
+            thread::sleep(gap);
+        }
+        let mut stderr = io::stderr().lock();
+        let _ = stderr.write_all(b"\x07");
+        let _ = stderr.flush();
+    }
+    // Keep the next cue from running into this one
+    thread::sleep(gap);
+}
+
+#[cfg(windows)]
+fn system_sound(severity: Severity) -> bool {
+    use winapi::um::winuser::{MB_ICONASTERISK, MB_ICONEXCLAMATION, MB_ICONHAND, MessageBeep};
+
+    let kind = match severity {
+        Severity::Error => MB_ICONHAND,
+        Severity::Warning => MB_ICONEXCLAMATION,
+        Severity::Info => MB_ICONASTERISK,
+    };
+    unsafe { MessageBeep(kind) != 0 }
+}
+
+#[cfg(not(windows))]
+fn system_sound(_severity: Severity) -> bool {
+    false
+}
@@ -0,0 +1,488 @@
+// Persisted read positions (`--state-file`), so a later run can resume where the last
+// one stopped instead of dumping the last N lines again.
+//
+// Each entry stores the offset reached plus the bytes just before it. On resume those
+// trailing bytes must still sit right before the offset; with `--rebase` they are also
+// searched for anywhere in the file, which lets a copied or moved log pick up from the
+// same content even though the path (and possibly the offsets) changed.
+//
+// Bookmarks are named offsets in a file, kept in the same state file with the bytes
+// before them, so `--goto-bookmark deploy-start` can start there again later. They are
+// set while following with `rail ctl <socket> bookmark <name>` (at the offset reached),
+// or from outside with `rail state bookmark <name> <file>` (at the end of the file, or
+// --offset). When the file has changed, the bookmark is found by its content.
+//
+// `rail state show --state-file <path> [--json]` lists the entries and whether each would
+// still resume, and the bookmarks; `rail state reset <file> --state-file <path>` forgets
+// one, so the next run starts that file over with the last N lines.
+
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::fields::push_json_string;
+use crate::open::open_log;
+
+const CONTEXT_LEN: u64 = 256;
+
+pub struct Entry {
+    pub path: String,
+    pub offset: u64,
+    pub context: Vec<u8>,
+}
+
+pub struct Bookmark {
+    pub name: String,
+    pub path: String,
+    pub offset: u64,
+    pub context: Vec<u8>,
+}
+
+pub struct StateFile {
+    path: String,
+    entries: Vec<Entry>,
+    bookmarks: Vec<Bookmark>,
+}
+
+// Whether this run keeps a state file bookmarks can go in
+static ACCEPTS_BOOKMARKS: AtomicBool = AtomicBool::new(false);
+// Bookmarks set over the control socket (name, file, offset), for the next save
+static PENDING: Mutex<Vec<(String, String, u64)>> = Mutex::new(Vec::new());
+
+pub fn accept_bookmarks() {
+    ACCEPTS_BOOKMARKS.store(true, Ordering::Relaxed);
+}
+
+// Bookmark `offset` in `filename`; it is written to the state file the next time the
+// position is
+pub fn bookmark_later(name: &str, filename: &str, offset: u64) -> Result<(), String> {
+    if !ACCEPTS_BOOKMARKS.load(Ordering::Relaxed) {
+        return Err("bookmarks are kept in the state file; rail was started without --state-file".to_string());
+    }
+    check_bookmark_name(name)?;
+    PENDING.lock().unwrap().push((name.to_string(), filename.to_string(), offset));
+    Ok(())
+}
+
+pub fn bookmarks_pending() -> bool {
+    !PENDING.lock().unwrap().is_empty()
+}
+
+fn check_bookmark_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(|c: char| c.is_control()) {
+        return Err(format!("'{}' can't be a bookmark name", name));
+    }
+    Ok(())
+}
+
+// Key entries by absolute path so the same log reached via different relative paths
+// shares one entry
+pub fn state_key(filename: &str) -> String {
+    match fs::canonicalize(filename) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => filename.to_string(),
+    }
+}
+
+impl StateFile {
+    // A missing state file just means nothing has been recorded yet
+    pub fn load(path: &str) -> io::Result<StateFile> {
+        let mut entries = Vec::new();
+        let mut bookmarks = Vec::new();
+        match File::open(path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if let Some(bookmark) = line.strip_prefix("bookmark\t") {
+                        bookmarks.extend(parse_bookmark(bookmark));
+                    } else if let Some(entry) = parse_entry(&line) {
+                        entries.push(entry);
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        Ok(StateFile { path: path.to_string(), entries, bookmarks })
+    }
+
+    pub fn save(&mut self) -> io::Result<()> {
+        let pending = std::mem::take(&mut *PENDING.lock().unwrap());
+        for (name, filename, offset) in pending {
+            let context = read_context(&mut open_log(&filename)?, offset)?;
+            self.set_bookmark(&name, &filename, offset, context);
+        }
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut out = io::BufWriter::new(File::create(&tmp_path)?);
+        for entry in &self.entries {
+            writeln!(out, "{}\t{}\t{}", entry.offset, to_hex(&entry.context), entry.path)?;
+        }
+        for bookmark in &self.bookmarks {
+            writeln!(out, "bookmark\t{}\t{}\t{}\t{}", bookmark.offset, to_hex(&bookmark.context), bookmark.name, bookmark.path)?;
+        }
+        out.flush()?;
+        drop(out);
+        fs::rename(&tmp_path, &self.path)
+    }
+
+    pub fn record(&mut self, filename: &str, offset: u64, context: Vec<u8>) {
+        let key = state_key(filename);
+        match self.entries.iter_mut().find(|e| e.path == key) {
+            Some(entry) => {
+                entry.offset = offset;
+                entry.context = context;
+            }
+            None => self.entries.push(Entry { path: key, offset, context }),
+        }
+    }
+
+    // Drop the entry for `filename` (or one recorded under exactly that path, for a file
+    // that is gone); false if there was none
+    pub fn forget(&mut self, filename: &str) -> bool {
+        let key = state_key(filename);
+        let before = self.entries.len();
+        self.entries.retain(|e| e.path != key && e.path != filename);
+        self.entries.len() != before
+    }
+
+    pub fn set_bookmark(&mut self, name: &str, filename: &str, offset: u64, context: Vec<u8>) {
+        let key = state_key(filename);
+        self.bookmarks.retain(|b| b.name != name || b.path != key);
+        self.bookmarks.push(Bookmark { name: name.to_string(), path: key, offset, context });
+    }
+
+    // Drop `filename`'s bookmark `name`; false if there was none
+    pub fn remove_bookmark(&mut self, name: &str, filename: &str) -> bool {
+        let key = state_key(filename);
+        let before = self.bookmarks.len();
+        self.bookmarks.retain(|b| b.name != name || (b.path != key && b.path != filename));
+        self.bookmarks.len() != before
+    }
+
+    // Where bookmark `name` is in `filename`: at its offset if the bytes before it are
+    // still the same, otherwise wherever they turn up closest to it
+    pub fn bookmark_offset(&self, filename: &str, name: &str) -> Result<u64, String> {
+        let key = state_key(filename);
+        let Some(bookmark) = self.bookmarks.iter().find(|b| b.name == name && b.path == key) else {
+            return Err(format!("No bookmark '{}' for '{}' in '{}'", name, filename, self.path));
+        };
+        let found = (|| {
+            let mut file = open_log(filename)?;
+            let len = file.metadata()?.len();
+            if bookmark.offset <= len && read_context(&mut file, bookmark.offset)? == bookmark.context {
+                return Ok(Some(bookmark.offset));
+            }
+            if bookmark.context.is_empty() {
+                return Ok(None);
+            }
+            find_context(&mut file, &bookmark.context, bookmark.offset)
+        })();
+        match found {
+            Ok(Some(offset)) => Ok(offset),
+            Ok(None) => Err(format!("The content at bookmark '{}' is no longer in '{}'", name, filename)),
+            Err(e) => Err(format!("Could not look for bookmark '{}' in '{}': {}", name, filename, e)),
+        }
+    }
+
+    // Where to resume reading `filename`, if recorded state still applies to it.
+    // Returns the offset and, when it was found by rebasing, the entry it came from.
+    pub fn resume_offset(&self, filename: &str, rebase: bool) -> io::Result<Option<(u64, Option<&str>)>> {
+        let mut file = open_log(filename)?;
+        let len = file.metadata()?.len();
+        let key = state_key(filename);
+
+        let own = self.entries.iter().find(|e| e.path == key);
+        if let Some(entry) = own
+            && entry.offset <= len
+            && read_context(&mut file, entry.offset)? == entry.context
+        {
+            return Ok(Some((entry.offset, None)));
+        }
+
+        if !rebase {
+            return Ok(None);
+        }
+
+        // Our own entry first (the file may have been rewritten with a different head),
+        // then positions recorded for other paths, in case this is a copy of one of them
+        let candidates = own.into_iter().chain(self.entries.iter().filter(|e| e.path != key));
+        for entry in candidates {
+            if entry.context.is_empty() {
+                continue;
+            }
+            if let Some(offset) = find_context(&mut file, &entry.context, entry.offset)? {
+                return Ok(Some((offset, Some(&entry.path))));
+            }
+        }
+        Ok(None)
+    }
+}
+
+// The bytes immediately before `end`, used to recognise the position later
+pub fn read_context(file: &mut File, end: u64) -> io::Result<Vec<u8>> {
+    let start = end.saturating_sub(CONTEXT_LEN);
+    let mut context = vec![0u8; (end - start) as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut context)?;
+    Ok(context)
+}
+
+// Find `context` in the file and return the offset just past the occurrence closest
+// to where it was originally recorded
+fn find_context(file: &mut File, context: &[u8], recorded: u64) -> io::Result<Option<u64>> {
+    const CHUNK: usize = 1024 * 1024;
+
+    file.seek(SeekFrom::Start(0))?;
+    let mut best: Option<u64> = None;
+    // Carry the tail of the previous chunk so matches spanning a boundary are found
+    let mut window: Vec<u8> = Vec::with_capacity(CHUNK + context.len());
+    let mut window_start = 0u64;
+    let mut chunk = vec![0u8; CHUNK];
+
+    loop {
+        let n = file.read(&mut chunk)?;
+        if n == 0 {
+            break;
+        }
+        window.extend_from_slice(&chunk[..n]);
+
+        let mut from = 0;
+        while let Some(i) = find_bytes(&window[from..], context) {
+            let end = window_start + (from + i + context.len()) as u64;
+            if best.is_none_or(|b| end.abs_diff(recorded) < b.abs_diff(recorded)) {
+                best = Some(end);
+            }
+            from += i + 1;
+        }
+
+        let keep = context.len().saturating_sub(1).min(window.len());
+        let drop_len = window.len() - keep;
+        window.drain(..drop_len);
+        window_start += drop_len as u64;
+    }
+    Ok(best)
+}
+
+// What became of the file an entry is for
+enum Status {
+    Resumes,
+    Missing,
+    Shorter(u64),
+    Changed,
+}
+
+impl Status {
+    fn of(entry: &Entry) -> Status {
+        let Ok(mut file) = open_log(&entry.path) else {
+            return Status::Missing;
+        };
+        let len = file.metadata().map_or(0, |m| m.len());
+        if len < entry.offset {
+            return Status::Shorter(len);
+        }
+        match read_context(&mut file, entry.offset) {
+            Ok(context) if context == entry.context => Status::Resumes,
+            _ => Status::Changed,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Status::Resumes => "resumes",
+            Status::Missing => "missing",
+            Status::Shorter(_) => "shorter",
+            Status::Changed => "changed",
+        }
+    }
+}
+
+// `rail state show|reset|bookmark|unbookmark ...`
+pub fn command(args: &[String]) -> io::Result<()> {
+    let mut state_path = None;
+    let mut json = false;
+    let mut offset = None;
+    let mut files = Vec::new();
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--state-file" => {
+                let Some(path) = args.get(i + 1) else {
+                    eprintln!("Error: --state-file requires a path argument");
+                    process::exit(1);
+                };
+                state_path = Some(path.as_str());
+                i += 2;
+            }
+            "--json" => {
+                json = true;
+                i += 1;
+            }
+            "--offset" => {
+                match args.get(i + 1).map(|n| n.parse::<u64>()) {
+                    Some(Ok(n)) => offset = Some(n),
+                    _ => {
+                        eprintln!("Error: --offset requires a byte offset");
+                        process::exit(1);
+                    }
+                }
+                i += 2;
+            }
+            arg if !arg.starts_with('-') => {
+                files.push(arg);
+                i += 1;
+            }
+            arg => {
+                eprintln!("Unknown option: {}", arg);
+                process::exit(1);
+            }
+        }
+    }
+    let Some(state_path) = state_path else {
+        eprintln!("Error: state {} requires --state-file", args.first().map_or("", |a| a.as_str()));
+        process::exit(1);
+    };
+    let mut state = StateFile::load(state_path)?;
+
+    match args.first().map(|a| a.as_str()) {
+        Some("show") if files.is_empty() => {
+            for entry in &state.entries {
+                let status = Status::of(entry);
+                if json {
+                    println!("{}", entry_json(entry, &status));
+                } else {
+                    print_entry(entry, &status);
+                }
+            }
+            for bookmark in &state.bookmarks {
+                if json {
+                    println!("{}", bookmark_json(bookmark));
+                } else {
+                    println!("bookmark {:?} in {} at offset {}", bookmark.name, bookmark.path, bookmark.offset);
+                }
+            }
+            if state.entries.is_empty() && state.bookmarks.is_empty() && !json {
+                println!("No positions recorded in '{}'", state_path);
+            }
+        }
+        Some("bookmark") if files.len() == 2 => {
+            let (name, file) = (files[0], files[1]);
+            if let Err(e) = check_bookmark_name(name) {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+            let mut log = open_log(file)?;
+            let len = log.metadata()?.len();
+            let offset = offset.unwrap_or(len);
+            if offset > len {
+                eprintln!("Error: '{}' is only {} bytes long", file, len);
+                process::exit(1);
+            }
+            let context = read_context(&mut log, offset)?;
+            state.set_bookmark(name, file, offset, context);
+            state.save()?;
+            println!("Bookmarked offset {} of '{}' as '{}'", offset, file, name);
+        }
+        Some("unbookmark") if files.len() == 2 => {
+            let (name, file) = (files[0], files[1]);
+            if !state.remove_bookmark(name, file) {
+                eprintln!("Error: No bookmark '{}' for '{}' in '{}'", name, file, state_path);
+                process::exit(1);
+            }
+            state.save()?;
+            println!("Removed bookmark '{}' of '{}'", name, file);
+        }
+        Some("reset") if !files.is_empty() => {
+            for file in &files {
+                if !state.forget(file) {
+                    eprintln!("Error: No position recorded for '{}' in '{}'", file, state_path);
+                    process::exit(1);
+                }
+            }
+            state.save()?;
+            for file in &files {
+                println!("Forgot the position recorded for '{}'", file);
+            }
+        }
+        _ => {
+            eprintln!("Usage: rail state show --state-file <path> [--json]");
+            eprintln!("       rail state reset <file>... --state-file <path>");
+            eprintln!("       rail state bookmark <name> <file> --state-file <path> [--offset <bytes>]");
+            eprintln!("       rail state unbookmark <name> <file> --state-file <path>");
+            process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+fn print_entry(entry: &Entry, status: &Status) {
+    println!("{}", entry.path);
+    println!("  offset:  {}", entry.offset);
+    let text = String::from_utf8_lossy(&entry.context);
+    let tail = text.char_indices().rev().nth(39).map_or(0, |(i, _)| i);
+    println!("  context: {} bytes before the offset, ending {:?}", entry.context.len(), &text[tail..]);
+    let status = match status {
+        Status::Resumes => "the next run resumes here".to_string(),
+        Status::Missing => "the file is gone or can't be opened".to_string(),
+        Status::Shorter(len) => format!("the file is now only {} bytes; the next run starts over", len),
+        Status::Changed => "the content before the offset changed; the next run starts over (unless --rebase finds it)".to_string(),
+    };
+    println!("  status:  {}", status);
+}
+
+fn entry_json(entry: &Entry, status: &Status) -> String {
+    let mut out = String::from("{\"path\":");
+    push_json_string(&mut out, &entry.path);
+    out.push_str(&format!(",\"offset\":{},\"context\":\"{}\",\"status\":\"{}\"", entry.offset, to_hex(&entry.context), status.name()));
+    if let Status::Shorter(len) = status {
+        out.push_str(&format!(",\"size\":{}", len));
+    }
+    out.push('}');
+    out
+}
+
+fn bookmark_json(bookmark: &Bookmark) -> String {
+    let mut out = String::from("{\"bookmark\":");
+    push_json_string(&mut out, &bookmark.name);
+    out.push_str(",\"path\":");
+    push_json_string(&mut out, &bookmark.path);
+    out.push_str(&format!(",\"offset\":{},\"context\":\"{}\"}}", bookmark.offset, to_hex(&bookmark.context)));
+    out
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let mut parts = line.splitn(3, '\t');
+    let offset = parts.next()?.parse().ok()?;
+    let context = from_hex(parts.next()?)?;
+    let path = parts.next()?.to_string();
+    Some(Entry { path, offset, context })
+}
+
+// offset, context, name and path, after "bookmark\t"
+fn parse_bookmark(line: &str) -> Option<Bookmark> {
+    let mut parts = line.splitn(4, '\t');
+    let offset = parts.next()?.parse().ok()?;
+    let context = from_hex(parts.next()?)?;
+    let name = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+    Some(Bookmark { name, path, offset, context })
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
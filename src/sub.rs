@@ -0,0 +1,224 @@
+// `--sub 's/<regex>/<replacement>/<flags>'`: sed-style substitutions applied to every
+// line before anything else looks at it, so a rule that masks a secret masks it in
+// the printed output, the parsed fields and what --fail-on sees alike.
+//
+// Any character can be the delimiter (s|a/b|c|). In the replacement, & or \0 is the
+// whole match, \1..\9 are numbered groups and ${name} a named one; \&, \$ and \\ are
+// literal. Flags: g replaces every match instead of the first, i ignores case.
+//
+// `--redact <preset,...>` adds canned rules for common kinds of sensitive text, and
+// `--demo-safe` turns on all of them. All rules run in command-line order.
+
+use std::borrow::Cow;
+use std::sync::Mutex;
+
+use crate::regex::Regex;
+
+#[derive(Debug)]
+enum Piece {
+    Literal(String),
+    Group(usize),
+}
+
+#[derive(Debug)]
+pub struct Rule {
+    regex: Regex,
+    replacement: Vec<Piece>,
+    global: bool,
+}
+
+pub fn parse_rule(spec: &str) -> Result<Rule, String> {
+    let mut chars = spec.chars();
+    if chars.next() != Some('s') {
+        return Err(format!("expected s/regex/replacement/flags, got '{}'", spec));
+    }
+    let delim = chars.next().ok_or("missing delimiter after 's'")?;
+    if delim.is_alphanumeric() || delim == '\\' || delim.is_whitespace() {
+        return Err(format!("'{}' can't be used as a delimiter", delim));
+    }
+
+    // Split on unescaped delimiters; an escaped delimiter stands for itself
+    let mut parts = vec![String::new()];
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(d) if d == delim => parts.last_mut().unwrap().push(d),
+                Some(other) => {
+                    parts.last_mut().unwrap().push('\\');
+                    parts.last_mut().unwrap().push(other);
+                }
+                None => return Err("trailing backslash".to_string()),
+            }
+        } else if c == delim {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+    let [pattern, replacement, flags] = <[String; 3]>::try_from(parts)
+        .map_err(|_| format!("expected s{0}regex{0}replacement{0}flags, got '{1}'", delim, spec))?;
+
+    let mut global = false;
+    let mut pattern = pattern;
+    for flag in flags.chars() {
+        match flag {
+            'g' => global = true,
+            'i' => pattern = format!("(?i){}", pattern),
+            _ => return Err(format!("unknown flag '{}'", flag)),
+        }
+    }
+    let regex = Regex::new(&pattern).map_err(|e| e.to_string())?;
+    let replacement = parse_replacement(&replacement, &regex)?;
+    Ok(Rule { regex, replacement, global })
+}
+
+fn parse_replacement(text: &str, regex: &Regex) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let group = |pieces: &mut Vec<Piece>, literal: &mut String, index: usize| {
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(std::mem::take(literal)));
+        }
+        pieces.push(Piece::Group(index));
+    };
+
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => group(&mut pieces, &mut literal, 0),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => {
+                    let index = d.to_digit(10).unwrap() as usize;
+                    if index >= regex.groups() {
+                        return Err(format!("no group {} in the regex", index));
+                    }
+                    group(&mut pieces, &mut literal, index);
+                }
+                Some(other) => literal.push(other),
+                None => literal.push('\\'),
+            },
+            '$' if chars.peek() == Some(&'{') => {
+                chars.next();
+                let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let index = regex.group_index(&name).ok_or(format!("no group named '{}' in the regex", name))?;
+                group(&mut pieces, &mut literal, index);
+            }
+            _ => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+impl Rule {
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        let mut out = String::new();
+        let mut replaced = false;
+        let mut copied = 0;
+        let mut pos = 0;
+        while pos <= text.len() {
+            let Some(groups) = self.regex.captures_at(text, pos) else {
+                break;
+            };
+            let (start, end) = groups[0].unwrap();
+            out.push_str(&text[copied..start]);
+            for piece in &self.replacement {
+                match piece {
+                    Piece::Literal(s) => out.push_str(s),
+                    Piece::Group(i) => {
+                        if let Some((s, e)) = groups[*i] {
+                            out.push_str(&text[s..e]);
+                        }
+                    }
+                }
+            }
+            copied = end;
+            replaced = true;
+            if !self.global {
+                break;
+            }
+            // After an empty match, step over a character so the search moves on
+            pos = if end > start { end } else { end + text[end..].chars().next().map_or(1, char::len_utf8) };
+        }
+        if !replaced {
+            return Cow::Borrowed(text);
+        }
+        out.push_str(&text[copied..]);
+        Cow::Owned(out)
+    }
+}
+
+// Canned redaction rules, by preset name
+const PRESETS: &[(&str, &[&str])] = &[
+    ("secrets", &[
+        r#"s/(?i)\b(password|passwd|pwd|secret|token|api[_-]?key|access[_-]?key|auth)(["']?\s*[=:]\s*["']?)[^\s"'&,;]+/\1\2***/g"#,
+        r"s/(?i)\b(bearer|basic)\s+[A-Za-z0-9._~+\/=-]+/\1 ***/g",
+    ]),
+    ("emails", &[r"s/[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)+/<email>/g"]),
+    ("hostnames", &[
+        // Well-known suffixes, then anything with three or more dotted name labels
+        r"s/\b[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)*\.(com|net|org|io|dev|cloud|internal|local|lan|corp|intra)\b/<host>/g",
+        r"s/\b[A-Za-z][A-Za-z0-9-]*(\.[A-Za-z][A-Za-z0-9-]*){2,}\b/<host>/g",
+    ]),
+    // Keep the network, drop the host part: IPv4 to its /24, IPv6 to its /48
+    ("ips", &[
+        r"s/\b(\d{1,3}\.\d{1,3}\.\d{1,3})\.\d{1,3}\b/\1.0/g",
+        r"s/\b([0-9A-Fa-f]{1,4}:[0-9A-Fa-f]{1,4}:[0-9A-Fa-f]{1,4})(:[0-9A-Fa-f]{0,4})+/\1::/g",
+        r"s/\b([0-9A-Fa-f]{1,4}:[0-9A-Fa-f]{1,4})::[0-9A-Fa-f]{1,4}(:[0-9A-Fa-f]{1,4})*\b/\1::/g",
+    ]),
+];
+
+// What --demo-safe turns on, in the order they must run: emails before hostnames, so an
+// address isn't half-masked as a host first
+pub const DEMO_SAFE: &str = "secrets,emails,hostnames,ips";
+
+pub fn preset_rules(names: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    for name in names.split(',') {
+        let (_, specs) = PRESETS.iter().find(|(n, _)| *n == name).ok_or_else(|| {
+            let known: Vec<&str> = PRESETS.iter().map(|(n, _)| *n).collect();
+            format!("unknown preset '{}' (expected {})", name, known.join(", "))
+        })?;
+        for spec in specs.iter() {
+            rules.push(parse_rule(spec).expect("built-in redaction rule must parse"));
+        }
+    }
+    Ok(rules)
+}
+
+static RULES: Mutex<Vec<Rule>> = Mutex::new(Vec::new());
+
+pub fn add_rule(rule: Rule) {
+    RULES.lock().unwrap().push(rule);
+}
+
+pub fn has_rules() -> bool {
+    !RULES.lock().unwrap().is_empty()
+}
+
+// Run every rule over the line, in the order given; the trailing newline is kept out of
+// reach so `$` and [^x]+ behave as they would on the bare line
+pub fn transform(line: &str) -> Cow<'_, str> {
+    let rules = RULES.lock().unwrap();
+    if rules.is_empty() {
+        return Cow::Borrowed(line);
+    }
+    let body = line.strip_suffix('\n').unwrap_or(line);
+    let mut text = Cow::Borrowed(body);
+    for rule in rules.iter() {
+        if let Cow::Owned(changed) = rule.apply(&text) {
+            text = Cow::Owned(changed);
+        }
+    }
+    match text {
+        Cow::Borrowed(_) => Cow::Borrowed(line),
+        Cow::Owned(mut changed) => {
+            if line.ends_with('\n') {
+                changed.push('\n');
+            }
+            Cow::Owned(changed)
+        }
+    }
+}
@@ -0,0 +1,132 @@
+// `--timestamps[=FORMAT]`: start each output line with the local time rail received it,
+// for programs that don't write one. FORMAT is strftime-style (default DEFAULT):
+//
+//   %Y %y %m %d %e %j  year, 2-digit year, month, day, space-padded day, day of year
+//   %H %I %p %M %S     hour, 12-hour hour, AM/PM, minute, second
+//   %3N %6N %N         milliseconds, microseconds, nanoseconds (as GNU date)
+//   %a %A %b %B        weekday and month names, short and long
+//   %z %s              UTC offset (+0200), seconds since the epoch
+//   %F %T %%           %Y-%m-%d, %H:%M:%S, a percent sign
+//
+// The time is followed by a space. It's when rail read the line, which for the lines
+// printed at startup (-n) is not when they were written.
+
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::tz;
+
+pub const DEFAULT: &str = "%Y-%m-%d %H:%M:%S.%3N";
+const WEEKDAYS: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November",
+    "December",
+];
+
+#[derive(Debug)]
+enum Piece {
+    Literal(String),
+    Field(char),
+    // Digits of the fraction of the second
+    Fraction(usize),
+}
+
+static FORMAT: OnceLock<Vec<Piece>> = OnceLock::new();
+
+pub fn set_format(format: &str) -> Result<(), String> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+        let conversion = chars.next().ok_or(format!("'{}' ends in a lone %", format))?;
+        if conversion == '%' {
+            literal.push('%');
+            continue;
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+        }
+        match conversion {
+            'F' => pieces.extend([Piece::Field('Y'), Piece::Literal("-".into()), Piece::Field('m'), Piece::Literal("-".into()), Piece::Field('d')]),
+            'T' => pieces.extend([Piece::Field('H'), Piece::Literal(":".into()), Piece::Field('M'), Piece::Literal(":".into()), Piece::Field('S')]),
+            'N' => pieces.push(Piece::Fraction(9)),
+            '3' | '6' | '9' if chars.peek() == Some(&'N') => {
+                chars.next();
+                pieces.push(Piece::Fraction(conversion as usize - '0' as usize));
+            }
+            c if "YymdejHIpMSaAbBzs".contains(c) => pieces.push(Piece::Field(c)),
+            c => return Err(format!("unknown conversion %{} in '{}'", c, format)),
+        }
+    }
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    let _ = FORMAT.set(pieces);
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    FORMAT.get().is_some()
+}
+
+// `text` (one or more lines) with the time in front of each line
+pub fn prefix(text: String) -> String {
+    let Some(format) = FORMAT.get() else {
+        return text;
+    };
+    let stamp = now(format);
+    let mut out = String::with_capacity(text.len() + stamp.len() + 1);
+    for line in text.split_inclusive('\n') {
+        out.push_str(&stamp);
+        out.push(' ');
+        out.push_str(line);
+    }
+    out
+}
+
+fn now(format: &[Piece]) -> String {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let utc = since_epoch.as_secs() as i64;
+    let offset = tz::local_zone().offset_at(utc);
+    let local = utc + offset as i64;
+    let days = local.div_euclid(86400);
+    let (year, month, day) = tz::civil_date(days);
+    let secs = local.rem_euclid(86400);
+    let (hour, minute, second) = (secs / 3600, secs / 60 % 60, secs % 60);
+    let weekday = (days + 3).rem_euclid(7) as usize;
+
+    let mut out = String::new();
+    for piece in format {
+        match piece {
+            Piece::Literal(s) => out.push_str(s),
+            Piece::Fraction(digits) => {
+                let nanos = format!("{:09}", since_epoch.subsec_nanos());
+                out.push_str(&nanos[..*digits]);
+            }
+            Piece::Field(c) => out.push_str(&match c {
+                'Y' => year.to_string(),
+                'y' => format!("{:02}", year.rem_euclid(100)),
+                'm' => format!("{:02}", month),
+                'd' => format!("{:02}", day),
+                'e' => format!("{:>2}", day),
+                'j' => format!("{:03}", days - tz::days_from_civil(year, 1, 1) + 1),
+                'H' => format!("{:02}", hour),
+                'I' => format!("{:02}", (hour + 11) % 12 + 1),
+                'p' => (if hour < 12 { "AM" } else { "PM" }).to_string(),
+                'M' => format!("{:02}", minute),
+                'S' => format!("{:02}", second),
+                'a' => WEEKDAYS[weekday][..3].to_string(),
+                'A' => WEEKDAYS[weekday].to_string(),
+                'b' => MONTHS[month as usize - 1][..3].to_string(),
+                'B' => MONTHS[month as usize - 1].to_string(),
+                'z' => format!("{}{:02}{:02}", if offset < 0 { '-' } else { '+' }, offset.abs() / 3600, offset.abs() / 60 % 60),
+                _ => utc.to_string(),
+            }),
+        }
+    }
+    out
+}
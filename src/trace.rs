@@ -0,0 +1,192 @@
+// `--trace-events <file.jsonl>`: record what the follow loop observes and decides, one
+// JSON object per line. `rail debug-replay <file.jsonl>` feeds the recorded observations
+// to the same decision logic (follow.rs) and reports wherever its decisions differ from
+// the recorded ones, so a follow bug can be reproduced without the reporter's files.
+//
+// Events, with "t" in milliseconds on the follow loop's clock:
+//
+//   start {pos, modified}   following begins (modified: mtime in ns since the epoch)
+//   stat {len, modified}    the path was checked before a read
+//   read {pos, bytes}       a read at pos returned bytes (0 at end of file)
+//   idle {len, handle_len, now}   the path and our handle were checked after sleeping
+//                           at end of file
+//   rotation, truncation, stall    what the loop decided
+//   stall_reopen {old_len}, fault_reopen {len}    the handle was reopened
+//   stat_error, read_error, denied    failures, kept for context; replay skips them
+//
+// The trace is flushed whenever the loop catches up, so at most one burst of reads is
+// lost if rail is killed.
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::follow::{Decision, Follow};
+
+static TRACE: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+
+pub fn enable(path: &str) -> io::Result<()> {
+    let file = File::create(path)?;
+    set_output(Box::new(BufWriter::new(file)));
+    Ok(())
+}
+
+// Send the trace somewhere other than a file (`rail simulate` reads its own)
+pub fn set_output(out: Box<dyn Write + Send>) {
+    *TRACE.lock().unwrap() = Some(out);
+}
+
+pub fn event(t: Duration, name: &str, values: &[(&str, u128)]) {
+    let mut trace = TRACE.lock().unwrap();
+    let Some(out) = trace.as_mut() else {
+        return;
+    };
+    let mut line = format!("{{\"t\":{},\"event\":\"{}\"", t.as_millis(), name);
+    for (key, value) in values {
+        line.push_str(&format!(",\"{}\":{}", key, value));
+    }
+    line.push_str("}\n");
+    if let Err(e) = out.write_all(line.as_bytes()) {
+        eprintln!("Warning: Could not write trace: {}", e);
+        *trace = None;
+    }
+}
+
+pub fn flush() {
+    if let Some(out) = TRACE.lock().unwrap().as_mut() {
+        let _ = out.flush();
+    }
+}
+
+pub struct Event {
+    pub t: u128,
+    pub name: String,
+    values: Vec<(String, u128)>,
+}
+
+impl Event {
+    fn get(&self, key: &str) -> Result<u128, String> {
+        self.values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| *v)
+            .ok_or(format!("'{}' event without {}", self.name, key))
+    }
+}
+
+// Only the flat objects event() writes: string event names, everything else numbers
+pub fn parse_event(line: &str) -> Result<Event, String> {
+    let body = line
+        .trim()
+        .strip_prefix('{')
+        .and_then(|l| l.strip_suffix('}'))
+        .ok_or("not a JSON object")?;
+    let mut event = Event { t: 0, name: String::new(), values: Vec::new() };
+    for pair in body.split(',') {
+        let (key, value) = pair.split_once(':').ok_or(format!("bad member '{}'", pair))?;
+        let key = key.trim().trim_matches('"');
+        let value = value.trim();
+        match key {
+            "event" => event.name = value.trim_matches('"').to_string(),
+            _ => {
+                let n = value.parse().map_err(|_| format!("bad value for {}: '{}'", key, value))?;
+                if key == "t" {
+                    event.t = n;
+                } else {
+                    event.values.push((key.to_string(), n));
+                }
+            }
+        }
+    }
+    if event.name.is_empty() {
+        return Err("missing event name".to_string());
+    }
+    Ok(event)
+}
+
+// Replays the trace at `path`; Ok(true) if every decision matched
+pub fn replay(path: &str) -> io::Result<bool> {
+    let invalid = |n: usize, e: String| io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n, e));
+    let mut follow: Option<Follow> = None;
+    // Decided by the replay but not (yet) seen in the recording
+    let mut pending: Option<(u128, Decision)> = None;
+    let (mut events, mut decisions, mut differences) = (0, 0, 0);
+    let mut differ = |t: u128, what: String| {
+        differences += 1;
+        println!("t={:.3}s: {}", t as f64 / 1000.0, what);
+    };
+
+    for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = parse_event(&line).map_err(|e| invalid(n + 1, e))?;
+        events += 1;
+        let field = |key: &str| event.get(key).map_err(|e| invalid(n + 1, e));
+
+        if let Some(decision) = Decision::from_name(&event.name) {
+            decisions += 1;
+            match pending.take() {
+                Some((_, replayed)) if replayed == decision => {}
+                Some((_, replayed)) => differ(event.t, format!("recorded {}, replay decided {}", decision, replayed)),
+                None => differ(event.t, format!("recorded {}, replay decided nothing", decision)),
+            }
+            continue;
+        }
+        if let Some((t, replayed)) = pending.take() {
+            differ(t, format!("replay decided {}, recording has nothing", replayed));
+        }
+
+        if event.name == "start" {
+            follow = Some(Follow::new(field("pos")? as u64, field("modified")?));
+            continue;
+        }
+        let Some(f) = follow.as_mut() else {
+            continue;
+        };
+        let decided = match event.name.as_str() {
+            "stat" => f.stat(field("len")? as u64, field("modified")?),
+            "read" => {
+                let pos = field("pos")? as u64;
+                if pos != f.pos {
+                    differ(event.t, format!("recorded read at {}, replay is at {}", pos, f.pos));
+                    f.pos = pos;
+                }
+                f.read(field("bytes")? as u64);
+                None
+            }
+            "idle" => f.idle(
+                field("len")? as u64,
+                field("handle_len")? as u64,
+                Duration::from_millis(field("now")? as u64),
+            ),
+            "identity" => f.identity(field("path")?, field("handle")?),
+            "stall_reopen" => {
+                f.stall_reopened(field("old_len")? as u64);
+                None
+            }
+            "fault_reopen" => {
+                f.fault_reopened(field("len")? as u64);
+                None
+            }
+            _ => None,
+        };
+        pending = decided.map(|d| (event.t, d));
+    }
+    if let Some((t, replayed)) = pending {
+        differ(t, format!("replay decided {}, recording has nothing", replayed));
+    }
+
+    if follow.is_none() {
+        println!("No 'start' event in {}; nothing to replay", path);
+        return Ok(false);
+    }
+    if differences == 0 {
+        println!("Replayed {} events: all {} decisions reproduced", events, decisions);
+    } else {
+        println!("Replayed {} events: {} difference(s) from the recording", events, differences);
+    }
+    Ok(differences == 0)
+}
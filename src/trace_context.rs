@@ -0,0 +1,201 @@
+// Trace context in log lines: `--trace <id>` keeps only the lines of one distributed
+// trace, `--color-traces` colours each line by its trace (on a terminal; with --a11y it
+// tags them instead), and
+// `--trace-link <url>` prints a link into a tracing UI the first time a trace is seen,
+// with {trace_id} and {span_id} in the URL replaced, e.g.
+//
+//   --trace-link 'https://jaeger.internal/trace/{trace_id}'
+//
+// The trace is found in the line as written (after --format rendering, so logfmt and
+// JSON output both work): a W3C traceparent (00-<32 hex>-<16 hex>-<2 hex>) or a
+// trace_id/traceId/trace-id/X-B3-TraceId/dd.trace_id key with a 16 or 32 hex digit
+// value, and likewise span_id and its spellings. IDs compare case-insensitively and
+// without leading zeros, so a 64-bit ID matches the same ID padded to 128 bits.
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::a11y;
+use crate::output;
+
+const TRACE_KEYS: [&str; 5] = ["trace_id", "traceid", "trace-id", "x-b3-traceid", "dd.trace_id"];
+const SPAN_KEYS: [&str; 5] = ["span_id", "spanid", "span-id", "x-b3-spanid", "dd.span_id"];
+// 256-colour palette entries that read well on dark and light backgrounds
+const PALETTE: [u8; 12] = [33, 37, 71, 97, 130, 133, 166, 169, 172, 28, 62, 125];
+// Traces remembered as already linked; forgotten all at once past this
+const MAX_LINKED: usize = 10_000;
+
+struct Options {
+    only: Option<String>,
+    color: bool,
+    link: Option<String>,
+    linked: HashSet<String>,
+}
+
+struct Context {
+    trace_id: String,
+    span_id: Option<String>,
+}
+
+static OPTIONS: Mutex<Option<Options>> = Mutex::new(None);
+
+fn with_options(f: impl FnOnce(&mut Options)) {
+    let mut options = OPTIONS.lock().unwrap();
+    f(options.get_or_insert_with(|| Options { only: None, color: false, link: None, linked: HashSet::new() }));
+}
+
+pub fn set_only(id: &str) -> Result<(), String> {
+    if !(1..=32).contains(&id.len()) || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(format!("expected a trace ID of up to 32 hex digits, got '{}'", id));
+    }
+    with_options(|o| o.only = Some(normalize(id)));
+    Ok(())
+}
+
+pub fn set_color() {
+    with_options(|o| o.color = true);
+}
+
+pub fn set_link(template: &str) -> Result<(), String> {
+    if !template.contains("{trace_id}") {
+        return Err(format!("'{}' has no {{trace_id}} to fill in", template));
+    }
+    with_options(|o| o.link = Some(template.to_string()));
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    OPTIONS.lock().unwrap().is_some()
+}
+
+fn normalize(id: &str) -> String {
+    let id = id.trim_start_matches('0').to_ascii_lowercase();
+    if id.is_empty() { "0".to_string() } else { id }
+}
+
+// What's left of `line` after --trace and the other trace options: None if it belongs
+// to another trace (or to none), else the line, coloured, followed by a link line the
+// first time its trace shows up
+pub fn transform(line: &str) -> Option<Cow<'_, str>> {
+    let mut options = OPTIONS.lock().unwrap();
+    let Some(options) = options.as_mut() else {
+        return Some(Cow::Borrowed(line));
+    };
+    let context = find(line);
+    if let Some(only) = &options.only
+        && context.as_ref().is_none_or(|c| normalize(&c.trace_id) != *only)
+    {
+        return None;
+    }
+    let Some(context) = context else {
+        return Some(Cow::Borrowed(line));
+    };
+    let mut out = String::with_capacity(line.len() + 16);
+    let text = line.strip_suffix('\n').unwrap_or(line);
+    if options.color && a11y::enabled() {
+        let id = normalize(&context.trace_id);
+        out.push_str(&format!("[trace {}] {}\n", &id[..id.len().min(8)], text));
+    } else if options.color && output::color_wanted() {
+        let color = PALETTE[(fnv(&normalize(&context.trace_id)) % PALETTE.len() as u64) as usize];
+        out.push_str(&format!("\x1b[38;5;{}m{}\x1b[0m\n", color, text));
+    } else {
+        out.push_str(text);
+        out.push('\n');
+    }
+    if let Some(template) = &options.link {
+        if options.linked.len() >= MAX_LINKED {
+            options.linked.clear();
+        }
+        if options.linked.insert(normalize(&context.trace_id)) {
+            let url = template
+                .replace("{trace_id}", &context.trace_id)
+                .replace("{span_id}", context.span_id.as_deref().unwrap_or(""));
+            out.push_str(&format!("  trace {}: {}\n", context.trace_id, url));
+        }
+    }
+    Some(Cow::Owned(out))
+}
+
+// FNV-1a, so a trace gets the same colour in every run
+fn fnv(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+fn find(line: &str) -> Option<Context> {
+    if let Some(context) = find_traceparent(line) {
+        return Some(context);
+    }
+    let lower = line.to_ascii_lowercase();
+    let trace_id = find_keyed(&lower, &TRACE_KEYS)?;
+    let span_id = find_keyed(&lower, &SPAN_KEYS);
+    Some(Context { trace_id, span_id })
+}
+
+fn hex_run(bytes: &[u8], at: usize) -> usize {
+    bytes[at.min(bytes.len())..].iter().take_while(|b| b.is_ascii_hexdigit()).count()
+}
+
+fn word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
+// version-traceid-parentid-flags, each field exactly as long as the spec says
+fn find_traceparent(line: &str) -> Option<Context> {
+    let bytes = line.as_bytes();
+    let mut from = 0;
+    while let Some(i) = line[from..].find("00-").map(|i| i + from) {
+        from = i + 1;
+        if i > 0 && word_byte(bytes[i - 1]) {
+            continue;
+        }
+        let trace = i + 3;
+        let span = trace + 33;
+        let flags = span + 17;
+        let fits = hex_run(bytes, trace) == 32
+            && bytes.get(trace + 32) == Some(&b'-')
+            && hex_run(bytes, span) == 16
+            && bytes.get(span + 16) == Some(&b'-')
+            && hex_run(bytes, flags) == 2
+            && bytes.get(flags + 2).is_none_or(|&b| !word_byte(b));
+        if fits {
+            return Some(Context {
+                trace_id: line[trace..trace + 32].to_ascii_lowercase(),
+                span_id: Some(line[span..span + 16].to_ascii_lowercase()),
+            });
+        }
+    }
+    None
+}
+
+// The value of the first of `keys` (in lowercased `line`) followed by = or : and an ID,
+// quotes and spaces allowed around them as JSON and logfmt write them
+fn find_keyed(line: &str, keys: &[&str]) -> Option<String> {
+    let bytes = line.as_bytes();
+    for key in keys {
+        let mut from = 0;
+        while let Some(i) = line[from..].find(key).map(|i| i + from) {
+            from = i + 1;
+            if i > 0 && (word_byte(bytes[i - 1]) || bytes[i - 1] == b'.') {
+                continue;
+            }
+            let mut pos = i + key.len();
+            let skip = |pos: &mut usize, set: &[u8]| {
+                while bytes.get(*pos).is_some_and(|b| set.contains(b)) {
+                    *pos += 1;
+                }
+            };
+            skip(&mut pos, b"\"' ");
+            if !matches!(bytes.get(pos), Some(b'=' | b':')) {
+                continue;
+            }
+            pos += 1;
+            skip(&mut pos, b"\"' ");
+            let len = hex_run(bytes, pos);
+            if (len == 16 || len == 32) && bytes.get(pos + len).is_none_or(|&b| !word_byte(b)) {
+                return Some(line[pos..pos + len].to_string());
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,128 @@
+// Sources that aren't files on this machine (so far: `rail adb`). A transport knows how
+// to open its stream and, optionally, how to pick up where a dropped connection left
+// off; the reconnect loop and its --reconnect-max / --reconnect-backoff policy live here
+// so every transport behaves the same way when the other end goes away.
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::thread;
+use std::time::Duration;
+
+use crate::output;
+
+pub trait Transport {
+    // How to refer to the source in status messages
+    fn describe(&self) -> String;
+
+    // Start streaming. `resume` is the token of the last line delivered before the
+    // previous connection dropped, if the transport gave one.
+    fn connect(&mut self, resume: Option<&str>) -> io::Result<Box<dyn Read>>;
+
+    // Called once a connection's stream has ended; report why as an error
+    fn disconnect(&mut self) -> io::Result<()>;
+
+    // A token `connect` can resume after this line from, for transports that support it
+    fn resume_token(&self, _line: &str) -> Option<String> {
+        None
+    }
+
+    // Whether a line from a resumed connection was already delivered before the drop
+    fn already_seen(&self, _line: &str, _resume: &str) -> bool {
+        false
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Reconnect {
+    // Consecutive failed connections before giving up
+    pub max_attempts: u32,
+    // First delay; doubles per failed attempt up to MAX_BACKOFF
+    pub backoff: Duration,
+}
+
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+impl Default for Reconnect {
+    fn default() -> Reconnect {
+        Reconnect { max_attempts: 5, backoff: Duration::from_secs(1) }
+    }
+}
+
+pub fn parse_backoff(s: &str) -> Result<Duration, String> {
+    let (digits, scale) = match s.strip_suffix("ms") {
+        Some(ms) => (ms, 1),
+        None => (s.strip_suffix('s').unwrap_or(s), 1000),
+    };
+    match digits.parse::<u64>() {
+        Ok(n) => Ok(Duration::from_millis(n * scale)),
+        Err(_) => Err(format!("expected a duration like 500ms or 2s, got '{}'", s)),
+    }
+}
+
+// Stream lines from the transport to the output, reconnecting per `policy`
+pub fn stream(transport: &mut dyn Transport, policy: Reconnect) -> io::Result<()> {
+    let mut resume: Option<String> = None;
+    let mut failures = 0;
+    let mut bytes = Vec::new();
+    loop {
+        let mut delivered = false;
+        let result = match transport.connect(resume.as_deref()) {
+            Ok(stream) => {
+                let mut reader = BufReader::new(stream);
+                loop {
+                    if reader.buffer().is_empty() {
+                        output::flush();
+                    }
+                    bytes.clear();
+                    match reader.read_until(b'\n', &mut bytes) {
+                        Ok(0) => break,
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(_) => break,
+                    }
+                    // Remote sources pass bytes through as written, valid UTF-8 or not
+                    let mut line = String::from_utf8_lossy(&bytes).into_owned();
+                    if line.ends_with("\r\n") {
+                        line.pop();
+                        line.pop();
+                        line.push('\n');
+                    } else if !line.ends_with('\n') {
+                        line.push('\n');
+                    }
+                    if let Some(token) = &resume
+                        && transport.already_seen(&line, token)
+                    {
+                        continue;
+                    }
+                    if let Some(token) = transport.resume_token(&line) {
+                        resume = Some(token);
+                    }
+                    delivered = true;
+                    output::emit(&line);
+                }
+                output::flush();
+                transport.disconnect()
+            }
+            Err(e) => Err(e),
+        };
+
+        // A connection that got data through resets the count
+        if delivered {
+            failures = 0;
+        }
+        let reason = match result {
+            Ok(()) if !delivered => "stream ended without data".to_string(),
+            Ok(()) => "stream ended".to_string(),
+            Err(e) => e.to_string(),
+        };
+        if failures >= policy.max_attempts {
+            return Err(io::Error::other(format!("{}: {}; giving up", transport.describe(), reason)));
+        }
+        let delay = (policy.backoff * 2u32.pow(failures.min(16))).min(MAX_BACKOFF.max(policy.backoff));
+        failures += 1;
+        output::status(format_args!(
+            "\n--- {}: {}; reconnecting in {:?} (attempt {}/{}) ---\n",
+            transport.describe(), reason, delay, failures, policy.max_attempts
+        ));
+        thread::sleep(delay);
+    }
+}
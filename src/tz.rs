@@ -0,0 +1,399 @@
+// Local time for schedules like --active-hours. std only knows UTC, so this reads the
+// system time zone itself: $TZ if set (a zone name, a path, or a POSIX rule string),
+// otherwise /etc/localtime, in the TZif format tzdata installs. Times past the end of
+// a file's transition table (and "slim" tzdata files that have almost none) use the
+// POSIX rule in the file's footer.
+
+use std::env;
+use std::fs;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LocalTime {
+    // 0 = Monday
+    pub weekday: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct Zone {
+    // (UTC time the offset takes effect, offset in seconds east of UTC)
+    transitions: Vec<(i64, i32)>,
+    // Before the first transition
+    initial: i32,
+    rule: Option<Rule>,
+}
+
+#[derive(Debug)]
+struct Rule {
+    std_offset: i32,
+    dst: Option<(i32, Date, i32, Date, i32)>,
+}
+
+// When a DST switch happens
+#[derive(Clone, Copy, Debug)]
+enum Date {
+    // Mm.w.d: weekday d (0 = Sunday) of week w (5 = last) of month m
+    MonthWeekDay(u32, u32, u32),
+    // Jn: day 1..365, February 29th never counted
+    Julian(u32),
+    // n: day 0..365, counting February 29th in leap years
+    Day(u32),
+}
+
+static LOCAL: OnceLock<Zone> = OnceLock::new();
+
+pub fn local_zone() -> &'static Zone {
+    LOCAL.get_or_init(|| {
+        let tz = env::var("TZ").ok().filter(|s| !s.is_empty());
+        match tz {
+            Some(spec) => {
+                let name = spec.strip_prefix(':').unwrap_or(&spec);
+                let path = if name.starts_with('/') { name.to_string() } else { format!("/usr/share/zoneinfo/{}", name) };
+                match fs::read(&path).ok().and_then(|data| Zone::from_tzif(&data)) {
+                    Some(zone) => zone,
+                    None => Zone { rule: parse_rule(name), ..Zone::default() },
+                }
+            }
+            None => fs::read("/etc/localtime").ok().and_then(|data| Zone::from_tzif(&data)).unwrap_or_default(),
+        }
+    })
+}
+
+pub fn now() -> LocalTime {
+    let utc = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() as i64);
+    local_zone().local_time(utc)
+}
+
+impl Zone {
+    fn from_tzif(data: &[u8]) -> Option<Zone> {
+        if data.get(..4)? != b"TZif" {
+            return None;
+        }
+        let version = data[4];
+        // Version 2+ repeats the data with 64-bit times after the 32-bit block; use that
+        let (start, time_size) = if version >= b'2' { (tzif_block_end(data, 0, 4)?, 8) } else { (0, 4) };
+        let [isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = tzif_counts(data, start)?;
+
+        let times_at = start + 44;
+        let idx_at = times_at + timecnt * time_size;
+        let types_at = idx_at + timecnt;
+        let read_int = |at: usize, size: usize| -> Option<i64> {
+            let bytes = data.get(at..at + size)?;
+            Some(if size == 8 {
+                i64::from_be_bytes(bytes.try_into().ok()?)
+            } else {
+                i32::from_be_bytes(bytes.try_into().ok()?) as i64
+            })
+        };
+        let offset_of = |t: usize| -> Option<i32> { Some(read_int(types_at + t * 6, 4)? as i32) };
+
+        let mut transitions = Vec::with_capacity(timecnt);
+        for i in 0..timecnt {
+            let at = read_int(times_at + i * time_size, time_size)?;
+            let t = *data.get(idx_at + i)? as usize;
+            if t >= typecnt {
+                return None;
+            }
+            transitions.push((at, offset_of(t)?));
+        }
+        let initial = offset_of(0)?;
+
+        // Followed by "\n<POSIX rule>\n"
+        let rule = if version >= b'2' {
+            let end = types_at + typecnt * 6 + charcnt + leapcnt * 12 + isstdcnt + isutcnt;
+            data.get(end..)
+                .and_then(|tail| std::str::from_utf8(tail).ok())
+                .and_then(|tail| tail.trim_matches('\n').lines().next().and_then(parse_rule))
+        } else {
+            None
+        };
+        Some(Zone { transitions, initial, rule })
+    }
+
+    pub fn offset_at(&self, utc: i64) -> i32 {
+        match (self.transitions.first(), self.transitions.last()) {
+            (Some(&(first, _)), _) if utc < first => self.initial,
+            (_, Some(&(last, _))) if utc < last || self.rule.is_none() => {
+                let i = self.transitions.partition_point(|&(t, _)| t <= utc);
+                self.transitions[i - 1].1
+            }
+            _ => self.rule.as_ref().map_or(self.initial, |rule| rule.offset_at(utc)),
+        }
+    }
+
+    pub fn local_time(&self, utc: i64) -> LocalTime {
+        let local = utc + self.offset_at(utc) as i64;
+        let days = local.div_euclid(86400);
+        let secs = local.rem_euclid(86400) as u32;
+        LocalTime {
+            // 1970-01-01 was a Thursday
+            weekday: (days + 3).rem_euclid(7) as u32,
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+        }
+    }
+}
+
+// isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt from the header at `start`
+fn tzif_counts(data: &[u8], start: usize) -> Option<[usize; 6]> {
+    let mut counts = [0usize; 6];
+    for (i, count) in counts.iter_mut().enumerate() {
+        let at = start + 20 + i * 4;
+        *count = u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as usize;
+    }
+    Some(counts)
+}
+
+// Where the header and data block starting at `start` end
+fn tzif_block_end(data: &[u8], start: usize, time_size: usize) -> Option<usize> {
+    let [isutcnt, isstdcnt, leapcnt, timecnt, typecnt, charcnt] = tzif_counts(data, start)?;
+    let len = timecnt * time_size + timecnt + typecnt * 6 + charcnt + leapcnt * (time_size + 4) + isstdcnt + isutcnt;
+    Some(start + 44 + len)
+}
+
+impl Rule {
+    fn offset_at(&self, utc: i64) -> i32 {
+        let Some((dst_offset, start, start_time, end, end_time)) = self.dst else {
+            return self.std_offset;
+        };
+        // Rules are written in local time: the start in standard time, the end in DST
+        let year = civil_year(utc + self.std_offset as i64);
+        let begins = day_of(year, start) * 86400 + start_time as i64 - self.std_offset as i64;
+        let ends = day_of(year, end) * 86400 + end_time as i64 - dst_offset as i64;
+        let in_dst = if begins < ends {
+            utc >= begins && utc < ends
+        } else {
+            // Southern hemisphere: DST spans the new year
+            utc >= begins || utc < ends
+        };
+        if in_dst { dst_offset } else { self.std_offset }
+    }
+}
+
+// "EST5EDT,M3.2.0,M11.1.0", "<+0330>-3:30", "CET-1CEST,M3.5.0,M10.5.0/3" …
+fn parse_rule(s: &str) -> Option<Rule> {
+    let mut rest = s;
+    skip_name(&mut rest)?;
+    // POSIX offsets count hours west of UTC; we keep seconds east
+    let std_offset = -parse_offset(&mut rest)?;
+    if rest.is_empty() {
+        return Some(Rule { std_offset, dst: None });
+    }
+    skip_name(&mut rest)?;
+    let dst_offset = if rest.starts_with(',') || rest.is_empty() { std_offset + 3600 } else { -parse_offset(&mut rest)? };
+    // No rule given: the traditional US one
+    let (start, start_time, end, end_time) = if rest.is_empty() {
+        (Date::MonthWeekDay(3, 2, 0), 7200, Date::MonthWeekDay(11, 1, 0), 7200)
+    } else {
+        let rest = rest.strip_prefix(',')?;
+        let (start, end) = rest.split_once(',')?;
+        let (start, start_time) = parse_date(start)?;
+        let (end, end_time) = parse_date(end)?;
+        (start, start_time, end, end_time)
+    };
+    Some(Rule { std_offset, dst: Some((dst_offset, start, start_time, end, end_time)) })
+}
+
+fn skip_name(rest: &mut &str) -> Option<()> {
+    let len = if let Some(quoted) = rest.strip_prefix('<') {
+        quoted.find('>')? + 2
+    } else {
+        rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len())
+    };
+    if len < 3 {
+        return None;
+    }
+    *rest = &rest[len..];
+    Some(())
+}
+
+// [+-]hh[:mm[:ss]] in seconds
+fn parse_offset(rest: &mut &str) -> Option<i32> {
+    let end = rest.find(|c: char| !(c.is_ascii_digit() || c == ':' || c == '+' || c == '-')).unwrap_or(rest.len());
+    let (text, tail) = rest.split_at(end);
+    *rest = tail;
+    let (sign, text) = match text.strip_prefix('-') {
+        Some(t) => (-1, t),
+        None => (1, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let mut secs = 0;
+    for (i, part) in text.split(':').enumerate() {
+        let n: i32 = part.parse().ok()?;
+        secs += n * [3600, 60, 1].get(i)?;
+    }
+    Some(sign * secs)
+}
+
+fn parse_date(s: &str) -> Option<(Date, i32)> {
+    let (date, time) = match s.split_once('/') {
+        Some((d, t)) => (d, parse_offset(&mut { t })?),
+        None => (s, 7200),
+    };
+    let date = if let Some(mwd) = date.strip_prefix('M') {
+        let mut parts = mwd.split('.').map(|p| p.parse::<u32>().ok());
+        Date::MonthWeekDay(parts.next()??, parts.next()??, parts.next()??)
+    } else if let Some(n) = date.strip_prefix('J') {
+        Date::Julian(n.parse().ok()?)
+    } else {
+        Date::Day(date.parse().ok()?)
+    };
+    Some((date, time))
+}
+
+fn is_leap(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+// Days since 1970-01-01 for a civil date (Howard Hinnant's algorithm)
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_year(secs: i64) -> i64 {
+    civil_date(secs.div_euclid(86400)).0
+}
+
+// The year, month and day `days` after 1970-01-01; the inverse of days_from_civil
+pub fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (yoe + era * 400 + if month <= 2 { 1 } else { 0 }, month, day)
+}
+
+// Days since the epoch of a rule date in `year`
+fn day_of(year: i64, date: Date) -> i64 {
+    let jan1 = days_from_civil(year, 1, 1);
+    match date {
+        Date::Julian(n) => {
+            let n = n as i64;
+            jan1 + n - 1 + if is_leap(year) && n >= 60 { 1 } else { 0 }
+        }
+        Date::Day(n) => jan1 + n as i64,
+        Date::MonthWeekDay(month, week, weekday) => {
+            let first = days_from_civil(year, month, 1);
+            // 1970-01-01 was a Thursday (4 with Sunday = 0)
+            let first_weekday = (first + 4).rem_euclid(7);
+            let mut day = first + (weekday as i64 - first_weekday).rem_euclid(7) + (week as i64 - 1) * 7;
+            let next_month = if month == 12 { days_from_civil(year + 1, 1, 1) } else { days_from_civil(year, month + 1, 1) };
+            while day >= next_month {
+                day -= 7;
+            }
+            day
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zone(rule: &str) -> Zone {
+        Zone { rule: Some(parse_rule(rule).unwrap()), ..Zone::default() }
+    }
+
+    // Seconds since the epoch of a UTC date and time
+    fn utc(year: i64, month: u32, day: u32, hour: i64) -> i64 {
+        days_from_civil(year, month, day) * 86400 + hour * 3600
+    }
+
+    #[test]
+    fn us_dst_transitions() {
+        let zone = zone("EST5EDT,M3.2.0,M11.1.0");
+        // 2024-03-10 02:00 EST and 2024-11-03 02:00 EDT
+        let begins = utc(2024, 3, 10, 7);
+        let ends = utc(2024, 11, 3, 6);
+        assert_eq!(zone.offset_at(begins - 1), -5 * 3600);
+        assert_eq!(zone.offset_at(begins), -4 * 3600);
+        assert_eq!(zone.offset_at(ends - 1), -4 * 3600);
+        assert_eq!(zone.offset_at(ends), -5 * 3600);
+        assert_eq!(zone.local_time(begins - 1), LocalTime { weekday: 6, hour: 1, minute: 59, second: 59 });
+        assert_eq!(zone.local_time(begins), LocalTime { weekday: 6, hour: 3, minute: 0, second: 0 });
+    }
+
+    #[test]
+    fn european_dst_transitions() {
+        let zone = zone("CET-1CEST,M3.5.0,M10.5.0/3");
+        // The last Sundays of March and October 2024, at 01:00 UTC
+        assert_eq!(zone.offset_at(utc(2024, 3, 31, 1) - 1), 3600);
+        assert_eq!(zone.offset_at(utc(2024, 3, 31, 1)), 7200);
+        assert_eq!(zone.offset_at(utc(2024, 10, 27, 1) - 1), 7200);
+        assert_eq!(zone.offset_at(utc(2024, 10, 27, 1)), 3600);
+    }
+
+    #[test]
+    fn southern_dst_spans_the_new_year() {
+        let zone = zone("AEST-10AEDT,M10.1.0,M4.1.0/3");
+        // 2024-04-07 03:00 AEDT and 2024-10-06 02:00 AEST
+        assert_eq!(zone.offset_at(utc(2024, 4, 6, 16) - 1), 11 * 3600);
+        assert_eq!(zone.offset_at(utc(2024, 4, 6, 16)), 10 * 3600);
+        assert_eq!(zone.offset_at(utc(2024, 10, 5, 16) - 1), 10 * 3600);
+        assert_eq!(zone.offset_at(utc(2024, 10, 5, 16)), 11 * 3600);
+        assert_eq!(zone.offset_at(utc(2025, 1, 1, 0)), 11 * 3600);
+    }
+
+    #[test]
+    fn rules_without_dst() {
+        assert_eq!(zone("UTC0").offset_at(0), 0);
+        assert_eq!(zone("<+0330>-3:30").offset_at(0), 3 * 3600 + 30 * 60);
+        assert_eq!(zone("<-03>3").offset_at(0), -3 * 3600);
+        assert!(parse_rule("X5").is_none());
+    }
+
+    #[test]
+    fn tzif_transitions_then_footer_rule() {
+        // Version 2: an empty 32-bit block, then 64-bit transitions into EDT and back in
+        // 2023, then the rule for later years
+        let mut data = b"TZif2".to_vec();
+        data.resize(44, 0);
+        let header = |data: &mut Vec<u8>, counts: [u32; 6]| {
+            data.extend(b"TZif2");
+            data.resize(data.len() + 15, 0);
+            counts.iter().for_each(|count| data.extend(count.to_be_bytes()));
+        };
+        header(&mut data, [0, 0, 0, 2, 2, 8]);
+        data.extend(utc(2023, 3, 12, 7).to_be_bytes());
+        data.extend(utc(2023, 11, 5, 6).to_be_bytes());
+        data.extend([1, 0]);
+        data.extend((-18000i32).to_be_bytes());
+        data.extend([0, 0]);
+        data.extend((-14400i32).to_be_bytes());
+        data.extend([1, 4]);
+        data.extend(b"EST\0EDT\0");
+        data.extend(b"\nEST5EDT,M3.2.0,M11.1.0\n");
+        let zone = Zone::from_tzif(&data).unwrap();
+        assert_eq!(zone.offset_at(utc(2023, 1, 1, 0)), -18000);
+        assert_eq!(zone.offset_at(utc(2023, 7, 1, 0)), -14400);
+        assert_eq!(zone.offset_at(utc(2023, 12, 1, 0)), -18000);
+        assert_eq!(zone.offset_at(utc(2024, 7, 1, 0)), -14400);
+        assert!(Zone::from_tzif(b"TZjunk").is_none());
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+        assert_eq!(days_from_civil(2000, 3, 1) - days_from_civil(2000, 2, 28), 2);
+        assert_eq!(days_from_civil(1900, 3, 1) - days_from_civil(1900, 2, 28), 1);
+        for days in [-800_000, -1, 0, 11_016, 19_782, 2_932_896] {
+            let (year, month, day) = civil_date(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+}
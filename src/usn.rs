@@ -0,0 +1,252 @@
+// `--usn-journal` (Windows): watch followed files through the NTFS change journal
+// instead of a change notification per file. One thread per volume reads the journal,
+// which records every create, rename, delete and write on the volume, and wakes the
+// followers whose file (directory and name) a record is about. An agent tailing
+// hundreds of IIS site logs then has one reader per volume, not a watch per file.
+//
+// NTFS writes a record for a file the first time each kind of change happens after it
+// is opened, and again when it is closed; a writer that keeps its file open and keeps
+// appending (as IIS does) causes one wakeup, after which the follow loop still checks
+// every watch::MAX_WAIT. Renames, creates and deletes always show up at once.
+//
+// Reading the journal needs a handle to the volume, which takes administrator rights,
+// and a volume with a journal (NTFS and ReFS; only NTFS-style records are read). Where
+// that fails rail says so once and uses change notifications.
+
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::thread;
+use std::time::Duration;
+
+use winapi::shared::minwindef::{DWORD, LPVOID};
+use winapi::um::fileapi::{BY_HANDLE_FILE_INFORMATION, CreateFileW, GetFileInformationByHandle, OPEN_EXISTING};
+use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
+use winapi::um::ioapiset::DeviceIoControl;
+use winapi::um::winbase::FILE_FLAG_BACKUP_SEMANTICS;
+use winapi::um::winnt::{FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, HANDLE};
+
+const FSCTL_QUERY_USN_JOURNAL: DWORD = 0x000900f4;
+const FSCTL_READ_USN_JOURNAL: DWORD = 0x000900bb;
+const USN_REASON_DATA_OVERWRITE: u32 = 0x1;
+const USN_REASON_DATA_EXTEND: u32 = 0x2;
+const USN_REASON_DATA_TRUNCATION: u32 = 0x4;
+const USN_REASON_FILE_CREATE: u32 = 0x100;
+const USN_REASON_FILE_DELETE: u32 = 0x200;
+const USN_REASON_RENAME_OLD_NAME: u32 = 0x1000;
+const USN_REASON_RENAME_NEW_NAME: u32 = 0x2000;
+const USN_REASON_CLOSE: u32 = 0x80000000;
+const REASONS: u32 = USN_REASON_DATA_OVERWRITE | USN_REASON_DATA_EXTEND | USN_REASON_DATA_TRUNCATION
+    | USN_REASON_FILE_CREATE | USN_REASON_FILE_DELETE | USN_REASON_RENAME_OLD_NAME | USN_REASON_RENAME_NEW_NAME
+    | USN_REASON_CLOSE;
+const SETTLE: Duration = Duration::from_millis(20);
+// After the journal can't be read (e.g. it was deleted and recreated), before trying
+// again
+const RETRY: Duration = Duration::from_secs(1);
+
+#[repr(C)]
+struct ReadUsnJournalData {
+    start_usn: i64,
+    reason_mask: u32,
+    return_only_on_close: u32,
+    timeout: u64,
+    bytes_to_wait_for: u64,
+    usn_journal_id: u64,
+}
+
+#[derive(Default)]
+struct Signal {
+    changed: Mutex<bool>,
+    condvar: Condvar,
+}
+
+// A follower's file, as the journal names it
+struct Entry {
+    parent: u64,
+    name: String,
+    signal: Weak<Signal>,
+}
+
+struct Handle(HANDLE);
+
+// The volume handle is only used by its reader thread
+unsafe impl Send for Handle {}
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        unsafe { CloseHandle(self.0) };
+    }
+}
+
+type Entries = Arc<Mutex<Vec<Entry>>>;
+
+// The volumes being read ("C:") and the files followed on each
+static VOLUMES: Mutex<Vec<(String, Entries)>> = Mutex::new(Vec::new());
+
+pub struct Waiter {
+    signal: Arc<Signal>,
+}
+
+impl Waiter {
+    // None if the journal of `dir`'s volume can't be read
+    pub fn new(dir: &Path, name: &OsStr) -> Option<Waiter> {
+        // canonicalize gives \\?\C:\...; shares have no journal we could read
+        let dir = std::fs::canonicalize(dir).ok()?;
+        let volume = dir.as_os_str().to_string_lossy().strip_prefix(r"\\?\")?.get(..2)?.to_string();
+        if !volume.ends_with(':') {
+            return None;
+        }
+        let parent = file_reference(&dir)?;
+        let entries = entries(&volume)?;
+        let signal = Arc::new(Signal::default());
+        entries.lock().unwrap().push(Entry {
+            parent,
+            name: name.to_string_lossy().to_lowercase(),
+            signal: Arc::downgrade(&signal),
+        });
+        Some(Waiter { signal })
+    }
+
+    // Return once the journal has said something about the file, or after `timeout`
+    pub fn wait(&mut self, timeout: Duration) {
+        let changed = self.signal.changed.lock().unwrap();
+        let (mut changed, _) = self.signal.condvar.wait_timeout_while(changed, timeout, |changed| !*changed).unwrap();
+        if *changed {
+            *changed = false;
+            drop(changed);
+            // A rotation is a rename and a create: let it finish, so the path isn't
+            // checked while nothing is there
+            thread::sleep(SETTLE);
+        }
+    }
+}
+
+// The followed files on `volume`, starting its reader if there is none yet
+fn entries(volume: &str) -> Option<Entries> {
+    let mut volumes = VOLUMES.lock().unwrap();
+    if let Some((_, entries)) = volumes.iter().find(|(v, _)| v == volume) {
+        return Some(entries.clone());
+    }
+    let handle = open(&format!(r"\\.\{}", volume), GENERIC_READ, 0)?;
+    let (journal_id, next) = query(&handle)?;
+    let entries = Arc::new(Mutex::new(Vec::new()));
+    let shared = entries.clone();
+    thread::spawn(move || reader(handle, journal_id, next, shared));
+    volumes.push((volume.to_string(), entries.clone()));
+    Some(entries)
+}
+
+fn open(path: &str, access: DWORD, flags: DWORD) -> Option<Handle> {
+    let wide: Vec<u16> = OsStr::new(path).encode_wide().chain(Some(0)).collect();
+    let share = FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE;
+    let handle = unsafe { CreateFileW(wide.as_ptr(), access, share, ptr::null_mut(), OPEN_EXISTING, flags, ptr::null_mut()) };
+    if handle == INVALID_HANDLE_VALUE { None } else { Some(Handle(handle)) }
+}
+
+// The directory's file reference number, which journal records name parents by
+fn file_reference(dir: &Path) -> Option<u64> {
+    let handle = open(&dir.to_string_lossy(), 0, FILE_FLAG_BACKUP_SEMANTICS)?;
+    let mut info: BY_HANDLE_FILE_INFORMATION = unsafe { std::mem::zeroed() };
+    if unsafe { GetFileInformationByHandle(handle.0, &mut info) } == 0 {
+        return None;
+    }
+    Some(((info.nFileIndexHigh as u64) << 32) | info.nFileIndexLow as u64)
+}
+
+// The journal's ID and the USN the next record will get
+fn query(volume: &Handle) -> Option<(u64, i64)> {
+    // USN_JOURNAL_DATA_V0, V1 or V2; they all start UsnJournalID, FirstUsn, NextUsn
+    let mut data = [0u8; 80];
+    let mut returned: DWORD = 0;
+    let ok = unsafe {
+        DeviceIoControl(
+            volume.0,
+            FSCTL_QUERY_USN_JOURNAL,
+            ptr::null_mut(),
+            0,
+            data.as_mut_ptr() as LPVOID,
+            data.len() as DWORD,
+            &mut returned,
+            ptr::null_mut(),
+        )
+    };
+    if ok == 0 || returned < 24 {
+        return None;
+    }
+    let id = u64::from_le_bytes(data[0..8].try_into().unwrap());
+    let next = i64::from_le_bytes(data[16..24].try_into().unwrap());
+    Some((id, next))
+}
+
+fn reader(volume: Handle, mut journal_id: u64, mut next: i64, entries: Entries) {
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let mut request = ReadUsnJournalData {
+            start_usn: next,
+            reason_mask: REASONS,
+            return_only_on_close: 0,
+            timeout: 0,
+            // Block until there is something to read
+            bytes_to_wait_for: 1,
+            usn_journal_id: journal_id,
+        };
+        let mut returned: DWORD = 0;
+        let ok = unsafe {
+            DeviceIoControl(
+                volume.0,
+                FSCTL_READ_USN_JOURNAL,
+                &mut request as *mut ReadUsnJournalData as LPVOID,
+                std::mem::size_of::<ReadUsnJournalData>() as DWORD,
+                buffer.as_mut_ptr() as LPVOID,
+                buffer.len() as DWORD,
+                &mut returned,
+                ptr::null_mut(),
+            )
+        };
+        if ok == 0 || returned < 8 {
+            // Records we hadn't read were dropped, or the journal was replaced: start
+            // over at its end. Followers' waits time out meanwhile
+            thread::sleep(RETRY);
+            if let Some((id, end)) = query(&volume) {
+                journal_id = id;
+                next = end;
+            }
+            continue;
+        }
+        next = i64::from_le_bytes(buffer[0..8].try_into().unwrap());
+        notify(&buffer[8..returned as usize], &entries);
+    }
+}
+
+// Wake the followers of the files the USN_RECORD_V2 records in `records` are about
+fn notify(records: &[u8], entries: &Mutex<Vec<Entry>>) {
+    let mut entries = entries.lock().unwrap();
+    entries.retain(|entry| entry.signal.strong_count() > 0);
+    let mut at = 0;
+    while at + 60 <= records.len() {
+        let record = &records[at..];
+        let length = u32::from_le_bytes(record[0..4].try_into().unwrap()) as usize;
+        if length < 60 || at + length > records.len() {
+            break;
+        }
+        let major = u16::from_le_bytes(record[4..6].try_into().unwrap());
+        if major == 2 {
+            let parent = u64::from_le_bytes(record[16..24].try_into().unwrap());
+            let name_length = u16::from_le_bytes(record[56..58].try_into().unwrap()) as usize;
+            let name_offset = u16::from_le_bytes(record[58..60].try_into().unwrap()) as usize;
+            if let Some(name) = record.get(name_offset..name_offset + name_length) {
+                let units: Vec<u16> = name.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+                let name = String::from_utf16_lossy(&units).to_lowercase();
+                for entry in entries.iter().filter(|e| e.parent == parent && e.name == name) {
+                    if let Some(signal) = entry.signal.upgrade() {
+                        *signal.changed.lock().unwrap() = true;
+                        signal.condvar.notify_all();
+                    }
+                }
+            }
+        }
+        at += length;
+    }
+}
@@ -0,0 +1,526 @@
+// Waiting for the followed file to change, instead of checking it every 100ms. While
+// idle the follow loop sleeps until the OS reports a change to the file (or to its name,
+// for rotations), so many idle files cost no wakeups and appended lines show up at once.
+//
+// The directory is watched rather than the file, so a new file created at the path
+// after a rotation is noticed too. Linux uses inotify, Windows change notifications, and
+// macOS, FreeBSD and OpenBSD kqueue. Elsewhere, and on network filesystems (NFS, SMB)
+// where changes made by other machines raise no events, the loop keeps polling. Even with
+// a watch the file is checked at least every MAX_WAIT, so an event that never comes only
+// delays things. (kqueue platforms don't tell network mounts apart, so there a change
+// made by another machine shows up within MAX_WAIT.)
+//
+// On Windows, `--usn-journal` reads the volume's change journal instead (see usn.rs).
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+pub const MAX_WAIT: Duration = Duration::from_secs(1);
+
+static USN_JOURNAL: AtomicBool = AtomicBool::new(false);
+
+pub struct Watch(sys::Watch);
+
+pub fn set_usn_journal() {
+    USN_JOURNAL.store(true, Ordering::Relaxed);
+}
+
+#[cfg(windows)]
+fn usn_journal() -> bool {
+    USN_JOURNAL.load(Ordering::Relaxed)
+}
+
+impl Watch {
+    // None where changes can't be watched for; the caller polls instead
+    pub fn new(path: &str) -> Option<Watch> {
+        let path = Path::new(path);
+        let name = path.file_name()?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        sys::Watch::new(dir, name).map(Watch)
+    }
+
+    // Return once the file may have changed, or after `timeout`
+    pub fn wait(&mut self, timeout: Duration) {
+        self.0.wait(timeout);
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use std::ffi::{CString, OsStr, OsString};
+    use std::fs::File;
+    use std::io::Read;
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::raw::{c_char, c_int, c_long, c_ulong};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const IN_NONBLOCK: c_int = 0o4000;
+    const IN_CLOEXEC: c_int = 0o2000000;
+    const IN_MODIFY: u32 = 0x2;
+    const IN_ATTRIB: u32 = 0x4;
+    const IN_CLOSE_WRITE: u32 = 0x8;
+    const IN_MOVED_FROM: u32 = 0x40;
+    const IN_MOVED_TO: u32 = 0x80;
+    const IN_CREATE: u32 = 0x100;
+    const IN_DELETE: u32 = 0x200;
+    const IN_DELETE_SELF: u32 = 0x400;
+    const IN_MOVE_SELF: u32 = 0x800;
+    const POLLIN: i16 = 0x1;
+    const SETTLE: Duration = Duration::from_millis(20);
+    // struct inotify_event without its name
+    const EVENT_HEADER: usize = 16;
+    // f_type values of filesystems whose changes may come from other machines
+    const NETWORK_FILESYSTEMS: [c_long; 5] = [
+        0x6969,              // NFS
+        0x517b,              // SMB
+        0xff534d42u32 as _,  // CIFS
+        0xfe534d42u32 as _,  // SMB2
+        0x0bd00bd0,          // Lustre
+    ];
+
+    #[repr(C)]
+    struct PollFd {
+        fd: c_int,
+        events: i16,
+        revents: i16,
+    }
+
+    unsafe extern "C" {
+        fn inotify_init1(flags: c_int) -> c_int;
+        fn inotify_add_watch(fd: c_int, path: *const c_char, mask: u32) -> c_int;
+        fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+        fn statfs(path: *const c_char, buf: *mut c_long) -> c_int;
+    }
+
+    pub struct Watch {
+        inotify: File,
+        name: OsString,
+    }
+
+    impl Watch {
+        pub fn new(dir: &Path, name: &OsStr) -> Option<Watch> {
+            let dir = CString::new(dir.as_os_str().as_bytes()).ok()?;
+            if is_network(&dir) {
+                return None;
+            }
+            let fd = unsafe { inotify_init1(IN_NONBLOCK | IN_CLOEXEC) };
+            if fd < 0 {
+                return None;
+            }
+            let inotify = File::from(unsafe { OwnedFd::from_raw_fd(fd) });
+            let mask = IN_MODIFY | IN_ATTRIB | IN_CLOSE_WRITE | IN_MOVED_FROM | IN_MOVED_TO | IN_CREATE
+                | IN_DELETE | IN_DELETE_SELF | IN_MOVE_SELF;
+            if unsafe { inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+                return None;
+            }
+            Some(Watch { inotify, name: name.to_os_string() })
+        }
+
+        pub fn wait(&mut self, timeout: Duration) {
+            let mut fds = PollFd { fd: self.inotify.as_raw_fd(), events: POLLIN, revents: 0 };
+            let mut left = timeout.as_millis() as i64;
+            let started = Instant::now();
+            // Events for other files in the directory don't count
+            while left > 0 {
+                if unsafe { poll(&mut fds, 1, left as c_int) } <= 0 {
+                    return;
+                }
+                if !self.drain() {
+                    // A rotation is a rename and a create: let it finish, so the path
+                    // isn't checked while nothing is there
+                    thread::sleep(SETTLE);
+                    self.drain();
+                    return;
+                }
+                left = timeout.as_millis() as i64 - started.elapsed().as_millis() as i64;
+            }
+        }
+
+        // Read the pending events; true if none of them was about our file (or the
+        // directory itself)
+        fn drain(&mut self) -> bool {
+            let mut buffer = [0u8; 4096];
+            let mut unrelated = true;
+            while let Ok(n) = self.inotify.read(&mut buffer) {
+                if related(&buffer[..n], &self.name) {
+                    unrelated = false;
+                }
+            }
+            unrelated
+        }
+    }
+
+    // Whether any of the events read is about `name`, or has no name: one about the
+    // directory itself, or IN_Q_OVERFLOW (events were lost, so ours may have been)
+    pub(super) fn related(events: &[u8], name: &OsStr) -> bool {
+        let mut related = false;
+        let mut at = 0;
+        while at + EVENT_HEADER <= events.len() {
+            let len = u32::from_ne_bytes(events[at + 12..at + 16].try_into().unwrap()) as usize;
+            let n = events.len();
+            let event_name = &events[(at + EVENT_HEADER).min(n)..(at + EVENT_HEADER + len).min(n)];
+            let event_name = &event_name[..event_name.iter().position(|&b| b == 0).unwrap_or(event_name.len())];
+            if event_name.is_empty() || event_name == name.as_bytes() {
+                related = true;
+            }
+            at += EVENT_HEADER + len;
+        }
+        related
+    }
+
+    // struct statfs starts with f_type; the buffer is larger than the whole struct
+    fn is_network(dir: &CString) -> bool {
+        let mut buf = [0 as c_long; 32];
+        let ok = unsafe { statfs(dir.as_ptr(), buf.as_mut_ptr()) } == 0;
+        ok && NETWORK_FILESYSTEMS.contains(&buf[0])
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use std::ffi::OsStr;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+    use std::sync::Once;
+    use std::time::Duration;
+
+    use winapi::shared::winerror::WAIT_TIMEOUT;
+    use winapi::um::fileapi::{FindCloseChangeNotification, FindFirstChangeNotificationW, FindNextChangeNotification, GetDriveTypeW};
+    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+    use winapi::um::synchapi::WaitForSingleObject;
+    use winapi::um::winbase::DRIVE_REMOTE;
+    use winapi::um::winnt::{
+        FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE, FILE_NOTIFY_CHANGE_SIZE, HANDLE,
+    };
+
+    use crate::usn;
+
+    pub enum Watch {
+        Notification(HANDLE),
+        Journal(usn::Waiter),
+    }
+
+    impl Watch {
+        pub fn new(dir: &Path, name: &OsStr) -> Option<Watch> {
+            if super::usn_journal() {
+                if let Some(waiter) = usn::Waiter::new(dir, name) {
+                    return Some(Watch::Journal(waiter));
+                }
+                static WARNED: Once = Once::new();
+                WARNED.call_once(|| {
+                    eprintln!("Warning: Could not read the change journal for '{}' (it needs administrator rights and an NTFS volume); using change notifications", dir.display());
+                });
+            }
+            let dir = std::fs::canonicalize(dir).ok()?;
+            if is_network(&dir) {
+                return None;
+            }
+            let wide: Vec<u16> = dir.as_os_str().encode_wide().chain(Some(0)).collect();
+            let filter = FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_SIZE | FILE_NOTIFY_CHANGE_LAST_WRITE;
+            let handle = unsafe { FindFirstChangeNotificationW(wide.as_ptr(), 0, filter) };
+            if handle == INVALID_HANDLE_VALUE {
+                return None;
+            }
+            Some(Watch::Notification(handle))
+        }
+
+        // Change notifications don't say which file changed, so any change in the
+        // directory wakes us
+        pub fn wait(&mut self, timeout: Duration) {
+            match self {
+                Watch::Notification(handle) => {
+                    if unsafe { WaitForSingleObject(*handle, timeout.as_millis() as u32) } != WAIT_TIMEOUT {
+                        unsafe { FindNextChangeNotification(*handle) };
+                    }
+                }
+                Watch::Journal(waiter) => waiter.wait(timeout),
+            }
+        }
+    }
+
+    impl Drop for Watch {
+        fn drop(&mut self) {
+            if let Watch::Notification(handle) = self {
+                unsafe { FindCloseChangeNotification(*handle) };
+            }
+        }
+    }
+
+    // canonicalize gives \\?\UNC\server\share\... for shares and \\?\C:\... for drives,
+    // which may be mapped to a share
+    fn is_network(dir: &Path) -> bool {
+        let path = dir.as_os_str().to_string_lossy();
+        if path.starts_with(r"\\?\UNC\") {
+            return true;
+        }
+        let Some(root) = path.strip_prefix(r"\\?\").and_then(|p| p.get(..3)) else {
+            return false;
+        };
+        let wide: Vec<u16> = OsStr::new(root).encode_wide().chain(Some(0)).collect();
+        unsafe { GetDriveTypeW(wide.as_ptr()) == DRIVE_REMOTE }
+    }
+}
+
+// kqueue watches open files rather than paths: the directory, for names coming and
+// going, and the file itself, for writes (a directory's events don't cover changes to
+// its files' contents). After a rotation the file is opened again by name.
+#[cfg(any(target_os = "macos", target_os = "freebsd", target_os = "openbsd"))]
+mod sys {
+    use std::ffi::OsStr;
+    use std::fs::{File, OpenOptions};
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+    use std::os::raw::{c_int, c_long, c_void};
+    use std::path::{Path, PathBuf};
+    use std::ptr;
+    use std::thread;
+    use std::time::Duration;
+
+    const EVFILT_VNODE: i16 = -4;
+    const EV_ADD: u16 = 0x1;
+    const EV_CLEAR: u16 = 0x20;
+    const NOTE_DELETE: u32 = 0x1;
+    const NOTE_WRITE: u32 = 0x2;
+    const NOTE_EXTEND: u32 = 0x4;
+    const NOTE_ATTRIB: u32 = 0x8;
+    const NOTE_RENAME: u32 = 0x20;
+    const SETTLE: Duration = Duration::from_millis(20);
+
+    #[repr(C)]
+    struct Kevent {
+        ident: usize,
+        filter: i16,
+        flags: u16,
+        fflags: u32,
+        #[cfg(target_os = "openbsd")]
+        data: i64,
+        #[cfg(not(target_os = "openbsd"))]
+        data: isize,
+        udata: *mut c_void,
+        #[cfg(target_os = "freebsd")]
+        ext: [u64; 4],
+    }
+
+    #[repr(C)]
+    struct Timespec {
+        #[cfg(target_os = "openbsd")]
+        tv_sec: i64,
+        #[cfg(not(target_os = "openbsd"))]
+        tv_sec: c_long,
+        tv_nsec: c_long,
+    }
+
+    unsafe extern "C" {
+        fn kqueue() -> c_int;
+        fn kevent(kq: c_int, changes: *const Kevent, nchanges: c_int, events: *mut Kevent, nevents: c_int, timeout: *const Timespec) -> c_int;
+    }
+
+    pub struct Watch {
+        kqueue: OwnedFd,
+        // Kept open: closing a descriptor drops its kqueue registration
+        _dir: File,
+        path: PathBuf,
+        file: Option<File>,
+    }
+
+    impl Watch {
+        pub fn new(dir: &Path, name: &OsStr) -> Option<Watch> {
+            let fd = unsafe { kqueue() };
+            if fd < 0 {
+                return None;
+            }
+            let kqueue = unsafe { OwnedFd::from_raw_fd(fd) };
+            let dir_file = open(dir)?;
+            if !register(&kqueue, &dir_file, NOTE_WRITE | NOTE_DELETE | NOTE_RENAME) {
+                return None;
+            }
+            let mut watch = Watch { kqueue, _dir: dir_file, path: dir.join(name), file: None };
+            watch.watch_file();
+            Some(watch)
+        }
+
+        // (Re)register the file now at the path, if there is one
+        fn watch_file(&mut self) {
+            self.file = open(&self.path)
+                .filter(|file| register(&self.kqueue, file, NOTE_WRITE | NOTE_EXTEND | NOTE_ATTRIB | NOTE_DELETE | NOTE_RENAME));
+        }
+
+        pub fn wait(&mut self, timeout: Duration) {
+            if self.file.is_none() {
+                self.watch_file();
+            }
+            let timeout = Timespec { tv_sec: timeout.as_secs() as _, tv_nsec: timeout.subsec_nanos() as c_long };
+            let mut event = change(0, 0);
+            let n = unsafe { kevent(self.kqueue.as_raw_fd(), ptr::null(), 0, &mut event, 1, &timeout) };
+            if n <= 0 {
+                return;
+            }
+            let file_fd = self.file.as_ref().map(|f| f.as_raw_fd() as usize);
+            let moved = event.fflags & (NOTE_DELETE | NOTE_RENAME) != 0;
+            if Some(event.ident) != file_fd || moved {
+                // A name changed in the directory, or the file went away: let a rotation
+                // finish, then watch whatever is at the path now
+                thread::sleep(SETTLE);
+                self.watch_file();
+            }
+        }
+    }
+
+    fn open(path: &Path) -> Option<File> {
+        let mut options = OpenOptions::new();
+        options.read(true);
+        // O_EVTONLY: a descriptor for events only, which doesn't keep the volume from
+        // being unmounted
+        #[cfg(target_os = "macos")]
+        std::os::unix::fs::OpenOptionsExt::custom_flags(&mut options, 0x8000);
+        options.open(path).ok()
+    }
+
+    fn change(ident: usize, fflags: u32) -> Kevent {
+        Kevent {
+            ident,
+            filter: EVFILT_VNODE,
+            flags: EV_ADD | EV_CLEAR,
+            fflags,
+            data: 0,
+            udata: ptr::null_mut(),
+            #[cfg(target_os = "freebsd")]
+            ext: [0; 4],
+        }
+    }
+
+    fn register(kqueue: &OwnedFd, file: &File, fflags: u32) -> bool {
+        let change = change(file.as_raw_fd() as usize, fflags);
+        unsafe { kevent(kqueue.as_raw_fd(), &change, 1, ptr::null_mut(), 0, ptr::null()) == 0 }
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", windows, target_os = "macos", target_os = "freebsd", target_os = "openbsd")))]
+mod sys {
+    use std::ffi::OsStr;
+    use std::path::Path;
+    use std::time::Duration;
+
+    pub struct Watch;
+
+    impl Watch {
+        pub fn new(_dir: &Path, _name: &OsStr) -> Option<Watch> {
+            None
+        }
+
+        pub fn wait(&mut self, _timeout: Duration) {}
+    }
+}
+
+#[cfg(all(test, any(target_os = "linux", target_os = "android", windows, target_os = "macos", target_os = "freebsd", target_os = "openbsd")))]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::thread;
+    use std::time::Instant;
+
+    // A directory of its own for each test, with a file `app.log` in it
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rail-watch-{}-{}", std::process::id(), test));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("app.log"), "first\n").unwrap();
+        dir
+    }
+
+    // How long a wait for up to MAX_WAIT takes when `change` is made to the directory
+    // shortly after it starts
+    fn woken_after(dir: &Path, change: impl FnOnce(&Path) + Send + 'static) -> Duration {
+        let mut watch = Watch::new(dir.join("app.log").to_str().unwrap()).expect("a watch on a local directory");
+        let dir = dir.to_path_buf();
+        let changer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            change(&dir);
+        });
+        let started = Instant::now();
+        watch.wait(MAX_WAIT);
+        let waited = started.elapsed();
+        changer.join().unwrap();
+        waited
+    }
+
+    #[test]
+    fn wakes_on_append() {
+        let dir = scratch("append");
+        let waited = woken_after(&dir, |dir| {
+            let mut file = OpenOptions::new().append(true).open(dir.join("app.log")).unwrap();
+            file.write_all(b"second\n").unwrap();
+        });
+        assert!(waited < MAX_WAIT, "waited {:?}", waited);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wakes_on_rename() {
+        let dir = scratch("rename");
+        let waited = woken_after(&dir, |dir| {
+            fs::rename(dir.join("app.log"), dir.join("app.log.1")).unwrap();
+            fs::write(dir.join("app.log"), "new\n").unwrap();
+        });
+        assert!(waited < MAX_WAIT, "waited {:?}", waited);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn wakes_on_delete() {
+        let dir = scratch("delete");
+        let waited = woken_after(&dir, |dir| fs::remove_file(dir.join("app.log")).unwrap());
+        assert!(waited < MAX_WAIT, "waited {:?}", waited);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn waits_out_the_timeout_when_nothing_changes() {
+        let dir = scratch("idle");
+        let mut watch = Watch::new(dir.join("app.log").to_str().unwrap()).unwrap();
+        let started = Instant::now();
+        watch.wait(Duration::from_millis(200));
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn inotify_ignores_other_files_but_not_overflows() {
+        use std::ffi::OsStr;
+
+        // struct inotify_event: wd, mask, cookie, len, then the name padded to len
+        fn event(mask: u32, name: &str) -> Vec<u8> {
+            let len = if name.is_empty() { 0 } else { (name.len() + 1).next_multiple_of(16) };
+            let mut event = Vec::new();
+            event.extend(1i32.to_ne_bytes());
+            event.extend(mask.to_ne_bytes());
+            event.extend(0u32.to_ne_bytes());
+            event.extend((len as u32).to_ne_bytes());
+            event.extend(name.as_bytes());
+            event.resize(16 + len, 0);
+            event
+        }
+        const IN_MODIFY: u32 = 0x2;
+        const IN_Q_OVERFLOW: u32 = 0x4000;
+        let name = OsStr::new("app.log");
+        assert!(!sys::related(&event(IN_MODIFY, "other.log"), name));
+        assert!(sys::related(&event(IN_MODIFY, "app.log"), name));
+        assert!(sys::related(&[event(IN_MODIFY, "other.log"), event(IN_MODIFY, "app.log")].concat(), name));
+        assert!(sys::related(&event(IN_Q_OVERFLOW, ""), name));
+        assert!(!sys::related(&event(IN_MODIFY, "app.log.1"), name));
+
+        let dir = scratch("other");
+        let waited = woken_after(&dir, |dir| fs::write(dir.join("other.log"), "x\n").unwrap());
+        assert!(waited >= MAX_WAIT, "woken after {:?}", waited);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,50 @@
+// Defense against silent stalls while following: if the file at the path keeps growing
+// past our position but reads from our handle keep coming back empty, the handle is
+// wedged or points at a file that was rotated away in a way the rotation checks missed
+// (e.g. replaced by a new file that was already bigger than our position). After
+// STALL_TIMEOUT of that, the follow loop reopens the file.
+//
+// Incidents are counted so they show up in --crash-dir reports.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+pub const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+static INCIDENTS: AtomicU64 = AtomicU64::new(0);
+
+pub struct Watchdog {
+    // Time since following started, when the stall began
+    stalled_since: Option<Duration>,
+}
+
+impl Watchdog {
+    pub fn new() -> Watchdog {
+        Watchdog { stalled_since: None }
+    }
+
+    // Called after a read found nothing new, with the time since following started; true
+    // once the stall has lasted long enough to reopen
+    pub fn idle(&mut self, pos: u64, path_len: u64, now: Duration) -> bool {
+        if path_len <= pos {
+            self.stalled_since = None;
+            return false;
+        }
+        let since = *self.stalled_since.get_or_insert(now);
+        if now - since < STALL_TIMEOUT {
+            return false;
+        }
+        self.stalled_since = None;
+        INCIDENTS.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    // Called when a read made progress
+    pub fn clear(&mut self) {
+        self.stalled_since = None;
+    }
+}
+
+pub fn incidents() -> u64 {
+    INCIDENTS.load(Ordering::Relaxed)
+}
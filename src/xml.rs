@@ -0,0 +1,200 @@
+// XML event logs (`--xml-record <element>`): each <element>...</element> is one record,
+// however many lines it spans. The record's attributes become fields under their own
+// names, the text of each child element a field named by its path below the record
+// ("source.host"), and a child's attributes "path@name". Text directly inside the
+// record element is the "text" field.
+//
+//   <event level="warn"><source host="db1">pg</source><msg>slow query</msg></event>
+//   -> level=warn source@host=db1 source=pg msg="slow query"
+//
+// Entities, CDATA sections and comments are understood; namespaces are not (a prefixed
+// name is just a name). Text on the same lines before a record's start tag or after
+// its end tag is dropped.
+
+use crate::fields::Record;
+
+// Where `<element` starts in `line`
+pub fn find_start(line: &str, element: &str) -> Option<usize> {
+    let tag = format!("<{}", element);
+    line.match_indices(&tag).map(|(i, _)| i).find(|&i| {
+        line[i + tag.len()..].starts_with(|c: char| c.is_whitespace() || c == '>' || c == '/')
+    })
+}
+
+pub enum Scan {
+    Complete(Record),
+    // The end tag hasn't arrived yet
+    Incomplete,
+    Invalid,
+}
+
+// Parse the element `text` starts with
+pub fn scan(text: &str) -> Scan {
+    let mut record = Record::default();
+    // Names of the open elements and the text collected in each
+    let mut open: Vec<(String, String)> = Vec::new();
+    let mut pos = 0;
+    loop {
+        let rest = &text[pos..];
+        if rest.is_empty() {
+            return Scan::Incomplete;
+        }
+        if !rest.starts_with('<') {
+            let end = rest.find('<').unwrap_or(rest.len());
+            if let Some((_, collected)) = open.last_mut() {
+                collected.push_str(&decode(&rest[..end]));
+            }
+            pos += end;
+            continue;
+        }
+
+        let skip = [("<!--", "-->"), ("<?", "?>"), ("<![CDATA[", "]]>"), ("<!", ">")];
+        if let Some((open_with, close_with)) = skip.iter().find(|(o, _)| rest.starts_with(o)) {
+            let Some(end) = rest.find(close_with) else {
+                return Scan::Incomplete;
+            };
+            if *open_with == "<![CDATA["
+                && let Some((_, collected)) = open.last_mut()
+            {
+                collected.push_str(&rest[open_with.len()..end]);
+            }
+            pos += end + close_with.len();
+            continue;
+        }
+
+        let Some(len) = tag_len(rest) else {
+            return Scan::Incomplete;
+        };
+        let tag = &rest[1..len - 1];
+        pos += len;
+        if let Some(name) = tag.strip_prefix('/') {
+            let Some((expected, collected)) = open.pop() else {
+                return Scan::Invalid;
+            };
+            if name.trim() != expected {
+                return Scan::Invalid;
+            }
+            add_text(&mut record, &open, &expected, collected.trim());
+            if open.is_empty() {
+                return Scan::Complete(record);
+            }
+            continue;
+        }
+
+        let (tag, self_closing) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+        let name = &tag[..name_end];
+        if name.is_empty() {
+            return Scan::Invalid;
+        }
+        let Some(attributes) = parse_attributes(&tag[name_end..]) else {
+            return Scan::Invalid;
+        };
+        let path = path_of(&open, name);
+        for (key, value) in attributes {
+            let key = match &path {
+                Some(path) => format!("{}@{}", path, key),
+                None => key,
+            };
+            record.fields.push((key, value));
+        }
+        if !self_closing {
+            open.push((name.to_string(), String::new()));
+        } else if open.is_empty() {
+            return Scan::Complete(record);
+        }
+    }
+}
+
+// The field name for element `name` opened inside `open`; None for the record itself
+fn path_of(open: &[(String, String)], name: &str) -> Option<String> {
+    if open.is_empty() {
+        return None;
+    }
+    let mut names: Vec<&str> = open[1..].iter().map(|(n, _)| n.as_str()).collect();
+    names.push(name);
+    Some(names.join("."))
+}
+
+fn add_text(record: &mut Record, open: &[(String, String)], name: &str, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+    let key = path_of(open, name).unwrap_or_else(|| "text".to_string());
+    record.fields.push((key, text.to_string()));
+}
+
+// Length of the tag `text` starts with, up to and including its '>'; quoted attribute
+// values may contain '>'
+fn tag_len(text: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in text.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '>') => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+// name="value" pairs, with single or double quotes
+fn parse_attributes(mut text: &str) -> Option<Vec<(String, String)>> {
+    let mut attributes = Vec::new();
+    loop {
+        text = text.trim_start();
+        if text.is_empty() {
+            return Some(attributes);
+        }
+        let (name, rest) = text.split_once('=')?;
+        let rest = rest.trim_start();
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let end = rest[1..].find(quote)?;
+        attributes.push((name.trim().to_string(), decode(&rest[1..end + 1])));
+        text = &rest[end + 2..];
+    }
+}
+
+// Replace entity and character references; unknown ones are left as they are
+fn decode(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest.find(';').and_then(|semi| {
+            let c = match &rest[1..semi] {
+                "lt" => '<',
+                "gt" => '>',
+                "amp" => '&',
+                "quot" => '"',
+                "apos" => '\'',
+                entity => {
+                    let code = match entity.strip_prefix("#x").or_else(|| entity.strip_prefix("#X")) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => entity.strip_prefix('#')?.parse().ok()?,
+                    };
+                    char::from_u32(code)?
+                }
+            };
+            Some((c, semi + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -0,0 +1,554 @@
+// xz decompression, for compressed input. A stream is a header, blocks, an index of the
+// blocks and a footer; each block is LZMA2 chunks, which are stored bytes or LZMA (a
+// range coder over literals and matches) and may reset the dictionary, the coder's state
+// or its lc/lp/pb properties. Streams can be concatenated, with zero padding between.
+//
+// Only blocks with LZMA2 as their one filter are supported: xz's default, but not what
+// --x86 and the other filter options write.
+
+use crate::compressed::Input;
+
+const HEADER_MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0];
+const FOOTER_MAGIC: [u8; 2] = [b'Y', b'Z'];
+const LZMA2: u64 = 0x21;
+// Probabilities are 11 bits; each starts at a half
+const PROB_INIT: u16 = 1024;
+// Literal coders are 0x300 probabilities each
+const LITERAL_SIZE: usize = 0x300;
+
+// Reads the stream a byte at a time, and is the range decoder when in LZMA data
+struct Coder<'a, I> {
+    input: &'a mut I,
+    out: &'a mut Vec<u8>,
+    // Bytes since the stream began; blocks and the index are padded to 4
+    read: u64,
+    range: u32,
+    code: u32,
+}
+
+impl<I: Input> Coder<'_, I> {
+    fn byte(&mut self) -> Option<u8> {
+        let b = self.input.byte(self.out)?;
+        self.read += 1;
+        Some(b)
+    }
+
+    fn skip(&mut self, n: u64) -> Option<()> {
+        for _ in 0..n {
+            self.byte()?;
+        }
+        Some(())
+    }
+
+    fn be16(&mut self) -> Option<u32> {
+        Some(((self.byte()? as u32) << 8) | self.byte()? as u32)
+    }
+
+    fn varint(&mut self) -> Option<u64> {
+        let mut value = 0;
+        for i in 0..9 {
+            let b = self.byte()?;
+            value |= ((b & 0x7f) as u64) << (7 * i);
+            if b & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    // Zero bytes up to a multiple of 4
+    fn padding(&mut self) -> Option<()> {
+        while !self.read.is_multiple_of(4) {
+            if self.byte()? != 0 {
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    fn start_range(&mut self) -> Option<()> {
+        if self.byte()? != 0 {
+            return None;
+        }
+        self.code = 0;
+        for _ in 0..4 {
+            self.code = (self.code << 8) | self.byte()? as u32;
+        }
+        self.range = u32::MAX;
+        (self.code != self.range).then_some(())
+    }
+
+    fn normalize(&mut self) -> Option<()> {
+        if self.range < 1 << 24 {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.byte()? as u32;
+        }
+        Some(())
+    }
+
+    fn bit(&mut self, prob: &mut u16) -> Option<u32> {
+        let bound = (self.range >> 11) * *prob as u32;
+        let bit = if self.code < bound {
+            *prob += (2048 - *prob) >> 5;
+            self.range = bound;
+            0
+        } else {
+            *prob -= *prob >> 5;
+            self.code -= bound;
+            self.range -= bound;
+            1
+        };
+        self.normalize()?;
+        Some(bit)
+    }
+
+    // Bits at even odds, most significant first
+    fn direct(&mut self, n: u32) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..n {
+            self.range >>= 1;
+            self.code = self.code.wrapping_sub(self.range);
+            let t = 0u32.wrapping_sub(self.code >> 31);
+            self.code = self.code.wrapping_add(self.range & t);
+            value = (value << 1).wrapping_add(t.wrapping_add(1));
+            self.normalize()?;
+        }
+        Some(value)
+    }
+
+    fn tree(&mut self, probs: &mut [u16], bits: u32) -> Option<u32> {
+        let mut m = 1;
+        for _ in 0..bits {
+            m = (m << 1) + self.bit(&mut probs[m])? as usize;
+        }
+        Some((m - (1 << bits)) as u32)
+    }
+
+    fn reverse_tree(&mut self, probs: &mut [u16], bits: u32) -> Option<u32> {
+        let (mut m, mut value) = (1, 0);
+        for i in 0..bits {
+            let bit = self.bit(&mut probs[m])?;
+            m = (m << 1) + bit as usize;
+            value |= bit << i;
+        }
+        Some(value)
+    }
+}
+
+// xz streams one after another, as xzcat reads them
+pub fn decode<I: Input>(input: &mut I, out: &mut Vec<u8>) {
+    let mut coder = Coder { input, out, read: 0, range: 0, code: 0 };
+    while stream(&mut coder).is_some() {
+        coder.input.boundary();
+    }
+}
+
+fn stream<I: Input>(c: &mut Coder<I>) -> Option<()> {
+    let mut first = c.byte()?;
+    while first == 0 {
+        first = c.byte()?;
+    }
+    c.read = 1;
+    // The magic is checked as it's read, so that a few bytes of something else aren't
+    // taken for a stream cut short
+    if first != HEADER_MAGIC[0] {
+        return None;
+    }
+    for &m in &HEADER_MAGIC[1..] {
+        if c.byte()? != m {
+            return None;
+        }
+    }
+    let flags = [c.byte()?, c.byte()?];
+    if flags[0] != 0 || flags[1] > 0x0f {
+        return None;
+    }
+    // The header's CRC32
+    c.skip(4)?;
+    let check = match flags[1] {
+        0 => 0,
+        kind => 4 << ((kind - 1) / 3),
+    };
+    loop {
+        match c.byte()? {
+            0 => break,
+            size => block(c, size, check)?,
+        }
+    }
+    index(c)?;
+    let mut footer = [0u8; 12];
+    for b in &mut footer {
+        *b = c.byte()?;
+    }
+    (footer[10..] == FOOTER_MAGIC).then_some(())
+}
+
+// A block whose header is `size`*4 + 4 bytes long, the first of which was read
+fn block<I: Input>(c: &mut Coder<I>, size: u8, check: u64) -> Option<()> {
+    let mut header = Vec::with_capacity(size as usize * 4 + 3);
+    for _ in 0..size as usize * 4 + 3 {
+        header.push(c.byte()?);
+    }
+    let flags = header[0];
+    if flags & 0x3f != 0 {
+        return None;
+    }
+    let mut at = 1;
+    for present in [0x40, 0x80] {
+        if flags & present != 0 {
+            varint(&header, &mut at)?;
+        }
+    }
+    if varint(&header, &mut at)? != LZMA2 || varint(&header, &mut at)? != 1 {
+        return None;
+    }
+    let props = *header.get(at)?;
+    let dict_size = match props {
+        0..40 => (2 | (props as u64 & 1)) << (props / 2 + 11),
+        40 => u32::MAX as u64,
+        _ => return None,
+    };
+    c.input.window(dict_size as usize);
+    lzma2(c, dict_size)?;
+    c.padding()?;
+    c.skip(check)
+}
+
+fn varint(bytes: &[u8], at: &mut usize) -> Option<u64> {
+    let mut value = 0;
+    for i in 0..9 {
+        let b = *bytes.get(*at)?;
+        *at += 1;
+        value |= ((b & 0x7f) as u64) << (7 * i);
+        if b & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+// The index after the blocks, its first byte read; its sizes aren't checked
+fn index<I: Input>(c: &mut Coder<I>) -> Option<()> {
+    let records = c.varint()?;
+    for _ in 0..records {
+        c.varint()?;
+        c.varint()?;
+    }
+    c.padding()?;
+    c.skip(4)
+}
+
+fn lzma2<I: Input>(c: &mut Coder<I>, dict_size: u64) -> Option<()> {
+    let mut lzma: Option<Lzma> = None;
+    // Bytes since the dictionary was reset, which the first chunk must do
+    let mut pos: Option<u64> = None;
+    loop {
+        let control = c.byte()?;
+        match control {
+            0 => return Some(()),
+            1 | 2 => {
+                if control == 1 {
+                    pos = Some(0);
+                }
+                let pos = pos.as_mut()?;
+                let size = c.be16()? + 1;
+                for _ in 0..size {
+                    let b = c.byte()?;
+                    c.out.push(b);
+                }
+                *pos += size as u64;
+            }
+            0x80.. => {
+                let unpacked = (((control as u32 & 0x1f) << 16) | c.be16()?) + 1;
+                let packed = c.be16()? + 1;
+                let reset = (control >> 5) & 3;
+                if reset == 3 {
+                    pos = Some(0);
+                }
+                if reset >= 2 {
+                    lzma = Some(Lzma::new(c.byte()?)?);
+                } else if reset == 1 {
+                    let old = lzma.as_ref()?;
+                    lzma = Some(Lzma::with(old.lc, old.lp, old.pb));
+                }
+                let (lzma, pos) = (lzma.as_mut()?, pos.as_mut()?);
+                let start = c.read;
+                c.start_range()?;
+                lzma.chunk(c, pos, *pos + unpacked as u64, dict_size)?;
+                let used = c.read - start;
+                if used > packed as u64 {
+                    return None;
+                }
+                c.skip(packed as u64 - used)?;
+            }
+            _ => return None,
+        }
+    }
+}
+
+// Probabilities for a match or rep length
+struct Length {
+    choice: u16,
+    choice2: u16,
+    low: [[u16; 8]; 16],
+    mid: [[u16; 8]; 16],
+    high: [u16; 256],
+}
+
+impl Length {
+    fn new() -> Length {
+        Length {
+            choice: PROB_INIT,
+            choice2: PROB_INIT,
+            low: [[PROB_INIT; 8]; 16],
+            mid: [[PROB_INIT; 8]; 16],
+            high: [PROB_INIT; 256],
+        }
+    }
+
+    // The length less 2
+    fn decode<I: Input>(&mut self, c: &mut Coder<I>, pos_state: usize) -> Option<usize> {
+        if c.bit(&mut self.choice)? == 0 {
+            return Some(c.tree(&mut self.low[pos_state], 3)? as usize);
+        }
+        if c.bit(&mut self.choice2)? == 0 {
+            return Some(8 + c.tree(&mut self.mid[pos_state], 3)? as usize);
+        }
+        Some(16 + c.tree(&mut self.high, 8)? as usize)
+    }
+}
+
+// An LZMA decoder's state, which carries from chunk to chunk until a reset
+struct Lzma {
+    lc: u32,
+    lp: u32,
+    pb: u32,
+    literal: Vec<u16>,
+    is_match: [u16; 192],
+    is_rep: [u16; 12],
+    is_rep_g0: [u16; 12],
+    is_rep_g1: [u16; 12],
+    is_rep_g2: [u16; 12],
+    is_rep0_long: [u16; 192],
+    slot: [[u16; 64]; 4],
+    special: [u16; 115],
+    align: [u16; 16],
+    len: Length,
+    rep_len: Length,
+    state: usize,
+    reps: [usize; 4],
+}
+
+impl Lzma {
+    // From the properties byte: (pb * 5 + lp) * 9 + lc
+    fn new(props: u8) -> Option<Lzma> {
+        let props = props as u32;
+        let (lc, lp, pb) = (props % 9, props / 9 % 5, props / 45);
+        (pb <= 4 && lc + lp <= 4).then(|| Lzma::with(lc, lp, pb))
+    }
+
+    fn with(lc: u32, lp: u32, pb: u32) -> Lzma {
+        Lzma {
+            lc,
+            lp,
+            pb,
+            literal: vec![PROB_INIT; LITERAL_SIZE << (lc + lp)],
+            is_match: [PROB_INIT; 192],
+            is_rep: [PROB_INIT; 12],
+            is_rep_g0: [PROB_INIT; 12],
+            is_rep_g1: [PROB_INIT; 12],
+            is_rep_g2: [PROB_INIT; 12],
+            is_rep0_long: [PROB_INIT; 192],
+            slot: [[PROB_INIT; 64]; 4],
+            special: [PROB_INIT; 115],
+            align: [PROB_INIT; 16],
+            len: Length::new(),
+            rep_len: Length::new(),
+            state: 0,
+            reps: [0; 4],
+        }
+    }
+
+    // Decode until `pos` reaches `end`; matches don't cross the end of a chunk
+    fn chunk<I: Input>(&mut self, c: &mut Coder<I>, pos: &mut u64, end: u64, dict_size: u64) -> Option<()> {
+        while *pos < end {
+            let pos_state = (*pos & ((1 << self.pb) - 1)) as usize;
+            let state = self.state;
+            if c.bit(&mut self.is_match[(state << 4) + pos_state])? == 0 {
+                let b = self.literal(c, *pos)?;
+                c.out.push(b);
+                *pos += 1;
+                self.state = match state {
+                    0..4 => 0,
+                    4..10 => state - 3,
+                    _ => state - 6,
+                };
+                continue;
+            }
+            let len = if c.bit(&mut self.is_rep[state])? == 1 {
+                if *pos == 0 {
+                    return None;
+                }
+                if c.bit(&mut self.is_rep_g0[state])? == 0 {
+                    if c.bit(&mut self.is_rep0_long[(state << 4) + pos_state])? == 0 {
+                        self.state = if state < 7 { 9 } else { 11 };
+                        let b = *c.out.get(c.out.len().checked_sub(self.reps[0] + 1)?)?;
+                        c.out.push(b);
+                        *pos += 1;
+                        continue;
+                    }
+                } else {
+                    let dist = if c.bit(&mut self.is_rep_g1[state])? == 0 {
+                        self.reps[1]
+                    } else {
+                        let dist = if c.bit(&mut self.is_rep_g2[state])? == 0 {
+                            self.reps[2]
+                        } else {
+                            let dist = self.reps[3];
+                            self.reps[3] = self.reps[2];
+                            dist
+                        };
+                        self.reps[2] = self.reps[1];
+                        dist
+                    };
+                    self.reps[1] = self.reps[0];
+                    self.reps[0] = dist;
+                }
+                self.state = if state < 7 { 8 } else { 11 };
+                self.rep_len.decode(c, pos_state)?
+            } else {
+                self.reps = [0, self.reps[0], self.reps[1], self.reps[2]];
+                let len = self.len.decode(c, pos_state)?;
+                self.state = if state < 7 { 7 } else { 10 };
+                self.reps[0] = self.distance(c, len)? as usize;
+                len
+            } + 2;
+
+            let dist = self.reps[0] + 1;
+            if dist as u64 > *pos || dist as u64 > dict_size || dist > c.out.len() || *pos + len as u64 > end {
+                return None;
+            }
+            let from = c.out.len() - dist;
+            if dist >= len {
+                c.out.extend_from_within(from..from + len);
+            } else {
+                for i in 0..len {
+                    c.out.push(c.out[from + i]);
+                }
+            }
+            *pos += len as u64;
+        }
+        Some(())
+    }
+
+    fn literal<I: Input>(&mut self, c: &mut Coder<I>, pos: u64) -> Option<u8> {
+        let prev = if pos > 0 { *c.out.last()? as usize } else { 0 };
+        let lit_state = (((pos as usize) & ((1 << self.lp) - 1)) << self.lc) + (prev >> (8 - self.lc));
+        let probs = &mut self.literal[LITERAL_SIZE * lit_state..LITERAL_SIZE * (lit_state + 1)];
+        let mut symbol = 1;
+        if self.state >= 7 {
+            let mut match_byte = *c.out.get(c.out.len().checked_sub(self.reps[0] + 1)?)? as usize;
+            while symbol < 0x100 {
+                let match_bit = (match_byte >> 7) & 1;
+                match_byte <<= 1;
+                let bit = c.bit(&mut probs[((1 + match_bit) << 8) + symbol])? as usize;
+                symbol = (symbol << 1) | bit;
+                if match_bit != bit {
+                    break;
+                }
+            }
+        }
+        while symbol < 0x100 {
+            symbol = (symbol << 1) | c.bit(&mut probs[symbol])? as usize;
+        }
+        Some((symbol - 0x100) as u8)
+    }
+
+    // The distance less 1, from its slot and the bits after it
+    fn distance<I: Input>(&mut self, c: &mut Coder<I>, len: usize) -> Option<u32> {
+        let slot = c.tree(&mut self.slot[len.min(3)], 6)?;
+        if slot < 4 {
+            return Some(slot);
+        }
+        let bits = (slot >> 1) - 1;
+        let mut dist = (2 | (slot & 1)) << bits;
+        if slot < 14 {
+            dist += c.reverse_tree(&mut self.special[(dist - slot) as usize..], bits)?;
+        } else {
+            dist += c.direct(bits - 4)? << 4;
+            dist += c.reverse_tree(&mut self.align, 4)?;
+        }
+        Some(dist)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressed::fixtures::{Units, binary, text};
+
+    // `xz -9` of fixtures::text() and fixtures::binary()
+    const TEXT: [u8; 208] = [
+        0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46, 0x04, 0xc0, 0x8f, 0x01,
+        0x8a, 0x06, 0x21, 0x01, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x8e, 0x1b, 0x32, 0x4a,
+        0xe0, 0x03, 0x09, 0x00, 0x87, 0x5d, 0x00, 0x19, 0x0c, 0x02, 0x92, 0x83, 0x80, 0x32, 0x0b, 0xde,
+        0x79, 0xbb, 0x85, 0xbb, 0x6a, 0x2e, 0x32, 0x04, 0x6d, 0x0a, 0x2c, 0xbe, 0xd7, 0x76, 0x3e, 0x5c,
+        0x79, 0x54, 0x54, 0x58, 0xde, 0xc9, 0x73, 0x80, 0xac, 0x98, 0x94, 0x35, 0x4d, 0x86, 0x91, 0xcf,
+        0xba, 0x63, 0xed, 0x98, 0x54, 0x6d, 0xa2, 0x7e, 0xc9, 0xd3, 0x64, 0xe4, 0x77, 0x42, 0xa5, 0x49,
+        0x7a, 0x17, 0x78, 0x78, 0x38, 0xd7, 0xf6, 0xed, 0xa4, 0xfe, 0xe5, 0xdd, 0x41, 0x80, 0x89, 0xce,
+        0x38, 0x50, 0xf0, 0xf1, 0x1f, 0x77, 0x2a, 0x5f, 0x00, 0x7a, 0xd9, 0x75, 0x4c, 0x76, 0x0b, 0x65,
+        0x86, 0x90, 0xb4, 0xd6, 0x3d, 0x62, 0x33, 0xa5, 0xc4, 0xc2, 0xfc, 0xeb, 0x59, 0x5f, 0x57, 0x2c,
+        0x33, 0x19, 0xc8, 0x3c, 0xb5, 0x62, 0xf2, 0x2d, 0xb6, 0xfd, 0x28, 0xd3, 0xd4, 0x66, 0xa6, 0x9e,
+        0x2f, 0x56, 0xb6, 0xb1, 0xc3, 0x5c, 0x00, 0xef, 0xc4, 0x16, 0x5d, 0x92, 0x9a, 0x6c, 0x00, 0x00,
+        0x06, 0x32, 0xa8, 0xf8, 0xbd, 0xc6, 0x1b, 0xbf, 0x00, 0x01, 0xab, 0x01, 0x8a, 0x06, 0x00, 0x00,
+        0xad, 0x36, 0x87, 0x0b, 0xb1, 0xc4, 0x67, 0xfb, 0x02, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5a,
+    ];
+    const BINARY: [u8; 128] = [
+        0xfd, 0x37, 0x7a, 0x58, 0x5a, 0x00, 0x00, 0x04, 0xe6, 0xd6, 0xb4, 0x46, 0x04, 0xc0, 0x44, 0x40,
+        0x21, 0x01, 0x1c, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x64, 0x4c, 0xe6, 0x26,
+        0x01, 0x00, 0x3f, 0x29, 0x72, 0xbb, 0x09, 0x52, 0x9b, 0xe4, 0x32, 0x7b, 0xc4, 0x12, 0x5b, 0xa4,
+        0xed, 0x3b, 0x84, 0xcd, 0x1b, 0x64, 0xad, 0xf6, 0x44, 0x8d, 0xd6, 0x24, 0x6d, 0xb6, 0x04, 0x4d,
+        0x96, 0xdf, 0x2d, 0x76, 0xbf, 0x0d, 0x56, 0x9f, 0xe8, 0x36, 0x7f, 0xc8, 0x16, 0x5f, 0xa8, 0xf1,
+        0x3f, 0x88, 0xd1, 0x1f, 0x68, 0xb1, 0xfa, 0x48, 0x91, 0xda, 0x28, 0x71, 0xba, 0x08, 0x51, 0x9a,
+        0xe3, 0x31, 0x7a, 0x00, 0x05, 0x26, 0x5f, 0x1a, 0xce, 0x75, 0x08, 0x91, 0x00, 0x01, 0x60, 0x40,
+        0x1c, 0x9f, 0xa7, 0x33, 0x1f, 0xb6, 0xf3, 0x7d, 0x01, 0x00, 0x00, 0x00, 0x00, 0x04, 0x59, 0x5a,
+    ];
+
+    fn decompress(data: &[u8]) -> (Vec<u8>, usize) {
+        let mut input = Units::new(data);
+        let mut out = Vec::new();
+        decode(&mut input, &mut out);
+        (out, input.units)
+    }
+
+    #[test]
+    fn decodes_what_the_reference_tool_wrote() {
+        assert_eq!(decompress(&TEXT), (text(), 1));
+        assert_eq!(decompress(&BINARY), (binary(), 1));
+    }
+
+    #[test]
+    fn decodes_concatenated_units() {
+        let (out, units) = decompress(&[&TEXT[..], &BINARY[..], &TEXT[..]].concat());
+        assert_eq!(out, [text(), binary(), text()].concat());
+        assert_eq!(units, 3);
+    }
+
+    #[test]
+    fn stops_where_input_is_cut_short() {
+        for len in [TEXT.len() - 1, TEXT.len() / 2, 3] {
+            let (out, units) = decompress(&TEXT[..len]);
+            assert_eq!(units, 0);
+            assert!(text().starts_with(&out));
+        }
+    }
+
+    #[test]
+    fn survives_corrupt_input() {
+        for i in 0..TEXT.len() {
+            let mut data = TEXT;
+            data[i] ^= 0x55;
+            decompress(&data);
+        }
+    }
+}
@@ -0,0 +1,650 @@
+// Zstandard decompression (RFC 8878), for compressed input. A frame is a header and
+// blocks, each stored, a run of one byte, or compressed: literals, Huffman-coded or not,
+// and sequences of (literals length, match length, offset) coded with FSE, a kind of
+// asymmetric numeral system whose bits are read from the end of the block back.
+// Huffman and FSE tables and the last three offsets carry from block to block within a
+// frame. Frames can be concatenated, and skippable frames are passed over.
+//
+// Frames that need a dictionary aren't supported.
+
+use crate::compressed::Input;
+
+const MAGIC: u32 = 0xfd2f_b528;
+// 0x184d2a50 to 0x184d2a5f
+const SKIPPABLE: u32 = 0x184d_2a50;
+const MAX_BLOCK: usize = 128 * 1024;
+const MAX_HUFFMAN_BITS: u32 = 11;
+
+// The predefined distributions, and the baselines and extra bits of each code
+const LL_DEFAULT: [i16; 36] = [
+    4, 3, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 2, 1, 1, 1, 2, 2, 2, 2, 2, 2, 2, 2, 2, 3, 2, 1, 1, 1, 1, 1,
+    -1, -1, -1, -1,
+];
+const ML_DEFAULT: [i16; 53] = [
+    1, 4, 3, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+    1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1, -1, -1,
+];
+const OF_DEFAULT: [i16; 29] = [
+    1, 1, 1, 1, 1, 1, 2, 2, 2, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, -1, -1, -1, -1, -1,
+];
+const LL_BASE: [u32; 36] = [
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 18, 20, 22, 24, 28, 32, 40, 48, 64,
+    128, 256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536,
+];
+const LL_BITS: [u8; 36] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 3, 3, 4, 6, 7, 8, 9, 10, 11,
+    12, 13, 14, 15, 16,
+];
+const ML_BASE: [u32; 53] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27,
+    28, 29, 30, 31, 32, 33, 34, 35, 37, 39, 41, 43, 47, 51, 59, 67, 83, 99, 131, 259, 515, 1027,
+    2051, 4099, 8195, 16387, 32771, 65539,
+];
+const ML_BITS: [u8; 53] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16,
+];
+
+// What carries from one block of a frame to the next
+struct Frame {
+    huffman: Option<Huffman>,
+    // Literals length, offset and match length
+    tables: [Option<Fse>; 3],
+    reps: [usize; 3],
+    // Bytes the frame has produced, which offsets can't reach past
+    decoded: u64,
+}
+
+// Zstandard frames one after another, as zstdcat reads them
+pub fn decode<I: Input>(input: &mut I, out: &mut Vec<u8>) {
+    while frame(input, out).is_some() {
+        input.boundary();
+    }
+}
+
+fn le<I: Input>(input: &mut I, out: &mut Vec<u8>, n: usize) -> Option<u64> {
+    let mut value = 0;
+    for i in 0..n {
+        value |= (input.byte(out)? as u64) << (8 * i);
+    }
+    Some(value)
+}
+
+fn frame<I: Input>(input: &mut I, out: &mut Vec<u8>) -> Option<()> {
+    let magic = le(input, out, 4)? as u32;
+    if magic & !0xf == SKIPPABLE {
+        let size = le(input, out, 4)?;
+        for _ in 0..size {
+            input.byte(out)?;
+        }
+        return Some(());
+    }
+    if magic != MAGIC {
+        return None;
+    }
+    let descriptor = input.byte(out)?;
+    if descriptor & 0x08 != 0 {
+        return None;
+    }
+    let single_segment = descriptor & 0x20 != 0;
+    let mut window = 0;
+    if !single_segment {
+        let b = input.byte(out)?;
+        let base = 1u64 << (10 + (b >> 3));
+        window = base + base / 8 * (b & 7) as u64;
+    }
+    if le(input, out, [0, 1, 2, 4][(descriptor & 3) as usize])? != 0 {
+        return None;
+    }
+    let content_size = match descriptor >> 6 {
+        0 => le(input, out, single_segment as usize)?,
+        1 => le(input, out, 2)? + 256,
+        2 => le(input, out, 4)?,
+        _ => le(input, out, 8)?,
+    };
+    if single_segment {
+        window = content_size;
+    }
+    input.window(window as usize);
+
+    let mut frame = Frame { huffman: None, tables: [None, None, None], reps: [1, 4, 8], decoded: 0 };
+    loop {
+        let header = le(input, out, 3)? as usize;
+        let size = header >> 3;
+        match (header >> 1) & 3 {
+            0 => {
+                for _ in 0..size {
+                    let b = input.byte(out)?;
+                    out.push(b);
+                }
+                frame.decoded += size as u64;
+            }
+            1 => {
+                let b = input.byte(out)?;
+                out.extend(std::iter::repeat_n(b, size));
+                frame.decoded += size as u64;
+            }
+            2 if size <= MAX_BLOCK => {
+                let mut data = Vec::with_capacity(size);
+                for _ in 0..size {
+                    data.push(input.byte(out)?);
+                }
+                block(&data, &mut frame, out)?;
+            }
+            _ => return None,
+        }
+        if header & 1 == 1 {
+            break;
+        }
+    }
+    // The checksum, which isn't verified
+    if descriptor & 0x04 != 0 {
+        le(input, out, 4)?;
+    }
+    Some(())
+}
+
+fn block(data: &[u8], frame: &mut Frame, out: &mut Vec<u8>) -> Option<()> {
+    let (literals, used) = literals(data, frame)?;
+    let before = out.len();
+    sequences(data.get(used..)?, frame, &literals, out)?;
+    frame.decoded += (out.len() - before) as u64;
+    Some(())
+}
+
+// The literals section: the literals and its size
+fn literals(data: &[u8], frame: &mut Frame) -> Option<(Vec<u8>, usize)> {
+    let first = *data.first()?;
+    let header = |n: usize| data.get(..n).map(|h| h.iter().rev().fold(0u64, |v, &b| (v << 8) | b as u64));
+    let kind = first & 3;
+    let format = (first >> 2) & 3;
+    if kind < 2 {
+        let (at, size) = match format {
+            0 | 2 => (1, (first >> 3) as usize),
+            1 => (2, (header(2)? >> 4) as usize),
+            _ => (3, (header(3)? >> 4) as usize),
+        };
+        return if kind == 0 {
+            Some((data.get(at..at + size)?.to_vec(), at + size))
+        } else {
+            Some((vec![*data.get(at)?; size], at + 1))
+        };
+    }
+
+    let (at, bits, streams) = match format {
+        0 => (3, 10, 1),
+        1 => (3, 10, 4),
+        2 => (4, 14, 4),
+        _ => (5, 18, 4),
+    };
+    let fields = header(at)? >> 4;
+    let size = (fields & ((1 << bits) - 1)) as usize;
+    let compressed = (fields >> bits) as usize;
+    let mut body = data.get(at..at + compressed)?;
+    // Treeless literals use the last block's table
+    if kind == 2 {
+        let (table, used) = Huffman::read(body)?;
+        frame.huffman = Some(table);
+        body = &body[used..];
+    }
+    let table = frame.huffman.as_ref()?;
+    let mut literals = Vec::with_capacity(size);
+    if streams == 1 {
+        table.stream(body, size, &mut literals)?;
+    } else {
+        let jump = body.get(..6)?;
+        let mut sizes = [0; 4];
+        for i in 0..3 {
+            sizes[i] = u16::from_le_bytes([jump[i * 2], jump[i * 2 + 1]]) as usize;
+        }
+        sizes[3] = body.len().checked_sub(6 + sizes[0] + sizes[1] + sizes[2])?;
+        let segment = size.div_ceil(4);
+        let mut start = 6;
+        for (i, len) in sizes.into_iter().enumerate() {
+            let count = if i < 3 { segment } else { size.checked_sub(3 * segment)? };
+            table.stream(&body[start..start + len], count, &mut literals)?;
+            start += len;
+        }
+    }
+    Some((literals, at + compressed))
+}
+
+// The sequences section, carried out: literals and matches appended to `out`
+fn sequences(data: &[u8], frame: &mut Frame, literals: &[u8], out: &mut Vec<u8>) -> Option<()> {
+    let first = *data.first()? as usize;
+    let (count, mut at) = match first {
+        0 => (0, 1),
+        1..128 => (first, 1),
+        128..255 => (((first - 128) << 8) + *data.get(1)? as usize, 2),
+        _ => (*data.get(1)? as usize + ((*data.get(2)? as usize) << 8) + 0x7f00, 3),
+    };
+    if count == 0 {
+        out.extend_from_slice(literals);
+        return Some(());
+    }
+    let modes = *data.get(at)?;
+    at += 1;
+    if modes & 3 != 0 {
+        return None;
+    }
+    let kinds: [(&[i16], u32, u32); 3] = [(&LL_DEFAULT, 6, 9), (&OF_DEFAULT, 5, 8), (&ML_DEFAULT, 6, 9)];
+    for (i, (default, default_log, max_log)) in kinds.into_iter().enumerate() {
+        let table = match (modes >> (6 - 2 * i)) & 3 {
+            0 => Fse::new(default, default_log)?,
+            1 => {
+                at += 1;
+                Fse::rle(*data.get(at - 1)?)
+            }
+            2 => {
+                let (table, used) = Fse::read(data.get(at..)?, max_log)?;
+                at += used;
+                table
+            }
+            _ => frame.tables[i].take()?,
+        };
+        frame.tables[i] = Some(table);
+    }
+    let [Some(ll), Some(of), Some(ml)] = &frame.tables else {
+        return None;
+    };
+
+    let frame_start = out.len() - (frame.decoded as usize).min(out.len());
+    let mut bits = Backward::new(data.get(at..)?)?;
+    let mut ll_state = bits.read(ll.log) as usize;
+    let mut of_state = bits.read(of.log) as usize;
+    let mut ml_state = bits.read(ml.log) as usize;
+    let mut reps = frame.reps;
+    let mut lit = 0;
+    for n in 0..count {
+        let of_code = of.symbol[of_state] as u32;
+        let ll_code = ll.symbol[ll_state] as usize;
+        let ml_code = ml.symbol[ml_state] as usize;
+        if of_code > 31 || ll_code >= LL_BASE.len() || ml_code >= ML_BASE.len() {
+            return None;
+        }
+        let offset_value = ((1u64 << of_code) + bits.read(of_code)) as usize;
+        let match_len = (ML_BASE[ml_code] as u64 + bits.read(ML_BITS[ml_code] as u32)) as usize;
+        let lit_len = (LL_BASE[ll_code] as u64 + bits.read(LL_BITS[ll_code] as u32)) as usize;
+        if n + 1 < count {
+            ll_state = ll.update(ll_state, &mut bits);
+            ml_state = ml.update(ml_state, &mut bits);
+            of_state = of.update(of_state, &mut bits);
+        }
+
+        // 1 to 3 are the last offsets used, shifted by one when there are no literals
+        let offset = if offset_value > 3 {
+            reps = [offset_value - 3, reps[0], reps[1]];
+            reps[0]
+        } else {
+            let index = offset_value - 1 + (lit_len == 0) as usize;
+            if index > 0 {
+                let offset = if index < 3 { reps[index] } else { reps[0].wrapping_sub(1) };
+                if index > 1 {
+                    reps[2] = reps[1];
+                }
+                reps[1] = reps[0];
+                reps[0] = offset;
+            }
+            reps[0]
+        };
+
+        out.extend_from_slice(literals.get(lit..lit + lit_len)?);
+        lit += lit_len;
+        if offset == 0 || offset > out.len() - frame_start {
+            return None;
+        }
+        let from = out.len() - offset;
+        if offset >= match_len {
+            out.extend_from_within(from..from + match_len);
+        } else {
+            for i in 0..match_len {
+                out.push(out[from + i]);
+            }
+        }
+    }
+    out.extend_from_slice(literals.get(lit..)?);
+    frame.reps = reps;
+    Some(())
+}
+
+// Bits read from the start of the data, least significant first
+struct Forward<'a> {
+    data: &'a [u8],
+    bit: usize,
+}
+
+impl Forward<'_> {
+    fn read(&mut self, n: u32) -> Option<u32> {
+        let mut value = 0;
+        for i in 0..n {
+            let b = *self.data.get(self.bit / 8)?;
+            value |= (((b >> (self.bit % 8)) & 1) as u32) << i;
+            self.bit += 1;
+        }
+        Some(value)
+    }
+}
+
+// Bits read from the end of the data back, starting below the highest 1 of the last
+// byte; past the start they read as 0
+struct Backward<'a> {
+    data: &'a [u8],
+    bit: isize,
+}
+
+impl Backward<'_> {
+    fn new(data: &[u8]) -> Option<Backward<'_>> {
+        let last = *data.last()?;
+        if last == 0 {
+            return None;
+        }
+        let bit = (data.len() * 8) as isize - last.leading_zeros() as isize - 1;
+        Some(Backward { data, bit })
+    }
+
+    fn read(&mut self, n: u32) -> u64 {
+        if n == 0 {
+            return 0;
+        }
+        self.bit -= n as isize;
+        let end = self.bit + n as isize;
+        if end <= 0 {
+            return 0;
+        }
+        let low = self.bit.max(0) as usize;
+        let mut word = [0u8; 8];
+        let from = low / 8;
+        let avail = (self.data.len() - from).min(8);
+        word[..avail].copy_from_slice(&self.data[from..from + avail]);
+        let count = end as usize - low;
+        let value = (u64::from_le_bytes(word) >> (low % 8)) & ((1 << count) - 1);
+        value << (low as isize - self.bit)
+    }
+
+    fn overflowed(&self) -> bool {
+        self.bit < 0
+    }
+}
+
+// An FSE decoding table: for each state, its symbol and how the next state is read
+struct Fse {
+    log: u32,
+    symbol: Vec<u8>,
+    bits: Vec<u8>,
+    base: Vec<u16>,
+}
+
+impl Fse {
+    // A table from its description at the start of `data`, and the description's size
+    fn read(data: &[u8], max_log: u32) -> Option<(Fse, usize)> {
+        let mut bits = Forward { data, bit: 0 };
+        let log = bits.read(4)? + 5;
+        if log > max_log {
+            return None;
+        }
+        let mut remaining = 1i32 << log;
+        let mut counts = Vec::new();
+        while remaining > 0 {
+            if counts.len() >= 256 {
+                return None;
+            }
+            // Values 0 to remaining + 1; the small ones take a bit less
+            let width = 32 - ((remaining + 1) as u32).leading_zeros();
+            let lower_mask = (1 << (width - 1)) - 1;
+            let threshold = (1 << width) - 1 - (remaining + 1);
+            let mut value = bits.read(width)? as i32;
+            if value & lower_mask < threshold {
+                bits.bit -= 1;
+                value &= lower_mask;
+            } else if value > lower_mask {
+                value -= threshold;
+            }
+            let count = value - 1;
+            remaining -= count.abs();
+            counts.push(count as i16);
+            if count == 0 {
+                loop {
+                    let repeat = bits.read(2)?;
+                    counts.extend(std::iter::repeat_n(0, repeat as usize));
+                    if repeat != 3 {
+                        break;
+                    }
+                }
+            }
+        }
+        if remaining != 0 || counts.len() > 256 {
+            return None;
+        }
+        Some((Fse::new(&counts, log)?, bits.bit.div_ceil(8)))
+    }
+
+    // The table for the given counts, -1 being "less than 1"
+    fn new(counts: &[i16], log: u32) -> Option<Fse> {
+        let size = 1usize << log;
+        let mut symbol = vec![0u8; size];
+        let mut next = vec![0u32; counts.len()];
+        let mut high = size;
+        for (s, &count) in counts.iter().enumerate() {
+            if count == -1 {
+                high = high.checked_sub(1)?;
+                symbol[high] = s as u8;
+                next[s] = 1;
+            }
+        }
+        let step = (size >> 1) + (size >> 3) + 3;
+        let mut pos = 0;
+        for (s, &count) in counts.iter().enumerate() {
+            if count <= 0 {
+                continue;
+            }
+            next[s] = count as u32;
+            for _ in 0..count {
+                symbol[pos] = s as u8;
+                pos = (pos + step) & (size - 1);
+                while pos >= high {
+                    pos = (pos + step) & (size - 1);
+                }
+            }
+        }
+        if pos != 0 {
+            return None;
+        }
+        let mut bits = vec![0u8; size];
+        let mut base = vec![0u16; size];
+        for i in 0..size {
+            let s = symbol[i] as usize;
+            let state = next[s];
+            next[s] += 1;
+            let width = log.checked_sub(31 - state.leading_zeros())?;
+            bits[i] = width as u8;
+            base[i] = ((state << width) as usize).checked_sub(size)? as u16;
+        }
+        Some(Fse { log, symbol, bits, base })
+    }
+
+    // Every state is `symbol`
+    fn rle(symbol: u8) -> Fse {
+        Fse { log: 0, symbol: vec![symbol], bits: vec![0], base: vec![0] }
+    }
+
+    fn update(&self, state: usize, bits: &mut Backward) -> usize {
+        self.base[state] as usize + bits.read(self.bits[state] as u32) as usize
+    }
+}
+
+// A Huffman decoding table, indexed by the next max_bits bits
+struct Huffman {
+    max_bits: u32,
+    symbol: Vec<u8>,
+    bits: Vec<u8>,
+}
+
+impl Huffman {
+    // A table from its description at the start of `data`, and the description's size
+    fn read(data: &[u8]) -> Option<(Huffman, usize)> {
+        let header = *data.first()? as usize;
+        let mut weights = Vec::new();
+        let used = if header < 128 {
+            // Weights coded with FSE, two states taking turns
+            let body = data.get(1..1 + header)?;
+            let (fse, at) = Fse::read(body, 6)?;
+            let mut bits = Backward::new(body.get(at..)?)?;
+            let mut states = [bits.read(fse.log) as usize, bits.read(fse.log) as usize];
+            'decode: loop {
+                for i in 0..2 {
+                    weights.push(fse.symbol[states[i]]);
+                    states[i] = fse.update(states[i], &mut bits);
+                    if bits.overflowed() {
+                        weights.push(fse.symbol[states[1 - i]]);
+                        break 'decode;
+                    }
+                }
+                if weights.len() > 255 {
+                    return None;
+                }
+            }
+            1 + header
+        } else {
+            // Weights 4 bits each
+            let count = header - 127;
+            let body = data.get(1..1 + count.div_ceil(2))?;
+            weights.extend((0..count).map(|i| if i % 2 == 0 { body[i / 2] >> 4 } else { body[i / 2] & 0xf }));
+            1 + count.div_ceil(2)
+        };
+        Some((Huffman::new(weights)?, used))
+    }
+
+    // The last weight is left out: it's what brings the total to a power of two
+    fn new(mut weights: Vec<u8>) -> Option<Huffman> {
+        if weights.len() > 255 {
+            return None;
+        }
+        let mut total = 0u32;
+        for &w in &weights {
+            if w > MAX_HUFFMAN_BITS as u8 {
+                return None;
+            }
+            if w > 0 {
+                total += 1 << (w - 1);
+            }
+        }
+        if total == 0 {
+            return None;
+        }
+        let max_bits = 32 - total.leading_zeros();
+        let left = (1 << max_bits) - total;
+        if !left.is_power_of_two() || max_bits > MAX_HUFFMAN_BITS {
+            return None;
+        }
+        weights.push(left.trailing_zeros() as u8 + 1);
+
+        let lengths: Vec<u32> = weights.iter().map(|&w| if w > 0 { max_bits + 1 - w as u32 } else { 0 }).collect();
+        let mut rank_count = [0usize; MAX_HUFFMAN_BITS as usize + 2];
+        for &len in &lengths {
+            rank_count[len as usize] += 1;
+        }
+        // Longest codes first
+        let mut rank_start = [0usize; MAX_HUFFMAN_BITS as usize + 2];
+        for len in (1..=max_bits as usize).rev() {
+            rank_start[len - 1] = rank_start[len] + rank_count[len] * (1 << (max_bits as usize - len));
+        }
+        let size = 1 << max_bits;
+        let mut symbol = vec![0u8; size];
+        let mut bits = vec![0u8; size];
+        for (s, &len) in lengths.iter().enumerate() {
+            if len == 0 {
+                continue;
+            }
+            let start = rank_start[len as usize];
+            let span = 1 << (max_bits - len);
+            symbol.get_mut(start..start + span)?.fill(s as u8);
+            bits[start..start + span].fill(len as u8);
+            rank_start[len as usize] += span;
+        }
+        Some(Huffman { max_bits, symbol, bits })
+    }
+
+    // `count` literals from one stream, which they must use up exactly
+    fn stream(&self, data: &[u8], count: usize, out: &mut Vec<u8>) -> Option<()> {
+        let mut bits = Backward::new(data)?;
+        let mask = (1 << self.max_bits) - 1;
+        let mut state = bits.read(self.max_bits) as usize;
+        for _ in 0..count {
+            out.push(self.symbol[state]);
+            let n = self.bits[state] as u32;
+            state = ((state << n) | bits.read(n) as usize) & mask;
+        }
+        (bits.bit == -(self.max_bits as isize)).then_some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compressed::fixtures::{Units, binary, text};
+
+    // `zstd -19` of fixtures::text() and fixtures::binary()
+    const TEXT: [u8; 129] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x64, 0x0a, 0x02, 0x9d, 0x03, 0x00, 0xf2, 0xc5, 0x11, 0x13, 0xa0, 0x1b,
+        0x1b, 0x24, 0xb9, 0xd5, 0xa8, 0xf3, 0x04, 0xf6, 0x37, 0x57, 0x76, 0xf7, 0x52, 0xed, 0x5d, 0xc8,
+        0x03, 0x40, 0xa2, 0xd0, 0x05, 0xc1, 0xba, 0x67, 0xff, 0xaa, 0x33, 0xaf, 0xf0, 0x3f, 0xa3, 0x5d,
+        0x5d, 0x5d, 0x3e, 0x01, 0x85, 0x7d, 0xde, 0xa6, 0xb8, 0x79, 0x44, 0xd7, 0xe1, 0xed, 0x57, 0x40,
+        0xa2, 0x90, 0xc9, 0x69, 0x90, 0x67, 0x41, 0x18, 0x3a, 0x08, 0xa3, 0x20, 0x0f, 0x2e, 0x2a, 0x07,
+        0xe9, 0x00, 0xcd, 0x16, 0x27, 0xa8, 0x11, 0xf0, 0x7d, 0xfd, 0x2e, 0x06, 0xc0, 0x67, 0x0d, 0x11,
+        0x30, 0x04, 0xef, 0x17, 0x1c, 0xa1, 0xf1, 0x01, 0xc1, 0xb2, 0x67, 0x15, 0xa0, 0xc5, 0x99, 0x38,
+        0x9b, 0xb2, 0x72, 0x89, 0x3e, 0x65, 0x16, 0x7f, 0x92, 0xab, 0x0a, 0x6c, 0x2a, 0x88, 0x53, 0xe8,
+        0xd6,
+    ];
+    const BINARY: [u8; 77] = [
+        0x28, 0xb5, 0x2f, 0xfd, 0x24, 0x40, 0x01, 0x02, 0x00, 0x29, 0x72, 0xbb, 0x09, 0x52, 0x9b, 0xe4,
+        0x32, 0x7b, 0xc4, 0x12, 0x5b, 0xa4, 0xed, 0x3b, 0x84, 0xcd, 0x1b, 0x64, 0xad, 0xf6, 0x44, 0x8d,
+        0xd6, 0x24, 0x6d, 0xb6, 0x04, 0x4d, 0x96, 0xdf, 0x2d, 0x76, 0xbf, 0x0d, 0x56, 0x9f, 0xe8, 0x36,
+        0x7f, 0xc8, 0x16, 0x5f, 0xa8, 0xf1, 0x3f, 0x88, 0xd1, 0x1f, 0x68, 0xb1, 0xfa, 0x48, 0x91, 0xda,
+        0x28, 0x71, 0xba, 0x08, 0x51, 0x9a, 0xe3, 0x31, 0x7a, 0x21, 0x35, 0x64, 0x06,
+    ];
+
+    fn decompress(data: &[u8]) -> (Vec<u8>, usize) {
+        let mut input = Units::new(data);
+        let mut out = Vec::new();
+        decode(&mut input, &mut out);
+        (out, input.units)
+    }
+
+    #[test]
+    fn decodes_what_the_reference_tool_wrote() {
+        assert_eq!(decompress(&TEXT), (text(), 1));
+        assert_eq!(decompress(&BINARY), (binary(), 1));
+    }
+
+    #[test]
+    fn decodes_concatenated_units() {
+        let (out, units) = decompress(&[&TEXT[..], &BINARY[..], &TEXT[..]].concat());
+        assert_eq!(out, [text(), binary(), text()].concat());
+        assert_eq!(units, 3);
+    }
+
+    #[test]
+    fn passes_over_skippable_frames() {
+        let skippable = [0x50, 0x2a, 0x4d, 0x18, 4, 0, 0, 0, 1, 2, 3, 4];
+        let (out, _) = decompress(&[&skippable[..], &TEXT[..]].concat());
+        assert_eq!(out, text());
+    }
+
+    #[test]
+    fn stops_where_input_is_cut_short() {
+        for len in [TEXT.len() - 1, TEXT.len() / 2, 3] {
+            let (out, units) = decompress(&TEXT[..len]);
+            assert_eq!(units, 0);
+            assert!(text().starts_with(&out));
+        }
+    }
+
+    #[test]
+    fn survives_corrupt_input() {
+        for i in 0..TEXT.len() {
+            let mut data = TEXT;
+            data[i] ^= 0x55;
+            decompress(&data);
+        }
+    }
+}